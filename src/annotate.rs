@@ -0,0 +1,248 @@
+use crate::db::DB;
+use crate::symbols::{self, Symbol, SymbolKind};
+use crate::verify::cosine;
+use anyhow::Result;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+// Annotate module runs `cearch annotate`: for each function or class of a working-tree file,
+// the most similar symbol elsewhere in the index, flagged as a possible duplicate.
+
+/// How `cearch annotate` prints its findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AnnotateFormat {
+    /// One `path:line name: possible duplicate of ...` line per flagged symbol
+    Text,
+    /// A JSON array of the flagged symbols
+    Json,
+    /// A Markdown table to paste into a pull request comment
+    Markdown,
+}
+
+/// An indexed symbol resembling one of the file's symbols.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Match {
+    pub id: i64,
+    pub path: String,
+    pub line: usize,
+    pub name: String,
+    /// Cosine similarity of the two symbols' embeddings
+    pub similarity: f32,
+}
+
+/// A symbol of the annotated file and its possible duplicate.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Annotation {
+    pub line: usize,
+    pub name: String,
+    pub duplicate_of: Match,
+}
+
+/// The functions and classes of `path` as it is on disk, indexed or not. Constants are left
+/// out: they are too short to tell copies from coincidences.
+pub fn file_symbols(path: &Path) -> Result<Vec<Symbol>, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let found: Vec<Symbol> = symbols::stream_symbols_from_source(path, Ok(source))
+        .map_err(|e| format!("{}: {}", path.display(), e))?
+        .filter(|s| !matches!(s.kind, SymbolKind::Constant))
+        .collect();
+    if found.is_empty() {
+        return Err(format!("{} has no functions or classes", path.display()));
+    }
+    Ok(found)
+}
+
+/// The text of each of `symbols` of the file at `path` that indexing would embed: its code,
+/// without leading annotations when the index was built with `--annotation-strip`.
+pub fn embedded_texts<'s>(symbols: &'s [Symbol], path: &Path, strip: bool) -> Vec<&'s str> {
+    let prefixes = symbols::annotation_prefixes(path);
+    symbols
+        .iter()
+        .map(|s| match strip {
+            true => symbols::strip_annotations(&s.code, prefixes),
+            false => s.code.as_str(),
+        })
+        .collect()
+}
+
+/// The nearest indexed symbol outside `own_path` (the file's stored path) for each of
+/// `symbols`, embedded as `vectors`, kept when at least `threshold` similar.
+pub fn annotate(
+    db: &DB,
+    own_path: &str,
+    symbols: &[Symbol],
+    vectors: &[Vec<f32>],
+    threshold: f32,
+) -> Result<Vec<Annotation>> {
+    let mut annotations = Vec::new();
+    for (symbol, vector) in symbols.iter().zip(vectors) {
+        // The file's indexed copies of its own symbols come first; page past them
+        let mut k = symbols.len() + 1;
+        let nearest = loop {
+            let hits = db.knn(vector, k)?;
+            let fetched = hits.len();
+            if let Some(hit) = hits.into_iter().find(|hit| hit.path != own_path) {
+                break Some(hit);
+            }
+            if fetched < k {
+                break None;
+            }
+            k *= 2;
+        };
+        let Some(hit) = nearest else { continue };
+        let Some(stored) = db.get_embedding(hit.id)? else {
+            continue;
+        };
+        let similarity = cosine(vector, &stored);
+        if similarity < threshold {
+            continue;
+        }
+        annotations.push(Annotation {
+            line: symbol.line,
+            name: symbol.name.clone(),
+            duplicate_of: Match {
+                id: hit.id,
+                path: hit.path,
+                line: hit.line,
+                name: hit.name,
+                similarity,
+            },
+        });
+    }
+    Ok(annotations)
+}
+
+/// Print `annotations` of the file at stored path `path` in `format`.
+pub fn write<W: Write>(
+    mut out: W,
+    path: &str,
+    annotations: &[Annotation],
+    format: AnnotateFormat,
+) -> std::io::Result<()> {
+    match format {
+        AnnotateFormat::Text => {
+            for a in annotations {
+                let d = &a.duplicate_of;
+                writeln!(
+                    out,
+                    "{}:{} {}: possible duplicate of {}:{} {} ({:.3})",
+                    path, a.line, a.name, d.path, d.line, d.name, d.similarity
+                )?;
+            }
+        }
+        AnnotateFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, annotations)?;
+            writeln!(out)?;
+        }
+        AnnotateFormat::Markdown => {
+            if annotations.is_empty() {
+                return writeln!(out, "No possible duplicates found in `{}`.", path);
+            }
+            writeln!(out, "**Possible duplicates in `{}`**\n", path)?;
+            writeln!(out, "| Symbol | Possible duplicate of | Similarity |")?;
+            writeln!(out, "| --- | --- | --- |")?;
+            for a in annotations {
+                let d = &a.duplicate_of;
+                writeln!(
+                    out,
+                    "| `{}` (line {}) | `{}` in `{}:{}` | {:.3} |",
+                    a.name, a.line, d.name, d.path, d.line, d.similarity
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SymbolRecord, WriteOptions};
+
+    #[test]
+    fn near_copies_of_indexed_functions_are_flagged() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 3, &WriteOptions::default()).expect("open");
+        let indexed: [(&str, &str, [f32; 3]); 4] = [
+            ("src/retry.rs", "retry_with_backoff", [0.0, 1.0, 0.0]),
+            ("src/parse.rs", "parse_header", [0.0, 0.0, 1.0]),
+            // The annotated file's own, indexed symbols
+            ("src/new.rs", "backoff_retry", [0.05, 1.0, 0.0]),
+            ("src/new.rs", "render", [1.0, 0.0, 0.0]),
+        ];
+        let records: Vec<SymbolRecord> = indexed
+            .iter()
+            .enumerate()
+            .map(|(i, (path, name, emb))| SymbolRecord::fixture(path, i + 1, name, emb))
+            .collect();
+        db.insert_symbols(&records).unwrap();
+
+        let file = dir.path().join("new.rs");
+        std::fs::write(
+            &file,
+            "fn backoff_retry(n: u32) {\n    sleep(n);\n}\n\nconst LIMIT: u32 = 3;\n\n\
+             fn render() {}\n",
+        )
+        .unwrap();
+        let symbols = file_symbols(&file).unwrap();
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["backoff_retry", "render"]);
+
+        // A near-copy of retry_with_backoff, and a function unlike anything else
+        let vectors = [vec![0.1, 1.0, 0.0], vec![1.0, 0.0, 0.1]];
+        let found = annotate(&db, "src/new.rs", &symbols, &vectors, 0.9).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "backoff_retry");
+        assert_eq!(found[0].line, 1);
+        assert_eq!(found[0].duplicate_of.path, "src/retry.rs");
+        assert_eq!(found[0].duplicate_of.name, "retry_with_backoff");
+        assert!(found[0].duplicate_of.similarity > 0.99);
+
+        let mut text = Vec::new();
+        write(&mut text, "src/new.rs", &found, AnnotateFormat::Text).unwrap();
+        assert!(String::from_utf8(text).unwrap().starts_with(
+            "src/new.rs:1 backoff_retry: possible duplicate of src/retry.rs:1 retry_with_backoff"
+        ));
+        let mut markdown = Vec::new();
+        write(&mut markdown, "src/new.rs", &[], AnnotateFormat::Markdown).unwrap();
+        assert_eq!(
+            String::from_utf8(markdown).unwrap(),
+            "No possible duplicates found in `src/new.rs`.\n"
+        );
+        assert!(file_symbols(&dir.path().join("missing.rs")).is_err());
+    }
+
+    #[test]
+    fn hits_past_the_files_own_symbols_are_found() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 2, &WriteOptions::default()).expect("open");
+        // More indexed symbols of the file itself than it has now, all nearer than the copy
+        let names: Vec<String> = (0..6).map(|i| format!("old_{}", i)).collect();
+        let mut records: Vec<SymbolRecord> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| SymbolRecord::fixture("src/new.rs", i + 1, name, &[1.0, 0.0]))
+            .collect();
+        records.push(SymbolRecord::fixture(
+            "src/copy.rs",
+            1,
+            "copied",
+            &[1.0, 0.1],
+        ));
+        db.insert_symbols(&records).unwrap();
+
+        let file = dir.path().join("new.rs");
+        std::fs::write(&file, "#[inline]\nfn copied() {}\n").unwrap();
+        let symbols = file_symbols(&file).unwrap();
+        let found = annotate(&db, "src/new.rs", &symbols, &[vec![1.0, 0.0]], 0.9).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].duplicate_of.path, "src/copy.rs");
+
+        assert_eq!(
+            embedded_texts(&symbols, &file, false),
+            [symbols[0].code.as_str()]
+        );
+        assert_eq!(embedded_texts(&symbols, &file, true), ["fn copied() {}"]);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::db::DB;
+use crate::embed;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+// Cache module runs `cearch cache`: sizes of the cached models and of the embeddings kept
+// besides the index, and the last-use manifest that `cache prune` goes by.
+
+/// When each cached model was last loaded, by directory name, in `.cearch/models`.
+const LAST_USE_FILE: &str = "last_use.json";
+
+/// A model directory under `.cearch/models`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedModel {
+    /// Directory name, the model's repository with `/` as `--`
+    pub name: String,
+    pub dir: PathBuf,
+    pub bytes: u64,
+    /// Seconds since the epoch the model was last loaded; for models loaded before uses
+    /// were recorded, when its directory last changed
+    pub last_used: Option<u64>,
+}
+
+/// Where models are cached, `.cearch/models`.
+pub fn models_dir(cache_root: &Path) -> PathBuf {
+    cache_root.join("models")
+}
+
+/// The embeddings of dependency sources and of replaced code, which `query` only reads
+/// with `--include-deps` and `--include-history`, as `(name, database)` for those present.
+pub fn embedding_caches(repo_root: &Path) -> Vec<(&'static str, PathBuf)> {
+    [
+        ("dependencies", DB::deps_path(repo_root)),
+        ("history", DB::history_path(repo_root)),
+    ]
+    .into_iter()
+    .filter(|(_, path)| path.exists())
+    .collect()
+}
+
+/// Bytes of the database at `path`, counting its WAL and other siblings.
+pub fn database_size(path: &Path) -> u64 {
+    DB::database_files(path).iter().map(|f| size_of(f)).sum()
+}
+
+fn read_last_use(cache_root: &Path) -> BTreeMap<String, u64> {
+    std::fs::read_to_string(models_dir(cache_root).join(LAST_USE_FILE))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Note in the manifest that model `repo` was loaded at `now` (seconds since the epoch).
+pub fn record_use(cache_root: &Path, repo: &str, now: u64) -> std::io::Result<()> {
+    let mut last_use = read_last_use(cache_root);
+    let dir = embed::model_dir(cache_root, repo);
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    last_use.insert(name.into_owned(), now);
+    write_last_use(&models_dir(cache_root).join(LAST_USE_FILE), &last_use)
+}
+
+/// Write the manifest aside and rename it over `path`, so that a concurrent reader never
+/// sees half of it.
+fn write_last_use(path: &Path, last_use: &BTreeMap<String, u64>) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(last_use)?;
+    let staging = path.with_extension(format!("json.{}", std::process::id()));
+    std::fs::write(&staging, json + "\n")?;
+    std::fs::rename(&staging, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&staging);
+    })
+}
+
+/// Seconds since the epoch.
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Bytes of the files under `path`, or of `path` itself; symlinks are not followed.
+pub fn size_of(path: &Path) -> u64 {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| size_of(&entry.path()))
+        .sum()
+}
+
+/// The cached models, by name.
+pub fn models(cache_root: &Path) -> Vec<CachedModel> {
+    let last_use = read_last_use(cache_root);
+    let mut models: Vec<CachedModel> = std::fs::read_dir(models_dir(cache_root))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let dir = entry.path();
+            let changed = || {
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                let since = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+                Some(since.as_secs())
+            };
+            CachedModel {
+                last_used: last_use.get(&name).copied().or_else(changed),
+                bytes: size_of(&dir),
+                name,
+                dir,
+            }
+        })
+        .collect();
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    models
+}
+
+/// Seconds in an age such as `90d`, `12h` or `2w`.
+pub fn parse_age(age: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid age {:?}; expected e.g. 90d, 12h or 2w", age);
+    let age = age.trim();
+    let (split, _) = age.char_indices().last().ok_or_else(invalid)?;
+    let (count, unit) = age.split_at(split);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    count.checked_mul(unit).ok_or_else(invalid)
+}
+
+/// The cached models last used more than `age` seconds before `now`.
+pub fn unused_models(cache_root: &Path, age: u64, now: u64) -> Vec<CachedModel> {
+    let cutoff = now.saturating_sub(age);
+    models(cache_root)
+        .into_iter()
+        .filter(|model| model.last_used.is_none_or(|used| used < cutoff))
+        .collect()
+}
+
+/// Delete `models` and their manifest entries.
+pub fn remove_models(cache_root: &Path, models: &[CachedModel]) -> std::io::Result<()> {
+    let mut last_use = read_last_use(cache_root);
+    for model in models {
+        std::fs::remove_dir_all(&model.dir)?;
+        last_use.remove(&model.name);
+    }
+    let manifest = models_dir(cache_root).join(LAST_USE_FILE);
+    if manifest.exists() {
+        write_last_use(&manifest, &last_use)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn models_are_sized_and_pruned_by_last_use() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let small = embed::model_dir(root, "Qdrant/small");
+        let large = embed::model_dir(root, "Qdrant/large");
+        std::fs::create_dir_all(small.join("onnx")).unwrap();
+        std::fs::create_dir_all(&large).unwrap();
+        std::fs::write(small.join("onnx/model.onnx"), [0u8; 300]).unwrap();
+        std::fs::write(small.join("tokenizer.json"), [0u8; 20]).unwrap();
+        std::fs::write(large.join("model.onnx"), [0u8; 1000]).unwrap();
+
+        let now = 1_000 * DAY;
+        record_use(root, "Qdrant/small", now - 100 * DAY).unwrap();
+        record_use(root, "Qdrant/large", now - 10 * DAY).unwrap();
+        record_use(root, "Qdrant/small", now - 95 * DAY).unwrap();
+
+        let found = models(root);
+        let summary: Vec<(&str, u64, Option<u64>)> = found
+            .iter()
+            .map(|m| (m.name.as_str(), m.bytes, m.last_used))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Qdrant--large", 1000, Some(now - 10 * DAY)),
+                ("Qdrant--small", 320, Some(now - 95 * DAY)),
+            ]
+        );
+
+        let stale = unused_models(root, parse_age("90d").unwrap(), now);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].name, "Qdrant--small");
+        remove_models(root, &stale).unwrap();
+        assert!(!small.exists());
+        assert_eq!(
+            read_last_use(root).keys().collect::<Vec<_>>(),
+            ["Qdrant--large"]
+        );
+        // Nothing is left staged beside the manifest
+        assert_eq!(
+            size_of(root),
+            1000 + size_of(&root.join("models/last_use.json"))
+        );
+
+        // Models loaded before uses were recorded go by when their directory changed
+        let unrecorded = embed::model_dir(root, "Qdrant/unrecorded");
+        std::fs::create_dir_all(&unrecorded).unwrap();
+        let found = models(root);
+        assert!(found[1].last_used.is_some_and(|t| t + DAY > super::now()));
+    }
+
+    #[test]
+    fn embedding_caches_count_their_wal() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        assert!(embedding_caches(root).is_empty());
+        let history = DB::history_path(root);
+        std::fs::create_dir_all(history.parent().unwrap()).unwrap();
+        std::fs::write(&history, [0u8; 4096]).unwrap();
+        std::fs::write(root.join(".cearch/history.sqlite-wal"), [0u8; 100]).unwrap();
+
+        assert_eq!(embedding_caches(root), [("history", history.clone())]);
+        assert_eq!(database_size(&history), 4196);
+        DB::remove_database(&history).unwrap();
+        assert_eq!(size_of(&root.join(".cearch")), 0);
+    }
+
+    #[test]
+    fn ages_take_hours_days_and_weeks() {
+        assert_eq!(parse_age("90d"), Ok(90 * DAY));
+        assert_eq!(parse_age("12h"), Ok(12 * 60 * 60));
+        assert_eq!(parse_age("2w"), Ok(14 * DAY));
+        for bad in ["", "d", "90", "90m", "-1d", "1.5d", "9é"] {
+            assert!(parse_age(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+// Config module reads per-repository settings from `.cearch/config.toml`.
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub model: ModelConfig,
+    #[serde(default)]
+    pub sqlite: SqliteConfig,
+    #[serde(default)]
+    pub vector: VectorConfig,
+    #[serde(default)]
+    pub index: IndexConfig,
+    /// `[indexes.<name>]` tables, read by `cearch index --index-name <name>`
+    #[serde(default)]
+    pub indexes: BTreeMap<String, IndexProfile>,
+    /// `[plugins.<name>]` tables: external symbol extractors for other languages
+    #[serde(default)]
+    pub plugins: BTreeMap<String, PluginConfig>,
+    #[serde(default)]
+    pub forge: ForgeConfig,
+}
+
+/// The `[model]` table: where the embedding model is downloaded from.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelConfig {
+    /// Base URL of a Hugging Face compatible host, e.g. an internal mirror
+    pub endpoint: Option<String>,
+    /// Proxy for model downloads; takes precedence over `HTTPS_PROXY`/`HTTP_PROXY`
+    pub proxy: Option<String>,
+}
+
+/// The `[sqlite]` table: connection tuning, e.g. to use less memory on small machines.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SqliteConfig {
+    /// Page cache for index runs in megabytes; `--sqlite-cache-size` takes precedence
+    pub cache_size_mb: Option<u32>,
+    /// Memory-mapped I/O for index runs and queries in megabytes; 0 disables it
+    pub mmap_size_mb: Option<u32>,
+    /// Keep temporary tables and indices of index runs in memory rather than on disk
+    pub temp_store_memory: Option<bool>,
+}
+
+/// The `[vector]` table: how the vector index is laid out and searched.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VectorConfig {
+    /// Vectors per `vec0` chunk (a multiple of 8); only applies when the index is created
+    pub chunk_size: Option<u32>,
+    /// Scan every vector instead of using the `vec0` index below this many vectors
+    pub brute_force_below: Option<usize>,
+}
+
+/// The `[index]` table: limits on what index runs store.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexConfig {
+    /// Stop an index run once the database uses more than this many megabytes (`--trim`
+    /// drops the largest symbols instead)
+    pub max_size_mb: Option<u64>,
+}
+
+/// The `[forge]` table: where `cearch query --url` links results to.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForgeConfig {
+    /// Remote whose URL names the forge and repository (default `origin`)
+    pub remote: Option<String>,
+    /// Link layout for self-hosted forges, e.g.
+    /// `https://{host}/{repo}/src/commit/{rev}/{path}#L{line}`
+    pub url_template: Option<String>,
+}
+
+/// An `[indexes.<name>]` table: which files the named index holds, and its model.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexProfile {
+    /// Only index files in these languages, e.g. `["Python"]` (case-insensitive)
+    #[serde(default)]
+    pub languages: Vec<String>,
+    /// Only index files under these repo-relative path prefixes, e.g. `["tools/"]`
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Skip files under these repo-relative path prefixes
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Embedding model, as accepted by `cearch models`
+    pub model: Option<String>,
+}
+
+/// A `[plugins.<name>]` table: a command printing the symbols of a file as JSON lines.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    /// Extensions of the files it extracts, e.g. `["dsl"]`; checked before built-in languages
+    pub extensions: Vec<String>,
+    /// Program and arguments, run from the repository root with the file's path appended
+    pub command: Vec<String>,
+    /// Write the file's contents to the command's stdin instead of passing its path
+    #[serde(default)]
+    pub stdin: bool,
+    /// Seconds before the command is killed and the file skipped (default 30)
+    pub timeout_secs: Option<u64>,
+}
+
+impl IndexProfile {
+    /// Whether the file at stored path `path` belongs in the index.
+    pub fn keeps(&self, path: &str) -> bool {
+        let language = crate::symbols::language_name(Path::new(path));
+        let language_ok = self.languages.is_empty()
+            || language.is_some_and(|l| self.languages.iter().any(|w| w.eq_ignore_ascii_case(l)));
+        let under = |prefix: &String| path.starts_with(prefix.trim_start_matches("./"));
+        language_ok
+            && (self.paths.is_empty() || self.paths.iter().any(under))
+            && !self.exclude.iter().any(under)
+    }
+}
+
+impl Config {
+    pub fn file_path(repo_root: &Path) -> PathBuf {
+        repo_root.join(".cearch").join("config.toml")
+    }
+
+    /// Load the repository's config; a missing file means defaults.
+    pub fn load(repo_root: &Path) -> Result<Self, String> {
+        let file = Self::file_path(repo_root);
+        match std::fs::read_to_string(&file) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| format!("invalid {}: {}", file.display(), e))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("failed to read {}: {}", file.display(), err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_model_table_and_defaults_when_missing() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        assert_eq!(Config::load(dir.path()).unwrap(), Config::default());
+
+        std::fs::create_dir_all(dir.path().join(".cearch")).unwrap();
+        std::fs::write(
+            Config::file_path(dir.path()),
+            "[model]\nendpoint = \"https://hf.internal\"\nproxy = \"http://proxy:3128\"\n",
+        )
+        .unwrap();
+        let config = Config::load(dir.path()).unwrap();
+        assert_eq!(
+            config.model.endpoint.as_deref(),
+            Some("https://hf.internal")
+        );
+        assert_eq!(config.model.proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(config.sqlite, SqliteConfig::default());
+
+        std::fs::write(
+            Config::file_path(dir.path()),
+            "[sqlite]\nmmap_size_mb = 0\ntemp_store_memory = false\n",
+        )
+        .unwrap();
+        let sqlite = Config::load(dir.path()).unwrap().sqlite;
+        assert_eq!(sqlite.mmap_size_mb, Some(0));
+        assert_eq!(sqlite.temp_store_memory, Some(false));
+        assert_eq!(sqlite.cache_size_mb, None);
+
+        std::fs::write(
+            Config::file_path(dir.path()),
+            "[vector]\nchunk_size = 256\nbrute_force_below = 0\n",
+        )
+        .unwrap();
+        let vector = Config::load(dir.path()).unwrap().vector;
+        assert_eq!(vector.chunk_size, Some(256));
+        assert_eq!(vector.brute_force_below, Some(0));
+
+        std::fs::write(
+            Config::file_path(dir.path()),
+            "[index]\nmax_size_mb = 500\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Config::load(dir.path()).unwrap().index.max_size_mb,
+            Some(500)
+        );
+
+        std::fs::write(
+            Config::file_path(dir.path()),
+            "[indexes.code]\nlanguages = [\"rust\"]\nexclude = [\"./vendor/\"]\n\n\
+             [indexes.scripts]\npaths = [\"tools/\"]\nmodel = \"all-MiniLM-L6-v2\"\n",
+        )
+        .unwrap();
+        let indexes = Config::load(dir.path()).unwrap().indexes;
+        let code = &indexes["code"];
+        assert!(code.keeps("src/lib.rs"));
+        assert!(!code.keeps("vendor/x/lib.rs"));
+        assert!(!code.keeps("app/main.py"));
+        let scripts = &indexes["scripts"];
+        assert!(scripts.keeps("tools/release.py"));
+        assert!(!scripts.keeps("src/lib.rs"));
+        assert_eq!(scripts.model.as_deref(), Some("all-MiniLM-L6-v2"));
+
+        std::fs::write(
+            Config::file_path(dir.path()),
+            "[plugins.dsl]\nextensions = [\"dsl\"]\ncommand = [\"python3\", \"tools/dsl.py\"]\n\
+             stdin = true\n",
+        )
+        .unwrap();
+        let plugins = Config::load(dir.path()).unwrap().plugins;
+        assert_eq!(plugins["dsl"].command, ["python3", "tools/dsl.py"]);
+        assert!(plugins["dsl"].stdin);
+        assert_eq!(plugins["dsl"].timeout_secs, None);
+
+        std::fs::write(
+            Config::file_path(dir.path()),
+            "[forge]\nurl_template = \"https://{host}/{repo}/src/commit/{rev}/{path}#L{line}\"\n",
+        )
+        .unwrap();
+        let forge = Config::load(dir.path()).unwrap().forge;
+        assert_eq!(forge.remote, None);
+        assert!(forge.url_template.unwrap().contains("/src/commit/{rev}/"));
+
+        std::fs::write(Config::file_path(dir.path()), "[model]\nmirror = \"x\"\n").unwrap();
+        assert!(Config::load(dir.path()).unwrap_err().contains("mirror"));
+    }
+}
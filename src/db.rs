@@ -1,7 +1,16 @@
-use anyhow::Result;
-use rusqlite::{Connection, params};
+use crate::config::{Config, SqliteConfig, VectorConfig};
+use crate::embedding_store::{EmbeddingStore, SplitEmbeddingStore};
+use crate::encryption;
+use crate::lock::IndexLock;
+use anyhow::{Context, Result, bail};
+use rusqlite::types::Value;
+use rusqlite::{Connection, ErrorCode, OpenFlags, OptionalExtension, params, params_from_iter};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::Once;
+use std::rc::Rc;
+use std::sync::{Once, OnceLock};
+use std::time::Duration;
 
 // Register sqlite-vec extension globally once so new connections auto-load it.
 fn ensure_vec_extension_loaded() {
@@ -18,6 +27,187 @@ fn ensure_vec_extension_loaded() {
     });
 }
 
+/// Ordered schema migrations; entry `i` upgrades `PRAGMA user_version` from `i` to `i + 1`.
+///
+/// Indexes created before versioning existed report version 0 but already contain the
+/// base tables, so the early statements must be idempotent.
+const MIGRATIONS: &[&str] = &[
+    // 1: base tables
+    r#"
+    CREATE TABLE IF NOT EXISTS symbols (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        line INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        name TEXT NOT NULL,
+        code TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    "#,
+    // 2: lookup indexes for per-path and per-name queries
+    r#"
+    CREATE INDEX IF NOT EXISTS idx_symbols_path ON symbols(path);
+    CREATE INDEX IF NOT EXISTS idx_symbols_name ON symbols(name);
+    "#,
+    // 3: duplicates of another symbol's code point at it instead of having their own vector
+    r#"
+    ALTER TABLE symbols ADD COLUMN canonical_rowid INTEGER REFERENCES symbols(id);
+    CREATE INDEX IF NOT EXISTS idx_symbols_canonical ON symbols(canonical_rowid);
+    "#,
+    // 4: keyword index over symbols; only populated by `index --build-fts`
+    r#"
+    CREATE VIRTUAL TABLE IF NOT EXISTS fts_symbols USING fts5(
+        name, code, content=symbols, content_rowid=id, tokenize='porter ascii'
+    );
+    "#,
+    // 5: supported files that yielded no symbols, for `cearch list --empty-files`
+    r#"
+    CREATE TABLE IF NOT EXISTS empty_files (
+        path TEXT PRIMARY KEY
+    );
+    "#,
+    // 6: code as embedded by `index --annotation-strip`; NULL when the original was embedded
+    r#"
+    ALTER TABLE symbols ADD COLUMN clean_code TEXT;
+    "#,
+    // 7: one row per symbol location; re-runs used to insert the same symbol again. The newest
+    // copy is kept and duplicates pointing at a dropped copy are moved to the kept one
+    r#"
+    UPDATE symbols SET canonical_rowid = (
+        SELECT MAX(k.id) FROM symbols o JOIN symbols k
+            ON k.path = o.path AND k.line = o.line AND k.kind = o.kind AND k.name = o.name
+        WHERE o.id = symbols.canonical_rowid
+    ) WHERE canonical_rowid IS NOT NULL;
+    DELETE FROM symbols WHERE id NOT IN (
+        SELECT MAX(id) FROM symbols GROUP BY path, line, kind, name
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_symbols_location ON symbols(path, line, kind, name);
+    INSERT INTO fts_symbols(fts_symbols) VALUES('rebuild');
+    "#,
+    // 8: near-duplicate pairs saved by `index --dedup-across-files --store-duplicates`
+    r#"
+    CREATE TABLE IF NOT EXISTS duplicate_pairs (
+        source_path TEXT NOT NULL,
+        source_line INTEGER NOT NULL,
+        target_path TEXT NOT NULL,
+        target_line INTEGER NOT NULL,
+        distance REAL NOT NULL
+    );
+    "#,
+    // 9: token ids of the embedded text, stored by `index --pre-tokenize` for `reembed`
+    r#"
+    ALTER TABLE symbols ADD COLUMN tokens BLOB;
+    "#,
+    // 10: blake3 of each symbol's code, so hits with identical code can be grouped
+    r#"
+    ALTER TABLE symbols ADD COLUMN code_hash BLOB;
+    CREATE INDEX IF NOT EXISTS idx_symbols_code_hash ON symbols(code_hash);
+    "#,
+    // 11: enclosing symbols, e.g. `Outer > method`; NULL for symbols stored before this
+    r#"
+    ALTER TABLE symbols ADD COLUMN parents TEXT;
+    "#,
+    // 12: files whose symbols came from a `[plugins.<name>]` extractor rather than tree-sitter
+    r#"
+    CREATE TABLE IF NOT EXISTS file_origins (
+        path TEXT PRIMARY KEY,
+        origin TEXT NOT NULL
+    );
+    "#,
+];
+
+/// Separator of the names in `symbols.parents`, outermost first.
+const PARENTS_SEPARATOR: &str = " > ";
+
+/// Passes of `trim_to`; each re-measures, since its per-symbol estimates are rough.
+const TRIM_ROUNDS: usize = 4;
+
+/// The part of the index a table (or index, by its table) belongs to, for `size_breakdown`.
+fn size_category(table: &str) -> &'static str {
+    match table {
+        "symbols" => "symbols (code and metadata)",
+        t if t == "vec_index" || t.starts_with("vec_index_") => "vectors",
+        t if t.starts_with("fts_symbols") => "keyword index",
+        _ => "other",
+    }
+}
+
+/// Schema version after which `vec_index` may hold vectors of symbols removed by migration 7.
+const LOCATION_UNIQUE_VERSION: usize = 7;
+
+/// Schema version that added `code_hash`; symbols stored before it are hashed on upgrade.
+const CODE_HASH_VERSION: usize = 10;
+
+/// Apply any migrations newer than the database's recorded schema version.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        // Plain SQL cannot skip a missing table, and metadata-only indexes have no vectors
+        if i + 1 == LOCATION_UNIQUE_VERSION {
+            let has_vectors: bool = tx.query_row(
+                "SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = 'vec_index'",
+                [],
+                |row| row.get(0),
+            )?;
+            if has_vectors {
+                tx.execute(
+                    "DELETE FROM vec_index WHERE rowid NOT IN (SELECT id FROM symbols)",
+                    [],
+                )?;
+            }
+        }
+        if i + 1 == CODE_HASH_VERSION {
+            let codes: Vec<(i64, String)> = tx
+                .prepare("SELECT id, code FROM symbols")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let mut update = tx.prepare("UPDATE symbols SET code_hash = ?2 WHERE id = ?1")?;
+            for (id, code) in codes {
+                update.execute(params![id, code_hash(&code)])?;
+            }
+        }
+        tx.pragma_update(None, "user_version", (i + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Map an `id, path, line, kind, name, code, canonical_rowid` row.
+fn stored_symbol(row: &rusqlite::Row) -> rusqlite::Result<StoredSymbol> {
+    let line: i64 = row.get(2)?;
+    Ok(StoredSymbol {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        line: line as usize,
+        kind: row.get(3)?,
+        name: row.get(4)?,
+        code: row.get(5)?,
+        canonical_rowid: row.get(6)?,
+    })
+}
+
+/// The `code_hash` stored for `code`.
+fn code_hash(code: &str) -> [u8; 32] {
+    *blake3::hash(code.as_bytes()).as_bytes()
+}
+
+/// `module`, a `vec_index` declaration such as `vec0(embedding float[384], chunk_size=256)`,
+/// with the embedding width changed to `dim` and everything else kept.
+fn with_dimension(module: &str, dim: usize) -> Result<String> {
+    let Some((head, rest)) = module.split_once("float[") else {
+        bail!("unexpected vec_index declaration: {}", module);
+    };
+    let Some((_, tail)) = rest.split_once(']') else {
+        bail!("unexpected vec_index declaration: {}", module);
+    };
+    Ok(format!("{}float[{}]{}", head, dim, tail))
+}
+
 fn f32s_to_blob(v: &[f32]) -> Vec<u8> {
     let mut out = Vec::with_capacity(v.len() * 4);
     for x in v {
@@ -26,94 +216,3690 @@ fn f32s_to_blob(v: &[f32]) -> Vec<u8> {
     out
 }
 
+/// Database file used instead of `.cearch/index.sqlite` (`--db-path` or `CEARCH_DB_PATH`).
+static PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keep the index database at `path` for the rest of the process, e.g. on a RAM disk.
+///
+/// Its directory is created if needed; other index files (the lock, the model cache and a
+/// separate embeddings file) stay in `.cearch`.
+pub fn set_path_override(path: &Path) -> Result<()> {
+    let path = std::path::absolute(path)
+        .with_context(|| format!("invalid database path {}", path.display()))?;
+    if path.is_dir() {
+        bail!("database path {} is a directory", path.display());
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("cannot create the directory of {}", path.display()))?;
+    }
+    PATH_OVERRIDE
+        .set(path)
+        .map_err(|_| anyhow::anyhow!("the database path was already set"))
+}
+
+/// Whether `set_path_override` moved the index database.
+pub fn path_overridden() -> bool {
+    PATH_OVERRIDE.get().is_some()
+}
+
+/// Name of the index when `--index-name` is not given, stored as `.cearch/index.sqlite`.
+pub const DEFAULT_INDEX_NAME: &str = "index";
+
+/// Databases in `.cearch` that are not named indexes.
+const RESERVED_INDEX_NAMES: &[&str] = &["deps", "history"];
+
+/// Index chosen with `--index-name`, when not the default.
+static INDEX_NAME: OnceLock<String> = OnceLock::new();
+
+/// Use the index named `name` (`.cearch/<name>.sqlite`) for the rest of the process.
+pub fn set_index_name(name: &str) -> Result<()> {
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if name.is_empty() || !valid {
+        bail!(
+            "invalid index name {:?}; use letters, digits, `-` and `_`",
+            name
+        );
+    }
+    if RESERVED_INDEX_NAMES.contains(&name) {
+        bail!(
+            "{:?} is reserved for `index --{}`; pick another index name",
+            name,
+            name
+        );
+    }
+    INDEX_NAME
+        .set(name.to_string())
+        .map_err(|_| anyhow::anyhow!("the index name was already set"))
+}
+
+/// Name of the index this process uses.
+pub fn index_name() -> &'static str {
+    INDEX_NAME.get().map_or(DEFAULT_INDEX_NAME, String::as_str)
+}
+
+/// How long a connection waits on another connection's lock before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Say what to do about the failures that a concurrent run, stale WAL files or a missing
+/// encryption key cause; `keyed` is whether the connection was given a key.
+fn explain_open_error(
+    err: anyhow::Error,
+    repo_root: &Path,
+    db_path: &Path,
+    keyed: bool,
+) -> anyhow::Error {
+    let code = err
+        .downcast_ref::<rusqlite::Error>()
+        .and_then(|e| e.sqlite_error_code());
+    match code {
+        Some(ErrorCode::NotADatabase) if keyed && !encryption::is_encrypted(db_path) => {
+            anyhow::anyhow!(
+                "index {} is not encrypted; unset {} and {}, or run `cearch rekey` to encrypt it",
+                db_path.display(),
+                encryption::KEY_ENV,
+                encryption::KEY_FILE_ENV
+            )
+        }
+        Some(ErrorCode::NotADatabase) if keyed => anyhow::anyhow!(
+            "the index key does not decrypt {}; check {} or {}",
+            db_path.display(),
+            encryption::KEY_ENV,
+            encryption::KEY_FILE_ENV
+        ),
+        Some(ErrorCode::NotADatabase) if encryption::is_encrypted(db_path) => anyhow::anyhow!(
+            "index {} is encrypted; set {} or {} to its key",
+            db_path.display(),
+            encryption::KEY_ENV,
+            encryption::KEY_FILE_ENV
+        ),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => anyhow::anyhow!(
+            "{}; another cearch process may be running; see {}",
+            err,
+            IndexLock::path(repo_root).display()
+        ),
+        Some(ErrorCode::SystemIoFailure) => anyhow::anyhow!(
+            "{}; leftover {}-wal/-shm files from a crash or a network filesystem can cause this; \
+             try `cearch check`, or `cearch clean` and re-index",
+            err,
+            db_path.display()
+        ),
+        _ => err,
+    }
+}
+
+fn l2_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
+
+fn blob_to_f32s(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+fn u32s_to_blob(v: &[u32]) -> Vec<u8> {
+    v.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn blob_to_u32s(blob: &[u8]) -> Vec<u32> {
+    blob.chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Map a `path, line, kind, name, code, distance, duplicate_files, id` row.
+fn search_result(row: &rusqlite::Row) -> rusqlite::Result<SearchResult> {
+    let line: i64 = row.get(1)?;
+    let duplicate_files: i64 = row.get(6)?;
+    Ok(SearchResult {
+        id: row.get(7)?,
+        path: row.get(0)?,
+        line: line as usize,
+        kind: row.get(2)?,
+        name: row.get(3)?,
+        code: row.get(4)?,
+        distance: row.get(5)?,
+        duplicate_files: duplicate_files as usize,
+        duplicates: Vec::new(),
+    })
+}
+
 pub struct DB {
     conn: Connection,
+    /// Vectors kept outside SQLite (`--embedding-store separate`)
+    split: Option<SplitEmbeddingStore>,
+    /// Vector count below which `knn` scans instead of using `vec0`'s `MATCH`
+    brute_force_below: usize,
+    /// Symbols are stored without vectors (`index --metadata-only`)
+    metadata_only: bool,
+}
+
+/// Below this many vectors a full scan is as fast as the `vec0` index and has fewer edge cases.
+pub const DEFAULT_BRUTE_FORCE_BELOW: usize = 5_000;
+
+/// Neighbours fetched per symbol when `near_duplicates` uses the `vec0` index.
+const NEAR_DUPLICATE_NEIGHBOURS: usize = 32;
+
+/// Rowids bound per statement by the `IN (...)` lookups, well under SQLite's variable limit.
+const ROWID_CHUNK: usize = 500;
+
+/// How `knn` finds the nearest vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnnStrategy {
+    /// `vec0` `MATCH` query
+    Indexed,
+    /// Distance to every stored vector
+    Scan,
+}
+
+impl KnnStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KnnStrategy::Indexed => "indexed",
+            KnnStrategy::Scan => "brute-force scan",
+        }
+    }
+}
+
+/// Connection tuning applied when opening the index for writing.
+///
+/// The defaults favour bulk inserts; `[sqlite]` in `.cearch/config.toml` can scale them down.
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    /// SQLite page size in bytes; only takes effect when the database is first created.
+    pub page_size: u32,
+    /// Page cache size in megabytes.
+    pub cache_size_mb: u32,
+    /// Memory-mapped I/O window in megabytes; 0 disables it.
+    pub mmap_size_mb: u32,
+    /// Keep temporary tables and indices in memory.
+    pub temp_store_memory: bool,
+    /// Size the WAL file is truncated back to after checkpoints, in megabytes.
+    pub journal_size_limit_mb: u32,
+    /// Vectors per `vec0` chunk; `None` keeps sqlite-vec's default. Fixed at creation.
+    pub vec_chunk_size: Option<u32>,
+    /// Journal mode to switch to; `None` keeps the database's (WAL for a new one).
+    pub journal_mode: Option<JournalMode>,
+    /// SQLCipher key of the database; defaults to the one `encryption::load_key` read.
+    pub key: Option<String>,
+}
+
+/// How SQLite journals writes to the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    /// Write-ahead log: faster writes and readers never block, but adds `-wal` and `-shm`
+    /// files next to the database
+    Wal,
+    /// Rollback journal deleted after each transaction: a single file at rest, slower bulk
+    /// writes, and readers wait for writers
+    Delete,
+}
+
+impl JournalMode {
+    /// The name `PRAGMA journal_mode` uses, lowercased as it reports it.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JournalMode::Wal => "wal",
+            JournalMode::Delete => "delete",
+        }
+    }
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            page_size: 4096,
+            cache_size_mb: 64,
+            mmap_size_mb: 256,
+            temp_store_memory: true,
+            journal_size_limit_mb: 64,
+            vec_chunk_size: None,
+            journal_mode: None,
+            key: encryption::key().map(String::from),
+        }
+    }
+}
+
+impl WriteOptions {
+    /// Apply the overrides from the repository's `[sqlite]` and `[vector]` config.
+    pub fn with_config(mut self, config: &SqliteConfig, vector: &VectorConfig) -> Self {
+        if vector.chunk_size.is_some() {
+            self.vec_chunk_size = vector.chunk_size;
+        }
+        if let Some(mb) = config.cache_size_mb {
+            self.cache_size_mb = mb;
+        }
+        if let Some(mb) = config.mmap_size_mb {
+            self.mmap_size_mb = mb;
+        }
+        if let Some(memory) = config.temp_store_memory {
+            self.temp_store_memory = memory;
+        }
+        self
+    }
+}
+
+/// Connection tuning applied when opening the index for queries.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// Memory-mapped I/O window in megabytes; 0 disables it.
+    pub mmap_size_mb: u32,
+    /// Scan every vector instead of using the `vec0` index below this many vectors.
+    pub brute_force_below: usize,
+    /// SQLCipher key of the database; defaults to the one `encryption::load_key` read.
+    pub key: Option<String>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            mmap_size_mb: 256,
+            brute_force_below: DEFAULT_BRUTE_FORCE_BELOW,
+            key: encryption::key().map(String::from),
+        }
+    }
+}
+
+impl ReadOptions {
+    /// Apply the overrides from the repository's `[sqlite]` and `[vector]` config.
+    pub fn with_config(mut self, config: &SqliteConfig, vector: &VectorConfig) -> Self {
+        if let Some(mb) = config.mmap_size_mb {
+            self.mmap_size_mb = mb;
+        }
+        if let Some(n) = vector.brute_force_below {
+            self.brute_force_below = n;
+        }
+        self
+    }
+}
+
+fn megabytes(mb: u32) -> i64 {
+    mb as i64 * 1024 * 1024
+}
+
+/// A symbol ready to be written to the index along with its embedding.
+pub struct SymbolRecord<'a> {
+    /// Repo-relative, `/`-separated path (see `paths::to_stored`).
+    pub path: &'a str,
+    pub line: usize,
+    pub kind: &'a str,
+    pub name: &'a str,
+    pub code: &'a str,
+    /// Text the embedding was computed from, when it differs from `code`
+    pub clean_code: Option<&'a str>,
+    /// Ignored by an index opened with `open_metadata_only`
+    pub embedding: &'a [f32],
+    /// Names of the enclosing symbols, outermost first; `None` when not known
+    pub parents: Option<&'a [String]>,
+}
+
+#[cfg(test)]
+impl<'a> SymbolRecord<'a> {
+    /// A function `name` at `path:line` whose code is its name, for tests; other fields are
+    /// set with struct update syntax.
+    pub fn fixture(path: &'a str, line: usize, name: &'a str, embedding: &'a [f32]) -> Self {
+        Self {
+            path,
+            line,
+            kind: "fn",
+            name,
+            code: name,
+            clean_code: None,
+            embedding,
+            parents: None,
+        }
+    }
+}
+
+/// A symbol whose code is identical to an already-indexed symbol; stored without a vector.
+pub struct DuplicateRecord<'a> {
+    pub path: &'a str,
+    pub line: usize,
+    pub kind: &'a str,
+    pub name: &'a str,
+    pub code: &'a str,
+    pub canonical_rowid: i64,
+    pub parents: Option<&'a [String]>,
+}
+
+/// A nearest-neighbour hit.
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    /// Rowid of the symbol, for `cearch show`
+    pub id: i64,
+    pub path: String,
+    pub line: usize,
+    pub kind: String,
+    pub name: String,
+    pub code: String,
+    pub distance: f32,
+    /// Number of other files holding an exact copy of this symbol (see `--symbol-hash-dedup`)
+    pub duplicate_files: usize,
+    /// Lower-ranked hits with the same code, grouped under this one
+    pub duplicates: Vec<DuplicateHit>,
+}
+
+/// Another location of a hit's code, folded into it by `search::collapse_duplicates`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct DuplicateHit {
+    pub id: i64,
+    pub path: String,
+    pub line: usize,
+    pub distance: f32,
+}
+
+/// Location of a stored symbol, as found by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolRef {
+    /// Rowid of the symbol holding the vector: the symbol itself, or the copy it duplicates
+    pub vector_rowid: i64,
+    pub path: String,
+    pub line: usize,
+}
+
+/// A stored symbol as listed by `cearch lookup`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolLocation {
+    pub path: String,
+    pub line: usize,
+    pub kind: String,
+    pub name: String,
+}
+
+/// A stored symbol with its code, as printed by `cearch show`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredSymbol {
+    pub id: i64,
+    pub path: String,
+    pub line: usize,
+    pub kind: String,
+    pub name: String,
+    pub code: String,
+    /// Symbol whose vector this one shares (`--symbol-hash-dedup`)
+    pub canonical_rowid: Option<i64>,
+}
+
+impl StoredSymbol {
+    /// Last line of the symbol's code.
+    pub fn end_line(&self) -> usize {
+        self.line + self.code.lines().count().max(1) - 1
+    }
+}
+
+/// Two symbols in different files whose embeddings are closer than a threshold.
+///
+/// `source` is the location that sorts first, so each pair is reported once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NearDuplicate {
+    pub source_path: String,
+    pub source_line: usize,
+    pub target_path: String,
+    pub target_line: usize,
+    pub distance: f32,
+}
+
+/// What the database's space is spent on, for `stats --size` and the size budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeBreakdown {
+    /// Bytes of pages in use; pages freed by deletions are not counted
+    pub used: u64,
+    /// Bytes of pages per part of the index (see `size_category`), largest first
+    pub tables: Vec<(&'static str, u64)>,
+    /// Estimated bytes of code and vectors per top-level directory, largest first
+    pub directories: Vec<(String, u64)>,
+}
+
+impl SizeBreakdown {
+    /// Lines for `stats --size` and the over-budget error.
+    pub fn report(&self) -> Vec<String> {
+        let mb = |bytes: u64| format!("{:>9.1} MB", bytes as f64 / (1024.0 * 1024.0));
+        let mut lines = vec![format!("index size: {}", mb(self.used).trim_start())];
+        for (table, bytes) in &self.tables {
+            lines.push(format!("  {:<28} {}", table, mb(*bytes)));
+        }
+        if !self.directories.is_empty() {
+            lines.push("largest directories (estimated code and vectors):".to_string());
+            for (dir, bytes) in &self.directories {
+                lines.push(format!("  {:<28} {}", dir, mb(*bytes)));
+            }
+        }
+        lines
+    }
+}
+
+/// Coarse consistency of the stored index, used to explain empty query results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexHealth {
+    /// No symbols have been indexed (fresh `init`, or nothing supported was found).
+    Empty,
+    /// Symbol rows exist but some have no vector (e.g. an interrupted or failed run).
+    MissingVectors {
+        symbols: usize,
+        vectors: usize,
+    },
+    Ready,
 }
 
 impl DB {
-    pub fn open_with_dim(repo_root: &Path, dim: usize) -> Result<Self> {
-        let db_path = repo_root.join(".cearch").join("index.sqlite");
-        std::fs::create_dir_all(db_path.parent().unwrap())?;
-        ensure_vec_extension_loaded();
-        let conn = Connection::open(db_path)?;
-        conn.execute_batch(
-            r#"
-            PRAGMA journal_mode = WAL;
-            PRAGMA synchronous = NORMAL;
-            CREATE TABLE IF NOT EXISTS symbols (
-                id INTEGER PRIMARY KEY,
-                path TEXT NOT NULL,
-                line INTEGER NOT NULL,
-                kind TEXT NOT NULL,
-                name TEXT NOT NULL,
-                code TEXT NOT NULL
-            );
-            "#,
+    pub fn open_with_dim(repo_root: &Path, dim: usize, opts: &WriteOptions) -> Result<Self> {
+        Self::open_inline_at(repo_root, &Self::file_path(repo_root), dim, opts)
+    }
+
+    /// Open the index of dependency sources (`cearch index --deps`) for writing.
+    ///
+    /// It is a database of its own, so its symbols never show up in the repository's results;
+    /// each crate's paths start with its `name-version` label.
+    pub fn open_deps(repo_root: &Path, dim: usize, opts: &WriteOptions) -> Result<Self> {
+        let db = Self::open_inline_at(repo_root, &Self::deps_path(repo_root), dim, opts)?;
+        db.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS dep_crates (label TEXT PRIMARY KEY, dir TEXT NOT NULL);",
         )?;
+        Ok(db)
+    }
+
+    /// Open the index of dependency sources for queries.
+    pub fn open_deps_read(repo_root: &Path) -> Result<Self> {
+        let config = Config::load(repo_root).map_err(|e| anyhow::anyhow!(e))?;
+        let opts = ReadOptions::default().with_config(&config.sqlite, &config.vector);
+        Self::open_read_at(repo_root, &Self::deps_path(repo_root), &opts)
+    }
+
+    /// Open the index of symbols no longer at `HEAD` (`cearch index --history`) for writing.
+    ///
+    /// Like the dependency index it is a database of its own, kept out of normal queries;
+    /// each file version's paths start with the hash of a commit holding it.
+    pub fn open_history(repo_root: &Path, dim: usize, opts: &WriteOptions) -> Result<Self> {
+        let db = Self::open_inline_at(repo_root, &Self::history_path(repo_root), dim, opts)?;
+        db.conn
+            .execute_batch("CREATE TABLE IF NOT EXISTS history_blobs (blob TEXT PRIMARY KEY);")?;
+        Ok(db)
+    }
+
+    /// Open the index of historical symbols for queries.
+    pub fn open_history_read(repo_root: &Path) -> Result<Self> {
+        let config = Config::load(repo_root).map_err(|e| anyhow::anyhow!(e))?;
+        let opts = ReadOptions::default().with_config(&config.sqlite, &config.vector);
+        Self::open_read_at(repo_root, &Self::history_path(repo_root), &opts)
+    }
+
+    fn open_inline_at(
+        repo_root: &Path,
+        db_path: &Path,
+        dim: usize,
+        opts: &WriteOptions,
+    ) -> Result<Self> {
+        let db = DB {
+            conn: Self::open_write_at(repo_root, db_path, opts, true)?,
+            split: None,
+            brute_force_below: DEFAULT_BRUTE_FORCE_BELOW,
+            metadata_only: false,
+        };
+        db.check_embedding_store(EmbeddingStore::Inline)?;
+        // An existing table keeps its dimension, so vectors of another size would only fail on insert
+        if let Some(stored) = db.embedding_dim()?
+            && stored != dim
+        {
+            bail!(
+                "index holds {}-dimension embeddings, not {}; run `cearch clean` and re-index",
+                stored,
+                dim
+            );
+        }
         // Create vector index table with specified dimension if not exists
+        let chunk_size = opts
+            .vec_chunk_size
+            .map(|n| format!(", chunk_size={}", n))
+            .unwrap_or_default();
         let sql = format!(
-            "CREATE VIRTUAL TABLE IF NOT EXISTS vec_index USING vec0(embedding float[{}]);",
-            dim
+            "CREATE VIRTUAL TABLE IF NOT EXISTS vec_index USING vec0(embedding float[{}]{});",
+            dim, chunk_size
         );
-        conn.execute_batch(&sql)?;
-        Ok(DB { conn })
+        db.conn.execute_batch(&sql)?;
+        Ok(db)
     }
 
-    pub fn open_read(repo_root: &Path) -> Result<Self> {
-        let db_path = repo_root.join(".cearch").join("index.sqlite");
-        ensure_vec_extension_loaded();
-        let conn = Connection::open(db_path)?;
-        Ok(DB { conn })
+    /// Open for writing with vectors in `.cearch/embeddings.bin` instead of SQLite.
+    ///
+    /// `vec_index` becomes a plain table mapping each symbol to its row in that file.
+    pub fn open_split(repo_root: &Path, dim: usize, opts: &WriteOptions) -> Result<Self> {
+        if opts.key.is_some() {
+            bail!(
+                "embeddings.bin is never encrypted; use --embedding-store inline with an encrypted index"
+            );
+        }
+        let mut db = DB {
+            conn: Self::open_write(repo_root, opts, true)?,
+            split: None,
+            brute_force_below: DEFAULT_BRUTE_FORCE_BELOW,
+            metadata_only: false,
+        };
+        db.check_embedding_store(EmbeddingStore::Separate)?;
+        if let Some(stored) = db.get_meta("embedding_dim")?
+            && stored != dim.to_string()
+        {
+            bail!(
+                "index holds {}-dimension embeddings, not {}; run `cearch clean` and re-index",
+                stored,
+                dim
+            );
+        }
+        db.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS vec_index (rowid INTEGER PRIMARY KEY, row INTEGER NOT NULL);",
+        )?;
+        db.set_meta("embedding_store", EmbeddingStore::Separate.as_str())?;
+        db.set_meta("embedding_dim", &dim.to_string())?;
+        db.split = Some(SplitEmbeddingStore::open_append(repo_root, dim)?);
+        Ok(db)
     }
 
-    pub fn insert_symbol(
-        &self,
-        path: &Path,
-        line: usize,
-        kind: &str,
-        name: &str,
-        code: &str,
-        embedding: &[f32],
+    /// Open for writing symbols only, for `index --metadata-only`.
+    ///
+    /// No vector table is created and the sqlite-vec extension is not loaded, so the index
+    /// serves name lookups and keyword search but not `cearch query`.
+    pub fn open_metadata_only(repo_root: &Path, opts: &WriteOptions) -> Result<Self> {
+        let db = DB {
+            conn: Self::open_write(repo_root, opts, false)?,
+            split: None,
+            brute_force_below: DEFAULT_BRUTE_FORCE_BELOW,
+            metadata_only: true,
+        };
+        if db.table_exists("vec_index")? {
+            bail!(
+                "index holds embeddings; run `cearch clean` before indexing with --metadata-only"
+            );
+        }
+        db.set_meta("metadata_only", "1")?;
+        Ok(db)
+    }
+
+    /// Whether the index was built with `--metadata-only` and has no vectors to search.
+    pub fn is_metadata_only(&self) -> Result<bool> {
+        Ok(self.get_meta("metadata_only")?.is_some())
+    }
+
+    /// Refuse to mix storage modes within one index.
+    fn check_embedding_store(&self, wanted: EmbeddingStore) -> Result<()> {
+        if self.is_metadata_only()? {
+            bail!("index was built with --metadata-only; run `cearch clean` before embedding it");
+        }
+        let current = match self.get_meta("embedding_store")?.as_deref() {
+            Some("separate") => EmbeddingStore::Separate,
+            _ if self.table_exists("vec_index")? => EmbeddingStore::Inline,
+            // No vectors yet, so either mode can start here
+            _ => return Ok(()),
+        };
+        if current != wanted {
+            bail!(
+                "index was built with --embedding-store {}; run `cearch clean` before switching to {}",
+                current.as_str(),
+                wanted.as_str()
+            );
+        }
+        Ok(())
+    }
+
+    /// Location of the index database: that of the index named by `set_index_name`, unless
+    /// `set_path_override` moved it.
+    pub fn file_path(repo_root: &Path) -> PathBuf {
+        match PATH_OVERRIDE.get() {
+            Some(path) => path.clone(),
+            None => Self::named_path(repo_root, index_name()),
+        }
+    }
+
+    /// Location of the index named `name`, `.cearch/<name>.sqlite`.
+    pub fn named_path(repo_root: &Path, name: &str) -> PathBuf {
+        repo_root.join(".cearch").join(format!("{}.sqlite", name))
+    }
+
+    /// Names of the indexes in `.cearch`, sorted.
+    pub fn index_names(repo_root: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(repo_root.join(".cearch"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry
+                    .file_name()
+                    .to_str()?
+                    .strip_suffix(".sqlite")?
+                    .to_string();
+                (!RESERVED_INDEX_NAMES.contains(&name.as_str())).then_some(name)
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Location of the index of dependency sources, always inside `.cearch`.
+    pub fn deps_path(repo_root: &Path) -> PathBuf {
+        repo_root.join(".cearch").join("deps.sqlite")
+    }
+
+    /// Location of the index of historical symbols, always inside `.cearch`.
+    pub fn history_path(repo_root: &Path) -> PathBuf {
+        repo_root.join(".cearch").join("history.sqlite")
+    }
+
+    /// Delete the index database along with its `-wal`, `-shm` and `-journal` siblings.
+    ///
+    /// Removing only the main file would let a leftover WAL be replayed into the next index.
+    pub fn remove_files(repo_root: &Path) -> std::io::Result<()> {
+        Self::remove_database(&Self::file_path(repo_root))
+    }
+
+    /// Delete the database at `path` the way `remove_files` deletes the index.
+    pub fn remove_database(path: &Path) -> std::io::Result<()> {
+        for file in Self::database_files(path) {
+            match std::fs::remove_file(&file) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The database at `path` and the siblings SQLite may keep beside it, siblings first.
+    pub fn database_files(path: &Path) -> Vec<PathBuf> {
+        ["-wal", "-shm", "-journal", ""]
+            .iter()
+            .map(|suffix| {
+                let mut name = path.as_os_str().to_owned();
+                name.push(suffix);
+                PathBuf::from(name)
+            })
+            .collect()
+    }
+
+    /// Encrypt the index databases with `new_key` (re-encrypting them if `old_key` opens
+    /// them), or decrypt them when `new_key` is `None`. The caller holds the index lock.
+    ///
+    /// Each database is exported into a sibling file under the new key, which then replaces
+    /// it, so an interrupted rekey leaves the old file intact.
+    pub fn rekey(repo_root: &Path, old_key: Option<&str>, new_key: Option<&str>) -> Result<()> {
+        encryption::ensure_available().map_err(anyhow::Error::msg)?;
+        Self::rekey_at(repo_root, &Self::file_path(repo_root), old_key, new_key)?;
+        // The indexes of dependency sources and of history share the key
+        for path in [Self::deps_path(repo_root), Self::history_path(repo_root)] {
+            if path.exists() {
+                Self::rekey_at(repo_root, &path, old_key, new_key)
+                    .with_context(|| format!("failed to rekey {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rekey_at(
+        repo_root: &Path,
+        db_path: &Path,
+        old_key: Option<&str>,
+        new_key: Option<&str>,
     ) -> Result<()> {
-        let tx = self.conn.unchecked_transaction()?;
-        self.conn.execute(
-            "INSERT INTO symbols(path,line,kind,name,code) VALUES(?,?,?,?,?)",
-            params![path.to_string_lossy(), line as i64, kind, name, code],
+        let opts = WriteOptions {
+            key: old_key.map(String::from),
+            ..WriteOptions::default()
+        };
+        let conn = Self::open_write_at(repo_root, db_path, &opts, true)?;
+        let mut rekeyed = db_path.as_os_str().to_owned();
+        rekeyed.push(".rekey");
+        let rekeyed = PathBuf::from(rekeyed);
+        match std::fs::remove_file(&rekeyed) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        // An empty key attaches a plain database
+        conn.execute(
+            "ATTACH DATABASE ?1 AS rekeyed KEY ?2",
+            params![rekeyed.to_string_lossy(), new_key.unwrap_or("")],
         )?;
-        // rowid of last insert
-        let rowid = self.conn.last_insert_rowid();
-        self.conn.execute(
-            "INSERT INTO vec_index(rowid, embedding) VALUES(?1, ?2)",
-            rusqlite::params![rowid, f32s_to_blob(embedding)],
+        conn.query_row("SELECT sqlcipher_export('rekeyed')", [], |_| Ok(()))?;
+        // The export copies the schema and rows but not the header fields
+        let version: i64 = conn.query_row("PRAGMA main.user_version", [], |row| row.get(0))?;
+        conn.pragma_update(Some("rekeyed"), "user_version", version)?;
+        let mode: String = conn.query_row("PRAGMA main.journal_mode", [], |row| row.get(0))?;
+        conn.query_row(
+            &format!("PRAGMA rekeyed.journal_mode = {}", mode),
+            [],
+            |_| Ok(()),
         )?;
-        tx.commit()?;
+        conn.execute_batch("DETACH DATABASE rekeyed")?;
+        // Closing the last connection checkpoints and removes the old WAL
+        conn.close().map_err(|(_, err)| err)?;
+        std::fs::rename(&rekeyed, db_path)?;
         Ok(())
     }
 
-    pub fn knn(&self, query: &[f32], k: usize) -> Result<Vec<(PathBuf, usize, String, f32)>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT s.path, s.line, s.name, v.distance \
-             FROM ( \
-               SELECT rowid, distance \
-               FROM vec_index \
-               WHERE embedding MATCH ?1 \
-               ORDER BY distance \
-               LIMIT ?2 \
-             ) AS v \
-             JOIN symbols s ON s.id = v.rowid \
-             ORDER BY v.distance",
-        )?;
-        let rows = stmt.query_map(params![f32s_to_blob(query), k as i64], |row| {
-            let path: String = row.get(0)?;
-            let line: i64 = row.get(1)?;
-            let name: String = row.get(2)?;
-            let dist: f32 = row.get(3)?;
-            Ok((PathBuf::from(path), line as usize, name, dist))
+    fn open_write(repo_root: &Path, opts: &WriteOptions, vectors: bool) -> Result<Connection> {
+        Self::open_write_at(repo_root, &Self::file_path(repo_root), opts, vectors)
+    }
+
+    fn open_write_at(
+        repo_root: &Path,
+        db_path: &Path,
+        opts: &WriteOptions,
+        vectors: bool,
+    ) -> Result<Connection> {
+        if !opts.page_size.is_power_of_two() || !(512..=65536).contains(&opts.page_size) {
+            bail!(
+                "invalid page size {}: must be a power of two between 512 and 65536",
+                opts.page_size
+            );
+        }
+        Self::connect_write(db_path, opts, vectors)
+            .map_err(|e| explain_open_error(e, repo_root, db_path, opts.key.is_some()))
+    }
+
+    fn connect_write(db_path: &Path, opts: &WriteOptions, vectors: bool) -> Result<Connection> {
+        std::fs::create_dir_all(db_path.parent().unwrap())?;
+        if vectors {
+            ensure_vec_extension_loaded();
+        }
+        let mut conn = Connection::open(db_path)?;
+        encryption::apply_key(&conn, opts.key.as_deref())?;
+        // Wait out a checkpoint or a reader instead of failing on the first conflict
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // Page size is fixed once the first table exists, so it must precede WAL and the schema
+        let is_new: bool = conn.query_row("SELECT COUNT(*) = 0 FROM sqlite_master", [], |row| {
+            row.get(0)
         })?;
-        let mut out = Vec::new();
-        for r in rows {
-            out.push(r?);
+        if is_new {
+            conn.pragma_update(None, "page_size", opts.page_size)?;
         }
-        Ok(out)
+        // Negative cache_size is interpreted by sqlite as KiB rather than pages
+        conn.pragma_update(None, "cache_size", -(opts.cache_size_mb as i64 * 1024))?;
+        conn.pragma_update(None, "mmap_size", megabytes(opts.mmap_size_mb))?;
+        conn.pragma_update(
+            None,
+            "temp_store",
+            if opts.temp_store_memory {
+                "MEMORY"
+            } else {
+                "DEFAULT"
+            },
+        )?;
+        conn.pragma_update(
+            None,
+            "journal_size_limit",
+            megabytes(opts.journal_size_limit_mb),
+        )?;
+        let current: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+        let mode = opts.journal_mode.unwrap_or(if is_new || current == "wal" {
+            JournalMode::Wal
+        } else {
+            JournalMode::Delete
+        });
+        // Without a WAL, only a full sync per commit keeps the database safe from power loss
+        let sync = match mode {
+            JournalMode::Wal => "NORMAL",
+            JournalMode::Delete => "FULL",
+        };
+        // Fold a WAL left by a crashed run into the database before adding to it; leaving
+        // WAL mode checkpoints it as well
+        if current == "wal" {
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        }
+        conn.query_row(
+            &format!("PRAGMA journal_mode = {}", mode.as_str()),
+            [],
+            |_| Ok(()),
+        )?;
+        conn.pragma_update(None, "synchronous", sync)?;
+        migrate(&mut conn)?;
+        conn.execute(
+            "INSERT INTO meta(key, value) VALUES('journal_mode', ?1) \
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![mode.as_str()],
+        )?;
+        Ok(conn)
+    }
+
+    /// Open for queries, with the `[sqlite]` and `[vector]` overrides from the repository's config.
+    pub fn open_read(repo_root: &Path) -> Result<Self> {
+        let config = Config::load(repo_root).map_err(|e| anyhow::anyhow!(e))?;
+        let opts = ReadOptions::default().with_config(&config.sqlite, &config.vector);
+        let db = Self::open_read_with(repo_root, &opts)?;
+        // The mode is a property of the file, so another tool may have changed it since
+        if let Ok(Some(recorded)) = db.get_meta("journal_mode")
+            && let Ok(actual) = db.journal_mode()
+            && actual != recorded
+        {
+            tracing::warn!(
+                "index database is in {} journal mode but was indexed for {}; re-run `cearch index{}` to restore it",
+                actual,
+                recorded,
+                if recorded == "delete" {
+                    " --no-wal"
+                } else {
+                    ""
+                }
+            );
+        }
+        Ok(db)
+    }
+
+    fn open_read_with(repo_root: &Path, opts: &ReadOptions) -> Result<Self> {
+        Self::open_read_at(repo_root, &Self::file_path(repo_root), opts)
+    }
+
+    fn open_read_at(repo_root: &Path, db_path: &Path, opts: &ReadOptions) -> Result<Self> {
+        let connect = || -> Result<Connection> {
+            ensure_vec_extension_loaded();
+            let mut conn = Connection::open(db_path)?;
+            encryption::apply_key(&conn, opts.key.as_deref())?;
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            // Keep older indexes queryable by bringing their schema up to date
+            migrate(&mut conn)?;
+            conn.pragma_update(None, "mmap_size", megabytes(opts.mmap_size_mb))?;
+            // Nothing after the migrations may write through a read connection
+            conn.pragma_update(None, "query_only", true)?;
+            Ok(conn)
+        };
+        let conn =
+            connect().map_err(|e| explain_open_error(e, repo_root, db_path, opts.key.is_some()))?;
+        let mut db = DB {
+            conn,
+            split: None,
+            brute_force_below: opts.brute_force_below,
+            metadata_only: false,
+        };
+        db.load_split_store(repo_root)?;
+        Ok(db)
+    }
+
+    /// Open any index database file read-only, e.g. a copy kept for `cearch diff-index`.
+    ///
+    /// The schema is left as it is, so only the symbols table is relied on, and vectors
+    /// (inline or split) are never read.
+    pub fn open_snapshot(path: &Path) -> Result<Self> {
+        ensure_vec_extension_loaded();
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        encryption::apply_key(&conn, encryption::key())?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        let db = DB {
+            conn,
+            split: None,
+            brute_force_below: DEFAULT_BRUTE_FORCE_BELOW,
+            metadata_only: false,
+        };
+        if !db.table_exists("symbols")? {
+            bail!(
+                "{} is not a cearch index (no symbols table)",
+                path.display()
+            );
+        }
+        Ok(db)
+    }
+
+    /// Open an existing index for maintenance (`cearch gc`), in whichever store mode it uses.
+    pub fn open_maintenance(repo_root: &Path) -> Result<Self> {
+        let mut db = DB {
+            conn: Self::open_write(repo_root, &WriteOptions::default(), true)?,
+            split: None,
+            brute_force_below: DEFAULT_BRUTE_FORCE_BELOW,
+            metadata_only: false,
+        };
+        db.load_split_store(repo_root)?;
+        Ok(db)
+    }
+
+    fn load_split_store(&mut self, repo_root: &Path) -> Result<()> {
+        if self.get_meta("embedding_store")?.as_deref() == Some("separate") {
+            let dim = self
+                .get_meta("embedding_dim")?
+                .and_then(|d| d.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("index metadata is missing embedding_dim"))?;
+            self.split = Some(SplitEmbeddingStore::open_read(repo_root, dim)?);
+        }
+        Ok(())
+    }
+
+    /// Insert a batch of symbols and their embeddings in a single transaction.
+    ///
+    /// A symbol already stored at the same path, line, kind and name is updated in place and
+    /// its vector replaced. Returns the rowids of the inserted symbols, in order.
+    #[tracing::instrument(level = "debug", skip_all, fields(rows = records.len()))]
+    pub fn insert_symbols(&self, records: &[SymbolRecord]) -> Result<Vec<i64>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let rowids = self.insert_symbols_in(&tx, records)?;
+        tx.commit()?;
+        Ok(rowids)
+    }
+
+    /// Replace the symbols stored for the file at `path` with `records` in a single
+    /// transaction, so a file indexed again keeps none of its earlier rows, e.g. symbols
+    /// that have since moved to another line.
+    #[tracing::instrument(level = "debug", skip(self, records), fields(rows = records.len()))]
+    pub fn replace_file_symbols(&self, path: &str, records: &[SymbolRecord]) -> Result<Vec<i64>> {
+        let tx = self.conn.unchecked_transaction()?;
+        self.delete_symbols_in(&tx, path)?;
+        let rowids = self.insert_symbols_in(&tx, records)?;
+        tx.commit()?;
+        Ok(rowids)
+    }
+
+    fn insert_symbols_in(&self, tx: &Connection, records: &[SymbolRecord]) -> Result<Vec<i64>> {
+        let mut rowids = Vec::with_capacity(records.len());
+        {
+            let mut insert_symbol = tx.prepare_cached(
+                "INSERT INTO symbols(path,line,kind,name,code,clean_code,code_hash,parents) \
+                 VALUES(?,?,?,?,?,?,?,?) \
+                 ON CONFLICT(path,line,kind,name) DO UPDATE SET \
+                   code = excluded.code, clean_code = excluded.clean_code, canonical_rowid = NULL, \
+                   tokens = NULL, code_hash = excluded.code_hash, parents = excluded.parents \
+                 RETURNING id",
+            )?;
+            let mut delete_vec = if self.metadata_only {
+                None
+            } else {
+                Some(tx.prepare_cached("DELETE FROM vec_index WHERE rowid = ?1")?)
+            };
+            let mut insert_vec = match (&self.split, self.metadata_only) {
+                (_, true) => None,
+                (Some(_), false) => {
+                    Some(tx.prepare_cached("INSERT INTO vec_index(rowid, row) VALUES(?1, ?2)")?)
+                }
+                (None, false) => Some(
+                    tx.prepare_cached("INSERT INTO vec_index(rowid, embedding) VALUES(?1, ?2)")?,
+                ),
+            };
+            for r in records {
+                let rowid: i64 = insert_symbol.query_row(
+                    params![
+                        r.path,
+                        r.line as i64,
+                        r.kind,
+                        r.name,
+                        r.code,
+                        r.clean_code,
+                        code_hash(r.code),
+                        r.parents.map(|p| p.join(PARENTS_SEPARATOR))
+                    ],
+                    |row| row.get(0),
+                )?;
+                // vec0 has no upsert, so an updated symbol's old vector is removed first
+                if let Some(delete_vec) = &mut delete_vec {
+                    delete_vec.execute(params![rowid])?;
+                }
+                match (&mut insert_vec, &self.split) {
+                    (None, _) => 0,
+                    // A row orphaned by a failed transaction is never referenced, so it is harmless
+                    (Some(insert_vec), Some(store)) => {
+                        insert_vec.execute(params![rowid, store.append(r.embedding)? as i64])?
+                    }
+                    (Some(insert_vec), None) => {
+                        insert_vec.execute(params![rowid, f32s_to_blob(r.embedding)])?
+                    }
+                };
+                rowids.push(rowid);
+            }
+        }
+        Ok(rowids)
+    }
+
+    /// Insert symbols that duplicate an existing symbol's code, linking them to it.
+    #[tracing::instrument(level = "debug", skip_all, fields(rows = records.len()))]
+    pub fn insert_duplicates(&self, records: &[DuplicateRecord]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO symbols(path,line,kind,name,code,canonical_rowid,code_hash,parents) \
+                 VALUES(?,?,?,?,?,?,?,?) \
+                 ON CONFLICT(path,line,kind,name) DO UPDATE SET \
+                   code = excluded.code, clean_code = NULL, canonical_rowid = excluded.canonical_rowid, \
+                   tokens = NULL, code_hash = excluded.code_hash, parents = excluded.parents \
+                 RETURNING id",
+            )?;
+            let mut delete_vec = if self.metadata_only {
+                None
+            } else {
+                Some(tx.prepare_cached("DELETE FROM vec_index WHERE rowid = ?1")?)
+            };
+            for r in records {
+                let rowid: i64 = insert.query_row(
+                    params![
+                        r.path,
+                        r.line as i64,
+                        r.kind,
+                        r.name,
+                        r.code,
+                        r.canonical_rowid,
+                        code_hash(r.code),
+                        r.parents.map(|p| p.join(PARENTS_SEPARATOR))
+                    ],
+                    |row| row.get(0),
+                )?;
+                // A symbol that had its own vector now shares its original's
+                if let Some(delete_vec) = &mut delete_vec {
+                    delete_vec.execute(params![rowid])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Give existing symbols the vectors in `embeddings`, as `(rowid, vector)`, in one
+    /// transaction; each symbol stops sharing a copy's vector. Returns how many symbols had
+    /// a vector that was replaced.
+    pub fn set_embeddings(&self, embeddings: &[(i64, &[f32])]) -> Result<usize> {
+        let mut replaced = 0;
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut delete_vec = tx.prepare_cached("DELETE FROM vec_index WHERE rowid = ?1")?;
+            let mut insert_vec = if self.split.is_some() {
+                tx.prepare_cached("INSERT INTO vec_index(rowid, row) VALUES(?1, ?2)")?
+            } else {
+                tx.prepare_cached("INSERT INTO vec_index(rowid, embedding) VALUES(?1, ?2)")?
+            };
+            let mut unlink =
+                tx.prepare_cached("UPDATE symbols SET canonical_rowid = NULL WHERE id = ?1")?;
+            for (rowid, embedding) in embeddings {
+                replaced += delete_vec.execute(params![rowid])?;
+                match &self.split {
+                    Some(store) => {
+                        insert_vec.execute(params![rowid, store.append(embedding)? as i64])?
+                    }
+                    None => insert_vec.execute(params![rowid, f32s_to_blob(embedding)])?,
+                };
+                unlink.execute(params![rowid])?;
+            }
+        }
+        tx.commit()?;
+        Ok(replaced)
+    }
+
+    /// Store the token ids each symbol's vector was computed from, as `(rowid, tokens)`.
+    pub fn set_tokens(&self, tokens: &[(i64, Vec<u32>)]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut update = tx.prepare_cached("UPDATE symbols SET tokens = ?2 WHERE id = ?1")?;
+            for (rowid, ids) in tokens {
+                update.execute(params![rowid, u32s_to_blob(ids)])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Token ids stored for a symbol by `index --pre-tokenize`.
+    pub fn get_tokens(&self, rowid: i64) -> Result<Vec<u32>> {
+        let blob: Option<Option<Vec<u8>>> = self
+            .conn
+            .query_row(
+                "SELECT tokens FROM symbols WHERE id = ?1",
+                params![rowid],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match blob {
+            Some(Some(blob)) => Ok(blob_to_u32s(&blob)),
+            Some(None) => bail!("symbol {} has no stored tokens", rowid),
+            None => bail!("no symbol with id {}", rowid),
+        }
+    }
+
+    /// Rowids of symbols with their own vector and stored tokens, in rowid order.
+    pub fn tokenized_symbols(&self) -> Result<Vec<i64>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id FROM symbols WHERE tokens IS NOT NULL AND canonical_rowid IS NULL \
+             ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Rowids of symbols with their own vector, in rowid order.
+    pub fn vector_rowids(&self) -> Result<Vec<i64>> {
+        if !self.table_exists("vec_index")? {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT s.id FROM symbols s JOIN vec_index v ON v.rowid = s.id ORDER BY s.id",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The text a symbol's vector was computed from: its code with annotations stripped
+    /// when the indexer stripped any, else its code.
+    pub fn embedded_text(&self, rowid: i64) -> Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT COALESCE(clean_code, code) FROM symbols WHERE id = ?1",
+                params![rowid],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Remove every stored vector, and the vector table itself, so vectors of another
+    /// dimension or model can be stored (`import-embeddings --force`).
+    pub fn drop_vectors(&self, repo_root: &Path) -> Result<()> {
+        self.conn.execute_batch("DROP TABLE IF EXISTS vec_index;")?;
+        for key in ["embedding_dim", "embedding_model", "metadata_only"] {
+            self.remove_meta(key)?;
+        }
+        if self.split.is_some() {
+            let path = SplitEmbeddingStore::file_path(repo_root);
+            if let Err(err) = std::fs::remove_file(&path)
+                && err.kind() != std::io::ErrorKind::NotFound
+            {
+                bail!("failed to remove {}: {}", path.display(), err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepare `migrate_vectors`, the staging table of `cearch migrate-model`, for vectors
+    /// from `model`, and return the rowids already staged. A staging table left by an
+    /// interrupted migration to the same model is kept, so the run resumes; one for another
+    /// model is emptied.
+    pub fn start_migration(&self, model: &str) -> Result<HashSet<i64>> {
+        if self.get_meta("migrate_model")?.as_deref() != Some(model) {
+            self.conn
+                .execute_batch("DROP TABLE IF EXISTS migrate_vectors;")?;
+        }
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS migrate_vectors \
+             (rowid INTEGER PRIMARY KEY, embedding BLOB NOT NULL);",
+        )?;
+        self.set_meta("migrate_model", model)?;
+        let mut stmt = self.conn.prepare("SELECT rowid FROM migrate_vectors")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Stage vectors from the model being migrated to, as `(rowid, vector)`, in one
+    /// transaction; searches keep using the current vectors until `finish_migration`.
+    pub fn stage_vectors(&self, embeddings: &[(i64, &[f32])]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT OR REPLACE INTO migrate_vectors(rowid, embedding) VALUES(?1, ?2)",
+            )?;
+            for (rowid, embedding) in embeddings {
+                insert.execute(params![rowid, f32s_to_blob(embedding)])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replace the vector table with the staged vectors of `model` and record the model,
+    /// all in one transaction. Returns how many vectors the index now holds, and their
+    /// dimensions.
+    pub fn finish_migration(&self, model: &str) -> Result<(usize, usize)> {
+        let tx = self.conn.unchecked_transaction()?;
+        let widths: Vec<i64> = {
+            let mut stmt =
+                tx.prepare("SELECT DISTINCT length(embedding) / 4 FROM migrate_vectors")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let dim = match widths.as_slice() {
+            [dim] if *dim > 0 => *dim as usize,
+            [] => bail!("no vectors were staged for {}", model),
+            _ => bail!(
+                "staged vectors have differing dimensions ({:?}); run `cearch migrate-model` again",
+                widths
+            ),
+        };
+        // The original declaration at the new width, so its chunk size carries over
+        let module = match self.vec_index_module()? {
+            Some(module) => with_dimension(&module, dim)?,
+            None => format!("vec0(embedding float[{}])", dim),
+        };
+        tx.execute_batch(&format!(
+            "DROP TABLE IF EXISTS vec_index; \
+             CREATE VIRTUAL TABLE vec_index USING {}; \
+             INSERT INTO vec_index(rowid, embedding) SELECT rowid, embedding FROM migrate_vectors; \
+             DROP TABLE migrate_vectors;",
+            module
+        ))?;
+        self.set_meta("embedding_model", model)?;
+        self.remove_meta("migrate_model")?;
+        let vectors: i64 = tx.query_row("SELECT COUNT(*) FROM vec_index", [], |row| row.get(0))?;
+        tx.commit()?;
+        Ok((vectors as usize, dim))
+    }
+
+    /// Symbols with their own vector whose code was stored empty, so their vector cannot be
+    /// recomputed from the index (e.g. created by `import-embeddings` without `code`).
+    pub fn count_vectors_without_code(&self) -> Result<usize> {
+        if !self.table_exists("vec_index")? {
+            return Ok(0);
+        }
+        let n: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM symbols s JOIN vec_index v ON v.rowid = s.id \
+             WHERE COALESCE(s.clean_code, s.code) = ''",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(n as usize)
+    }
+
+    /// Model the stored vectors were computed with, when a run or an import recorded it.
+    pub fn embedding_model(&self) -> Result<Option<String>> {
+        self.get_meta("embedding_model")
+    }
+
+    /// Rowids of up to two symbols at `path:line`, narrowed by name and kind when given; two
+    /// means the location is ambiguous.
+    pub fn symbols_at_line(
+        &self,
+        path: &str,
+        line: usize,
+        name: Option<&str>,
+        kind: Option<&str>,
+    ) -> Result<Vec<i64>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id FROM symbols WHERE path = ?1 AND line = ?2 \
+               AND (?3 IS NULL OR name = ?3) AND (?4 IS NULL OR kind = ?4) \
+             ORDER BY id LIMIT 2",
+        )?;
+        let rows = stmt.query_map(params![path, line as i64, name, kind], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Whether a symbol with this rowid exists.
+    pub fn symbol_exists(&self, rowid: i64) -> Result<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT 1 FROM symbols WHERE id = ?1",
+                params![rowid],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Nearest neighbours of `query`; an empty or missing vector table yields no results.
+    #[tracing::instrument(level = "debug", skip(self, query))]
+    pub fn knn(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        if self.count_vectors()? == 0 || !self.table_exists("symbols")? {
+            return Ok(Vec::new());
+        }
+        if let Some(dim) = self.embedding_dim()?
+            && dim != query.len()
+        {
+            bail!(
+                "query embedding has {} dimensions but the index holds {}-dimension embeddings",
+                query.len(),
+                dim
+            );
+        }
+        if let Some(store) = &self.split {
+            return self.knn_split(store, query, k);
+        }
+        // Both yield `rowid, distance` in ascending distance order
+        let nearest = match self.knn_strategy()? {
+            KnnStrategy::Indexed => {
+                "SELECT rowid, distance FROM vec_index \
+                 WHERE embedding MATCH ?1 ORDER BY distance LIMIT ?2"
+            }
+            KnnStrategy::Scan => {
+                "SELECT rowid, vec_distance_l2(embedding, ?1) AS distance FROM vec_index \
+                 ORDER BY distance LIMIT ?2"
+            }
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT s.path, s.line, s.kind, s.name, s.code, v.distance, \
+               (SELECT COUNT(DISTINCT d.path) FROM symbols d \
+                WHERE d.canonical_rowid = s.id AND d.path != s.path), s.id \
+             FROM ({}) AS v \
+             JOIN symbols s ON s.id = v.rowid \
+             ORDER BY v.distance",
+            nearest
+        ))?;
+        let rows = stmt.query_map(params![f32s_to_blob(query), k as i64], search_result)?;
+        let mut out = Vec::new();
+        for r in rows {
+            out.push(r?);
+        }
+        Ok(out)
+    }
+
+    /// Pairs of symbols in different files whose embeddings are less than `threshold` apart
+    /// (L2), closest first.
+    ///
+    /// Small indexes are compared pairwise; larger ones ask the `vec0` index for each symbol's
+    /// nearest neighbours, so a symbol with very many close copies may have some left out.
+    /// Exact copies linked by `--symbol-hash-dedup` share their canonical hit's vector and
+    /// are paired at distance 0.
+    pub fn near_duplicates(&self, threshold: f32) -> Result<Vec<NearDuplicate>> {
+        if !self.table_exists("vec_index")? {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT v.rowid, s.path, s.line FROM vec_index v \
+             JOIN symbols s ON COALESCE(s.canonical_rowid, s.id) = v.rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)? as usize,
+            ))
+        })?;
+        let mut vectors: HashMap<i64, Option<Rc<Vec<f32>>>> = HashMap::new();
+        let mut symbols = Vec::new();
+        for r in rows {
+            let (rowid, path, line) = r?;
+            let vector = match vectors.entry(rowid) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => entry
+                    .insert(self.get_embedding(rowid)?.map(Rc::new))
+                    .clone(),
+            };
+            if let Some(vector) = vector {
+                symbols.push((rowid, path, line, vector));
+            }
+        }
+
+        // Index pairs into `symbols`
+        let mut close: Vec<(usize, usize, f32)> = Vec::new();
+        match self.knn_strategy()? {
+            KnnStrategy::Scan => {
+                for (i, a) in symbols.iter().enumerate() {
+                    for (j, b) in symbols.iter().enumerate().skip(i + 1) {
+                        if a.1 != b.1 {
+                            let distance = l2_distance(&a.3, &b.3);
+                            if distance < threshold {
+                                close.push((i, j, distance));
+                            }
+                        }
+                    }
+                }
+            }
+            KnnStrategy::Indexed => {
+                // Symbols by vector; copies linked to a canonical hit share its vector
+                let mut position: HashMap<i64, Vec<usize>> = HashMap::new();
+                for (i, s) in symbols.iter().enumerate() {
+                    position.entry(s.0).or_default().push(i);
+                }
+                let mut nearest = self.conn.prepare(
+                    "SELECT rowid, distance FROM vec_index \
+                     WHERE embedding MATCH ?1 ORDER BY distance LIMIT ?2",
+                )?;
+                for (i, a) in symbols.iter().enumerate() {
+                    let rows = nearest.query_map(
+                        params![f32s_to_blob(&a.3), NEAR_DUPLICATE_NEIGHBOURS as i64],
+                        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)? as f32)),
+                    )?;
+                    for r in rows {
+                        let (rowid, distance) = r?;
+                        if distance >= threshold {
+                            break;
+                        }
+                        // Each pair is found from both ends; keep it from the lower one
+                        for &j in position.get(&rowid).into_iter().flatten() {
+                            if j > i && symbols[j].1 != a.1 {
+                                close.push((i, j, distance));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut pairs: Vec<NearDuplicate> = close
+            .into_iter()
+            .map(|(i, j, distance)| {
+                let (a, b) = (&symbols[i], &symbols[j]);
+                let (source, target) = if (&a.1, a.2) <= (&b.1, b.2) {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                NearDuplicate {
+                    source_path: source.1.clone(),
+                    source_line: source.2,
+                    target_path: target.1.clone(),
+                    target_line: target.2,
+                    distance,
+                }
+            })
+            .collect();
+        pairs.sort_by(|a, b| {
+            a.distance
+                .total_cmp(&b.distance)
+                .then_with(|| (&a.source_path, a.source_line).cmp(&(&b.source_path, b.source_line)))
+                .then_with(|| (&a.target_path, a.target_line).cmp(&(&b.target_path, b.target_line)))
+        });
+        Ok(pairs)
+    }
+
+    /// Replace the saved near-duplicate pairs with `pairs`.
+    pub fn store_duplicate_pairs(&self, pairs: &[NearDuplicate]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM duplicate_pairs", [])?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO duplicate_pairs(source_path, source_line, target_path, target_line, distance) \
+                 VALUES(?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for p in pairs {
+                insert.execute(params![
+                    p.source_path,
+                    p.source_line as i64,
+                    p.target_path,
+                    p.target_line as i64,
+                    p.distance as f64
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// How `knn` will search this index: small indexes are scanned, as are separate stores.
+    pub fn knn_strategy(&self) -> Result<KnnStrategy> {
+        Ok(
+            if self.split.is_some() || self.count_vectors()? < self.brute_force_below {
+                KnnStrategy::Scan
+            } else {
+                KnnStrategy::Indexed
+            },
+        )
+    }
+
+    /// Exhaustive scan of the mapped embeddings file; rows are read in file order.
+    fn knn_split(
+        &self,
+        store: &SplitEmbeddingStore,
+        query: &[f32],
+        k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT rowid, row FROM vec_index ORDER BY row")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?;
+        let mut nearest: Vec<(f32, i64)> = Vec::new();
+        for r in rows {
+            let (rowid, row) = r?;
+            if let Some(distance) = store.distance(query, row as u64) {
+                nearest.push((distance, rowid));
+            }
+        }
+        nearest.sort_by(|a, b| a.0.total_cmp(&b.0));
+        nearest.truncate(k);
+        self.hits(nearest)
+    }
+
+    /// The `code_hash` of each of `rowids` that has one, keyed by rowid.
+    pub fn code_hashes(&self, rowids: &[i64]) -> Result<HashMap<i64, Vec<u8>>> {
+        let mut hashes = HashMap::with_capacity(rowids.len());
+        for chunk in rowids.chunks(ROWID_CHUNK) {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT id, code_hash FROM symbols WHERE code_hash IS NOT NULL AND id IN ({})",
+                vec!["?"; chunk.len()].join(",")
+            ))?;
+            let rows = stmt.query_map(
+                params_from_iter(chunk.iter().map(|&id| Value::Integer(id))),
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)),
+            )?;
+            for r in rows {
+                let (rowid, hash) = r?;
+                hashes.insert(rowid, hash);
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// The enclosing symbols of the given rowids, outermost first; symbols stored before they
+    /// were recorded are absent.
+    pub fn symbol_parents(&self, rowids: &[i64]) -> Result<HashMap<i64, Vec<String>>> {
+        let mut parents = HashMap::with_capacity(rowids.len());
+        for chunk in rowids.chunks(ROWID_CHUNK) {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT id, parents FROM symbols WHERE parents IS NOT NULL AND id IN ({})",
+                vec!["?"; chunk.len()].join(",")
+            ))?;
+            let rows = stmt.query_map(
+                params_from_iter(chunk.iter().map(|&id| Value::Integer(id))),
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )?;
+            for r in rows {
+                let (rowid, chain) = r?;
+                let names = chain
+                    .split(PARENTS_SEPARATOR)
+                    .filter(|name| !name.is_empty())
+                    .map(String::from)
+                    .collect();
+                parents.insert(rowid, names);
+            }
+        }
+        Ok(parents)
+    }
+
+    /// Nearest neighbours of `query` among the symbols with the given rowids, e.g. the
+    /// results of an earlier query (`query --within`). Rowids without a vector are ignored.
+    pub fn knn_within(&self, query: &[f32], k: usize, rowids: &[i64]) -> Result<Vec<SearchResult>> {
+        if self.count_vectors()? == 0 {
+            return Ok(Vec::new());
+        }
+        if let Some(dim) = self.embedding_dim()?
+            && dim != query.len()
+        {
+            bail!(
+                "query embedding has {} dimensions but the index holds {}-dimension embeddings",
+                query.len(),
+                dim
+            );
+        }
+        let mut rowids = rowids.to_vec();
+        rowids.sort_unstable();
+        rowids.dedup();
+        let mut nearest: Vec<(f32, i64)> = Vec::new();
+        for chunk in rowids.chunks(ROWID_CHUNK) {
+            let ids = chunk.iter().map(|&id| Value::Integer(id));
+            let placeholders = vec!["?"; chunk.len()].join(",");
+            match &self.split {
+                Some(store) => {
+                    let mut stmt = self.conn.prepare(&format!(
+                        "SELECT rowid, row FROM vec_index WHERE rowid IN ({})",
+                        placeholders
+                    ))?;
+                    let rows = stmt.query_map(params_from_iter(ids), |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+                    })?;
+                    for r in rows {
+                        let (rowid, row) = r?;
+                        if let Some(distance) = store.distance(query, row as u64) {
+                            nearest.push((distance, rowid));
+                        }
+                    }
+                }
+                None => {
+                    let mut stmt = self.conn.prepare(&format!(
+                        "SELECT rowid, vec_distance_l2(embedding, ?) FROM vec_index \
+                         WHERE rowid IN ({})",
+                        placeholders
+                    ))?;
+                    let values = std::iter::once(Value::Blob(f32s_to_blob(query))).chain(ids);
+                    let rows = stmt.query_map(params_from_iter(values), |row| {
+                        Ok((row.get::<_, f64>(1)? as f32, row.get::<_, i64>(0)?))
+                    })?;
+                    for r in rows {
+                        nearest.push(r?);
+                    }
+                }
+            }
+        }
+        nearest.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        nearest.truncate(k);
+        self.hits(nearest)
+    }
+
+    /// Search results for `(distance, rowid)` pairs, in their order.
+    fn hits(&self, nearest: Vec<(f32, i64)>) -> Result<Vec<SearchResult>> {
+        let mut lookup = self.conn.prepare(
+            "SELECT s.path, s.line, s.kind, s.name, s.code, ?2, \
+               (SELECT COUNT(DISTINCT d.path) FROM symbols d \
+                WHERE d.canonical_rowid = s.id AND d.path != s.path), s.id \
+             FROM symbols s WHERE s.id = ?1",
+        )?;
+        let mut out = Vec::with_capacity(nearest.len());
+        for (distance, rowid) in nearest {
+            if let Some(hit) = lookup
+                .query_row(params![rowid, distance as f64], search_result)
+                .optional()?
+            {
+                out.push(hit);
+            }
+        }
+        Ok(out)
+    }
+
+    /// First indexed symbol named `name` (ignoring ASCII case), optionally under `path_prefix`.
+    pub fn search_by_name(
+        &self,
+        name: &str,
+        path_prefix: Option<&str>,
+    ) -> Result<Option<SymbolRef>> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT COALESCE(canonical_rowid, id), path, line FROM symbols \
+                 WHERE name = ?1 COLLATE NOCASE AND substr(path, 1, length(?2)) = ?2 \
+                 ORDER BY id LIMIT 1",
+                params![name, path_prefix.unwrap_or("")],
+                |row| {
+                    let line: i64 = row.get(2)?;
+                    Ok(SymbolRef {
+                        vector_rowid: row.get(0)?,
+                        path: row.get(1)?,
+                        line: line as usize,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(found)
+    }
+
+    /// Every indexed symbol, in path and line order.
+    pub fn all_symbols(&self) -> Result<Vec<SymbolLocation>> {
+        if !self.table_exists("symbols")? {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, line, kind, name FROM symbols ORDER BY path, line, id")?;
+        let rows = stmt.query_map([], |row| {
+            let line: i64 = row.get(1)?;
+            Ok(SymbolLocation {
+                path: row.get(0)?,
+                line: line as usize,
+                kind: row.get(2)?,
+                name: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The symbol stored under rowid `id`, with its full code.
+    pub fn get_symbol(&self, id: i64) -> Result<Option<StoredSymbol>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT path, line, kind, name, code, canonical_rowid FROM symbols WHERE id = ?1",
+                params![id],
+                |row| {
+                    let line: i64 = row.get(1)?;
+                    Ok(StoredSymbol {
+                        id,
+                        path: row.get(0)?,
+                        line: line as usize,
+                        kind: row.get(2)?,
+                        name: row.get(3)?,
+                        code: row.get(4)?,
+                        canonical_rowid: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// Every stored symbol with its code, ordered by path and line.
+    pub fn stored_symbols(&self) -> Result<Vec<StoredSymbol>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, path, line, kind, name, code, canonical_rowid FROM symbols \
+             ORDER BY path, line, id",
+        )?;
+        let rows = stmt.query_map([], stored_symbol)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The symbols with the given rowids, in the order asked for, e.g. to re-score
+    /// candidates from an earlier search. Rowids with no symbol are skipped and repeated
+    /// ones returned once.
+    pub fn get_symbols_by_rowids(&self, rowids: &[i64]) -> Result<Vec<StoredSymbol>> {
+        let mut found: HashMap<i64, StoredSymbol> = HashMap::with_capacity(rowids.len());
+        for chunk in rowids.chunks(ROWID_CHUNK) {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT id, path, line, kind, name, code, canonical_rowid FROM symbols \
+                 WHERE id IN ({})",
+                vec!["?"; chunk.len()].join(",")
+            ))?;
+            let rows = stmt.query_map(
+                params_from_iter(chunk.iter().map(|&id| Value::Integer(id))),
+                stored_symbol,
+            )?;
+            for r in rows {
+                let symbol = r?;
+                found.insert(symbol.id, symbol);
+            }
+        }
+        Ok(rowids.iter().filter_map(|id| found.remove(id)).collect())
+    }
+
+    /// Up to `limit` symbols whose name contains the characters of `query` in order (ignoring
+    /// ASCII case): exact matches first, then prefix matches, then shorter names.
+    pub fn fuzzy_symbols(&self, query: &str, limit: usize) -> Result<Vec<SymbolLocation>> {
+        if !self.table_exists("symbols")? {
+            return Ok(Vec::new());
+        }
+        let escape = |c: char| match c {
+            '%' | '_' | '\\' => format!("\\{}", c),
+            c => c.to_string(),
+        };
+        let subsequence: String = query.chars().map(|c| format!("%{}", escape(c))).collect();
+        let prefix: String = query.chars().map(escape).collect();
+        let mut stmt = self.conn.prepare(
+            "SELECT path, line, kind, name FROM symbols \
+             WHERE name LIKE ?1 || '%' ESCAPE '\\' \
+             ORDER BY name = ?2 COLLATE NOCASE DESC, name LIKE ?3 || '%' ESCAPE '\\' DESC, \
+               length(name), name, path, line \
+             LIMIT ?4",
+        )?;
+        let rows = stmt.query_map(params![subsequence, query, prefix, limit as i64], |row| {
+            let line: i64 = row.get(1)?;
+            Ok(SymbolLocation {
+                path: row.get(0)?,
+                line: line as usize,
+                kind: row.get(2)?,
+                name: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The symbol in `path` that starts closest above or at `line`, e.g. the one enclosing
+    /// an editor's cursor.
+    pub fn symbol_at(&self, path: &str, line: usize) -> Result<Option<SymbolRef>> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT COALESCE(canonical_rowid, id), path, line FROM symbols \
+                 WHERE path = ?1 AND line <= ?2 ORDER BY line DESC, id LIMIT 1",
+                params![path, line as i64],
+                |row| {
+                    let line: i64 = row.get(2)?;
+                    Ok(SymbolRef {
+                        vector_rowid: row.get(0)?,
+                        path: row.get(1)?,
+                        line: line as usize,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(found)
+    }
+
+    /// Every indexed symbol named `name` (ignoring ASCII case), optionally under
+    /// `path_prefix`, in path and line order. Needs no vectors.
+    pub fn find_symbols(
+        &self,
+        name: &str,
+        path_prefix: Option<&str>,
+    ) -> Result<Vec<SymbolLocation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, line, kind, name FROM symbols \
+             WHERE name = ?1 COLLATE NOCASE AND substr(path, 1, length(?2)) = ?2 \
+             ORDER BY path, line",
+        )?;
+        let rows = stmt.query_map(params![name, path_prefix.unwrap_or("")], |row| {
+            let line: i64 = row.get(1)?;
+            Ok(SymbolLocation {
+                path: row.get(0)?,
+                line: line as usize,
+                kind: row.get(2)?,
+                name: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The stored embedding of the symbol with `rowid`, if it has one.
+    pub fn get_embedding(&self, rowid: i64) -> Result<Option<Vec<f32>>> {
+        Ok(match &self.split {
+            Some(store) => self
+                .conn
+                .query_row(
+                    "SELECT row FROM vec_index WHERE rowid = ?1",
+                    params![rowid],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?
+                .and_then(|row| store.vector(row as u64)),
+            None => self
+                .conn
+                .query_row(
+                    "SELECT embedding FROM vec_index WHERE rowid = ?1",
+                    params![rowid],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .optional()?
+                .map(|blob| blob_to_f32s(&blob)),
+        })
+    }
+
+    /// Repopulate the keyword index from the symbols table.
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn rebuild_fts(&self) -> Result<()> {
+        self.conn
+            .execute("INSERT INTO fts_symbols(fts_symbols) VALUES('rebuild')", [])?;
+        Ok(())
+    }
+
+    /// Whether the last run filled the keyword index (`index --build-fts`); a run without it
+    /// leaves the index behind the symbols, so `query --keyword` refuses it.
+    pub fn has_keyword_index(&self) -> Result<bool> {
+        Ok(self.get_meta("fts_built")?.is_some())
+    }
+
+    /// Best keyword (BM25) matches for any of the words in `text`.
+    ///
+    /// Words are quoted, so FTS5 query syntax in `text` is matched literally. The score
+    /// stored in `distance` is FTS5's rank, where lower is better.
+    pub fn keyword_search(&self, text: &str, k: usize) -> Result<Vec<SearchResult>> {
+        let terms: Vec<String> = text
+            .split_whitespace()
+            .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+            .collect();
+        if terms.is_empty() || !self.table_exists("fts_symbols")? {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT s.path, s.line, s.kind, s.name, s.code, f.rank, \
+               (SELECT COUNT(DISTINCT d.path) FROM symbols d \
+                WHERE d.canonical_rowid = s.id AND d.path != s.path), s.id \
+             FROM fts_symbols f \
+             JOIN symbols s ON s.id = f.rowid \
+             WHERE fts_symbols MATCH ?1 \
+             ORDER BY f.rank \
+             LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![terms.join(" OR "), k as i64], |row| {
+            let line: i64 = row.get(1)?;
+            let rank: f64 = row.get(5)?;
+            let duplicate_files: i64 = row.get(6)?;
+            Ok(SearchResult {
+                id: row.get(7)?,
+                path: row.get(0)?,
+                line: line as usize,
+                kind: row.get(2)?,
+                name: row.get(3)?,
+                code: row.get(4)?,
+                distance: rank as f32,
+                duplicate_files: duplicate_files as usize,
+                duplicates: Vec::new(),
+            })
+        })?;
+        let mut out = Vec::new();
+        for r in rows {
+            out.push(r?);
+        }
+        Ok(out)
+    }
+
+    /// Page size of the underlying database file in bytes.
+    pub fn page_size(&self) -> Result<u32> {
+        // SQLCipher answers for an encrypted database with its cipher page size, as text
+        let size: Value = self
+            .conn
+            .query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        match size {
+            Value::Integer(n) => Ok(n as u32),
+            Value::Text(s) => Ok(s.parse()?),
+            other => bail!("unexpected page size {:?}", other),
+        }
+    }
+
+    /// The database's journal mode as SQLite reports it, e.g. `wal` or `delete`.
+    pub fn journal_mode(&self) -> Result<String> {
+        let mode: String = self
+            .conn
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+        Ok(mode.to_ascii_lowercase())
+    }
+
+    /// Store a key/value pair describing the index (settings used, run counters).
+    pub fn set_meta(&self, key: &str, value: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO meta(key, value) VALUES(?1, ?2) \
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_meta(&self, key: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM meta WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
+    /// Read a meta value; indexes created before the meta table existed yield `None`.
+    pub fn get_meta(&self, key: &str) -> Result<Option<String>> {
+        if !self.table_exists("meta")? {
+            return Ok(None);
+        }
+        let value = self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value)
+    }
+
+    /// Commit the index was built from: `git_commit`, or `indexed_commit` as older runs
+    /// recorded it.
+    pub fn git_commit(&self) -> Result<Option<String>> {
+        match self.get_meta("git_commit")? {
+            Some(commit) => Ok(Some(commit)),
+            None => self.get_meta("indexed_commit"),
+        }
+    }
+
+    pub fn count_symbols(&self) -> Result<usize> {
+        if !self.table_exists("symbols")? {
+            return Ok(0);
+        }
+        let n: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM symbols", [], |row| row.get(0))?;
+        Ok(n as usize)
+    }
+
+    pub fn count_vectors(&self) -> Result<usize> {
+        if !self.table_exists("vec_index")? {
+            return Ok(0);
+        }
+        let n: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM vec_index", [], |row| row.get(0))?;
+        Ok(n as usize)
+    }
+
+    /// Count symbols that should have a vector, i.e. excluding linked duplicates.
+    pub fn count_canonical_symbols(&self) -> Result<usize> {
+        if !self.table_exists("symbols")? {
+            return Ok(0);
+        }
+        let n: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM symbols WHERE canonical_rowid IS NULL",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(n as usize)
+    }
+
+    pub fn health(&self) -> Result<IndexHealth> {
+        let symbols = self.count_canonical_symbols()?;
+        let vectors = self.count_vectors()?;
+        Ok(if symbols == 0 {
+            IndexHealth::Empty
+        } else if vectors < symbols {
+            IndexHealth::MissingVectors { symbols, vectors }
+        } else {
+            IndexHealth::Ready
+        })
+    }
+
+    /// Record whether the file at stored `path` yielded no symbols in the latest run.
+    pub fn set_file_empty(&self, path: &str, empty: bool) -> Result<()> {
+        if empty {
+            self.conn.execute(
+                "INSERT OR IGNORE INTO empty_files(path) VALUES (?1)",
+                params![path],
+            )?;
+        } else {
+            self.conn
+                .execute("DELETE FROM empty_files WHERE path = ?1", params![path])?;
+        }
+        Ok(())
+    }
+
+    /// Record what extracted the symbols of the file at stored `path`, e.g. `plugin:dsl`;
+    /// `None` for the built-in parsers.
+    pub fn set_file_origin(&self, path: &str, origin: Option<&str>) -> Result<()> {
+        match origin {
+            Some(origin) => self.conn.execute(
+                "INSERT INTO file_origins(path, origin) VALUES (?1, ?2) \
+                 ON CONFLICT(path) DO UPDATE SET origin = excluded.origin",
+                params![path, origin],
+            )?,
+            None => self
+                .conn
+                .execute("DELETE FROM file_origins WHERE path = ?1", params![path])?,
+        };
+        Ok(())
+    }
+
+    /// What extracted the symbols of the file at stored `path`, if not a built-in parser.
+    pub fn file_origin(&self, path: &str) -> Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT origin FROM file_origins WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Remove everything indexed for the file at stored `path` so it can be indexed afresh.
+    ///
+    /// Duplicates elsewhere that point at the file's symbols stay: the first of each takes
+    /// over its original's vector (see `promote_duplicates`). Rows of a separate embeddings
+    /// file are left unused. Returns the number of symbols removed.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn delete_file(&self, path: &str) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let deleted = self.delete_symbols_in(&tx, path)?;
+        tx.execute("DELETE FROM empty_files WHERE path = ?1", params![path])?;
+        tx.execute("DELETE FROM file_origins WHERE path = ?1", params![path])?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Remove the symbols of every indexed file whose stored path is not in `tracked`, e.g.
+    /// files deleted from git since they were indexed. Returns `(symbols, files)` removed.
+    pub fn prune_deleted(&self, tracked: &HashSet<String>) -> Result<(usize, usize)> {
+        let mut removed = (0, 0);
+        for path in self.indexed_files()? {
+            if !tracked.contains(&path) {
+                removed.0 += self.delete_file(&path)?;
+                removed.1 += 1;
+            }
+        }
+        // The keyword index reads from the symbols table but keeps its own copy of the words
+        if removed.1 > 0 && self.has_keyword_index()? {
+            self.rebuild_fts()?;
+        }
+        Ok(removed)
+    }
+
+    /// Remove the symbols stored for `path` and their vectors, leaving what is recorded about
+    /// the file itself.
+    fn delete_symbols_in(&self, tx: &Connection, path: &str) -> Result<usize> {
+        let doomed: HashSet<i64> = {
+            let mut stmt = tx.prepare_cached("SELECT id FROM symbols WHERE path = ?1")?;
+            stmt.query_map(params![path], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?
+        };
+        self.promote_duplicates(tx, &doomed)?;
+        if self.table_exists("vec_index")? {
+            tx.execute(
+                "DELETE FROM vec_index WHERE rowid IN (SELECT id FROM symbols WHERE path = ?1)",
+                params![path],
+            )?;
+        }
+        let deleted = tx.execute("DELETE FROM symbols WHERE path = ?1", params![path])?;
+        if deleted > 0 {
+            tx.execute(
+                "INSERT INTO meta(key, value) VALUES('pending_deletes', ?1) \
+                 ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + excluded.value",
+                params![deleted as i64],
+            )?;
+        }
+        Ok(deleted)
+    }
+
+    /// Source directories of the crates in an index of dependency sources, by
+    /// `name-version` label.
+    pub fn dep_crates(&self) -> Result<HashMap<String, PathBuf>> {
+        let mut stmt = self.conn.prepare("SELECT label, dir FROM dep_crates")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                PathBuf::from(row.get::<_, String>(1)?),
+            ))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Record that every source file of the crate `label` in `dir` has been indexed.
+    pub fn record_dep_crate(&self, label: &str, dir: &Path) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO dep_crates(label, dir) VALUES(?1, ?2) \
+             ON CONFLICT(label) DO UPDATE SET dir = excluded.dir",
+            params![label, dir.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// Delete the symbols of the crate `label`, including those of a partly indexed one, and
+    /// its record. Returns the number of symbols removed.
+    pub fn remove_dep_crate(&self, label: &str) -> Result<usize> {
+        let prefix = format!("{}/", label);
+        let mut removed = 0;
+        for path in self.indexed_files()? {
+            if path.starts_with(&prefix) {
+                removed += self.delete_file(&path)?;
+            }
+        }
+        self.conn.execute(
+            "DELETE FROM empty_files WHERE substr(path, 1, length(?1)) = ?1",
+            params![prefix],
+        )?;
+        self.conn
+            .execute("DELETE FROM dep_crates WHERE label = ?1", params![label])?;
+        Ok(removed)
+    }
+
+    /// Object ids of the file versions in an index of historical symbols.
+    pub fn history_blobs(&self) -> Result<HashSet<String>> {
+        let mut stmt = self.conn.prepare("SELECT blob FROM history_blobs")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Record that the symbols of the file version `blob` have been indexed.
+    pub fn record_history_blob(&self, blob: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO history_blobs(blob) VALUES(?1)",
+            params![blob],
+        )?;
+        Ok(())
+    }
+
+    /// The `code_hash` of every stored symbol.
+    pub fn all_code_hashes(&self) -> Result<HashSet<[u8; 32]>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT code_hash FROM symbols WHERE code_hash IS NOT NULL")?;
+        let rows = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+        let mut hashes = HashSet::new();
+        for hash in rows {
+            if let Ok(hash) = <[u8; 32]>::try_from(hash?) {
+                hashes.insert(hash);
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Symbols deleted since the index was last vacuumed, whose space is not yet reclaimed.
+    pub fn pending_deletes(&self) -> Result<u64> {
+        Ok(self
+            .get_meta("pending_deletes")?
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(0))
+    }
+
+    /// Stored paths of files that had no extractable symbols, sorted.
+    pub fn empty_files(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path FROM empty_files ORDER BY path")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Stored path of the most recently inserted symbol, i.e. the file a run was last in.
+    pub fn last_inserted_path(&self) -> Result<Option<String>> {
+        if !self.table_exists("symbols")? {
+            return Ok(None);
+        }
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT path FROM symbols ORDER BY id DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Stored paths of files with at least one indexed symbol, sorted.
+    pub fn indexed_files(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT path FROM symbols ORDER BY path")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn count_files(&self) -> Result<usize> {
+        if !self.table_exists("symbols")? {
+            return Ok(0);
+        }
+        let n: i64 =
+            self.conn
+                .query_row("SELECT COUNT(DISTINCT path) FROM symbols", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(n as usize)
+    }
+
+    /// Symbols stored per file, in path order.
+    pub fn symbols_per_file(&self) -> Result<Vec<(String, usize)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, COUNT(*) FROM symbols GROUP BY path ORDER BY path")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The database's schema version, i.e. how many of `MIGRATIONS` it has.
+    pub fn schema_version(&self) -> Result<u32> {
+        let version: i64 = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(version as u32)
+    }
+
+    /// Whether vectors live in `.cearch/embeddings.bin` rather than in `vec_index`.
+    pub fn is_split(&self) -> bool {
+        self.split.is_some()
+    }
+
+    /// Bytes of database pages held by `vec_index` and its shadow tables.
+    pub fn vec_index_bytes(&self) -> Result<u64> {
+        let bytes: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(d.pgsize), 0) FROM dbstat d \
+             JOIN sqlite_master m ON m.name = d.name \
+             WHERE m.tbl_name = 'vec_index' OR m.tbl_name GLOB 'vec_index_*'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(bytes as u64)
+    }
+
+    /// Bytes of database pages in use. Counted from pages rather than the file length, so
+    /// neither a WAL file nor pages freed by deletions (and reused by later writes) distort it.
+    pub fn used_bytes(&self) -> Result<u64> {
+        let bytes: i64 = self.conn.query_row(
+            "SELECT (p.page_count - f.freelist_count) * s.page_size \
+             FROM pragma_page_count() p, pragma_freelist_count() f, pragma_page_size() s",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(bytes as u64)
+    }
+
+    /// Where the space goes: pages per part of the index from `dbstat`, and the `top` largest
+    /// top-level directories by an estimate of the code and vectors stored for them.
+    pub fn size_breakdown(&self, top: usize) -> Result<SizeBreakdown> {
+        let mut tables: HashMap<&'static str, u64> = HashMap::new();
+        {
+            let mut stmt = self.conn.prepare(
+                "SELECT COALESCE(m.tbl_name, d.name), SUM(d.pgsize) FROM dbstat d \
+                 LEFT JOIN sqlite_master m ON m.name = d.name GROUP BY 1",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            for row in rows {
+                let (table, bytes) = row?;
+                *tables.entry(size_category(&table)).or_default() += bytes as u64;
+            }
+        }
+        let vector_bytes = if self.is_metadata_only()? {
+            0
+        } else {
+            self.embedding_dim()?.unwrap_or(0) as i64 * 4
+        };
+        let mut directories: HashMap<String, u64> = HashMap::new();
+        if self.table_exists("symbols")? {
+            // Duplicates share their canonical copy's vector
+            let mut stmt = self.conn.prepare(
+                "SELECT path, SUM(LENGTH(CAST(code AS BLOB)) \
+                     + COALESCE(LENGTH(CAST(clean_code AS BLOB)), 0) + COALESCE(LENGTH(tokens), 0) \
+                     + (canonical_rowid IS NULL) * ?1) \
+                 FROM symbols GROUP BY path",
+            )?;
+            let rows = stmt.query_map(params![vector_bytes], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            for row in rows {
+                let (path, bytes) = row?;
+                let dir = path.split_once('/').map_or(".", |(dir, _)| dir);
+                *directories.entry(dir.to_string()).or_default() += bytes as u64;
+            }
+        }
+        let mut tables: Vec<(&'static str, u64)> = tables.into_iter().collect();
+        tables.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let mut directories: Vec<(String, u64)> = directories.into_iter().collect();
+        directories.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        directories.truncate(top);
+        Ok(SizeBreakdown {
+            used: self.used_bytes()?,
+            tables,
+            directories,
+        })
+    }
+
+    /// Delete the largest symbols until the database uses at most `max_bytes`, compacting
+    /// `vec_index` so their vectors' space is freed too. Returns the number of symbols removed;
+    /// the budget may still be exceeded if deleting stopped paying off.
+    pub fn trim_to(&self, max_bytes: u64) -> Result<usize> {
+        let vector_bytes = if self.is_metadata_only()? {
+            0
+        } else {
+            self.embedding_dim()?.unwrap_or(0) as i64 * 4
+        };
+        let mut removed = 0;
+        for _ in 0..TRIM_ROUNDS {
+            let used = self.used_bytes()?;
+            if used <= max_bytes {
+                break;
+            }
+            let mut excess = (used - max_bytes) as i64;
+            let mut ids = Vec::new();
+            {
+                let mut stmt = self.conn.prepare(
+                    "SELECT id, LENGTH(CAST(code AS BLOB)) \
+                         + COALESCE(LENGTH(CAST(clean_code AS BLOB)), 0) + COALESCE(LENGTH(tokens), 0) \
+                         + (canonical_rowid IS NULL) * ?1 \
+                     FROM symbols ORDER BY 2 DESC, id",
+                )?;
+                let mut rows = stmt.query(params![vector_bytes])?;
+                while excess > 0
+                    && let Some(row) = rows.next()?
+                {
+                    ids.push(row.get::<_, i64>(0)?);
+                    excess -= row.get::<_, i64>(1)?;
+                }
+            }
+            if ids.is_empty() {
+                break;
+            }
+            removed += self.delete_symbols(&ids)?;
+            if self.split.is_none() {
+                self.vacuum_vec_index()?;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove the symbols with these rowids; duplicates pointing at them that are not removed
+    /// too are kept, as in `delete_file`. Returns the number of symbols removed.
+    pub fn delete_symbols(&self, ids: &[i64]) -> Result<usize> {
+        let has_vectors = self.table_exists("vec_index")?;
+        let tx = self.conn.unchecked_transaction()?;
+        self.promote_duplicates(&tx, &ids.iter().copied().collect())?;
+        let mut deleted = 0;
+        for chunk in ids.chunks(ROWID_CHUNK) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            if has_vectors {
+                tx.execute(
+                    &format!("DELETE FROM vec_index WHERE rowid IN ({})", placeholders),
+                    params_from_iter(chunk),
+                )?;
+            }
+            deleted += tx.execute(
+                &format!("DELETE FROM symbols WHERE id IN ({})", placeholders),
+                params_from_iter(chunk),
+            )?;
+        }
+        if deleted > 0 {
+            tx.execute(
+                "INSERT INTO meta(key, value) VALUES('pending_deletes', ?1) \
+                 ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + excluded.value",
+                params![deleted as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Before the symbols in `doomed` are removed, make the first duplicate outside `doomed` of
+    /// each its new original: it takes over the vector, tokens and embedded code, and the
+    /// other duplicates point at it. Duplicates without a surviving copy are in `doomed`
+    /// themselves, so none is left pointing at a removed symbol.
+    fn promote_duplicates(&self, conn: &Connection, doomed: &HashSet<i64>) -> Result<()> {
+        let has_vectors = self.table_exists("vec_index")?;
+        let column = if self.split.is_some() {
+            "row"
+        } else {
+            "embedding"
+        };
+        let mut copies =
+            conn.prepare_cached("SELECT id FROM symbols WHERE canonical_rowid = ?1 ORDER BY id")?;
+        let mut inherit = conn.prepare_cached(
+            "UPDATE symbols SET canonical_rowid = NULL, \
+               tokens = (SELECT tokens FROM symbols WHERE id = ?2), \
+               clean_code = (SELECT clean_code FROM symbols WHERE id = ?2) \
+             WHERE id = ?1",
+        )?;
+        let mut repoint = conn
+            .prepare_cached("UPDATE symbols SET canonical_rowid = ?1 WHERE canonical_rowid = ?2")?;
+        for &original in doomed {
+            let heir = copies
+                .query_map(params![original], |row| row.get::<_, i64>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+                .into_iter()
+                .find(|id| !doomed.contains(id));
+            let Some(heir) = heir else {
+                continue;
+            };
+            inherit.execute(params![heir, original])?;
+            repoint.execute(params![heir, original])?;
+            if has_vectors {
+                // Read first: sqlite-vec does not insert into a vec0 table it is reading
+                let vector: Option<Value> = conn
+                    .query_row(
+                        &format!("SELECT {} FROM vec_index WHERE rowid = ?1", column),
+                        params![original],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                if let Some(vector) = vector {
+                    conn.execute(
+                        &format!("INSERT INTO vec_index(rowid, {}) VALUES(?1, ?2)", column),
+                        params![heir, vector],
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild `vec_index` from its live rows, dropping the space left by deleted ones.
+    ///
+    /// `vec0` keeps deleted rows' slots in its chunks and `VACUUM` does not compact them.
+    /// sqlite-vec cannot rename a `vec0` table, so the live rows are copied out to
+    /// `vec_index_new` and back into a recreated `vec_index`, all in one transaction.
+    pub fn vacuum_vec_index(&self) -> Result<()> {
+        if self.split.is_some() {
+            bail!("vectors are kept in .cearch/embeddings.bin, not in vec_index");
+        }
+        // Recreate with the original declaration, which carries the dimension and chunk size
+        let Some(module) = self.vec_index_module()? else {
+            return Ok(());
+        };
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute_batch(&format!(
+            "CREATE TEMP TABLE vec_index_new (id INTEGER PRIMARY KEY, embedding BLOB NOT NULL);
+             INSERT INTO vec_index_new SELECT rowid, embedding FROM vec_index;
+             DROP TABLE vec_index;
+             CREATE VIRTUAL TABLE vec_index USING {};
+             INSERT INTO vec_index(rowid, embedding) SELECT id, embedding FROM vec_index_new ORDER BY id;
+             DROP TABLE temp.vec_index_new;",
+            module
+        ))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The module and arguments `vec_index` was declared with, e.g.
+    /// `vec0(embedding float[384], chunk_size=256)`; `None` without the table.
+    fn vec_index_module(&self) -> Result<Option<String>> {
+        let sql: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE name = 'vec_index'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        sql.map(|sql| match sql.split_once(" USING ") {
+            Some((_, module)) => Ok(module.to_string()),
+            None => bail!("unexpected vec_index declaration: {}", sql),
+        })
+        .transpose()
+    }
+
+    /// Reclaim the space of deleted symbols: rebuild `vec_index` (unless vectors are kept
+    /// in a separate file), then vacuum.
+    pub fn compact(&self) -> Result<()> {
+        if self.split.is_none() {
+            self.vacuum_vec_index()?;
+        }
+        self.vacuum()
+    }
+
+    /// Compact once at least `threshold` deleted symbols are waiting to be reclaimed
+    /// (`index --compact-after`); zero never compacts. Returns how many were pending when
+    /// it compacted.
+    pub fn compact_if_due(&self, threshold: u64) -> Result<Option<u64>> {
+        let pending = self.pending_deletes()?;
+        if threshold == 0 || pending < threshold {
+            return Ok(None);
+        }
+        self.compact()?;
+        Ok(Some(pending))
+    }
+
+    /// Rewrite the database file so pages freed by deletions are returned to the filesystem,
+    /// then reset the count of pending deletes.
+    pub fn vacuum(&self) -> Result<()> {
+        self.conn.execute_batch("VACUUM;")?;
+        self.remove_meta("pending_deletes")?;
+        // In WAL mode the rewritten pages only reach the main file at a checkpoint
+        self.conn
+            .execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+        Ok(())
+    }
+
+    /// Dimension of the stored vectors, or `None` before the vector table exists.
+    pub fn embedding_dim(&self) -> Result<Option<usize>> {
+        if self.split.is_some() {
+            return Ok(self.get_meta("embedding_dim")?.and_then(|d| d.parse().ok()));
+        }
+        let sql: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE name = 'vec_index'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        // Declared as `vec0(embedding float[N]...)` by `open_with_dim`
+        Ok(sql.and_then(|sql| {
+            let (_, rest) = sql.split_once("float[")?;
+            rest.split_once(']')?.0.trim().parse().ok()
+        }))
+    }
+
+    fn table_exists(&self, name: &str) -> Result<bool> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE name = ?1",
+                params![name],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        DB, DEFAULT_INDEX_NAME, DuplicateRecord, IndexHealth, JournalMode, KnnStrategy,
+        ROWID_CHUNK, ReadOptions, SymbolRecord, WriteOptions, explain_open_error, set_index_name,
+    };
+    use crate::config::{SqliteConfig, VectorConfig};
+    use rusqlite::params;
+    use std::path::{Path, PathBuf};
+
+    fn query_plan(db: &DB, sql: &str) -> String {
+        let mut stmt = db
+            .conn
+            .prepare(&format!("EXPLAIN QUERY PLAN {}", sql))
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(3))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        rows.join("\n")
+    }
+
+    #[test]
+    fn search_by_name_finds_the_first_symbol_ignoring_case() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 2, &WriteOptions::default()).expect("open");
+        let ids = db
+            .insert_symbols(&[
+                SymbolRecord::fixture("lib.rs", 4, "parse_config", &[1.0, 0.0]),
+                SymbolRecord::fixture("src/config.rs", 1, "Parse_Config", &[0.8, 0.6]),
+                SymbolRecord::fixture("src/render.rs", 2, "render", &[0.0, 1.0]),
+            ])
+            .unwrap();
+        let found = db.search_by_name("PARSE_CONFIG", None).unwrap().unwrap();
+        assert_eq!((found.path.as_str(), found.line), ("lib.rs", 4));
+        assert_eq!(found.vector_rowid, ids[0]);
+        let found = db
+            .search_by_name("parse_config", Some("src/"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.path, "src/config.rs");
+        assert!(db.search_by_name("parse", None).unwrap().is_none());
+        assert!(db.search_by_name("render", Some("lib")).unwrap().is_none());
+
+        // `query --near-symbol` searches with the symbol's own vector, so it comes first
+        let embedding = db.get_embedding(found.vector_rowid).unwrap().unwrap();
+        let hits = db.knn(&embedding, 3).unwrap();
+        assert_eq!(hits[0].path, "src/config.rs");
+        assert_eq!(hits[1].path, "lib.rs");
+    }
+
+    #[test]
+    fn keyword_search_uses_stemming_and_literal_terms() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 2, &WriteOptions::default()).expect("open");
+        let mut parse = SymbolRecord::fixture("a.py", 1, "parse_config", &[1.0, 0.0]);
+        parse.code = "def parse_config(path):\n    return parsing(path)";
+        let mut render = SymbolRecord::fixture("b.py", 1, "render", &[0.0, 1.0]);
+        render.code = "def render(template):\n    pass";
+        db.insert_symbols(&[parse, render]).unwrap();
+        assert!(db.keyword_search("parsed", 5).unwrap().is_empty());
+
+        db.rebuild_fts().unwrap();
+        let hits = db.keyword_search("parsed", 5).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].name, "parse_config");
+        // FTS5 operators are treated as plain words
+        let hits = db.keyword_search("NOT \"render*", 5).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].name, "render");
+        assert!(db.keyword_search("   ", 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn meta_round_trips_and_overwrites() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        assert_eq!(db.get_meta("symbol_limit").unwrap(), None);
+        db.set_meta("symbol_limit", "10").unwrap();
+        db.set_meta("symbol_limit", "20").unwrap();
+        assert_eq!(db.get_meta("symbol_limit").unwrap().as_deref(), Some("20"));
+        db.remove_meta("symbol_limit").unwrap();
+        assert_eq!(db.get_meta("symbol_limit").unwrap(), None);
+
+        // Older runs recorded the commit as indexed_commit
+        assert_eq!(db.git_commit().unwrap(), None);
+        db.set_meta("indexed_commit", "0ld").unwrap();
+        assert_eq!(db.git_commit().unwrap().as_deref(), Some("0ld"));
+        db.set_meta("git_commit", "abc").unwrap();
+        assert_eq!(db.git_commit().unwrap().as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn counts_symbols_and_distinct_files() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        let emb = [0.0f32, 0.1, 0.2, 0.3];
+        db.insert_symbols(&[
+            SymbolRecord::fixture("a.rs", 1, "a", &emb),
+            SymbolRecord::fixture("a.rs", 3, "b", &emb),
+            SymbolRecord::fixture("b.py", 1, "c", &emb),
+        ])
+        .unwrap();
+        assert_eq!(db.count_symbols().unwrap(), 3);
+        assert_eq!(db.count_files().unwrap(), 2);
+    }
+
+    #[test]
+    fn knn_on_never_indexed_db_is_empty() {
+        // `open_read` on a freshly `init`ed repo creates a database with no tables at all
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir_all(dir.path().join(".cearch")).unwrap();
+        let db = DB::open_read(dir.path()).expect("open");
+        assert!(db.knn(&[0.0, 0.1, 0.2, 0.3], 5).unwrap().is_empty());
+        assert_eq!(db.health().unwrap(), IndexHealth::Empty);
+    }
+
+    #[test]
+    fn separate_store_answers_knn_after_reopen() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_split(dir.path(), 2, &WriteOptions::default()).expect("open");
+        db.insert_symbols(&[
+            SymbolRecord::fixture("a.rs", 1, "far", &[10.0, 10.0]),
+            SymbolRecord::fixture("b.rs", 2, "near", &[1.0, 0.0]),
+            SymbolRecord::fixture("c.rs", 3, "middle", &[3.0, 4.0]),
+        ])
+        .unwrap();
+        drop(db);
+        assert!(crate::embedding_store::SplitEmbeddingStore::file_path(dir.path()).exists());
+
+        let db = DB::open_read(dir.path()).expect("reopen");
+        assert_eq!(db.health().unwrap(), IndexHealth::Ready);
+        let hits = db.knn(&[0.0, 0.0], 2).unwrap();
+        let names: Vec<&str> = hits.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, ["near", "middle"]);
+        assert_eq!(hits[1].distance, 5.0);
+        assert_eq!(hits[1].line, 3);
+        let middle = db.search_by_name("middle", None).unwrap().expect("found");
+        assert_eq!(
+            db.get_embedding(middle.vector_rowid).unwrap(),
+            Some(vec![3.0, 4.0])
+        );
+
+        let err = DB::open_with_dim(dir.path(), 2, &WriteOptions::default())
+            .err()
+            .expect("mode switch refused")
+            .to_string();
+        assert!(err.contains("--embedding-store separate"), "{}", err);
+        assert!(DB::open_split(dir.path(), 3, &WriteOptions::default()).is_err());
+    }
+
+    #[test]
+    fn scan_and_indexed_knn_agree() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let opts = WriteOptions {
+            vec_chunk_size: Some(8),
+            ..WriteOptions::default()
+        };
+        let mut db = DB::open_with_dim(dir.path(), 4, &opts).expect("open");
+        let embeddings: Vec<[f32; 4]> = (0..40)
+            .map(|i| {
+                let x = i as f32;
+                [x.sin(), (x * 0.7).cos(), x / 40.0, (x * 1.3).sin()]
+            })
+            .collect();
+        let names: Vec<String> = (0..40).map(|i| format!("s{}", i)).collect();
+        let records: Vec<SymbolRecord> = embeddings
+            .iter()
+            .zip(&names)
+            .map(|(emb, name)| SymbolRecord::fixture("a.rs", 1, name, emb))
+            .collect();
+        db.insert_symbols(&records).unwrap();
+
+        let query = [0.3f32, -0.2, 0.5, 0.1];
+        let top = |db: &DB| -> Vec<(String, f32)> {
+            db.knn(&query, 7)
+                .unwrap()
+                .into_iter()
+                .map(|h| (h.name, h.distance))
+                .collect()
+        };
+        db.brute_force_below = 0;
+        assert_eq!(db.knn_strategy().unwrap(), KnnStrategy::Indexed);
+        let indexed = top(&db);
+        db.brute_force_below = usize::MAX;
+        assert_eq!(db.knn_strategy().unwrap(), KnnStrategy::Scan);
+        let scanned = top(&db);
+        assert_eq!(indexed.len(), 7);
+        assert_eq!(indexed, scanned);
+
+        let bad = WriteOptions {
+            vec_chunk_size: Some(12),
+            ..WriteOptions::default()
+        };
+        let other = tempfile::tempdir().expect("tempdir");
+        assert!(DB::open_with_dim(other.path(), 4, &bad).is_err());
+    }
+
+    #[test]
+    fn migration_keeps_the_chunk_size() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let opts = WriteOptions {
+            vec_chunk_size: Some(8),
+            ..WriteOptions::default()
+        };
+        let db = DB::open_with_dim(dir.path(), 2, &opts).expect("open");
+        let ids = db
+            .insert_symbols(&[SymbolRecord::fixture("a.rs", 1, "a", &[1.0, 0.0])])
+            .unwrap();
+        db.start_migration("wide").unwrap();
+        db.stage_vectors(&[(ids[0], &[0.0, 1.0, 0.0][..])]).unwrap();
+        assert_eq!(db.finish_migration("wide").unwrap(), (1, 3));
+        assert_eq!(
+            db.vec_index_module().unwrap().as_deref(),
+            Some("vec0(embedding float[3], chunk_size=8)")
+        );
+        assert_eq!(db.knn(&[0.0, 1.0, 0.0], 1).unwrap()[0].name, "a");
+    }
+
+    #[test]
+    fn mismatched_dimensions_are_refused_up_front() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        assert_eq!(db.embedding_dim().unwrap(), Some(4));
+        db.insert_symbols(&[SymbolRecord::fixture("a.rs", 1, "a", &[0.0; 4])])
+            .unwrap();
+        drop(db);
+
+        let err = DB::open_with_dim(dir.path(), 8, &WriteOptions::default())
+            .err()
+            .expect("dimension mismatch")
+            .to_string();
+        assert!(
+            err.contains("4-dimension") && err.contains("not 8"),
+            "{}",
+            err
+        );
+
+        let db = DB::open_read(dir.path()).expect("open read");
+        let err = db.knn(&[0.0; 3], 5).unwrap_err().to_string();
+        assert!(err.contains("has 3 dimensions"), "{}", err);
+        assert_eq!(db.knn(&[0.0; 4], 5).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn pruning_drops_every_trace_of_untracked_files() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 2, &WriteOptions::default()).expect("open");
+        let mut kept = SymbolRecord::fixture("a.rs", 1, "kept", &[1.0, 0.0]);
+        kept.code = "fn kept() { render() }";
+        let mut gone = SymbolRecord::fixture("gone.rs", 1, "gone", &[0.0, 1.0]);
+        gone.code = "fn gone() { render() }";
+        db.insert_symbols(&[kept, gone]).unwrap();
+        db.rebuild_fts().unwrap();
+        db.set_meta("fts_built", "1").unwrap();
+        let fts_rows = |word: &str| -> i64 {
+            db.conn
+                .query_row(
+                    "SELECT COUNT(*) FROM fts_symbols WHERE fts_symbols MATCH ?1",
+                    params![word],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(fts_rows("render"), 2);
+
+        let tracked: std::collections::HashSet<String> =
+            ["a.rs".to_string(), "new.rs".to_string()].into();
+        assert_eq!(db.prune_deleted(&tracked).unwrap(), (1, 1));
+        assert_eq!(db.prune_deleted(&tracked).unwrap(), (0, 0));
+        assert_eq!(db.indexed_files().unwrap(), ["a.rs"]);
+        assert_eq!(db.count_symbols().unwrap(), 1);
+        assert_eq!(db.count_vectors().unwrap(), 1);
+        assert_eq!(fts_rows("render"), 1);
+        assert_eq!(fts_rows("gone"), 0);
+        let hits = db.keyword_search("render", 5).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, "a.rs");
+    }
+
+    #[test]
+    fn vacuum_vec_index_reclaims_deleted_rows() {
+        let emb = |i: usize| [i as f32, 1.0, 0.0, 0.0];
+        let names: Vec<String> = (0..2000).map(|i| format!("s{}", i)).collect();
+
+        let fresh_dir = tempfile::tempdir().expect("tempdir");
+        let fresh = DB::open_with_dim(fresh_dir.path(), 4, &WriteOptions::default()).expect("open");
+        let embeddings: Vec<[f32; 4]> = (0..2000).map(emb).collect();
+        let records: Vec<SymbolRecord> = (0..1000)
+            .map(|i| SymbolRecord::fixture("a.rs", i, &names[i], &embeddings[i]))
+            .collect();
+        fresh.insert_symbols(&records).unwrap();
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        let records: Vec<SymbolRecord> = (0..2000)
+            .map(|i| {
+                SymbolRecord::fixture(
+                    if i < 1000 { "gone.rs" } else { "a.rs" },
+                    i,
+                    &names[i],
+                    &embeddings[i],
+                )
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+        assert_eq!(db.pending_deletes().unwrap(), 0);
+        db.delete_file("gone.rs").unwrap();
+        db.delete_file("never_indexed.rs").unwrap();
+        assert_eq!(db.pending_deletes().unwrap(), 1000);
+        let before = db.vec_index_bytes().unwrap();
+        assert!(before > fresh.vec_index_bytes().unwrap());
+
+        db.vacuum_vec_index().unwrap();
+        assert_eq!(
+            db.vec_index_bytes().unwrap(),
+            fresh.vec_index_bytes().unwrap()
+        );
+        assert_eq!(db.count_vectors().unwrap(), 1000);
+        assert_eq!(db.embedding_dim().unwrap(), Some(4));
+        let hits = db.knn(&emb(1500), 1).unwrap();
+        assert_eq!(hits[0].name, "s1500");
+        db.delete_file("a.rs").unwrap();
+        assert_eq!(db.pending_deletes().unwrap(), 2000);
+        db.compact().unwrap();
+        assert_eq!(db.pending_deletes().unwrap(), 0);
+        assert_eq!(db.count_vectors().unwrap(), 0);
+    }
+
+    #[test]
+    fn compaction_waits_for_enough_deleted_symbols() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 2, &WriteOptions::default()).expect("open");
+        let names: Vec<String> = (0..6).map(|i| format!("s{}", i)).collect();
+        let records: Vec<SymbolRecord> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                SymbolRecord::fixture(if i < 3 { "a.rs" } else { "b.rs" }, i, name, &[1.0, 0.0])
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+        db.delete_file("a.rs").unwrap();
+        assert_eq!(db.compact_if_due(4).unwrap(), None);
+        assert_eq!(db.pending_deletes().unwrap(), 3);
+        // Counted across runs until a compaction
+        db.delete_file("b.rs").unwrap();
+        assert_eq!(db.compact_if_due(0).unwrap(), None);
+        assert_eq!(db.compact_if_due(4).unwrap(), Some(6));
+        assert_eq!(db.pending_deletes().unwrap(), 0);
+        assert_eq!(db.compact_if_due(1).unwrap(), None);
+    }
+
+    #[test]
+    fn knn_on_schema_without_rows_is_empty() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        assert!(db.knn(&[0.0, 0.1, 0.2, 0.3], 5).unwrap().is_empty());
+        assert_eq!(db.health().unwrap(), IndexHealth::Empty);
+    }
+
+    #[test]
+    fn symbols_without_vectors_are_reported() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        db.insert_symbols(&[SymbolRecord::fixture("a.rs", 1, "a", &[0.0; 4])])
+            .unwrap();
+        db.conn.execute_batch("DELETE FROM vec_index").unwrap();
+        assert!(db.knn(&[0.0, 0.1, 0.2, 0.3], 5).unwrap().is_empty());
+        assert_eq!(
+            db.health().unwrap(),
+            IndexHealth::MissingVectors {
+                symbols: 1,
+                vectors: 0
+            }
+        );
+    }
+
+    #[test]
+    fn path_and_name_lookups_use_indexes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        let by_path = query_plan(&db, "SELECT id FROM symbols WHERE path = 'a.rs'");
+        assert!(by_path.contains("idx_symbols_path"), "{}", by_path);
+        let by_name = query_plan(&db, "SELECT id FROM symbols WHERE name = 'a'");
+        assert!(by_name.contains("idx_symbols_name"), "{}", by_name);
+    }
+
+    #[test]
+    fn migrations_upgrade_unversioned_databases() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir_all(dir.path().join(".cearch")).unwrap();
+        // Schema as written before versioning: base tables only, user_version 0
+        let legacy = rusqlite::Connection::open(dir.path().join(".cearch/index.sqlite")).unwrap();
+        legacy
+            .execute_batch(
+                "CREATE TABLE symbols (id INTEGER PRIMARY KEY, path TEXT NOT NULL, \
+                 line INTEGER NOT NULL, kind TEXT NOT NULL, name TEXT NOT NULL, code TEXT NOT NULL); \
+                 INSERT INTO symbols(path,line,kind,name,code) VALUES('a.rs',1,'fn','a','fn a() {}');",
+            )
+            .unwrap();
+        drop(legacy);
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("reopen");
+        let version: i64 = db
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version as usize, super::MIGRATIONS.len());
+        assert_eq!(db.count_symbols().unwrap(), 1);
+        let plan = query_plan(&db, "SELECT id FROM symbols WHERE path = 'a.rs'");
+        assert!(plan.contains("idx_symbols_path"), "{}", plan);
+    }
+
+    #[test]
+    fn reinserting_a_symbol_replaces_it() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        let first = db
+            .insert_symbols(&[SymbolRecord::fixture("a.rs", 1, "a", &[1.0, 0.0, 0.0, 0.0])])
+            .unwrap();
+        let moved = [0.0f32, 1.0, 0.0, 0.0];
+        let again = db
+            .insert_symbols(&[SymbolRecord::fixture("a.rs", 1, "a", &moved)])
+            .unwrap();
+        assert_eq!(first, again);
+        assert_eq!(db.count_symbols().unwrap(), 1);
+        assert_eq!(db.count_vectors().unwrap(), 1);
+        assert_eq!(db.get_embedding(first[0]).unwrap(), Some(moved.to_vec()));
+
+        db.insert_symbols(&[SymbolRecord::fixture("b.rs", 1, "b", &[0.0, 1.0, 0.1, 0.0])])
+            .unwrap();
+        let dup = DuplicateRecord {
+            path: "a.rs",
+            line: 1,
+            kind: "fn",
+            name: "a",
+            code: "fn x() {}",
+            canonical_rowid: first[0] + 1,
+            parents: None,
+        };
+        db.insert_duplicates(&[dup]).unwrap();
+        // Now a copy of b.rs, so it has no vector of its own and knn finds b.rs once
+        assert_eq!(db.count_symbols().unwrap(), 2);
+        assert_eq!(db.count_vectors().unwrap(), 1);
+        let hits = db.knn(&moved, 10).unwrap();
+        let places: Vec<(&str, usize)> = hits.iter().map(|h| (h.path.as_str(), h.line)).collect();
+        assert_eq!(places, [("b.rs", 1)]);
+        assert_eq!(hits[0].duplicate_files, 1);
+    }
+
+    #[test]
+    fn replacing_a_file_leaves_one_row_per_location() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        let v = [1.0f32, 0.0, 0.0, 0.0];
+        db.insert_symbols(&[
+            SymbolRecord::fixture("a.py", 1, "alpha", &v),
+            SymbolRecord::fixture("b.py", 1, "other", &[0.0, 1.0, 0.0, 0.0]),
+        ])
+        .unwrap();
+        // The file was edited so alpha now starts two lines further down
+        for _ in 0..2 {
+            db.replace_file_symbols("a.py", &[SymbolRecord::fixture("a.py", 3, "alpha", &v)])
+                .unwrap();
+        }
+        assert_eq!(db.count_symbols().unwrap(), 2);
+        assert_eq!(db.count_vectors().unwrap(), 2);
+        let hits = db.knn(&v, 10).unwrap();
+        let places: Vec<(&str, usize)> = hits.iter().map(|h| (h.path.as_str(), h.line)).collect();
+        assert_eq!(places, [("a.py", 3), ("b.py", 1)]);
+    }
+
+    #[test]
+    fn migration_collapses_repeated_symbols() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        // Back to the schema before locations were unique, then insert as old runs did
+        db.conn
+            .execute_batch(
+                "DROP INDEX idx_symbols_location; ALTER TABLE symbols DROP COLUMN tokens; \
+                 DROP INDEX idx_symbols_code_hash; ALTER TABLE symbols DROP COLUMN code_hash; \
+                 ALTER TABLE symbols DROP COLUMN parents; PRAGMA user_version = 6;",
+            )
+            .unwrap();
+        for (i, emb) in [[1.0f32, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]]
+            .iter()
+            .enumerate()
+        {
+            db.conn
+                .execute(
+                    "INSERT INTO symbols(path,line,kind,name,code) VALUES('a.rs',1,'fn','a',?1)",
+                    params![format!("fn a() {{ {} }}", i)],
+                )
+                .unwrap();
+            db.conn
+                .execute(
+                    "INSERT INTO vec_index(rowid, embedding) VALUES(last_insert_rowid(), ?1)",
+                    params![super::f32s_to_blob(emb)],
+                )
+                .unwrap();
+        }
+        assert_eq!(db.count_symbols().unwrap(), 2);
+        drop(db);
+
+        let db = DB::open_read(dir.path()).expect("migrate");
+        assert_eq!(db.count_symbols().unwrap(), 1);
+        assert_eq!(db.count_vectors().unwrap(), 1);
+        let hits = db.knn(&[0.0, 1.0, 0.0, 0.0], 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].code, "fn a() { 1 }");
+        assert_eq!(hits[0].distance, 0.0);
+        // Symbols stored before code hashes existed are hashed on upgrade
+        let hashes = db.code_hashes(&[hits[0].id]).unwrap();
+        assert_eq!(hashes[&hits[0].id], super::code_hash("fn a() { 1 }"));
+    }
+
+    #[test]
+    fn page_size_applies_only_to_new_databases() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let opts = WriteOptions {
+            page_size: 8192,
+            cache_size_mb: 16,
+            ..WriteOptions::default()
+        };
+        let db = DB::open_with_dim(dir.path(), 4, &opts).expect("open");
+        assert_eq!(db.page_size().unwrap(), 8192);
+        let cache: i64 = db
+            .conn
+            .query_row("PRAGMA cache_size", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(cache, -16 * 1024);
+        drop(db);
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("reopen");
+        assert_eq!(db.page_size().unwrap(), 8192);
+    }
+
+    #[test]
+    fn journal_mode_is_chosen_at_index_time_and_kept_by_maintenance() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let opts = WriteOptions {
+            journal_mode: Some(JournalMode::Delete),
+            ..WriteOptions::default()
+        };
+        let db = DB::open_with_dim(dir.path(), 4, &opts).expect("open");
+        assert_eq!(db.journal_mode().unwrap(), "delete");
+        let sync: i64 = db
+            .conn
+            .query_row("PRAGMA synchronous", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(sync, 2, "FULL");
+        assert_eq!(
+            db.get_meta("journal_mode").unwrap().as_deref(),
+            Some("delete")
+        );
+        drop(db);
+        let wal = DB::file_path(dir.path()).with_extension("sqlite-wal");
+        assert!(!wal.exists());
+
+        let db = DB::open_maintenance(dir.path()).expect("maintenance");
+        assert_eq!(db.journal_mode().unwrap(), "delete");
+        drop(db);
+        let db = DB::open_with_dim(
+            dir.path(),
+            4,
+            &WriteOptions {
+                journal_mode: Some(JournalMode::Wal),
+                ..WriteOptions::default()
+            },
+        )
+        .expect("reopen");
+        assert_eq!(db.journal_mode().unwrap(), "wal");
+        assert_eq!(db.get_meta("journal_mode").unwrap().as_deref(), Some("wal"));
+    }
+
+    #[test]
+    fn connections_use_their_pragma_profiles() {
+        let pragma = |db: &DB, name: &str| -> i64 {
+            db.conn
+                .query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))
+                .unwrap()
+        };
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        assert_eq!(pragma(&db, "mmap_size"), 256 * 1024 * 1024);
+        // 2 = MEMORY
+        assert_eq!(pragma(&db, "temp_store"), 2);
+        assert_eq!(pragma(&db, "journal_size_limit"), 64 * 1024 * 1024);
+        assert_eq!(pragma(&db, "query_only"), 0);
+        drop(db);
+
+        let low_memory = SqliteConfig {
+            mmap_size_mb: Some(0),
+            temp_store_memory: Some(false),
+            ..SqliteConfig::default()
+        };
+        let db = DB::open_with_dim(
+            dir.path(),
+            4,
+            &WriteOptions::default().with_config(&low_memory, &VectorConfig::default()),
+        )
+        .expect("reopen");
+        assert_eq!(pragma(&db, "mmap_size"), 0);
+        assert_eq!(pragma(&db, "temp_store"), 0);
+        drop(db);
+
+        let db = DB::open_read(dir.path()).expect("open read");
+        assert_eq!(pragma(&db, "query_only"), 1);
+        assert_eq!(pragma(&db, "mmap_size"), 256 * 1024 * 1024);
+        assert!(db.set_meta("k", "v").is_err());
+        let db = DB::open_read_with(
+            dir.path(),
+            &ReadOptions::default().with_config(&low_memory, &VectorConfig::default()),
+        )
+        .expect("open read");
+        assert_eq!(pragma(&db, "mmap_size"), 0);
+    }
+
+    /// Child half of `leftover_wal_from_a_killed_writer_is_recovered`: commits a batch, then
+    /// dies in the middle of a second transaction. Does nothing when run on its own.
+    #[test]
+    fn wal_writer_child() {
+        let Some(dir) = std::env::var_os("CEARCH_TEST_WAL_CHILD") else {
+            return;
+        };
+        // A tiny cache makes the open transaction spill pages into the WAL
+        let opts = WriteOptions {
+            cache_size_mb: 1,
+            ..WriteOptions::default()
+        };
+        let db = DB::open_with_dim(Path::new(&dir), 4, &opts).unwrap();
+        db.insert_symbols(&[SymbolRecord::fixture("kept.rs", 1, "kept", &[1.0; 4])])
+            .unwrap();
+        let _tx = db.conn.unchecked_transaction().unwrap();
+        let code = "x".repeat(4096);
+        for line in 0..1000 {
+            db.conn
+                .execute(
+                    "INSERT INTO symbols(path,line,kind,name,code) VALUES('lost.rs',?1,'fn','lost',?2)",
+                    params![line, code],
+                )
+                .unwrap();
+        }
+        std::process::abort();
+    }
+
+    #[test]
+    fn leftover_wal_from_a_killed_writer_is_recovered() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "db::tests::wal_writer_child"])
+            .env("CEARCH_TEST_WAL_CHILD", dir.path())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .expect("run child");
+        assert!(!status.success());
+        let db_path = DB::file_path(dir.path());
+        let sibling = |suffix: &str| {
+            let mut name = db_path.clone().into_os_string();
+            name.push(suffix);
+            PathBuf::from(name)
+        };
+        assert!(std::fs::metadata(sibling("-wal")).unwrap().len() > 0);
+        assert!(sibling("-shm").exists());
+
+        // Committed work survives, the interrupted transaction does not
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("reopen");
+        assert_eq!(db.indexed_files().unwrap(), ["kept.rs"]);
+        assert_eq!(db.count_vectors().unwrap(), 1);
+        assert_eq!(std::fs::metadata(sibling("-wal")).unwrap().len(), 0);
+        drop(db);
+        let db = DB::open_read(dir.path()).expect("open read");
+        assert_eq!(db.count_symbols().unwrap(), 1);
+        drop(db);
+
+        DB::remove_files(dir.path()).unwrap();
+        for suffix in ["", "-wal", "-shm"] {
+            assert!(!sibling(suffix).exists(), "{}", suffix);
+        }
+        DB::remove_files(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn dependency_crates_are_kept_apart_and_removed_alone() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let deps = DB::open_deps(root, 2, &WriteOptions::default()).expect("open deps");
+        deps.insert_symbols(&[
+            SymbolRecord::fixture("serde-1.0.200/src/de.rs", 1, "deserialize", &[1.0, 0.0]),
+            SymbolRecord::fixture("serde-1.0.200/src/ser.rs", 1, "serialize", &[0.0, 1.0]),
+            SymbolRecord::fixture("tiny-0.2.0/src/lib.rs", 1, "tiny", &[1.0, 1.0]),
+        ])
+        .unwrap();
+        deps.record_dep_crate("serde-1.0.200", Path::new("/cargo/serde-1.0.200"))
+            .unwrap();
+        deps.record_dep_crate("tiny-0.2.0", Path::new("/vendor/tiny"))
+            .unwrap();
+        assert!(!DB::file_path(root).exists());
+
+        assert_eq!(deps.remove_dep_crate("serde-1.0.200").unwrap(), 2);
+        assert_eq!(deps.indexed_files().unwrap(), ["tiny-0.2.0/src/lib.rs"]);
+        assert_eq!(deps.knn(&[1.0, 0.0], 5).unwrap().len(), 1);
+        drop(deps);
+        let deps = DB::open_deps_read(root).expect("open deps read");
+        let crates = deps.dep_crates().unwrap();
+        assert_eq!(crates.len(), 1);
+        assert_eq!(crates["tiny-0.2.0"], PathBuf::from("/vendor/tiny"));
+    }
+
+    #[test]
+    fn named_indexes_live_side_by_side() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let open = |name: &str, symbol: &str, embedding: &[f32]| {
+            let path = DB::named_path(root, name);
+            let db = DB::open_inline_at(root, &path, 2, &WriteOptions::default()).unwrap();
+            db.insert_symbols(&[SymbolRecord::fixture("a.rs", 1, symbol, embedding)])
+                .unwrap();
+            db.set_meta("git_commit", name).unwrap();
+        };
+        open("code", "parse", &[1.0, 0.0]);
+        open(DEFAULT_INDEX_NAME, "render", &[0.0, 1.0]);
+        std::fs::write(DB::deps_path(root), b"").unwrap();
+        assert_eq!(DB::index_names(root), ["code", "index"]);
+        assert_eq!(
+            DB::named_path(root, DEFAULT_INDEX_NAME),
+            DB::file_path(root)
+        );
+
+        let read = |name: &str| {
+            DB::open_read_at(root, &DB::named_path(root, name), &ReadOptions::default()).unwrap()
+        };
+        let code = read("code");
+        let hits = code.knn(&[0.0, 1.0], 5).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].name, "parse");
+        assert_eq!(
+            code.get_meta("git_commit").unwrap().as_deref(),
+            Some("code")
+        );
+        drop(code);
+
+        DB::remove_database(&DB::named_path(root, "code")).unwrap();
+        assert_eq!(DB::index_names(root), ["index"]);
+        assert_eq!(
+            read(DEFAULT_INDEX_NAME).knn(&[0.0, 1.0], 5).unwrap()[0].name,
+            "render"
+        );
+
+        for bad in ["", "a b", "../x", "deps", "history"] {
+            assert!(set_index_name(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn lock_and_io_errors_say_what_to_do() {
+        let failure = |code| {
+            anyhow::Error::from(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(code),
+                None,
+            ))
+        };
+        let root = Path::new("/repo");
+        let busy = explain_open_error(
+            failure(rusqlite::ffi::SQLITE_BUSY),
+            root,
+            &DB::file_path(root),
+            false,
+        )
+        .to_string();
+        assert!(
+            busy.contains("another cearch process may be running"),
+            "{}",
+            busy
+        );
+        assert!(busy.contains("index.lock"), "{}", busy);
+        let io = explain_open_error(
+            failure(rusqlite::ffi::SQLITE_IOERR),
+            root,
+            &DB::file_path(root),
+            false,
+        )
+        .to_string();
+        assert!(io.contains("cearch check"), "{}", io);
+        let other = explain_open_error(
+            failure(rusqlite::ffi::SQLITE_CORRUPT),
+            root,
+            &DB::file_path(root),
+            false,
+        )
+        .to_string();
+        assert!(!other.contains(';'), "{}", other);
+
+        // Without the header of a plain database, a file that is not one is taken as encrypted
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir_all(dir.path().join(".cearch")).unwrap();
+        std::fs::write(DB::file_path(dir.path()), [7u8; 4096]).unwrap();
+        let opts = ReadOptions {
+            key: None,
+            ..ReadOptions::default()
+        };
+        let locked = DB::open_read_with(dir.path(), &opts)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            locked.contains("is encrypted; set CEARCH_INDEX_KEY"),
+            "{}",
+            locked
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_index_round_trips_and_rekeys() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let body = "fn secret_retry_loop() { attempt_the_request_again(); }";
+        let keyed = |key: Option<&str>| ReadOptions {
+            key: key.map(String::from),
+            ..ReadOptions::default()
+        };
+        let opts = WriteOptions {
+            key: Some("first key".to_string()),
+            ..WriteOptions::default()
+        };
+        let db = DB::open_with_dim(root, 2, &opts).expect("open");
+        db.insert_symbols(&[SymbolRecord {
+            code: body,
+            ..SymbolRecord::fixture("src/net.rs", 1, "secret_retry_loop", &[1.0, 0.0])
+        }])
+        .unwrap();
+        drop(db);
+        let raw = std::fs::read(DB::file_path(root)).unwrap();
+        let contains =
+            |raw: &[u8], text: &str| raw.windows(text.len()).any(|w| w == text.as_bytes());
+        assert!(!contains(&raw, "attempt_the_request_again"));
+        assert!(!contains(&raw, "src/net.rs"));
+        assert!(crate::encryption::is_encrypted(&DB::file_path(root)));
+
+        let db = DB::open_read_with(root, &keyed(Some("first key"))).expect("open read");
+        let hits = db.knn(&[1.0, 0.0], 1).unwrap();
+        assert_eq!(hits[0].name, "secret_retry_loop");
+        assert_eq!(hits[0].code, body);
+        assert_eq!(db.page_size().unwrap(), 4096);
+        drop(db);
+        let missing = DB::open_read_with(root, &keyed(None))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(missing.contains("is encrypted"), "{}", missing);
+        let wrong = DB::open_read_with(root, &keyed(Some("other")))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(wrong.contains("does not decrypt"), "{}", wrong);
+
+        DB::rekey(root, Some("first key"), Some("second key")).unwrap();
+        assert!(DB::open_read_with(root, &keyed(Some("first key"))).is_err());
+        let db = DB::open_read_with(root, &keyed(Some("second key"))).expect("rekeyed");
+        assert_eq!(db.knn(&[1.0, 0.0], 1).unwrap()[0].code, body);
+        drop(db);
+
+        DB::rekey(root, Some("second key"), None).unwrap();
+        assert!(!crate::encryption::is_encrypted(&DB::file_path(root)));
+        let plain = DB::open_read_with(root, &keyed(Some("second key")))
+            .err()
+            .unwrap();
+        assert!(plain.to_string().contains("is not encrypted"), "{}", plain);
+        let db = DB::open_read_with(root, &keyed(None)).expect("decrypted");
+        assert_eq!(db.knn(&[1.0, 0.0], 1).unwrap()[0].code, body);
+        assert_eq!(db.journal_mode().unwrap(), "wal");
+    }
+
+    #[test]
+    fn rejects_invalid_page_sizes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let opts = WriteOptions {
+            page_size: 5000,
+            ..WriteOptions::default()
+        };
+        assert!(DB::open_with_dim(dir.path(), 4, &opts).is_err());
+    }
+
+    #[test]
+    fn symbols_are_looked_up_by_rowid_in_the_order_asked() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_metadata_only(dir.path(), &WriteOptions::default()).expect("open");
+        let records: Vec<SymbolRecord> = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| SymbolRecord::fixture("a.rs", i + 1, name, &[]))
+            .collect();
+        let rowids = db.insert_symbols(&records).unwrap();
+
+        // Enough ids to span several statements, with gaps and repeats
+        let mut wanted: Vec<i64> = vec![rowids[2], -1, rowids[0]];
+        wanted.extend(1000..1000 + ROWID_CHUNK as i64);
+        wanted.extend([rowids[1], rowids[2]]);
+        let names: Vec<String> = db
+            .get_symbols_by_rowids(&wanted)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, ["c", "a", "b"]);
+        assert!(db.get_symbols_by_rowids(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn duplicates_are_counted_on_their_canonical_hit() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 4, &WriteOptions::default()).expect("open");
+        let emb = [1.0f32, 0.0, 0.0, 0.0];
+        let rowids = db
+            .insert_symbols(&[SymbolRecord::fixture("a.rs", 1, "a", &emb)])
+            .unwrap();
+        let dup = |path| DuplicateRecord {
+            path,
+            line: 7,
+            kind: "fn",
+            name: "a",
+            code: "fn x() {}",
+            canonical_rowid: rowids[0],
+            parents: None,
+        };
+        db.insert_duplicates(&[dup("b.rs"), dup("c.rs"), dup("a.rs")])
+            .unwrap();
+        let hits = db.knn(&emb, 5).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, "a.rs");
+        assert_eq!(hits[0].duplicate_files, 2);
+        // Duplicates have no vectors by design, so they don't make the index inconsistent
+        assert_eq!(db.health().unwrap(), IndexHealth::Ready);
+
+        // A duplicate found by name resolves to the vector of its canonical copy
+        let found = db.search_by_name("A", Some("b.")).unwrap().expect("found");
+        assert_eq!(found.path, "b.rs");
+        assert_eq!(found.vector_rowid, rowids[0]);
+        assert_eq!(
+            db.get_embedding(found.vector_rowid).unwrap(),
+            Some(emb.to_vec())
+        );
+        assert_eq!(db.search_by_name("a", None).unwrap().unwrap().path, "a.rs");
+        assert!(db.search_by_name("a", Some("src/")).unwrap().is_none());
+
+        // Deleting the canonical file leaves the copies elsewhere, the first of them with the
+        // vector and the others pointing at it
+        db.insert_symbols(&[SymbolRecord::fixture("d.rs", 2, "d", &emb)])
+            .unwrap();
+        assert_eq!(db.last_inserted_path().unwrap().as_deref(), Some("d.rs"));
+        db.set_file_empty("a.rs", true).unwrap();
+        assert_eq!(db.delete_file("a.rs").unwrap(), 2);
+        assert_eq!(db.indexed_files().unwrap(), ["b.rs", "c.rs", "d.rs"]);
+        assert!(db.empty_files().unwrap().is_empty());
+        assert_eq!(db.count_vectors().unwrap(), 2);
+        assert_eq!(db.health().unwrap(), IndexHealth::Ready);
+        let hits = db.knn(&emb, 5).unwrap();
+        let promoted = hits.iter().find(|h| h.path == "b.rs").expect("b.rs");
+        assert_eq!(promoted.duplicate_files, 1);
+        let promoted = db.search_by_name("a", Some("b.")).unwrap().expect("found");
+        assert_eq!(
+            db.get_embedding(promoted.vector_rowid).unwrap(),
+            Some(emb.to_vec())
+        );
+        assert_eq!(
+            db.search_by_name("a", Some("c."))
+                .unwrap()
+                .expect("found")
+                .vector_rowid,
+            promoted.vector_rowid
+        );
+
+        // Removed together with their original, copies have nothing to take over
+        let copy = db
+            .get_symbol(promoted.vector_rowid + 1)
+            .unwrap()
+            .expect("c.rs");
+        assert_eq!(copy.canonical_rowid, Some(promoted.vector_rowid));
+        assert_eq!(
+            db.delete_symbols(&[promoted.vector_rowid, copy.id])
+                .unwrap(),
+            2
+        );
+        assert_eq!(db.indexed_files().unwrap(), ["d.rs"]);
+        assert_eq!(db.count_vectors().unwrap(), 1);
+        assert_eq!(db.health().unwrap(), IndexHealth::Ready);
+    }
+
+    #[test]
+    fn near_duplicates_pair_close_symbols_across_files() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut db = DB::open_with_dim(dir.path(), 2, &WriteOptions::default()).expect("open");
+        let ids = db
+            .insert_symbols(&[
+                SymbolRecord::fixture("b.rs", 4, "copy", &[1.0, 0.05]),
+                SymbolRecord::fixture("a.rs", 1, "orig", &[1.0, 0.0]),
+                // Same file as `orig`, so never reported
+                SymbolRecord::fixture("a.rs", 9, "sibling", &[1.0, 0.01]),
+                SymbolRecord::fixture("c.rs", 2, "other", &[0.0, 5.0]),
+                // An exact copy of `other` with a vector of its own
+                SymbolRecord::fixture("e.rs", 3, "other", &[0.0, 5.0]),
+            ])
+            .unwrap();
+        // An exact copy of `other` linked to it, sharing its vector
+        db.insert_duplicates(&[DuplicateRecord {
+            path: "d.rs",
+            line: 7,
+            kind: "fn",
+            name: "other",
+            code: "other",
+            canonical_rowid: ids[3],
+            parents: None,
+        }])
+        .unwrap();
+        // Both the pairwise scan and the vec0 index find the same pairs
+        for brute_force_below in [super::DEFAULT_BRUTE_FORCE_BELOW, 0] {
+            db.brute_force_below = brute_force_below;
+            let pairs = db.near_duplicates(0.1).unwrap();
+            let found: Vec<(&str, usize, &str, usize)> = pairs
+                .iter()
+                .map(|p| {
+                    (
+                        p.source_path.as_str(),
+                        p.source_line,
+                        p.target_path.as_str(),
+                        p.target_line,
+                    )
+                })
+                .collect();
+            // Closest first, exact copies at 0
+            assert_eq!(
+                found,
+                [
+                    ("c.rs", 2, "d.rs", 7),
+                    ("c.rs", 2, "e.rs", 3),
+                    ("d.rs", 7, "e.rs", 3),
+                    ("a.rs", 9, "b.rs", 4),
+                    ("a.rs", 1, "b.rs", 4),
+                ]
+            );
+            assert_eq!(pairs[0].distance, 0.0);
+            assert!((pairs[3].distance - 0.04).abs() < 1e-4);
+            assert!((pairs[4].distance - 0.05).abs() < 1e-4);
+        }
+        assert_eq!(db.near_duplicates(0.01).unwrap().len(), 3);
+
+        let pairs = db.near_duplicates(0.1).unwrap();
+        db.store_duplicate_pairs(&pairs).unwrap();
+        db.store_duplicate_pairs(&pairs[..1]).unwrap();
+        let stored: i64 = db
+            .conn
+            .query_row("SELECT COUNT(*) FROM duplicate_pairs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+
+    #[test]
+    fn tokens_round_trip_and_are_cleared_on_reindex() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 2, &WriteOptions::default()).expect("open");
+        let ids = db
+            .insert_symbols(&[
+                SymbolRecord::fixture("a.rs", 1, "alpha", &[1.0, 0.0]),
+                SymbolRecord::fixture("a.rs", 5, "beta", &[0.0, 1.0]),
+            ])
+            .unwrap();
+        db.set_tokens(&[(ids[0], vec![101, 70_000, 102])]).unwrap();
+        assert_eq!(db.get_tokens(ids[0]).unwrap(), [101, 70_000, 102]);
+        assert!(db.get_tokens(ids[1]).is_err());
+        assert_eq!(db.tokenized_symbols().unwrap(), [ids[0]]);
+
+        // Re-indexed code may tokenize differently, so its old tokens are dropped
+        db.insert_symbols(&[SymbolRecord::fixture("a.rs", 1, "alpha", &[1.0, 0.0])])
+            .unwrap();
+        assert!(db.tokenized_symbols().unwrap().is_empty());
+    }
+
+    #[test]
+    fn metadata_only_indexes_store_symbols_without_vectors() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_metadata_only(dir.path(), &WriteOptions::default()).expect("open");
+        db.insert_symbols(&[
+            SymbolRecord::fixture("src/b.rs", 3, "Open", &[]),
+            SymbolRecord::fixture("src/a.rs", 9, "open", &[]),
+            SymbolRecord::fixture("tests/a.rs", 1, "open", &[]),
+        ])
+        .unwrap();
+        assert!(db.is_metadata_only().unwrap());
+        assert_eq!(db.count_vectors().unwrap(), 0);
+        let found = db.find_symbols("OPEN", Some("src/")).unwrap();
+        let places: Vec<(&str, usize)> = found.iter().map(|s| (s.path.as_str(), s.line)).collect();
+        assert_eq!(places, [("src/a.rs", 9), ("src/b.rs", 3)]);
+        db.delete_file("src/a.rs").unwrap();
+        assert_eq!(db.find_symbols("open", None).unwrap().len(), 2);
+        drop(db);
+
+        let db = DB::open_read(dir.path()).expect("open read");
+        assert!(db.is_metadata_only().unwrap());
+        assert_eq!(db.find_symbols("open", Some("tests/")).unwrap().len(), 1);
+        drop(db);
+
+        // The two kinds of index are not mixed
+        let err = DB::open_with_dim(dir.path(), 4, &WriteOptions::default())
+            .err()
+            .expect("refused");
+        assert!(err.to_string().contains("--metadata-only"), "{}", err);
+        let other = tempfile::tempdir().expect("tempdir");
+        drop(DB::open_with_dim(other.path(), 4, &WriteOptions::default()).expect("open"));
+        assert!(DB::open_metadata_only(other.path(), &WriteOptions::default()).is_err());
     }
 }
@@ -0,0 +1,302 @@
+use crate::db::SearchResult;
+use crate::paths;
+use crate::search::Origin;
+use crate::symbols;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+// Deps module finds the sources of the crates in Cargo.lock for `cearch index --deps`, and
+// locates hits from their index for `query --include-deps`.
+
+/// A dependency whose sources were found, e.g. serde 1.0.200 in the cargo registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepCrate {
+    pub name: String,
+    pub version: String,
+    pub dir: PathBuf,
+}
+
+impl DepCrate {
+    /// `name-version`, which starts the stored paths of the crate's files.
+    pub fn label(&self) -> String {
+        label(&self.name, &self.version)
+    }
+}
+
+fn label(name: &str, version: &str) -> String {
+    format!("{}-{}", name, version)
+}
+
+/// The dependencies of a repository, split by whether their sources are on disk.
+#[derive(Debug, Default)]
+pub struct Located {
+    pub found: Vec<DepCrate>,
+    /// Labels of the crates with no sources in the registry or `vendor/`
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    /// Absent for the workspace's own packages
+    source: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    package: Option<ManifestPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestPackage {
+    version: Option<toml::Value>,
+}
+
+/// `$CARGO_HOME`, else `~/.cargo`.
+pub fn cargo_home() -> Option<PathBuf> {
+    std::env::var_os("CARGO_HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|home| home.join(".cargo")))
+}
+
+/// Find the sources of every dependency in the repository's `Cargo.lock`: vendored ones in
+/// `vendor/` first, then those unpacked in `cargo_home`'s registry. The workspace's own
+/// packages are left out.
+pub fn locate(repo_root: &Path, cargo_home: Option<&Path>) -> Result<Located, String> {
+    let lock_path = repo_root.join("Cargo.lock");
+    let text = std::fs::read_to_string(&lock_path).map_err(|e| {
+        format!(
+            "failed to read {}: {}; `index --deps` indexes the crates it lists",
+            lock_path.display(),
+            e
+        )
+    })?;
+    let lock: Lockfile =
+        toml::from_str(&text).map_err(|e| format!("invalid {}: {}", lock_path.display(), e))?;
+    let registries = cargo_home
+        .map(|home| subdirectories(&home.join("registry").join("src")))
+        .unwrap_or_default();
+    let vendor = repo_root.join("vendor");
+    let mut located = Located::default();
+    for package in lock.package {
+        if package.source.is_none() {
+            continue;
+        }
+        let label = label(&package.name, &package.version);
+        let dir = vendored(&vendor, &package.name, &package.version).or_else(|| {
+            registries
+                .iter()
+                .map(|registry| registry.join(&label))
+                .find(|dir| dir.is_dir())
+        });
+        match dir {
+            Some(dir) => located.found.push(DepCrate {
+                name: package.name,
+                version: package.version,
+                dir,
+            }),
+            None => located.missing.push(label),
+        }
+    }
+    Ok(located)
+}
+
+/// `cargo vendor` names a crate's directory `name`, or `name-version` when several versions
+/// are vendored; only one holding the locked version counts.
+fn vendored(vendor: &Path, name: &str, version: &str) -> Option<PathBuf> {
+    let versioned = vendor.join(label(name, version));
+    if versioned.is_dir() {
+        return Some(versioned);
+    }
+    let plain = vendor.join(name);
+    let manifest = std::fs::read_to_string(plain.join("Cargo.toml")).ok()?;
+    let manifest: Manifest = toml::from_str(&manifest).ok()?;
+    let found = manifest.package?.version?;
+    (found.as_str() == Some(version)).then_some(plain)
+}
+
+fn subdirectories(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Files under `dir` in a language cearch parses, sorted; hidden directories and `target`
+/// are skipped.
+pub fn source_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() && !name.starts_with('.') && name != "target" => {
+                    pending.push(path)
+                }
+                Ok(kind) if kind.is_file() && symbols::language_name(&path).is_some() => {
+                    files.push(path)
+                }
+                _ => {}
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Hits from the dependency index, each with its crate's label, for `search::merge_ranked`.
+///
+/// A hit's path becomes the absolute path of its file, found through `crates` (label to
+/// source directory); hits of crates no longer recorded are dropped.
+pub fn locate_hits(
+    deps: Vec<SearchResult>,
+    crates: &HashMap<String, PathBuf>,
+) -> Vec<(SearchResult, Origin)> {
+    let mut located = Vec::with_capacity(deps.len());
+    for mut hit in deps {
+        let Some((label, rest)) = hit.path.split_once('/') else {
+            continue;
+        };
+        let Some(dir) = crates.get(label) else {
+            continue;
+        };
+        let label = label.to_string();
+        hit.path = paths::to_native(dir, rest).display().to_string();
+        located.push((hit, Origin::Dependency(label)));
+    }
+    located
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = r#"
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["serde", "tiny"]
+
+[[package]]
+name = "serde"
+version = "1.0.200"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "tiny"
+version = "0.2.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "gone"
+version = "3.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn finds_registry_and_vendored_sources() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let repo = dir.path().join("repo");
+        let home = dir.path().join("cargo");
+        write(&repo.join("Cargo.lock"), LOCK);
+        write(
+            &repo.join("vendor/tiny/Cargo.toml"),
+            "[package]\nname = \"tiny\"\nversion = \"0.2.0\"\n",
+        );
+        write(&repo.join("vendor/tiny/src/lib.rs"), "pub fn tiny() {}\n");
+        let serde = home.join("registry/src/index.crates.io-1949cf8c6b5b557f/serde-1.0.200");
+        write(&serde.join("src/de.rs"), "pub fn deserialize() {}\n");
+        write(&serde.join("README.md"), "serde\n");
+        write(&serde.join("target/debug/build.rs"), "fn main() {}\n");
+
+        let located = locate(&repo, Some(&home)).unwrap();
+        let labels: Vec<String> = located.found.iter().map(DepCrate::label).collect();
+        assert_eq!(labels, ["serde-1.0.200", "tiny-0.2.0"]);
+        assert_eq!(located.found[0].dir, serde);
+        assert_eq!(located.found[1].dir, repo.join("vendor/tiny"));
+        assert_eq!(located.missing, ["gone-3.0.0"]);
+        assert_eq!(source_files(&serde), [serde.join("src/de.rs")]);
+
+        // A vendored copy of another version is not the locked crate
+        write(
+            &repo.join("vendor/tiny/Cargo.toml"),
+            "[package]\nname = \"tiny\"\nversion = \"0.3.0\"\n",
+        );
+        let located = locate(&repo, None).unwrap();
+        assert!(located.found.is_empty());
+        assert!(
+            locate(&home, None)
+                .unwrap_err()
+                .contains("`index --deps` indexes")
+        );
+    }
+
+    #[test]
+    fn merged_results_are_ranked_and_located() {
+        let hit = |path: &str, distance: f32| SearchResult {
+            id: 1,
+            path: path.to_string(),
+            line: 1,
+            kind: "fn".to_string(),
+            name: "x".to_string(),
+            code: String::new(),
+            distance,
+            duplicate_files: 0,
+            duplicates: Vec::new(),
+        };
+        let crates = HashMap::from([(
+            "serde-1.0.200".to_string(),
+            PathBuf::from("/cargo/serde-1.0.200"),
+        )]);
+        let mut hits = vec![
+            (hit("src/main.rs", 0.2), Origin::Repo),
+            (hit("src/lib.rs", 0.9), Origin::Repo),
+        ];
+        hits.extend(locate_hits(
+            vec![
+                hit("serde-1.0.200/src/de.rs", 0.5),
+                hit("removed-0.1.0/src/lib.rs", 0.1),
+            ],
+            &crates,
+        ));
+        let merged = crate::search::merge_ranked(hits, 2, None);
+        let summary: Vec<(&str, &Origin)> = merged
+            .iter()
+            .map(|(hit, origin)| (hit.path.as_str(), origin))
+            .collect();
+        let de = PathBuf::from("/cargo/serde-1.0.200/src/de.rs");
+        assert_eq!(
+            summary,
+            [
+                ("src/main.rs", &Origin::Repo),
+                (
+                    de.to_str().unwrap(),
+                    &Origin::Dependency("serde-1.0.200".to_string())
+                )
+            ]
+        );
+    }
+}
@@ -1,16 +1,164 @@
-use anyhow::{Result, anyhow};
-use fastembed::{TextEmbedding, TextInitOptions};
+use crate::config::{Config, ModelConfig};
+use crate::download::{self, FileDigest, ProxySettings};
+use anyhow::{Context, Result, anyhow, bail};
+use fastembed::{
+    InitOptionsUserDefined, OutputKey, Pooling, SingleBatchOutput, TextEmbedding, TokenizerFiles,
+    UserDefinedEmbeddingModel,
+};
+use ndarray::Array2;
+use ort::session::Session;
+use ort::value::Tensor;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+/// Hugging Face repository of the default model (all-MiniLM-L6-v2, 384 dimensions).
+pub const DEFAULT_MODEL_REPO: &str = "Qdrant/all-MiniLM-L6-v2-onnx";
+/// Files the embedder loads from a model directory.
+const MODEL_FILES: &[&str] = &[
+    "model.onnx",
+    "tokenizer.json",
+    "config.json",
+    "special_tokens_map.json",
+    "tokenizer_config.json",
+];
+/// Digests of the downloaded files, kept next to them in the model directory.
+const MANIFEST_FILE: &str = "manifest.json";
+/// Used when neither `model.endpoint` nor `HF_ENDPOINT` is set.
+const DEFAULT_ENDPOINT: &str = "https://huggingface.co";
+/// Model outputs tried in turn by `embed_from_tokens`, as fastembed tries them for text.
+const OUTPUT_PRECEDENCE: &[OutputKey] = &[
+    OutputKey::OnlyOne,
+    OutputKey::ByName("text_embeds"),
+    OutputKey::ByName("last_hidden_state"),
+    OutputKey::ByName("sentence_embedding"),
+];
+
+/// Where model files are downloaded from, and through which proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ModelSource {
+    endpoint: String,
+    proxies: ProxySettings,
+}
+
+impl ModelSource {
+    /// Config keys win over `HF_ENDPOINT` and the proxy environment variables.
+    fn resolve(config: &ModelConfig, hf_endpoint: Option<String>, proxies: ProxySettings) -> Self {
+        let endpoint = config
+            .endpoint
+            .clone()
+            .or(hf_endpoint)
+            .filter(|e| !e.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            proxies,
+        }
+    }
+
+    fn from_env(config: &ModelConfig) -> Self {
+        Self::resolve(
+            config,
+            std::env::var("HF_ENDPOINT").ok(),
+            ProxySettings::from_env(config.proxy.clone()),
+        )
+    }
+
+    fn file_url(&self, repo: &str, file: &str) -> String {
+        format!("{}/{}/resolve/main/{}", self.endpoint, repo, file)
+    }
+}
 
 pub struct Embedder {
     model: TextEmbedding,
+    /// Repository of the loaded model
+    repo: &'static str,
+    /// Model directory, read again when `embed_from_tokens` first needs a session
+    dir: PathBuf,
+    /// fastembed keeps its session private, so running the model on token ids needs another
+    session: Option<Session>,
+    /// Where `change_model` looks for and downloads models
+    cearch_dir: PathBuf,
+    source: ModelSource,
+    /// Dimensions of the vectors the last `embed` call returned
+    last_dim: Option<usize>,
+    /// Dimensions the loaded model declares in its config, when it does
+    model_dim: Option<usize>,
 }
 
 impl Embedder {
     pub fn new_default() -> Result<Self> {
-        let cache_dir = repo_cearch_dir()?;
-        let opts = TextInitOptions::default().with_cache_dir(cache_dir);
-        let model = TextEmbedding::try_new(opts)?;
-        Ok(Self { model })
+        Self::new(DEFAULT_MODEL_REPO)
+    }
+
+    /// Load the model `name`, as accepted by `cearch models`.
+    pub fn new(name: &str) -> Result<Self> {
+        let repo = resolve_model_name(name)?;
+        let cearch_dir = repo_cearch_dir()?;
+        let config = cearch_dir
+            .parent()
+            .map(Config::load)
+            .transpose()
+            .map_err(|e| anyhow!(e))?
+            .unwrap_or_default();
+        let source = ModelSource::from_env(&config.model);
+        let dir = model_dir(&cearch_dir, repo);
+        let model = fetch_and_load(&dir, repo, &source)?;
+        record_use(&cearch_dir, repo);
+        Ok(Self {
+            model,
+            repo,
+            model_dim: model_dimension(&dir),
+            dir,
+            session: None,
+            cearch_dir,
+            source,
+            last_dim: None,
+        })
+    }
+
+    /// Load the model an index was embedded with, as its `embedding_model` records, so new
+    /// vectors land in the same space. An index that records none, or holds imported
+    /// embeddings from a model cearch cannot load, gets the default model.
+    pub fn for_index(model: Option<&str>) -> Result<Self> {
+        match model.filter(|m| is_loadable(m)) {
+            Some(model) => Self::new(model),
+            None => Self::new_default(),
+        }
+    }
+
+    /// Repository of the loaded model, as recorded in the index's `embedding_model`.
+    pub fn model_repo(&self) -> &'static str {
+        self.repo
+    }
+
+    /// Width of the loaded model's vectors, as its `config.json` declares it.
+    pub fn dimension(&self) -> Option<usize> {
+        self.model_dim
+    }
+
+    /// Load the model `name` (as accepted by `cearch models`) in place of the current one,
+    /// for long-running modes that should not restart when the index's model changes.
+    /// Nothing changes if the new model fails to load.
+    pub fn change_model(&mut self, name: &str) -> Result<()> {
+        let repo = resolve_model_name(name)?;
+        let dir = model_dir(&self.cearch_dir, repo);
+        self.model = fetch_and_load(&dir, repo, &self.source)?;
+        record_use(&self.cearch_dir, repo);
+        self.repo = repo;
+        self.model_dim = model_dimension(&dir);
+        self.dir = dir;
+        self.session = None;
+        Ok(())
+    }
+
+    /// Whether the loaded model's vectors have other dimensions than those the last
+    /// `embed` call returned, i.e. vectors from before and after a `change_model` cannot be
+    /// compared. False until something has been embedded.
+    pub fn dimension_changed_since_last_embed(&self) -> bool {
+        matches!((self.last_dim, self.model_dim), (Some(last), Some(now)) if last != now)
     }
 
     pub fn embed<'a, T: AsRef<str> + 'a>(
@@ -21,14 +169,560 @@ impl Embedder {
             .into_iter()
             .map(|s| s.as_ref().to_string())
             .collect();
+        let _span = tracing::debug_span!("run_model", texts = texts.len()).entered();
         let embs = self.model.embed(texts, None)?;
+        if let Some(first) = embs.first() {
+            self.last_dim = Some(first.len());
+            self.model_dim = Some(first.len());
+        }
         Ok(embs)
     }
+
+    /// Embed `(id, text)` pairs and return each embedding under its id, so callers need not
+    /// rely on outputs lining up with inputs. A repeated id keeps its last text's embedding.
+    pub fn embed_with_ids<I: Clone + Eq + Hash, T: AsRef<str>>(
+        &mut self,
+        items: impl IntoIterator<Item = (I, T)>,
+    ) -> Result<HashMap<I, Vec<f32>>> {
+        let (ids, texts): (Vec<I>, Vec<T>) = items.into_iter().unzip();
+        let embeddings = self.embed(texts.iter().map(|t| t.as_ref()))?;
+        by_id(ids, embeddings.into_iter().map(Some).collect())
+    }
+
+    /// A copy of the model's tokenizer, for use alongside a `BatchEmbedder`.
+    pub fn tokenizer(&self) -> Tokenizer {
+        Tokenizer(self.model.tokenizer.clone())
+    }
+
+    /// Embed token id sequences from `Tokenizer::encode` by running the model on them
+    /// directly; pooling and normalization are the same as `embed`'s.
+    pub fn embed_from_tokens(&mut self, sequences: &[Vec<u32>]) -> Result<Vec<Vec<f32>>> {
+        let vocab = self.model.tokenizer.get_vocab_size(true);
+        if let Some(id) = sequences.iter().flatten().find(|&&id| id as usize >= vocab) {
+            bail!(
+                "token id {} is outside the {}-token vocabulary of {}",
+                id,
+                vocab,
+                self.repo
+            );
+        }
+        let width = sequences.iter().map(Vec::len).max().unwrap_or(0);
+        if width == 0 {
+            return Ok(vec![Vec::new(); sequences.len()]);
+        }
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => {
+                let path = self.dir.join("model.onnx");
+                let bytes = std::fs::read(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                self.session
+                    .insert(Session::builder()?.commit_from_memory(&bytes)?)
+            }
+        };
+        // Shorter sequences are padded with id 0 and masked out, as the tokenizer pads them
+        let shape = [sequences.len(), width];
+        let mut ids = Vec::with_capacity(shape[0] * width);
+        let mut mask = Vec::with_capacity(shape[0] * width);
+        for sequence in sequences {
+            ids.extend(sequence.iter().map(|&id| id as i64));
+            ids.resize(ids.len() + width - sequence.len(), 0);
+            mask.extend((0..width).map(|i| (i < sequence.len()) as i64));
+        }
+        let mask = Array2::from_shape_vec(shape, mask)?;
+        let mut inputs = ort::inputs![
+            "input_ids" => Tensor::from_array((shape, ids))?,
+            "attention_mask" => Tensor::from_array(mask.clone())?,
+        ];
+        if session.inputs.iter().any(|i| i.name == "token_type_ids") {
+            inputs.push((
+                "token_type_ids".into(),
+                Tensor::from_array((shape, vec![0i64; shape[0] * width]))?.into(),
+            ));
+        }
+        let outputs = session
+            .run(inputs)?
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        let batch = SingleBatchOutput {
+            outputs,
+            attention_mask_array: mask,
+        };
+        let pooled = batch.select_and_pool_output(&OUTPUT_PRECEDENCE, Some(Pooling::Mean))?;
+        Ok(pooled
+            .rows()
+            .into_iter()
+            .map(|row| normalize(&row.to_vec()))
+            .collect())
+    }
+}
+
+/// Scale `v` to unit length, as fastembed does with the embeddings it returns.
+fn normalize(v: &[f32]) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    v.iter().map(|x| x / (norm + 1e-12)).collect()
+}
+
+/// The embedding model's tokenizer, configured (truncation included) as the model uses it.
+#[derive(Clone)]
+pub struct Tokenizer(tokenizers::Tokenizer);
+
+impl Tokenizer {
+    /// Token ids the model sees for each text, without padding.
+    pub fn encode(&self, texts: &[&str]) -> Result<Vec<Vec<u32>>> {
+        texts
+            .iter()
+            .map(|text| {
+                let encoding = self
+                    .0
+                    .encode(*text, true)
+                    .map_err(|e| anyhow!("failed to tokenize: {}", e))?;
+                Ok(encoding
+                    .get_ids()
+                    .iter()
+                    .zip(encoding.get_attention_mask())
+                    .filter(|(_, mask)| **mask == 1)
+                    .map(|(id, _)| *id)
+                    .collect())
+            })
+            .collect()
+    }
+}
+
+/// Key `embeddings` by the id of the input at the same position, leaving out skipped (`None`)
+/// inputs; a model returning a different number of outputs is an error, not a misalignment.
+fn by_id<I: Eq + Hash>(
+    ids: Vec<I>,
+    embeddings: Vec<Option<Vec<f32>>>,
+) -> Result<HashMap<I, Vec<f32>>> {
+    if embeddings.len() != ids.len() {
+        bail!(
+            "model returned {} embeddings for {} inputs",
+            embeddings.len(),
+            ids.len()
+        );
+    }
+    Ok(ids
+        .into_iter()
+        .zip(embeddings)
+        .filter_map(|(id, embedding)| Some((id, embedding?)))
+        .collect())
+}
+
+/// Outcome of running a single batch under a watchdog.
+enum BatchOutcome {
+    Done(Vec<Vec<f32>>),
+    TimedOut,
+}
+
+/// Embedder thread that can be abandoned if the model hangs on a batch.
+pub struct Worker {
+    jobs: Sender<Vec<String>>,
+    results: Receiver<Result<Vec<Vec<f32>>>>,
+}
+
+impl Worker {
+    fn spawn(mut embedder: Embedder) -> Self {
+        let (jobs, job_rx) = mpsc::channel::<Vec<String>>();
+        let (result_tx, results) = mpsc::channel();
+        std::thread::spawn(move || {
+            for texts in job_rx {
+                if result_tx.send(embedder.embed(texts)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { jobs, results }
+    }
+}
+
+/// Embeds indexing batches, optionally under a timeout that isolates inputs which hang the model.
+pub enum BatchEmbedder {
+    Inline(Box<Embedder>),
+    Watchdog {
+        worker: Option<Worker>,
+        timeout: Duration,
+    },
+}
+
+impl BatchEmbedder {
+    pub fn new(embedder: Embedder, timeout: Option<Duration>) -> Self {
+        match timeout {
+            None => Self::Inline(Box::new(embedder)),
+            Some(timeout) => Self::Watchdog {
+                worker: Some(Worker::spawn(embedder)),
+                timeout,
+            },
+        }
+    }
+
+    /// Embed `(id, text)` pairs like `Embedder::embed_with_ids`; inputs skipped for hanging
+    /// the model have no entry.
+    pub fn embed_with_ids<'a, I: Clone + Eq + Hash>(
+        &mut self,
+        items: impl IntoIterator<Item = (I, &'a str)>,
+    ) -> Result<HashMap<I, Vec<f32>>> {
+        match self {
+            Self::Inline(embedder) => embedder.embed_with_ids(items),
+            Self::Watchdog { worker, timeout } => {
+                let timeout = *timeout;
+                let (ids, texts): (Vec<I>, Vec<&str>) = items.into_iter().unzip();
+                let embeddings =
+                    isolate_hangs(&texts, &mut |batch| run_watched(worker, timeout, batch))?;
+                by_id(ids, embeddings)
+            }
+        }
+    }
+}
+
+/// Run one batch on the worker, replacing the worker if the batch does not finish in time.
+fn run_watched(
+    worker: &mut Option<Worker>,
+    timeout: Duration,
+    texts: &[&str],
+) -> Result<BatchOutcome> {
+    // A timed-out worker is still stuck inside the model; load a fresh one rather than wait
+    let active = match worker {
+        Some(w) => w,
+        None => worker.insert(Worker::spawn(Embedder::new_default()?)),
+    };
+    let owned = texts.iter().map(|t| t.to_string()).collect();
+    active
+        .jobs
+        .send(owned)
+        .map_err(|_| anyhow!("embedding worker exited"))?;
+    match active.results.recv_timeout(timeout) {
+        Ok(result) => Ok(BatchOutcome::Done(result?)),
+        Err(RecvTimeoutError::Timeout) => {
+            *worker = None;
+            Ok(BatchOutcome::TimedOut)
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(anyhow!("embedding worker exited")),
+    }
+}
+
+/// Embed `texts`, splitting timed-out batches in half until the offending inputs are isolated.
+fn isolate_hangs(
+    texts: &[&str],
+    run: &mut impl FnMut(&[&str]) -> Result<BatchOutcome>,
+) -> Result<Vec<Option<Vec<f32>>>> {
+    if texts.is_empty() {
+        return Ok(Vec::new());
+    }
+    match run(texts)? {
+        BatchOutcome::Done(embeddings) => Ok(embeddings.into_iter().map(Some).collect()),
+        BatchOutcome::TimedOut if texts.len() == 1 => Ok(vec![None]),
+        BatchOutcome::TimedOut => {
+            let (left, right) = texts.split_at(texts.len() / 2);
+            let mut out = isolate_hangs(left, run)?;
+            out.extend(isolate_hangs(right, run)?);
+            Ok(out)
+        }
+    }
+}
+
+/// Note that `repo` was loaded, for `cearch cache prune`; a failure only costs the note.
+fn record_use(cache_root: &Path, repo: &str) {
+    if let Err(err) = crate::cache::record_use(cache_root, repo, crate::cache::now()) {
+        tracing::debug!("failed to record the use of {}: {}", repo, err);
+    }
+}
+
+/// Directory holding the files of model `repo` under a cache root.
+pub fn model_dir(cache_root: &Path, repo: &str) -> PathBuf {
+    cache_root.join("models").join(repo.replace('/', "--"))
+}
+
+fn read_manifest(dir: &Path) -> BTreeMap<String, FileDigest> {
+    std::fs::read_to_string(dir.join(MANIFEST_FILE))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Make sure every model file is present and matches its recorded size, downloading
+/// missing or damaged ones.
+fn ensure_model_files(dir: &Path, repo: &str, source: &ModelSource) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut manifest = read_manifest(dir);
+    for &file in MODEL_FILES {
+        let path = dir.join(file);
+        if path.exists() {
+            // Files without a manifest entry were placed there by hand; trust them
+            let Some(digest) = manifest.get(file) else {
+                continue;
+            };
+            match digest.verify(&path, false) {
+                Ok(()) => continue,
+                Err(reason) => {
+                    tracing::warn!(
+                        "cached {} is damaged ({}); downloading it again",
+                        path.display(),
+                        reason
+                    );
+                    std::fs::remove_file(&path)?;
+                }
+            }
+        }
+        eprintln!("downloading {} for {} from {}", file, repo, source.endpoint);
+        let digest = download::fetch(&source.file_url(repo, file), &path, &source.proxies)?;
+        manifest.insert(file.to_string(), digest);
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Download `repo`'s files into `dir` where missing, then load the model.
+fn fetch_and_load(dir: &Path, repo: &str, source: &ModelSource) -> Result<TextEmbedding> {
+    ensure_model_files(dir, repo, source)?;
+    match load_model(dir) {
+        Ok(model) => Ok(model),
+        // Files that pass the size check can still be corrupt; fetch them once more
+        Err(err) => {
+            tracing::warn!(
+                "cached model in {} failed to load ({:#}); downloading it again",
+                dir.display(),
+                err
+            );
+            std::fs::remove_dir_all(dir)?;
+            ensure_model_files(dir, repo, source)?;
+            load_model(dir)
+        }
+    }
+}
+
+/// The embedding width a model directory's `config.json` declares (`hidden_size`).
+fn model_dimension(dir: &Path) -> Option<usize> {
+    let config = std::fs::read_to_string(dir.join("config.json")).ok()?;
+    let config: serde_json::Value = serde_json::from_str(&config).ok()?;
+    config.get("hidden_size")?.as_u64().map(|d| d as usize)
+}
+
+fn load_model(dir: &Path) -> Result<TextEmbedding> {
+    let read = |file: &str| {
+        let path = dir.join(file);
+        std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+    };
+    let tokenizer_files = TokenizerFiles {
+        tokenizer_file: read("tokenizer.json")?,
+        config_file: read("config.json")?,
+        special_tokens_map_file: read("special_tokens_map.json")?,
+        tokenizer_config_file: read("tokenizer_config.json")?,
+    };
+    // all-MiniLM-L6-v2 is mean-pooled, as fastembed configures it
+    let model = UserDefinedEmbeddingModel::new(read("model.onnx")?, tokenizer_files)
+        .with_pooling(Pooling::Mean);
+    TextEmbedding::try_new_from_user_defined(model, InitOptionsUserDefined::default())
+}
+
+/// Map a name given to `cearch models` to the model's repository.
+///
+/// Only the default model is supported; its repository id and short names are accepted.
+pub fn resolve_model_name(name: &str) -> Result<&'static str> {
+    let short = DEFAULT_MODEL_REPO
+        .rsplit('/')
+        .next()
+        .unwrap_or(DEFAULT_MODEL_REPO);
+    if name == DEFAULT_MODEL_REPO || name == short || Some(name) == short.strip_suffix("-onnx") {
+        Ok(DEFAULT_MODEL_REPO)
+    } else {
+        Err(anyhow!(
+            "unknown model {}; supported: {}",
+            name,
+            DEFAULT_MODEL_REPO
+        ))
+    }
+}
+
+/// Whether `model`, as an index's `embedding_model` names it, is one cearch can load.
+pub fn is_loadable(model: &str) -> bool {
+    resolve_model_name(model).is_ok()
+}
+
+fn is_gzip(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("gz" | "tgz")
+    )
+}
+
+/// The directory under `source` holding the model files: `source` itself, or its only
+/// subdirectory (archives often wrap their contents in one).
+fn locate_model_files(source: &Path) -> Result<PathBuf> {
+    let missing = |dir: &Path| -> Vec<&str> {
+        MODEL_FILES
+            .iter()
+            .copied()
+            .filter(|f| !dir.join(f).is_file())
+            .collect()
+    };
+    let at_top = missing(source);
+    if at_top.is_empty() {
+        return Ok(source.to_path_buf());
+    }
+    let subdirs: Vec<PathBuf> = std::fs::read_dir(source)
+        .with_context(|| format!("failed to read {}", source.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_dir())
+        .collect();
+    if let [only] = subdirs.as_slice()
+        && missing(only).is_empty()
+    {
+        return Ok(only.clone());
+    }
+    Err(anyhow!(
+        "{} is missing model files: {}",
+        source.display(),
+        at_top.join(", ")
+    ))
+}
+
+/// Copy model files carried over by hand into the cache for `repo`.
+///
+/// `from` is a directory or a `.tar`/`.tar.gz` archive (as written by `export_model`). The
+/// files are validated, hashed into the manifest and checked with `verify` (e.g.
+/// `verify_model`) in a staging directory, and swapped in only once that passes, so a failed
+/// install leaves any existing copy untouched. Returns the model directory.
+pub fn install_model(
+    cache_root: &Path,
+    repo: &str,
+    from: &Path,
+    verify: impl FnOnce(&Path) -> Result<()>,
+) -> Result<PathBuf> {
+    let dir = model_dir(cache_root, repo);
+    let mut staging_name = dir.file_name().unwrap_or_default().to_os_string();
+    staging_name.push(".installing");
+    let staging = dir.with_file_name(staging_name);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging)?;
+
+    let copied = (|| -> Result<()> {
+        let unpacked = staging.join("unpacked");
+        let source = if from.is_dir() {
+            from.to_path_buf()
+        } else {
+            let file = std::fs::File::open(from)
+                .with_context(|| format!("failed to open {}", from.display()))?;
+            let unpack = |reader: Box<dyn std::io::Read>| {
+                tar::Archive::new(reader)
+                    .unpack(&unpacked)
+                    .with_context(|| format!("failed to extract {}", from.display()))
+            };
+            if is_gzip(from) {
+                unpack(Box::new(flate2::read::GzDecoder::new(file)))?;
+            } else {
+                unpack(Box::new(file))?;
+            }
+            unpacked.clone()
+        };
+        let source = locate_model_files(&source)?;
+
+        let mut manifest = BTreeMap::new();
+        for &file in MODEL_FILES {
+            let dest = staging.join(file);
+            std::fs::copy(source.join(file), &dest)
+                .with_context(|| format!("failed to copy {}", source.join(file).display()))?;
+            let digest = FileDigest {
+                size: std::fs::metadata(&dest)?.len(),
+                sha256: Some(download::sha256_file(&dest)?),
+            };
+            manifest.insert(file.to_string(), digest);
+        }
+        std::fs::write(
+            staging.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+        if unpacked.exists() {
+            std::fs::remove_dir_all(&unpacked)?;
+        }
+        verify(&staging).context("installed files failed to load")
+    })();
+    if let Err(err) = copied {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(err);
+    }
+
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::rename(&staging, &dir)?;
+    Ok(dir)
+}
+
+/// Load the model in `dir` once to check that it works.
+pub fn verify_model(dir: &Path) -> Result<()> {
+    load_model(dir).map(drop)
+}
+
+/// Write the cached files of `repo` to a `.tar` (or `.tar.gz`) archive for `install_model`.
+pub fn export_model(cache_root: &Path, repo: &str, to: &Path) -> Result<()> {
+    let dir = model_dir(cache_root, repo);
+    let missing: Vec<&str> = MODEL_FILES
+        .iter()
+        .copied()
+        .filter(|f| !dir.join(f).is_file())
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "{} is not fully downloaded in {} (missing {}); run `cearch init` first",
+            repo,
+            dir.display(),
+            missing.join(", ")
+        ));
+    }
+    fn append<W: std::io::Write>(out: W, dir: &Path) -> Result<W> {
+        let mut archive = tar::Builder::new(out);
+        for &file in MODEL_FILES {
+            archive.append_path_with_name(dir.join(file), file)?;
+        }
+        Ok(archive.into_inner()?)
+    }
+    let file =
+        std::fs::File::create(to).with_context(|| format!("failed to create {}", to.display()))?;
+    if is_gzip(to) {
+        let gz = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        append(gz, &dir)?.finish()?.sync_all()?;
+    } else {
+        append(file, &dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Write `embedding` as raw little-endian `f32`s for `query --query-embedding-file`.
+pub fn write_query_embedding(path: &Path, embedding: &[f32]) -> Result<()> {
+    let mut bytes = Vec::with_capacity(embedding.len() * 4);
+    for x in embedding {
+        bytes.extend_from_slice(&x.to_le_bytes());
+    }
+    std::fs::write(path, bytes).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Read a query embedding written by `write_query_embedding` (`dim * 4` bytes, no header).
+pub fn read_query_embedding(path: &Path) -> Result<Vec<f32>> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if bytes.is_empty() || bytes.len() % 4 != 0 {
+        bail!(
+            "{} holds {} bytes, not a whole number of little-endian f32 values",
+            path.display(),
+            bytes.len()
+        );
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
 }
 
 fn repo_cearch_dir() -> Result<std::path::PathBuf> {
     let cwd = std::env::current_dir()?;
-    let root = crate::index::find_git_root(&cwd)
+    let root = crate::index::repo_root(&cwd)
         .ok_or_else(|| anyhow!("not inside a git repository: {}", cwd.display()))?;
     let dir = root.join(".cearch");
     std::fs::create_dir_all(&dir)?;
@@ -45,6 +739,47 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn can_change_model_and_track_dimensions() {
+        let mut embedder = Embedder::new_default().expect("init model");
+        assert!(!embedder.dimension_changed_since_last_embed());
+        let before = embedder.embed(["fn a() {}"]).expect("embed");
+        assert_eq!(before[0].len(), 384);
+
+        assert!(embedder.change_model("no-such-model").is_err());
+        assert_eq!(embedder.model_repo(), DEFAULT_MODEL_REPO);
+        embedder
+            .change_model("all-MiniLM-L6-v2")
+            .expect("change model");
+        assert_eq!(embedder.model_repo(), DEFAULT_MODEL_REPO);
+        assert!(!embedder.dimension_changed_since_last_embed());
+        // A model of another width is flagged until it has embedded something
+        embedder.model_dim = Some(768);
+        assert!(embedder.dimension_changed_since_last_embed());
+        assert_eq!(embedder.embed(["fn a() {}"]).expect("embed"), before);
+        assert!(!embedder.dimension_changed_since_last_embed());
+    }
+
+    #[test]
+    fn only_known_models_are_loaded_for_an_index() {
+        assert!(is_loadable(DEFAULT_MODEL_REPO));
+        assert!(is_loadable("all-MiniLM-L6-v2"));
+        // Imported embeddings may name any model
+        assert!(!is_loadable("openai/text-embedding-3-small"));
+    }
+
+    #[test]
+    fn model_dimension_is_read_from_config() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        assert_eq!(model_dimension(dir.path()), None);
+        std::fs::write(
+            dir.path().join("config.json"),
+            r#"{"model_type": "bert", "hidden_size": 384}"#,
+        )
+        .unwrap();
+        assert_eq!(model_dimension(dir.path()), Some(384));
+    }
+
     #[test]
     fn can_embed_simple_snippets() {
         let mut embedder = Embedder::new_default().expect("init model");
@@ -54,8 +789,172 @@ mod tests {
         ];
         let embeddings = embedder.embed(&snippets).expect("embed");
         assert_eq!(embeddings.len(), snippets.len());
-        for vector in embeddings {
+        for vector in &embeddings {
             assert!(!vector.is_empty());
         }
+        let keyed = embedder
+            .embed_with_ids(snippets.iter().rev().enumerate())
+            .expect("embed");
+        assert_eq!(keyed[&1], embeddings[0]);
+        assert_eq!(keyed[&0], embeddings[1]);
+    }
+
+    #[test]
+    fn embeddings_are_keyed_by_input_id() {
+        let ids = vec!["a.rs:1", "a.rs:9", "b.rs:3"];
+        let embeddings = vec![Some(vec![1.0]), None, Some(vec![3.0])];
+        let keyed = by_id(ids.clone(), embeddings).expect("aligned");
+        assert_eq!(keyed.len(), 2);
+        assert_eq!(keyed["a.rs:1"], [1.0]);
+        assert_eq!(keyed["b.rs:3"], [3.0]);
+        assert!(!keyed.contains_key("a.rs:9"));
+
+        let err = by_id(ids, vec![Some(vec![1.0])]).unwrap_err();
+        assert_eq!(err.to_string(), "model returned 1 embeddings for 3 inputs");
+    }
+
+    #[test]
+    fn isolates_inputs_that_hang() {
+        let texts = ["a", "b", "hang", "c", "d"];
+        let mut calls = 0;
+        let mut run = |batch: &[&str]| {
+            calls += 1;
+            if batch.contains(&"hang") {
+                Ok(BatchOutcome::TimedOut)
+            } else {
+                Ok(BatchOutcome::Done(
+                    batch.iter().map(|t| vec![t.len() as f32]).collect(),
+                ))
+            }
+        };
+        let out = isolate_hangs(&texts, &mut run).expect("embed");
+        assert_eq!(
+            out,
+            vec![
+                Some(vec![1.0]),
+                Some(vec![1.0]),
+                None,
+                Some(vec![1.0]),
+                Some(vec![1.0])
+            ]
+        );
+        // Whole batch, then halves, then quarters around the bad input
+        assert!(calls <= 7, "too many retries: {}", calls);
+    }
+
+    /// Stand-in model files; only their presence and bytes are checked on install.
+    fn fabricate_model(dir: &Path) {
+        std::fs::create_dir_all(dir).unwrap();
+        for &file in MODEL_FILES {
+            std::fs::write(dir.join(file), format!("contents of {}", file)).unwrap();
+        }
+    }
+
+    #[test]
+    fn install_copies_files_into_cache_layout() {
+        let source = tempfile::tempdir().expect("tempdir");
+        let cache = tempfile::tempdir().expect("tempdir");
+        // Wrapped in one directory, as an unpacked download usually is
+        fabricate_model(&source.path().join("all-MiniLM-L6-v2-onnx"));
+
+        let dir = install_model(cache.path(), DEFAULT_MODEL_REPO, source.path(), |_| Ok(()))
+            .expect("install");
+        assert_eq!(dir, model_dir(cache.path(), DEFAULT_MODEL_REPO));
+        let manifest = read_manifest(&dir);
+        for &file in MODEL_FILES {
+            assert!(
+                manifest[file].verify(&dir.join(file), true).is_ok(),
+                "{}",
+                file
+            );
+        }
+        assert!(!dir.join("unpacked").exists());
+
+        // Files that do not load are not swapped in
+        let err = install_model(cache.path(), DEFAULT_MODEL_REPO, source.path(), |staged| {
+            assert!(staged.join("model.onnx").is_file());
+            Err(anyhow!("not a model"))
+        })
+        .unwrap_err();
+        assert!(format!("{:#}", err).ends_with("not a model"), "{:#}", err);
+        assert!(
+            manifest[MODEL_FILES[0]]
+                .verify(&dir.join(MODEL_FILES[0]), true)
+                .is_ok()
+        );
+
+        std::fs::remove_file(source.path().join("all-MiniLM-L6-v2-onnx/config.json")).unwrap();
+        let err = install_model(cache.path(), DEFAULT_MODEL_REPO, source.path(), |_| Ok(()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("missing model files"), "{}", err);
+        // The earlier install survives a failed one
+        assert!(dir.join("config.json").exists());
+    }
+
+    #[test]
+    fn export_round_trips_through_an_archive() {
+        let connected = tempfile::tempdir().expect("tempdir");
+        let offline = tempfile::tempdir().expect("tempdir");
+        let archive = connected.path().join("model.tar.gz");
+        assert!(export_model(connected.path(), DEFAULT_MODEL_REPO, &archive).is_err());
+
+        fabricate_model(&model_dir(connected.path(), DEFAULT_MODEL_REPO));
+        export_model(connected.path(), DEFAULT_MODEL_REPO, &archive).expect("export");
+        let dir = install_model(offline.path(), DEFAULT_MODEL_REPO, &archive, |_| Ok(()))
+            .expect("install");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("tokenizer.json")).unwrap(),
+            "contents of tokenizer.json"
+        );
+        assert_eq!(
+            resolve_model_name("all-MiniLM-L6-v2").unwrap(),
+            DEFAULT_MODEL_REPO
+        );
+        assert!(resolve_model_name("bge-small").is_err());
+    }
+
+    #[test]
+    fn query_embedding_file_round_trips() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("query.f32");
+        let embedding = [0.25f32, -1.5, f32::MIN_POSITIVE];
+        write_query_embedding(&path, &embedding).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), 12);
+        assert_eq!(read_query_embedding(&path).unwrap(), embedding);
+
+        std::fs::write(&path, [0u8; 6]).unwrap();
+        let err = read_query_embedding(&path).unwrap_err().to_string();
+        assert!(err.contains("6 bytes"), "{}", err);
+        std::fs::write(&path, []).unwrap();
+        assert!(read_query_embedding(&path).is_err());
+    }
+
+    #[test]
+    fn model_source_prefers_config_over_env() {
+        let default = ModelSource::resolve(&ModelConfig::default(), None, ProxySettings::default());
+        assert_eq!(
+            default.file_url("Qdrant/m", "model.onnx"),
+            "https://huggingface.co/Qdrant/m/resolve/main/model.onnx"
+        );
+        let env = ModelSource::resolve(
+            &ModelConfig::default(),
+            Some("https://hf-mirror.example/".into()),
+            ProxySettings::default(),
+        );
+        assert_eq!(env.endpoint, "https://hf-mirror.example");
+        let config = ModelConfig {
+            endpoint: Some("http://artifacts.corp/hf".into()),
+            proxy: None,
+        };
+        let configured = ModelSource::resolve(
+            &config,
+            Some("https://hf-mirror.example".into()),
+            ProxySettings::default(),
+        );
+        assert_eq!(
+            configured.file_url("Qdrant/m", "config.json"),
+            "http://artifacts.corp/hf/Qdrant/m/resolve/main/config.json"
+        );
     }
 }
@@ -0,0 +1,198 @@
+use anyhow::{Result, bail};
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+// Embedding store module keeps vectors outside SQLite for `index --embedding-store separate`.
+
+/// Where the vectors of an index live.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmbeddingStore {
+    /// In the `vec_index` table of `index.sqlite`
+    #[default]
+    Inline,
+    /// In `.cearch/embeddings.bin`; `vec_index` only maps symbols to rows
+    Separate,
+}
+
+impl EmbeddingStore {
+    /// Value recorded in the index metadata.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmbeddingStore::Inline => "inline",
+            EmbeddingStore::Separate => "separate",
+        }
+    }
+}
+
+/// Flat file of row-major little-endian `f32` vectors, `dim` values per row.
+///
+/// Rows are only ever appended. Readers map the file and scan it sequentially; rows written
+/// after the map was taken are not visible to it.
+pub struct SplitEmbeddingStore {
+    dim: usize,
+    rows: Cell<u64>,
+    writer: Option<RefCell<File>>,
+    map: Option<memmap2::Mmap>,
+}
+
+impl SplitEmbeddingStore {
+    /// `.cearch/embeddings.bin`, or `<name>.embeddings.bin` for an index named with
+    /// `--index-name`.
+    pub fn file_path(repo_root: &Path) -> PathBuf {
+        let file = match crate::db::index_name() {
+            crate::db::DEFAULT_INDEX_NAME => "embeddings.bin".to_string(),
+            name => format!("{}.embeddings.bin", name),
+        };
+        repo_root.join(".cearch").join(file)
+    }
+
+    fn row_count(file: &File, dim: usize) -> Result<u64> {
+        let len = file.metadata()?.len();
+        let row_bytes = (dim * 4) as u64;
+        if dim == 0 || len % row_bytes != 0 {
+            bail!(
+                "embeddings file holds {} bytes, not a whole number of {}-dimension rows",
+                len,
+                dim
+            );
+        }
+        Ok(len / row_bytes)
+    }
+
+    /// Open the store for appending, creating it if needed.
+    pub fn open_append(repo_root: &Path, dim: usize) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(Self::file_path(repo_root))?;
+        let rows = Self::row_count(&file, dim)?;
+        Ok(Self {
+            dim,
+            rows: Cell::new(rows),
+            writer: Some(RefCell::new(file)),
+            map: None,
+        })
+    }
+
+    /// Open the store for searching by mapping it into memory.
+    pub fn open_read(repo_root: &Path, dim: usize) -> Result<Self> {
+        let path = Self::file_path(repo_root);
+        let file = File::open(&path)
+            .map_err(|e| anyhow::anyhow!("failed to open {}: {}", path.display(), e))?;
+        let rows = Self::row_count(&file, dim)?;
+        // Mapping an empty file fails on some platforms, and there is nothing to read anyway
+        let map = if rows == 0 {
+            None
+        } else {
+            // Safety: the file is only appended to, so the mapped range stays valid
+            Some(unsafe { memmap2::Mmap::map(&file)? })
+        };
+        Ok(Self {
+            dim,
+            rows: Cell::new(rows),
+            writer: None,
+            map,
+        })
+    }
+
+    /// Append `embedding` and return its row number.
+    pub fn append(&self, embedding: &[f32]) -> Result<u64> {
+        let Some(writer) = &self.writer else {
+            bail!("embeddings file was opened read-only");
+        };
+        if embedding.len() != self.dim {
+            bail!(
+                "embedding has {} dimensions, store expects {}",
+                embedding.len(),
+                self.dim
+            );
+        }
+        let mut bytes = Vec::with_capacity(self.dim * 4);
+        for x in embedding {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        writer.borrow_mut().write_all(&bytes)?;
+        let row = self.rows.get();
+        self.rows.set(row + 1);
+        Ok(row)
+    }
+
+    fn row_values(&self, row: u64) -> Option<impl Iterator<Item = f32> + '_> {
+        let map = self.map.as_ref()?;
+        let start = usize::try_from(row).ok()?.checked_mul(self.dim * 4)?;
+        let bytes = map.get(start..start + self.dim * 4)?;
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        )
+    }
+
+    /// Euclidean distance from `query` to `row`, matching `vec0`'s default metric.
+    pub fn distance(&self, query: &[f32], row: u64) -> Option<f32> {
+        let sum: f32 = self
+            .row_values(row)?
+            .zip(query)
+            .map(|(v, q)| {
+                let d = v - q;
+                d * d
+            })
+            .sum();
+        Some(sum.sqrt())
+    }
+
+    /// The vector stored at `row`; a store opened for appending reads it back from the file.
+    pub fn vector(&self, row: u64) -> Option<Vec<f32>> {
+        if let Some(writer) = &self.writer {
+            if row >= self.rows.get() {
+                return None;
+            }
+            let mut bytes = vec![0u8; self.dim * 4];
+            let mut file = writer.borrow_mut();
+            // Appends always go to the end, whatever the read position
+            file.seek(SeekFrom::Start(row * bytes.len() as u64)).ok()?;
+            file.read_exact(&mut bytes).ok()?;
+            return Some(
+                bytes
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect(),
+            );
+        }
+        Some(self.row_values(row)?.collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appended_rows_are_read_back_by_distance() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir_all(dir.path().join(".cearch")).unwrap();
+        let store = SplitEmbeddingStore::open_append(dir.path(), 2).unwrap();
+        assert_eq!(store.append(&[0.0, 0.0]).unwrap(), 0);
+        assert_eq!(store.append(&[3.0, 4.0]).unwrap(), 1);
+        assert!(store.append(&[1.0]).is_err());
+        drop(store);
+
+        // Reopening continues the row numbering
+        let store = SplitEmbeddingStore::open_append(dir.path(), 2).unwrap();
+        assert_eq!(store.append(&[1.0, 1.0]).unwrap(), 2);
+        assert_eq!(store.vector(1), Some(vec![3.0, 4.0]));
+        assert_eq!(store.append(&[5.0, 6.0]).unwrap(), 3);
+        assert_eq!(store.vector(3), Some(vec![5.0, 6.0]));
+        assert_eq!(store.vector(4), None);
+        drop(store);
+
+        let store = SplitEmbeddingStore::open_read(dir.path(), 2).unwrap();
+        assert_eq!(store.distance(&[0.0, 0.0], 1), Some(5.0));
+        assert_eq!(store.distance(&[1.0, 1.0], 2), Some(0.0));
+        assert_eq!(store.distance(&[0.0, 0.0], 4), None);
+        assert_eq!(store.vector(1), Some(vec![3.0, 4.0]));
+        assert!(SplitEmbeddingStore::open_read(dir.path(), 5).is_err());
+    }
+}
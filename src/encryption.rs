@@ -0,0 +1,143 @@
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+// Encryption module finds the key of an encrypted index and applies it to connections;
+// the encryption itself is SQLCipher's, compiled in with the `encryption` feature.
+
+/// Passphrase of the index database.
+pub const KEY_ENV: &str = "CEARCH_INDEX_KEY";
+/// File holding the passphrase, read when `CEARCH_INDEX_KEY` is not set.
+pub const KEY_FILE_ENV: &str = "CEARCH_INDEX_KEY_FILE";
+/// Passphrase `cearch rekey` re-encrypts the index with.
+pub const NEW_KEY_ENV: &str = "CEARCH_INDEX_NEW_KEY";
+
+/// First bytes of every unencrypted SQLite database; SQLCipher leaves no such header.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+const NOT_BUILT: &str =
+    "index encryption needs cearch built with `cargo install cearch --features encryption`";
+
+/// Key of the index for the rest of the process, from `load_key`.
+static KEY: OnceLock<String> = OnceLock::new();
+
+/// Whether this build can read and write encrypted indexes.
+pub fn available() -> bool {
+    cfg!(feature = "encryption")
+}
+
+/// Fail with how to get an encryption-enabled build unless this is one.
+pub fn ensure_available() -> Result<(), String> {
+    if available() {
+        Ok(())
+    } else {
+        Err(NOT_BUILT.to_string())
+    }
+}
+
+/// The key given directly, else the trimmed contents of `file`; `None` when neither is set.
+pub fn read_key(value: Option<String>, file: Option<PathBuf>) -> Result<Option<String>, String> {
+    let (key, source) = match (value.filter(|v| !v.is_empty()), file) {
+        (Some(value), _) => (value, "the key".to_string()),
+        (None, Some(file)) => {
+            let contents = std::fs::read_to_string(&file)
+                .map_err(|e| format!("failed to read key file {}: {}", file.display(), e))?;
+            (contents.trim().to_string(), file.display().to_string())
+        }
+        (None, None) => return Ok(None),
+    };
+    if key.is_empty() {
+        return Err(format!("{} is empty", source));
+    }
+    ensure_available()?;
+    Ok(Some(key))
+}
+
+/// Read the key from `CEARCH_INDEX_KEY` or `CEARCH_INDEX_KEY_FILE` and use it for every
+/// index opened from now on.
+pub fn load_key() -> Result<(), String> {
+    let file = std::env::var_os(KEY_FILE_ENV)
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from);
+    if let Some(key) = read_key(std::env::var(KEY_ENV).ok(), file)? {
+        KEY.set(key)
+            .map_err(|_| "the index key was already set".to_string())?;
+    }
+    Ok(())
+}
+
+/// The key set by `load_key`, if any.
+pub fn key() -> Option<&'static str> {
+    KEY.get().map(String::as_str)
+}
+
+/// Key `conn` with `key` before anything reads it. SQLCipher only checks the key on the
+/// first read, where a wrong one fails with `SQLITE_NOTADB`.
+pub fn apply_key(conn: &Connection, key: Option<&str>) -> anyhow::Result<()> {
+    let Some(key) = key else {
+        return Ok(());
+    };
+    ensure_available().map_err(anyhow::Error::msg)?;
+    conn.pragma_update(None, "key", key)?;
+    // SQLCipher logs a failed decryption to stderr once keyed; the open error explains it
+    conn.query_row("PRAGMA cipher_log_level = NONE", [], |_| Ok(()))?;
+    Ok(())
+}
+
+/// Whether the database at `path` exists but lacks the plain SQLite header, as an encrypted
+/// one does.
+pub fn is_encrypted(path: &Path) -> bool {
+    use std::io::Read;
+    let mut header = [0u8; 16];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| &header != SQLITE_HEADER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_come_from_the_value_or_a_file() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let file = dir.path().join("index.key");
+        std::fs::write(&file, "  from file\n").unwrap();
+        let empty = dir.path().join("empty.key");
+        std::fs::write(&empty, "\n").unwrap();
+
+        assert_eq!(read_key(None, None), Ok(None));
+        assert!(
+            read_key(None, Some(empty))
+                .unwrap_err()
+                .contains("is empty")
+        );
+        let read =
+            |value: Option<&str>, file: Option<PathBuf>| read_key(value.map(String::from), file);
+        if available() {
+            assert_eq!(
+                read(Some("direct"), Some(file.clone())),
+                Ok(Some("direct".to_string()))
+            );
+            assert_eq!(
+                read(Some(""), Some(file)),
+                Ok(Some("from file".to_string()))
+            );
+        } else {
+            assert!(
+                read(Some("direct"), None)
+                    .unwrap_err()
+                    .contains("--features encryption")
+            );
+        }
+
+        let plain = dir.path().join("plain.sqlite");
+        Connection::open(&plain)
+            .unwrap()
+            .execute_batch("CREATE TABLE t(x)")
+            .unwrap();
+        assert!(!is_encrypted(&plain));
+        assert!(!is_encrypted(&dir.path().join("missing.sqlite")));
+        std::fs::write(dir.path().join("noise"), [7u8; 64]).unwrap();
+        assert!(is_encrypted(&dir.path().join("noise")));
+    }
+}
@@ -0,0 +1,378 @@
+use crate::db::DB;
+use crate::query_file::mean_pool;
+use crate::verify::{cosine, sample};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+// Graph module links symbols (or files) to their nearest neighbours for `cearch graph`.
+
+/// File formats `cearch graph` can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// GraphML, e.g. for Gephi
+    Graphml,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphOptions {
+    /// Neighbours linked from each node
+    pub k: usize,
+    /// Smallest cosine similarity an edge may have
+    pub threshold: f32,
+    /// One node per file, with the mean of its symbols' vectors
+    pub per_file: bool,
+    /// Only link neighbours from this many nodes, picked by `seed`
+    pub sample: Option<usize>,
+    pub seed: u64,
+    /// Only include symbols under this repo-relative path prefix
+    pub path_prefix: Option<String>,
+}
+
+/// An undirected graph; edges refer to nodes by index and are listed once, lower index first.
+#[derive(Debug, Default, PartialEq)]
+pub struct Graph {
+    /// Node labels: the qualified symbol name, or the path per file. Labels are for display
+    /// and need not be unique, e.g. for methods of the same name in two `impl` blocks
+    pub nodes: Vec<String>,
+    pub edges: Vec<(usize, usize, f32)>,
+}
+
+impl Graph {
+    /// The node for `key` (a symbol id, or a file's position), added with `label` if new.
+    fn node(
+        &mut self,
+        index: &mut HashMap<i64, usize>,
+        key: i64,
+        label: impl FnOnce() -> String,
+    ) -> usize {
+        *index.entry(key).or_insert_with(|| {
+            self.nodes.push(label());
+            self.nodes.len() - 1
+        })
+    }
+}
+
+/// Link each (sampled) symbol or file to its `k` nearest neighbours at or above the
+/// similarity threshold. Symbols are searched with `DB::knn`; files are compared directly.
+pub fn build(db: &DB, opts: &GraphOptions) -> Result<Graph> {
+    let prefix = opts.path_prefix.as_deref().unwrap_or("");
+    let symbols: Vec<_> = db
+        .get_symbols_by_rowids(&db.vector_rowids()?)?
+        .into_iter()
+        .filter(|s| s.path.starts_with(prefix))
+        .collect();
+    let mut vectors: HashMap<i64, Vec<f32>> = HashMap::new();
+    for s in &symbols {
+        if let Some(vector) = db.get_embedding(s.id)? {
+            vectors.insert(s.id, vector);
+        }
+    }
+    let mut edges: BTreeMap<(usize, usize), f32> = BTreeMap::new();
+    let mut graph = Graph::default();
+    let mut index = HashMap::new();
+
+    if opts.per_file {
+        let mut by_path: BTreeMap<&str, Vec<Vec<f32>>> = BTreeMap::new();
+        for s in &symbols {
+            if let Some(vector) = vectors.get(&s.id) {
+                by_path.entry(&s.path).or_default().push(vector.clone());
+            }
+        }
+        let files: Vec<(&str, Vec<f32>)> = by_path
+            .into_iter()
+            .filter_map(|(path, vectors)| Some((path, mean_pool(&vectors)?)))
+            .collect();
+        let positions: Vec<i64> = (0..files.len() as i64).collect();
+        for source in pick(&positions, opts) {
+            let (path, vector) = &files[source as usize];
+            let mut nearest: Vec<(f32, usize)> = files
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != source as usize)
+                .map(|(i, (_, other))| (cosine(vector, other), i))
+                .filter(|&(similarity, _)| similarity >= opts.threshold)
+                .collect();
+            nearest.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let from = graph.node(&mut index, source, || path.to_string());
+            for (similarity, i) in nearest.into_iter().take(opts.k) {
+                let to = graph.node(&mut index, i as i64, || files[i].0.to_string());
+                add_edge(&mut edges, from, to, similarity);
+            }
+        }
+    } else {
+        let ids: Vec<i64> = symbols.iter().map(|s| s.id).collect();
+        let labels: HashMap<i64, String> = symbols
+            .iter()
+            .map(|s| (s.id, format!("{}::{}", s.path, s.name)))
+            .collect();
+        // Room for the symbol itself and for neighbours outside the prefix
+        let fetch = opts.k + 1 + if prefix.is_empty() { 0 } else { opts.k };
+        for source in pick(&ids, opts) {
+            let Some(vector) = vectors.get(&source) else {
+                continue;
+            };
+            let from = graph.node(&mut index, source, || labels[&source].clone());
+            let hits = db.knn(vector, fetch)?;
+            let neighbours = hits
+                .iter()
+                .filter(|hit| hit.id != source)
+                .filter_map(|hit| Some((hit.id, labels.get(&hit.id)?, vectors.get(&hit.id)?)))
+                .take(opts.k);
+            for (id, label, other) in neighbours {
+                let similarity = cosine(vector, other);
+                if similarity >= opts.threshold {
+                    let to = graph.node(&mut index, id, || label.clone());
+                    add_edge(&mut edges, from, to, similarity);
+                }
+            }
+        }
+    }
+    graph.edges = edges.into_iter().map(|((a, b), s)| (a, b, s)).collect();
+    Ok(graph)
+}
+
+fn pick(ids: &[i64], opts: &GraphOptions) -> Vec<i64> {
+    match opts.sample {
+        Some(n) => sample(ids, n, opts.seed),
+        None => ids.to_vec(),
+    }
+}
+
+/// Neighbours found from both ends share one edge.
+fn add_edge(edges: &mut BTreeMap<(usize, usize), f32>, a: usize, b: usize, similarity: f32) {
+    if a == b {
+        return;
+    }
+    let weight = edges.entry((a.min(b), a.max(b))).or_insert(similarity);
+    *weight = weight.max(similarity);
+}
+
+pub fn write<W: Write>(out: W, graph: &Graph, format: GraphFormat) -> std::io::Result<()> {
+    match format {
+        GraphFormat::Dot => write_dot(out, graph),
+        GraphFormat::Graphml => write_graphml(out, graph),
+    }
+}
+
+/// An undirected DOT graph; nodes are `n0`, `n1`, ... with the label as `label` and edges
+/// carry the similarity as `weight`.
+fn write_dot<W: Write>(mut out: W, graph: &Graph) -> std::io::Result<()> {
+    writeln!(out, "graph cearch {{")?;
+    for (i, label) in graph.nodes.iter().enumerate() {
+        let escaped = label.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(out, "  n{} [label=\"{}\"];", i, escaped)?;
+    }
+    for &(a, b, similarity) in &graph.edges {
+        writeln!(
+            out,
+            "  n{} -- n{} [weight={:.4}, label=\"{:.2}\"];",
+            a, b, similarity, similarity
+        )?;
+    }
+    writeln!(out, "}}")?;
+    out.flush()
+}
+
+fn write_graphml<W: Write>(mut out: W, graph: &Graph) -> std::io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        out,
+        r#"  <key id="label" for="node" attr.name="label" attr.type="string"/>"#
+    )?;
+    writeln!(
+        out,
+        r#"  <key id="weight" for="edge" attr.name="weight" attr.type="double"/>"#
+    )?;
+    writeln!(out, r#"  <graph id="cearch" edgedefault="undirected">"#)?;
+    for (i, label) in graph.nodes.iter().enumerate() {
+        writeln!(
+            out,
+            r#"    <node id="n{}"><data key="label">{}</data></node>"#,
+            i,
+            escape_xml(label)
+        )?;
+    }
+    for &(a, b, similarity) in &graph.edges {
+        writeln!(
+            out,
+            r#"    <edge source="n{}" target="n{}"><data key="weight">{:.4}</data></edge>"#,
+            a, b, similarity
+        )?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    out.flush()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SymbolRecord, WriteOptions};
+    use std::collections::HashSet;
+
+    fn build_index(root: &std::path::Path) -> DB {
+        let db = DB::open_with_dim(root, 2, &WriteOptions::default()).expect("open");
+        let symbols: [(&str, &str, [f32; 2]); 4] = [
+            ("src/a.rs", "left", [1.0, 0.0]),
+            ("src/a.rs", "mostly_left", [0.95, 0.31]),
+            ("src/b.rs", "up", [0.0, 1.0]),
+            ("vendor/c.rs", "mostly_up", [0.31, 0.95]),
+        ];
+        let records: Vec<SymbolRecord> = symbols
+            .iter()
+            .enumerate()
+            .map(|(i, (path, name, emb))| SymbolRecord::fixture(path, i + 1, name, emb))
+            .collect();
+        db.insert_symbols(&records).unwrap();
+        db
+    }
+
+    fn options() -> GraphOptions {
+        GraphOptions {
+            k: 3,
+            threshold: 0.7,
+            per_file: false,
+            sample: None,
+            seed: 0,
+            path_prefix: None,
+        }
+    }
+
+    type Edges = Vec<(String, String, f32)>;
+
+    /// Node ids and labels, and edges between declared nodes, from DOT as `write_dot` emits.
+    fn parse_dot(dot: &str) -> (HashMap<String, String>, Edges) {
+        let body = dot
+            .strip_prefix("graph cearch {\n")
+            .and_then(|rest| rest.strip_suffix("}\n"))
+            .expect("one undirected graph");
+        let (mut nodes, mut edges) = (HashMap::new(), Vec::new());
+        for line in body.lines() {
+            let statement = line.trim().strip_suffix("];").expect("statement");
+            let (head, attrs) = statement.split_once(" [").expect("attributes");
+            match head.split_once(" -- ") {
+                Some((a, b)) => {
+                    assert!(nodes.contains_key(a) && nodes.contains_key(b), "{}", line);
+                    let weight = attrs
+                        .strip_prefix("weight=")
+                        .and_then(|w| w.split(',').next())
+                        .and_then(|w| w.parse().ok())
+                        .expect("weight");
+                    edges.push((a.to_string(), b.to_string(), weight));
+                }
+                None => {
+                    let label = attrs
+                        .strip_prefix("label=\"")
+                        .and_then(|l| l.strip_suffix('"'))
+                        .expect("label");
+                    nodes.insert(head.to_string(), label.to_string());
+                }
+            }
+        }
+        (nodes, edges)
+    }
+
+    #[test]
+    fn symbols_link_to_close_neighbours_as_valid_dot() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = build_index(dir.path());
+        let graph = build(&db, &options()).unwrap();
+        let mut dot = Vec::new();
+        write(&mut dot, &graph, GraphFormat::Dot).unwrap();
+        let (nodes, edges) = parse_dot(&String::from_utf8(dot).unwrap());
+        let pairs: HashSet<(&str, &str)> = edges
+            .iter()
+            .map(|(a, b, _)| {
+                let (a, b) = (nodes[a].as_str(), nodes[b].as_str());
+                (a.min(b), a.max(b))
+            })
+            .collect();
+        assert_eq!(
+            pairs,
+            HashSet::from([
+                ("src/a.rs::left", "src/a.rs::mostly_left"),
+                ("src/b.rs::up", "vendor/c.rs::mostly_up"),
+            ])
+        );
+        assert!(edges.iter().all(|&(_, _, w)| (0.7..=1.0).contains(&w)));
+
+        // Neighbours outside the prefix are left out, and sampling limits the sources
+        let graph = build(
+            &db,
+            &GraphOptions {
+                path_prefix: Some("src/".to_string()),
+                threshold: 0.0,
+                sample: Some(1),
+                ..options()
+            },
+        )
+        .unwrap();
+        assert!(graph.nodes.iter().all(|label| label.starts_with("src/")));
+        assert_eq!(graph.edges.len(), 2);
+    }
+
+    #[test]
+    fn same_named_symbols_are_separate_nodes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 2, &WriteOptions::default()).expect("open");
+        // Two `new` methods in one file, in different impl blocks
+        let records: Vec<SymbolRecord> = [(1, [1.0f32, 0.0]), (9, [0.95, 0.31])]
+            .iter()
+            .map(|(line, emb)| SymbolRecord {
+                code: "fn new() -> Self",
+                ..SymbolRecord::fixture("src/a.rs", *line, "new", emb)
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+        let graph = build(&db, &options()).unwrap();
+        assert_eq!(graph.nodes, ["src/a.rs::new", "src/a.rs::new"]);
+        assert_eq!(graph.edges.len(), 1);
+        assert_eq!((graph.edges[0].0, graph.edges[0].1), (0, 1));
+    }
+
+    #[test]
+    fn files_average_their_symbols_and_graphml_escapes_labels() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = build_index(dir.path());
+        let graph = build(
+            &db,
+            &GraphOptions {
+                per_file: true,
+                k: 1,
+                threshold: 0.5,
+                ..options()
+            },
+        )
+        .unwrap();
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.edges.len(), 1);
+        let (a, b, _) = graph.edges[0];
+        let mut linked = [graph.nodes[a].as_str(), graph.nodes[b].as_str()];
+        linked.sort();
+        assert_eq!(linked, ["src/b.rs", "vendor/c.rs"]);
+
+        let graph = Graph {
+            nodes: vec!["a<b>&\"c\"".to_string(), "d".to_string()],
+            edges: vec![(0, 1, 0.5)],
+        };
+        let mut xml = Vec::new();
+        write(&mut xml, &graph, GraphFormat::Graphml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.contains(r#"<data key="label">a&lt;b&gt;&amp;&quot;c&quot;</data>"#));
+        assert!(xml.contains(r#"<edge source="n0" target="n1"><data key="weight">0.5000</data>"#));
+        assert_eq!(xml.matches("<node ").count(), 2);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::db::SearchResult;
+use crate::search::Origin;
+// History module decides which commits `cearch index --history` walks, and turns hits from
+// the history index into `query --include-history` results.
+
+/// Length of the abbreviated commit hashes shown with historical results.
+const SHORT_HASH: usize = 7;
+
+/// How far back `index --history` looks: a number of commits, or a date git understands
+/// (e.g. `2024-01-31` or `6.months.ago`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Range {
+    Commits(usize),
+    Since(String),
+}
+
+impl Range {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            return Err("--history needs a number of commits or a date".to_string());
+        }
+        match spec.parse::<usize>() {
+            Ok(0) => Err("--history needs at least 1 commit".to_string()),
+            Ok(n) => Ok(Self::Commits(n)),
+            Err(_) if spec.starts_with('-') => Err(format!(
+                "--history {:?} is neither a number of commits nor a date",
+                spec
+            )),
+            Err(_) => Ok(Self::Since(spec.to_string())),
+        }
+    }
+
+    /// The `git log` options selecting the commits.
+    pub fn git_args(&self) -> Vec<String> {
+        match self {
+            Self::Commits(n) => vec![format!("--max-count={}", n)],
+            Self::Since(date) => vec![format!("--since={}", date)],
+        }
+    }
+}
+
+/// An abbreviated commit hash, as shown after a historical result's path (`path@abc1234:line`)
+/// and in blame.
+pub fn short_hash(commit: &str) -> &str {
+    commit.get(..SHORT_HASH).unwrap_or(commit)
+}
+
+/// Hits from the history index, each with the commit holding it, for
+/// `search::merge_ranked`. Stored paths are `<commit>/<path>`; the commit is split off so
+/// the path is the file's own.
+pub fn locate_hits(history: Vec<SearchResult>) -> Vec<(SearchResult, Origin)> {
+    history
+        .into_iter()
+        .filter_map(|mut hit| {
+            let (commit, path) = hit.path.split_once('/')?;
+            let (commit, path) = (commit.to_string(), path.to_string());
+            hit.path = path;
+            Some((hit, Origin::History(commit)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_are_commit_counts_or_dates() {
+        assert_eq!(Range::parse("20"), Ok(Range::Commits(20)));
+        assert_eq!(
+            Range::parse(" 2024-01-31 "),
+            Ok(Range::Since("2024-01-31".to_string()))
+        );
+        assert_eq!(Range::Commits(5).git_args(), ["--max-count=5"]);
+        assert_eq!(
+            Range::Since("6.months.ago".to_string()).git_args(),
+            ["--since=6.months.ago"]
+        );
+        assert!(Range::parse("0").is_err());
+        assert!(Range::parse("").is_err());
+        assert!(Range::parse("--all").is_err());
+    }
+
+    #[test]
+    fn hits_lose_their_commit_prefix() {
+        let commit = "3c31b8cf628988c07380c435c47b1fca594463aa";
+        let hit = SearchResult {
+            id: 4,
+            path: format!("{}/src/retry.rs", commit),
+            line: 12,
+            kind: "fn".to_string(),
+            name: "with_retry".to_string(),
+            code: String::new(),
+            distance: 0.3,
+            duplicate_files: 0,
+            duplicates: Vec::new(),
+        };
+        let located = locate_hits(vec![hit]);
+        assert_eq!(located[0].0.path, "src/retry.rs");
+        assert_eq!(located[0].1, Origin::History(commit.to_string()));
+        assert_eq!(short_hash(commit), "3c31b8c");
+        assert_eq!(short_hash("abc"), "abc");
+    }
+}
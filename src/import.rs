@@ -0,0 +1,360 @@
+use crate::db::{self, DB, SymbolRecord};
+use crate::embed::DEFAULT_MODEL_REPO;
+use crate::paths;
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::io::BufRead;
+use std::path::Path;
+// Import module loads embeddings computed elsewhere into the index (`cearch import-embeddings`).
+
+/// Records matched and written per transaction.
+const BATCH_SIZE: usize = 512;
+
+/// Kinds a symbol created by `--create-missing` may have, as stored by `cearch index`.
+const KINDS: &[&str] = &["fn", "class", "const"];
+
+/// One line of an import file.
+///
+/// A record names its symbol by `symbol_id` (the index rowid) or by `path` and `line`,
+/// narrowed by `name` and `kind` when several symbols start on the same line.
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingRecord {
+    pub path: Option<String>,
+    pub line: Option<usize>,
+    pub symbol_id: Option<i64>,
+    pub name: Option<String>,
+    pub kind: Option<String>,
+    /// Stored for symbols created by `--create-missing`
+    pub code: Option<String>,
+    pub model: String,
+    pub vector: Vec<f32>,
+}
+
+/// A validated import file: every record has the same model and dimension.
+#[derive(Debug)]
+pub struct Embeddings {
+    pub model: String,
+    pub dim: usize,
+    pub records: Vec<EmbeddingRecord>,
+}
+
+/// Parse and validate JSON Lines records; errors name the offending line.
+pub fn read_records(input: impl BufRead) -> Result<Embeddings> {
+    let mut records: Vec<EmbeddingRecord> = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let n = i + 1;
+        let mut record: EmbeddingRecord = serde_json::from_str(&line)
+            .map_err(|e| anyhow::anyhow!("line {}: invalid record: {}", n, e))?;
+        if record.vector.is_empty() {
+            bail!("line {}: vector is empty", n);
+        }
+        if !record.vector.iter().all(|x| x.is_finite()) {
+            bail!("line {}: vector holds a NaN or infinite value", n);
+        }
+        if record.symbol_id.is_none() && (record.path.is_none() || record.line.is_none()) {
+            bail!("line {}: record needs a symbol_id, or a path and line", n);
+        }
+        if let Some(first) = records.first() {
+            if record.model != first.model {
+                bail!(
+                    "line {}: model {} differs from {} on earlier lines; import one model at a time",
+                    n,
+                    record.model,
+                    first.model
+                );
+            }
+            if record.vector.len() != first.vector.len() {
+                bail!(
+                    "line {}: vector has {} dimensions, earlier lines have {}",
+                    n,
+                    record.vector.len(),
+                    first.vector.len()
+                );
+            }
+        }
+        record.path = record.path.map(|p| paths::normalize_separators(&p));
+        records.push(record);
+    }
+    let Some(first) = records.first() else {
+        bail!("no embedding records found");
+    };
+    Ok(Embeddings {
+        model: first.model.clone(),
+        dim: first.vector.len(),
+        records,
+    })
+}
+
+/// Settings of one `cearch import-embeddings` run.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Insert symbols that are not in the index instead of skipping their records
+    pub create_missing: bool,
+    /// Replace vectors of another model or dimension instead of refusing the import
+    pub force: bool,
+}
+
+/// What an import did with its records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Symbols given a vector they did not have, including created ones
+    pub imported: usize,
+    /// Symbols whose existing vector was replaced
+    pub updated: usize,
+    /// Records matching no symbol (or several) that were not created
+    pub skipped: usize,
+    /// Symbols inserted by `--create-missing`
+    pub created: usize,
+}
+
+/// Store `embeddings` in the index at `repo_root`, in whichever embedding store it uses.
+///
+/// The index records the model; vectors of a different model or dimension are only
+/// replaced (all of them, since mixed models cannot be compared) with `force`.
+pub fn import(
+    repo_root: &Path,
+    embeddings: &Embeddings,
+    opts: &ImportOptions,
+    write_opts: &db::WriteOptions,
+) -> Result<ImportReport> {
+    let existing = DB::open_maintenance(repo_root)?;
+    // Vectors from before the model was recorded came from the built-in model
+    let stored_model = match existing.embedding_model()? {
+        Some(model) => Some(model),
+        None if existing.count_vectors()? > 0 => Some(DEFAULT_MODEL_REPO.to_string()),
+        None => None,
+    };
+    let stored_dim = if existing.is_metadata_only()? {
+        None
+    } else {
+        existing.embedding_dim()?
+    };
+    let conflict = match (&stored_model, stored_dim) {
+        (Some(model), _) if *model != embeddings.model => Some(format!("model {}", model)),
+        (_, Some(dim)) if dim != embeddings.dim => Some(format!("{}-dimension vectors", dim)),
+        _ => None,
+    };
+    if let Some(conflict) = conflict {
+        if !opts.force {
+            bail!(
+                "index holds embeddings from {}, not {} ({} dimensions); pass --force to replace all of them",
+                conflict,
+                embeddings.model,
+                embeddings.dim
+            );
+        }
+        existing.drop_vectors(repo_root)?;
+    } else {
+        existing.remove_meta("metadata_only")?;
+    }
+    let separate = existing.get_meta("embedding_store")?.as_deref() == Some("separate");
+    drop(existing);
+
+    let db = if separate {
+        DB::open_split(repo_root, embeddings.dim, write_opts)?
+    } else {
+        DB::open_with_dim(repo_root, embeddings.dim, write_opts)?
+    };
+    db.set_meta("embedding_model", &embeddings.model)?;
+
+    let mut report = ImportReport::default();
+    for chunk in embeddings.records.chunks(BATCH_SIZE) {
+        let mut matched: Vec<(i64, &[f32])> = Vec::new();
+        let mut created: Vec<SymbolRecord> = Vec::new();
+        for r in chunk {
+            let by_id = match r.symbol_id {
+                Some(id) if db.symbol_exists(id)? => Some(id),
+                _ => None,
+            };
+            let found = match (by_id, r.path.as_deref(), r.line) {
+                (Some(id), _, _) => vec![id],
+                (None, Some(path), Some(line)) => {
+                    db.symbols_at_line(path, line, r.name.as_deref(), r.kind.as_deref())?
+                }
+                _ => Vec::new(),
+            };
+            match (found.as_slice(), &r.path, r.line, &r.name, &r.kind) {
+                ([id], ..) => matched.push((*id, &r.vector)),
+                ([], Some(path), Some(line), Some(name), Some(kind))
+                    if opts.create_missing && KINDS.contains(&kind.as_str()) =>
+                {
+                    created.push(SymbolRecord {
+                        path,
+                        line,
+                        kind,
+                        name,
+                        code: r.code.as_deref().unwrap_or(""),
+                        clean_code: None,
+                        embedding: &r.vector,
+                        parents: None,
+                    })
+                }
+                _ => report.skipped += 1,
+            }
+        }
+        let replaced = db.set_embeddings(&matched)?;
+        report.updated += replaced;
+        report.imported += matched.len() - replaced;
+        db.insert_symbols(&created)?;
+        report.imported += created.len();
+        report.created += created.len();
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::WriteOptions;
+
+    fn index_with(root: &Path, symbols: &[(&str, usize, &str)]) -> Vec<i64> {
+        let db = DB::open_metadata_only(root, &WriteOptions::default()).expect("open");
+        let records: Vec<SymbolRecord> = symbols
+            .iter()
+            .map(|(path, line, name)| SymbolRecord::fixture(path, *line, name, &[]))
+            .collect();
+        db.insert_symbols(&records).unwrap()
+    }
+
+    fn records(lines: &[&str]) -> Embeddings {
+        read_records(lines.join("\n").as_bytes()).expect("valid")
+    }
+
+    #[test]
+    fn records_must_agree_on_model_and_dimension() {
+        let err = |lines: &[&str]| {
+            read_records(lines.join("\n").as_bytes())
+                .unwrap_err()
+                .to_string()
+        };
+        let a = r#"{"path":"a.rs","line":1,"model":"big","vector":[1,0]}"#;
+        assert_eq!(records(&[a, "", a]).records.len(), 2);
+        assert!(
+            err(&[
+                a,
+                r#"{"path":"a.rs","line":2,"model":"big","vector":[1,0,0]}"#
+            ])
+            .starts_with("line 2: vector has 3 dimensions")
+        );
+        assert!(
+            err(&[
+                a,
+                r#"{"path":"a.rs","line":2,"model":"small","vector":[1,0]}"#
+            ])
+            .starts_with("line 2: model small differs")
+        );
+        assert!(err(&[r#"{"name":"a","model":"big","vector":[1]}"#]).contains("symbol_id"));
+        assert!(err(&[r#"{"symbol_id":1,"model":"big","vector":[]}"#]).contains("empty"));
+        assert!(err(&["not json"]).starts_with("line 1: invalid record"));
+        assert_eq!(err(&[""]), "no embedding records found");
+    }
+
+    #[test]
+    fn import_matches_updates_skips_and_creates() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let ids = index_with(root, &[("a.rs", 1, "alpha"), ("b.rs", 4, "beta")]);
+        let file = records(&[
+            &format!(r#"{{"symbol_id":{},"model":"big","vector":[1,0]}}"#, ids[0]),
+            r#"{"path":"b.rs","line":4,"name":"beta","model":"big","vector":[0,1]}"#,
+            r#"{"path":"c.rs","line":2,"name":"gamma","kind":"fn","code":"fn gamma() {}","model":"big","vector":[0.5,0.5]}"#,
+            r#"{"path":"b.rs","line":4,"name":"other","model":"big","vector":[0,1]}"#,
+        ]);
+        let opts = ImportOptions::default();
+        let report = import(root, &file, &opts, &WriteOptions::default()).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 2,
+                updated: 0,
+                skipped: 2,
+                created: 0
+            }
+        );
+        // A metadata-only index becomes searchable
+        let db = DB::open_read(root).unwrap();
+        assert!(!db.is_metadata_only().unwrap());
+        assert_eq!(db.embedding_model().unwrap().as_deref(), Some("big"));
+        assert_eq!(db.knn(&[0.0, 1.0], 1).unwrap()[0].name, "beta");
+        drop(db);
+
+        let create = ImportOptions {
+            create_missing: true,
+            ..Default::default()
+        };
+        let report = import(root, &file, &create, &WriteOptions::default()).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 1,
+                updated: 2,
+                skipped: 1,
+                created: 1
+            }
+        );
+        let db = DB::open_read(root).unwrap();
+        assert_eq!(db.count_vectors().unwrap(), 3);
+        let gamma = db.knn(&[0.5, 0.5], 1).unwrap();
+        assert_eq!((gamma[0].name.as_str(), gamma[0].line), ("gamma", 2));
+        assert_eq!(gamma[0].code, "fn gamma() {}");
+    }
+
+    #[test]
+    fn another_model_needs_force_and_replaces_all_vectors() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        index_with(root, &[("a.rs", 1, "alpha"), ("b.rs", 4, "beta")]);
+        let big = records(&[
+            r#"{"path":"a.rs","line":1,"model":"big","vector":[1,0]}"#,
+            r#"{"path":"b.rs","line":4,"model":"big","vector":[0,1]}"#,
+        ]);
+        import(
+            root,
+            &big,
+            &ImportOptions::default(),
+            &WriteOptions::default(),
+        )
+        .unwrap();
+
+        let huge = records(&[r#"{"path":"a.rs","line":1,"model":"huge","vector":[1,0,0]}"#]);
+        let err = import(
+            root,
+            &huge,
+            &ImportOptions::default(),
+            &WriteOptions::default(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("model big") && err.contains("--force"),
+            "{}",
+            err
+        );
+        let same_dim = records(&[r#"{"path":"a.rs","line":1,"model":"other","vector":[1,0]}"#]);
+        assert!(
+            import(
+                root,
+                &same_dim,
+                &ImportOptions::default(),
+                &WriteOptions::default()
+            )
+            .is_err()
+        );
+
+        let force = ImportOptions {
+            force: true,
+            ..Default::default()
+        };
+        let report = import(root, &huge, &force, &WriteOptions::default()).unwrap();
+        assert_eq!(report.imported, 1);
+        let db = DB::open_read(root).unwrap();
+        assert_eq!(db.embedding_dim().unwrap(), Some(3));
+        assert_eq!(db.embedding_model().unwrap().as_deref(), Some("huge"));
+        // beta's vector came from the old model, so it is gone
+        assert_eq!(db.count_vectors().unwrap(), 1);
+    }
+}
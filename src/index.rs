@@ -1,5 +1,8 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::OnceLock;
 // Index module handles repository discovery and file enumeration only.
 
 /// Walk upward from a starting path to locate the root directory of a Git repository.
@@ -35,43 +38,697 @@ pub fn find_git_root(start_path: impl AsRef<Path>) -> Option<PathBuf> {
     }
 }
 
+/// Start a git command; every git invocation in cearch goes through here.
+///
+/// `core.quotepath` is turned off so non-ASCII paths come back verbatim instead of
+/// octal-escaped, including from commands that have no `-z` option.
+fn git() -> Command {
+    let mut command = Command::new(git_program());
+    command.args(["-c", "core.quotepath=off"]);
+    command
+}
+
+/// What `git --version` prints, e.g. `git version 2.43.0`; `None` without a working git.
+pub fn git_version() -> Option<String> {
+    output_line(git().arg("--version")).map(|line| String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Path of the git executable, looked up on `PATH` once.
+///
+/// On Windows this finds `git.exe` (or a `git.cmd` shim) the same way a shell would; if the
+/// lookup fails the bare name is used so spawning reports the usual not-found error.
+fn git_program() -> &'static Path {
+    static PROGRAM: OnceLock<PathBuf> = OnceLock::new();
+    PROGRAM.get_or_init(|| which::which("git").unwrap_or_else(|_| PathBuf::from("git")))
+}
+
+/// Stdout of a successful `command` without its trailing newline, if not empty.
+fn output_line(command: &mut Command) -> Option<Vec<u8>> {
+    let output = command.stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut line = output.stdout;
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+    (!line.is_empty()).then_some(line)
+}
+
+/// A path printed by git, taken byte for byte where the platform allows it.
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+    }
+    // git writes UTF-8 paths everywhere else
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Native path of a repo-relative, `/`-separated path as git reports it.
+fn native_from_git(repo_root: &Path, rel: &[u8]) -> PathBuf {
+    let mut native = repo_root.to_path_buf();
+    native.extend(
+        rel.split(|b| *b == b'/')
+            .filter(|seg| !seg.is_empty())
+            .map(path_from_bytes),
+    );
+    native
+}
+
+/// `GIT_DIR`/`GIT_WORK_TREE` overrides, made absolute against the directory they were set in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GitEnv {
+    pub git_dir: Option<PathBuf>,
+    pub work_tree: Option<PathBuf>,
+}
+
+impl GitEnv {
+    pub fn from_env() -> Self {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut env = Self::from_vars(&cwd, |name| std::env::var_os(name));
+        // A work tree alone leaves git to find the repository from the cwd; pin that down too
+        if env.git_dir.is_none() && env.work_tree.is_some() {
+            env.git_dir = output_line(git().args(["rev-parse", "--absolute-git-dir"]))
+                .map(|line| path_from_bytes(&line));
+        }
+        env
+    }
+
+    pub fn from_vars(cwd: &Path, var: impl Fn(&str) -> Option<std::ffi::OsString>) -> Self {
+        // git treats an empty variable as unset
+        let path = |name| var(name).filter(|v| !v.is_empty()).map(|v| cwd.join(v));
+        Self {
+            git_dir: path("GIT_DIR"),
+            work_tree: path("GIT_WORK_TREE"),
+        }
+    }
+
+    /// A `git -C <repo_root>` command whose view of the repository matches `repo_root`.
+    ///
+    /// With `GIT_DIR` set, the work tree is pinned to `repo_root`; otherwise both variables
+    /// are cleared so git discovers the repository from `repo_root` as cearch did.
+    pub(crate) fn command(&self, repo_root: &Path) -> Command {
+        let mut command = git();
+        command.arg("-C").arg(repo_root);
+        match &self.git_dir {
+            Some(git_dir) => command
+                .env("GIT_DIR", git_dir)
+                .env("GIT_WORK_TREE", repo_root),
+            None => command.env_remove("GIT_DIR").env_remove("GIT_WORK_TREE"),
+        };
+        command
+    }
+}
+
+/// Locate the work tree cearch should index from `start`, honouring `GIT_DIR`/`GIT_WORK_TREE`.
+///
+/// `git rev-parse --show-toplevel` decides when git is available; without it, an explicit
+/// `GIT_WORK_TREE` is used, then the nearest directory holding `.git`.
+pub fn resolve_repo_root(start: impl AsRef<Path>, env: &GitEnv) -> Option<PathBuf> {
+    let start = start.as_ref();
+    let mut command = git();
+    command
+        .arg("-C")
+        .arg(start)
+        .args(["rev-parse", "--show-toplevel"])
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_WORK_TREE");
+    if let Some(git_dir) = &env.git_dir {
+        command.env("GIT_DIR", git_dir);
+    }
+    if let Some(work_tree) = &env.work_tree {
+        command.env("GIT_WORK_TREE", work_tree);
+    }
+    if let Some(top) = output_line(&mut command) {
+        let top = path_from_bytes(&top);
+        return Some(top.canonicalize().unwrap_or(top));
+    }
+    if let Some(work_tree) = &env.work_tree {
+        return work_tree.canonicalize().ok();
+    }
+    find_git_root(start)
+}
+
+/// `resolve_repo_root` with the process environment.
+pub fn repo_root(start: impl AsRef<Path>) -> Option<PathBuf> {
+    resolve_repo_root(start, &GitEnv::from_env())
+}
+
 /// Return absolute paths for all files tracked by Git in the provided repository root.
 ///
 /// This invokes `git ls-files -z` to ensure results match Git's notion of "tracked".
 pub fn list_git_tracked_files(repo_root: impl AsRef<Path>) -> Result<Vec<PathBuf>, String> {
+    stream_git_tracked_files(repo_root)?.collect()
+}
+
+/// Like `list_git_tracked_files`, but yields paths while `git ls-files` is still running.
+///
+/// A listing that fails part-way ends with an `Err` item after the paths read so far.
+pub fn stream_git_tracked_files(repo_root: impl AsRef<Path>) -> Result<TrackedFiles, String> {
     let repo_root = repo_root.as_ref();
+    let env = GitEnv::from_env();
 
-    // Ensure the directory looks like a git repo root
-    if !repo_root.join(".git").exists() {
+    // Ensure the directory looks like a git repo root; GIT_DIR may keep it elsewhere
+    if env.git_dir.is_none() && !repo_root.join(".git").exists() {
         return Err(format!(
             "{} is not a Git repository root (missing .git)",
             repo_root.display()
         ));
     }
 
-    let output = Command::new("git")
-        .arg("-C")
-        .arg(repo_root)
-        .arg("ls-files")
-        .arg("-z")
+    let mut command = env.command(repo_root);
+    command.arg("ls-files").arg("-z");
+    TrackedFiles::spawn(repo_root, command)
+}
+
+/// Paths read incrementally from the NUL-separated output of a `git ls-files -z` child.
+pub struct TrackedFiles {
+    repo_root: PathBuf,
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+    done: bool,
+}
+
+impl TrackedFiles {
+    fn spawn(repo_root: &Path, mut command: Command) -> Result<Self, String> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("failed to invoke git: {}", e))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| "failed to read git output".to_string())?;
+        Ok(Self {
+            repo_root: repo_root.to_path_buf(),
+            child,
+            stdout: BufReader::new(stdout),
+            done: false,
+        })
+    }
+}
+
+impl Iterator for TrackedFiles {
+    type Item = Result<PathBuf, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut rel_bytes = Vec::new();
+        while !self.done {
+            rel_bytes.clear();
+            match self.stdout.read_until(0, &mut rel_bytes) {
+                // End of output: only now does the exit status say whether the listing was whole
+                Ok(0) => {
+                    self.done = true;
+                    return match self.child.wait() {
+                        Ok(status) if status.success() => None,
+                        Ok(status) => {
+                            Some(Err(format!("git ls-files failed with status {}", status)))
+                        }
+                        Err(e) => Some(Err(format!("failed to wait for git ls-files: {}", e))),
+                    };
+                }
+                Ok(_) => {
+                    if rel_bytes.last() == Some(&0) {
+                        rel_bytes.pop();
+                    }
+                    if rel_bytes.is_empty() {
+                        continue;
+                    }
+                    // git always reports `/`-separated paths, even on Windows
+                    return Some(Ok(native_from_git(&self.repo_root, &rel_bytes)));
+                }
+                Err(e) => {
+                    self.done = true;
+                    let _ = self.child.kill();
+                    let _ = self.child.wait();
+                    return Some(Err(format!("failed to read git ls-files output: {}", e)));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Drop for TrackedFiles {
+    // Stopping early must not leave git blocked on a full pipe or unreaped
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// A listed file and its contents, or the error reading them.
+pub type ReadFile = (PathBuf, std::io::Result<String>);
+
+/// Reads the next files of a listing on a tokio runtime while the current one is indexed,
+/// so slow (e.g. network) filesystems are not waited on one file at a time.
+///
+/// Files come out in listing order. Only files in a supported language are read; others
+/// yield empty contents. A listing error is passed through once the files before it are out.
+pub struct ReadAhead<I> {
+    files: I,
+    window: usize,
+    runtime: tokio::runtime::Runtime,
+    /// Submitted reads in listing order
+    queued: VecDeque<(PathBuf, tokio::task::JoinHandle<std::io::Result<String>>)>,
+    listing_error: Option<String>,
+    listing_done: bool,
+}
+
+impl<I: Iterator<Item = Result<PathBuf, String>>> ReadAhead<I> {
+    /// Read up to `workers` files at once, and at most twice that many ahead.
+    pub fn new(files: I, workers: usize) -> Self {
+        let workers = workers.max(1);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .max_blocking_threads(workers)
+            .thread_name("cearch-read")
+            .build()
+            .expect("failed to start the file reader runtime");
+        Self {
+            files,
+            window: workers * 2,
+            runtime,
+            queued: VecDeque::new(),
+            listing_error: None,
+            listing_done: false,
+        }
+    }
+
+    /// Submit listed files until the window is full or the listing ends.
+    fn fill(&mut self) {
+        while !self.listing_done && self.queued.len() < self.window {
+            match self.files.next() {
+                Some(Ok(path)) => {
+                    let read = path.clone();
+                    let task = self.runtime.spawn(async move {
+                        if crate::symbols::language_name(&read).is_some() {
+                            tokio::fs::read_to_string(&read).await
+                        } else {
+                            Ok(String::new())
+                        }
+                    });
+                    self.queued.push_back((path, task));
+                }
+                // Nothing after an error is read
+                Some(Err(err)) => {
+                    self.listing_error = Some(err);
+                    self.listing_done = true;
+                }
+                None => self.listing_done = true,
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<PathBuf, String>>> Iterator for ReadAhead<I> {
+    type Item = Result<ReadFile, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fill();
+        let Some((path, task)) = self.queued.pop_front() else {
+            return self.listing_error.take().map(Err);
+        };
+        let contents = self
+            .runtime
+            .block_on(task)
+            .unwrap_or_else(|err| Err(std::io::Error::other(err)));
+        Some(Ok((path, contents)))
+    }
+}
+
+/// Order files oldest-modified first; files whose metadata can't be read sort first.
+///
+/// The sort is stable, so files with equal timestamps keep their `git ls-files` order.
+pub fn sort_by_modification_time(files: &mut [PathBuf]) {
+    files.sort_by_cached_key(|f| {
+        std::fs::metadata(f)
+            .and_then(|m| m.modified())
+            .unwrap_or(std::time::SystemTime::UNIX_EPOCH)
+    });
+}
+
+/// Full SHA of the commit checked out at `repo_root`.
+pub fn head_commit(repo_root: impl AsRef<Path>) -> Result<String, String> {
+    let output = GitEnv::from_env()
+        .command(repo_root.as_ref())
+        .args(["rev-parse", "--verify", "HEAD"])
+        .stderr(Stdio::null())
         .output()
         .map_err(|e| format!("failed to invoke git: {}", e))?;
+    if !output.status.success() {
+        return Err("git rev-parse HEAD failed; does the repository have a commit yet?".into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
+/// Whether tracked files at `repo_root` have changes not committed yet; untracked files,
+/// which are never indexed, do not count.
+pub fn worktree_dirty(repo_root: impl AsRef<Path>) -> Result<bool, String> {
+    let output = GitEnv::from_env()
+        .command(repo_root.as_ref())
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to invoke git: {}", e))?;
     if !output.status.success() {
-        return Err(format!("git ls-files failed with status {}", output.status));
+        return Err(format!("git status failed with status {}", output.status));
     }
+    Ok(!output.stdout.is_empty())
+}
+
+/// URL of the remote named `remote`, if the repository has one.
+pub fn remote_url(repo_root: impl AsRef<Path>, remote: &str) -> Option<String> {
+    output_line(
+        GitEnv::from_env()
+            .command(repo_root.as_ref())
+            .args(["remote", "get-url", remote]),
+    )
+    .map(|url| String::from_utf8_lossy(&url).into_owned())
+}
+
+/// Name of the branch checked out at `repo_root`; `None` when `HEAD` is detached.
+pub fn current_branch(repo_root: impl AsRef<Path>) -> Option<String> {
+    output_line(GitEnv::from_env().command(repo_root.as_ref()).args([
+        "symbolic-ref",
+        "--quiet",
+        "--short",
+        "HEAD",
+    ]))
+    .map(|name| String::from_utf8_lossy(&name).into_owned())
+}
+
+/// Repo-relative, `/`-separated paths of the tracked files whose contents in the working
+/// tree differ from `commit`, committed or not.
+pub fn paths_changed_since(
+    repo_root: impl AsRef<Path>,
+    commit: &str,
+) -> Result<HashSet<String>, String> {
+    let mut command = GitEnv::from_env().command(repo_root.as_ref());
+    command.args(["diff", "--name-only", "-z", "--no-renames", commit, "--"]);
+    Ok(split_paths(&git_output(command, "git diff")?))
+}
+
+/// Repo-relative, `/`-separated paths of the files in `commit`.
+pub fn paths_at_commit(
+    repo_root: impl AsRef<Path>,
+    commit: &str,
+) -> Result<HashSet<String>, String> {
+    let mut command = GitEnv::from_env().command(repo_root.as_ref());
+    command.args(["ls-tree", "-r", "-z", "--name-only", commit]);
+    Ok(split_paths(&git_output(command, "git ls-tree")?))
+}
+
+fn split_paths(output: &[u8]) -> HashSet<String> {
+    output
+        .split(|b| *b == 0)
+        .filter(|rel| !rel.is_empty())
+        .map(|rel| String::from_utf8_lossy(rel).into_owned())
+        .collect()
+}
 
-    let mut files = Vec::new();
-    for rel_bytes in output.stdout.split(|b| *b == 0) {
-        if rel_bytes.is_empty() {
+/// Absolute paths of files that differ between `commit` and `HEAD`, including deleted ones.
+///
+/// This invokes `git diff --name-only` between the two commits, so edits that are not
+/// committed yet are not reported. Renames are listed as a deletion plus an addition.
+pub fn files_changed_since(
+    repo_root: impl AsRef<Path>,
+    commit: &str,
+) -> Result<Vec<PathBuf>, String> {
+    let repo_root = repo_root.as_ref();
+    let output = GitEnv::from_env()
+        .command(repo_root)
+        .args([
+            "diff",
+            "--name-only",
+            "-z",
+            "--no-renames",
+            commit,
+            "HEAD",
+            "--",
+        ])
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to invoke git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git diff against {} failed with status {}",
+            commit, output.status
+        ));
+    }
+    Ok(output
+        .stdout
+        .split(|b| *b == 0)
+        .filter(|rel| !rel.is_empty())
+        .map(|rel| native_from_git(repo_root, rel))
+        .collect())
+}
+
+/// A version of a file that no longer exists at `HEAD`, found by `historical_blobs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoricalBlob {
+    /// Object id of the file's contents
+    pub blob: String,
+    /// Absolute path the file had
+    pub path: PathBuf,
+    /// Full hash of a commit holding this version
+    pub commit: String,
+}
+
+/// Object id git prints for the missing side of an addition or deletion.
+const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
+/// Run `command` and return its standard output, or an error naming `what` failed.
+fn git_output(mut command: Command, what: &str) -> Result<Vec<u8>, String> {
+    let output = command
+        .output()
+        .map_err(|e| format!("failed to invoke git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Versions of regular files touched by the commits `range` selects (`git log` options such
+/// as `-n 20` or `--since=2024-01-01`, along `HEAD`'s first parents) that `HEAD` no longer
+/// has: both what a commit wrote and what it replaced or deleted, renames followed. Newest
+/// first, each blob once.
+pub fn historical_blobs(
+    repo_root: impl AsRef<Path>,
+    range: &[String],
+) -> Result<Vec<HistoricalBlob>, String> {
+    let repo_root = repo_root.as_ref();
+    let env = GitEnv::from_env();
+    let mut command = env.command(repo_root);
+    command.args(["ls-tree", "-r", "-z", "HEAD"]);
+    let tree = git_output(command, "git ls-tree HEAD")?;
+    // `<mode> <type> <oid>\t<path>`
+    let at_head: HashSet<&[u8]> = tree
+        .split(|b| *b == 0)
+        .filter_map(|entry| entry.split(|b| *b == b'\t').next())
+        .filter_map(|meta| meta.split(|b| *b == b' ').nth(2))
+        .collect();
+
+    let mut command = env.command(repo_root);
+    command
+        .args(["log", "--first-parent", "-M", "--raw", "--no-abbrev", "-z"])
+        .arg("--format=%x01%H %P")
+        .args(range)
+        .args(["HEAD", "--"]);
+    let log = git_output(command, "git log")?;
+
+    let mut blobs = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let (mut commit, mut parent) = (String::new(), None);
+    let mut tokens = log
+        .split(|b| *b == 0)
+        .map(|t| t.strip_prefix(b"\n").unwrap_or(t));
+    while let Some(token) = tokens.next() {
+        if let Some(header) = token.strip_prefix(b"\x01") {
+            // `<hash> <first parent> ...`, with no parents for a root commit
+            let header = String::from_utf8_lossy(header);
+            let mut hashes = header.split_whitespace().map(str::to_string);
+            commit = hashes.next().unwrap_or_default();
+            parent = hashes.next();
+            continue;
+        }
+        // `:<old mode> <new mode> <old oid> <new oid> <status>`, then one path or two
+        let Some(meta) = token.strip_prefix(b":") else {
+            continue;
+        };
+        let meta = String::from_utf8_lossy(meta);
+        let fields: Vec<&str> = meta.split(' ').collect();
+        let [old_mode, new_mode, old_oid, new_oid, status] = fields[..] else {
+            return Err(format!("unexpected git log output: {}", meta));
+        };
+        let old_path = tokens.next().unwrap_or_default();
+        let new_path = if status.starts_with(['R', 'C']) {
+            tokens.next().unwrap_or_default()
+        } else {
+            old_path
+        };
+        // What the commit wrote, then what was there before it
+        let sides = [
+            (new_mode, new_oid, new_path, Some(commit.as_str())),
+            (old_mode, old_oid, old_path, parent.as_deref()),
+        ];
+        for (mode, oid, path, holder) in sides {
+            let Some(holder) = holder else { continue };
+            let regular = mode == "100644" || mode == "100755";
+            if !regular || oid == NULL_OID || at_head.contains(oid.as_bytes()) {
+                continue;
+            }
+            if seen.insert(oid.to_string()) {
+                blobs.push(HistoricalBlob {
+                    blob: oid.to_string(),
+                    path: native_from_git(repo_root, path),
+                    commit: holder.to_string(),
+                });
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+/// The contents of the blob `oid`, which must be UTF-8 text.
+pub fn read_blob(repo_root: impl AsRef<Path>, oid: &str) -> Result<String, String> {
+    let mut command = GitEnv::from_env().command(repo_root.as_ref());
+    command.args(["cat-file", "blob", oid]);
+    let contents = git_output(command, &format!("git cat-file {}", oid))?;
+    String::from_utf8(contents).map_err(|_| format!("blob {} is not UTF-8 text", oid))
+}
+
+/// Who last changed a line, from `git blame` (`query --blame`).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Blame {
+    pub author: String,
+    pub email: String,
+    /// Full hash of the commit that last changed the line
+    pub commit: String,
+    /// Author time, in seconds since the Unix epoch
+    pub time: u64,
+}
+
+/// Blame of `lines` of the repo-relative `path` as it is in the work tree, from one
+/// `git blame` run. Lines not committed yet are left out.
+fn blame_lines(
+    repo_root: impl AsRef<Path>,
+    path: &str,
+    lines: &[usize],
+) -> Result<HashMap<usize, Blame>, String> {
+    let mut command = GitEnv::from_env().command(repo_root.as_ref());
+    command.args(["blame", "--porcelain"]);
+    for line in lines {
+        command.arg(format!("-L{},{}", line, line));
+    }
+    command.args(["--", path]);
+    let output = git_output(command, &format!("git blame {}", path))?;
+    Ok(parse_blame(&String::from_utf8_lossy(&output)))
+}
+
+/// Blame by final line number from `git blame --porcelain` output, which describes each
+/// commit in full only the first time it appears.
+fn parse_blame(porcelain: &str) -> HashMap<usize, Blame> {
+    let mut commits: HashMap<String, Blame> = HashMap::new();
+    let mut found = HashMap::new();
+    let mut current: Option<(String, usize)> = None;
+    for line in porcelain.lines() {
+        if line.starts_with('\t') {
+            // The line's contents end its entry
+            if let Some((commit, line)) = current.take()
+                && commit != NULL_OID
+                && let Some(blame) = commits.get(&commit)
+            {
+                found.insert(line, blame.clone());
+            }
+            continue;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        if current.is_none() {
+            // `<commit> <original line> <final line> [<lines in group>]`
+            let final_line = value.split(' ').nth(1).and_then(|n| n.parse().ok());
+            if let Some(final_line) = final_line {
+                current = Some((key.to_string(), final_line));
+                commits.entry(key.to_string()).or_insert_with(|| Blame {
+                    author: String::new(),
+                    email: String::new(),
+                    commit: key.to_string(),
+                    time: 0,
+                });
+            }
+            continue;
+        }
+        let Some(blame) = current.as_ref().and_then(|(c, _)| commits.get_mut(c)) else {
             continue;
+        };
+        match key {
+            "author" => blame.author = value.to_string(),
+            "author-mail" => {
+                blame.email = value
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            }
+            "author-time" => blame.time = value.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+    found
+}
+
+/// `git blame` results kept for one invocation, so each file and line is looked up once.
+pub struct BlameCache {
+    repo_root: PathBuf,
+    lines: HashMap<(String, usize), Option<Blame>>,
+}
+
+impl BlameCache {
+    pub fn new(repo_root: &Path) -> Self {
+        Self {
+            repo_root: repo_root.to_path_buf(),
+            lines: HashMap::new(),
+        }
+    }
+
+    /// Look up the `(repo-relative path, line)` pairs not seen yet, one `git blame` per file.
+    /// Files git cannot blame (e.g. untracked ones) get no blame.
+    pub fn fill(&mut self, wanted: &[(&str, usize)]) {
+        let mut by_file: HashMap<&str, Vec<usize>> = HashMap::new();
+        for &(path, line) in wanted {
+            if !self.lines.contains_key(&(path.to_string(), line)) {
+                by_file.entry(path).or_default().push(line);
+            }
+        }
+        for (path, mut lines) in by_file {
+            lines.sort_unstable();
+            lines.dedup();
+            let mut found = blame_lines(&self.repo_root, path, &lines).unwrap_or_else(|err| {
+                tracing::debug!("no blame for {}: {}", path, err);
+                HashMap::new()
+            });
+            for line in lines {
+                self.lines
+                    .insert((path.to_string(), line), found.remove(&line));
+            }
         }
-        let rel_str = String::from_utf8_lossy(rel_bytes);
-        let rel_path = PathBuf::from(rel_str.as_ref());
-        files.push(repo_root.join(rel_path));
     }
 
-    Ok(files)
+    /// The blame of `line` of `path`, once `fill` has looked it up.
+    pub fn get(&self, path: &str, line: usize) -> Option<&Blame> {
+        self.lines.get(&(path.to_string(), line))?.as_ref()
+    }
 }
 
 // Re-export for external callers
@@ -79,8 +736,50 @@ pub fn list_git_tracked_files(repo_root: impl AsRef<Path>) -> Result<Vec<PathBuf
 
 #[cfg(test)]
 mod tests {
-    use super::find_git_root;
-    use std::path::PathBuf;
+    use super::*;
+
+    #[test]
+    fn read_ahead_keeps_listing_order_and_errors() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut listing = Vec::new();
+        for i in 0..20 {
+            let path = dir.path().join(format!("f{}.py", i));
+            std::fs::write(&path, format!("def f{}(): pass\n", i)).unwrap();
+            listing.push(Ok(path));
+        }
+        listing.insert(3, Ok(dir.path().join("missing.py")));
+        listing.insert(4, Ok(dir.path().join("notes.txt")));
+        listing.push(Err("git ls-files failed".to_string()));
+        listing.push(Ok(dir.path().join("after-error.py")));
+
+        let read: Vec<Result<ReadFile, String>> =
+            ReadAhead::new(listing.clone().into_iter(), 3).collect();
+        assert_eq!(read.len(), 23);
+        for (got, want) in read.iter().zip(&listing) {
+            match (got, want) {
+                (Ok((path, _)), Ok(want)) => assert_eq!(path, want),
+                (Err(err), Err(want)) => assert_eq!(err, want),
+                _ => panic!("out of order: {:?}", got.as_ref().map(|(p, _)| p)),
+            }
+        }
+        let (_, missing) = read[3].as_ref().unwrap();
+        assert!(missing.is_err());
+        // Unsupported files are not read
+        let (_, notes) = read[4].as_ref().unwrap();
+        assert_eq!(notes.as_ref().unwrap(), "");
+        let (_, f5) = read[10].as_ref().unwrap();
+        assert_eq!(f5.as_ref().unwrap(), "def f8(): pass\n");
+    }
+
+    #[test]
+    fn git_is_resolved_on_path() {
+        let program = git_program();
+        // git.exe on Windows, or a git.cmd shim; plain git elsewhere
+        assert!(program.is_absolute(), "{}", program.display());
+        assert_eq!(program.file_stem(), Some("git".as_ref()));
+        assert!(std::ptr::eq(program, git_program()));
+        assert!(git_version().is_some());
+    }
 
     #[test]
     fn returns_none_for_non_repo_paths() {
@@ -101,4 +800,316 @@ mod tests {
 
         assert!(find_git_root(&root).is_none());
     }
+
+    #[test]
+    fn streams_tracked_files_in_git_order() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(root)
+                .args(args)
+                .stdout(Stdio::null())
+                .status()
+                .expect("run git");
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        std::fs::create_dir(root.join("src")).unwrap();
+        for f in ["b.py", "src/a.rs", "with space.py"] {
+            std::fs::write(root.join(f), "").unwrap();
+        }
+        git(&["add", "."]);
+
+        let streamed: Vec<PathBuf> = stream_git_tracked_files(root)
+            .expect("spawn")
+            .collect::<Result<_, _>>()
+            .expect("listing");
+        assert_eq!(
+            streamed,
+            vec![
+                root.join("b.py"),
+                crate::paths::to_native(root, "src/a.rs"),
+                root.join("with space.py")
+            ]
+        );
+        assert_eq!(list_git_tracked_files(root).unwrap(), streamed);
+    }
+
+    #[test]
+    fn lists_files_changed_between_commits() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(root)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .stdout(Stdio::null())
+                .status()
+                .expect("run git");
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        assert!(head_commit(root).is_err());
+        for f in ["a.py", "b.py", "c.py"] {
+            std::fs::write(root.join(f), f).unwrap();
+        }
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "one"]);
+        let first = head_commit(root).unwrap();
+        assert_eq!(first.len(), 40);
+
+        std::fs::write(root.join("a.py"), "changed").unwrap();
+        git(&["rm", "-q", "b.py"]);
+        git(&["commit", "-q", "-am", "two"]);
+        std::fs::write(root.join("untracked.py"), "new").unwrap();
+        assert_eq!(worktree_dirty(root), Ok(false));
+        // Uncommitted edits are not part of the diff
+        std::fs::write(root.join("c.py"), "dirty").unwrap();
+        assert_eq!(worktree_dirty(root), Ok(true));
+
+        let changed = files_changed_since(root, &first).unwrap();
+        assert_eq!(changed, vec![root.join("a.py"), root.join("b.py")]);
+        assert!(files_changed_since(root, "0000000000000000000000000000000000000000").is_err());
+
+        // Against the working tree, uncommitted edits count too
+        let set = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect::<HashSet<_>>();
+        assert_eq!(
+            paths_changed_since(root, &first).unwrap(),
+            set(&["a.py", "b.py", "c.py"])
+        );
+        assert_eq!(
+            paths_at_commit(root, &first).unwrap(),
+            set(&["a.py", "b.py", "c.py"])
+        );
+        assert_eq!(
+            paths_at_commit(root, "HEAD").unwrap(),
+            set(&["a.py", "c.py"])
+        );
+
+        assert_eq!(remote_url(root, "origin"), None);
+        git(&["remote", "add", "origin", "git@github.com:org/repo.git"]);
+        assert_eq!(
+            remote_url(root, "origin").as_deref(),
+            Some("git@github.com:org/repo.git")
+        );
+        git(&["checkout", "-q", "-b", "topic/x"]);
+        assert_eq!(current_branch(root).as_deref(), Some("topic/x"));
+        git(&["checkout", "-q", "--detach"]);
+        assert_eq!(current_branch(root), None);
+    }
+
+    #[test]
+    fn history_yields_versions_head_no_longer_has() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(root)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .stdout(Stdio::null())
+                .status()
+                .expect("run git");
+            assert!(status.success(), "git {:?}", args);
+        };
+        let retry = "def with_retry(f):\n    return f()\n";
+        git(&["init", "-q"]);
+        std::fs::write(root.join("retry.py"), retry).unwrap();
+        std::fs::write(root.join("kept.py"), "def kept():\n    pass\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "one"]);
+        // Renamed, then deleted: the function only lives on in history
+        git(&["mv", "retry.py", "util.py"]);
+        git(&["commit", "-q", "-m", "two"]);
+        let second = head_commit(root).unwrap();
+        git(&["rm", "-q", "util.py"]);
+        git(&["commit", "-q", "-m", "three"]);
+
+        let blobs = historical_blobs(root, &[]).unwrap();
+        assert_eq!(blobs.len(), 1, "{:?}", blobs);
+        // Newest first: the version the deletion removed, at the commit before it
+        assert_eq!(blobs[0].path, root.join("util.py"));
+        assert_eq!(blobs[0].commit, second);
+        assert_eq!(read_blob(root, &blobs[0].blob).unwrap(), retry);
+
+        // Only the last commit, which deleted the file, is walked
+        let recent = historical_blobs(root, &["--max-count=1".to_string()]).unwrap();
+        assert_eq!(recent, blobs);
+        assert!(read_blob(root, NULL_OID).is_err());
+    }
+
+    #[test]
+    fn blame_names_the_last_author_of_each_line() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let git = |author: &str, args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(root)
+                .args(["-c", &format!("user.name={}", author)])
+                .args(["-c", &format!("user.email={}@example.com", author)])
+                .args(args)
+                .stdout(Stdio::null())
+                .status()
+                .expect("run git");
+            assert!(status.success(), "git {:?}", args);
+        };
+        git("alice", &["init", "-q"]);
+        std::fs::write(root.join("a.py"), "def one():\n    pass\n").unwrap();
+        git("alice", &["add", "."]);
+        git("alice", &["commit", "-q", "-m", "one"]);
+        let first = head_commit(root).unwrap();
+        std::fs::write(
+            root.join("a.py"),
+            "def one():\n    pass\n\ndef two():\n    pass\n",
+        )
+        .unwrap();
+        git("bob", &["commit", "-q", "-am", "two"]);
+        let second = head_commit(root).unwrap();
+        // Dirty and untracked files degrade to no blame for what is not committed
+        std::fs::write(
+            root.join("a.py"),
+            "def one():\n    pass\n\ndef two():\n    pass\n\ndef three():\n    pass\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("new.py"), "def new():\n    pass\n").unwrap();
+
+        let mut cache = BlameCache::new(root);
+        cache.fill(&[
+            ("a.py", 1),
+            ("a.py", 4),
+            ("a.py", 7),
+            ("new.py", 1),
+            ("a.py", 1),
+        ]);
+        let one = cache.get("a.py", 1).expect("blame of line 1");
+        assert_eq!(
+            (one.author.as_str(), one.email.as_str()),
+            ("alice", "alice@example.com")
+        );
+        assert_eq!(one.commit, first);
+        assert!(one.time > 0);
+        let two = cache.get("a.py", 4).expect("blame of line 4");
+        assert_eq!(
+            (two.author.as_str(), two.commit.as_str()),
+            ("bob", second.as_str())
+        );
+        assert_eq!(cache.get("a.py", 7), None);
+        assert_eq!(cache.get("new.py", 1), None);
+        assert_eq!(cache.get("a.py", 2), None);
+    }
+
+    #[test]
+    fn unicode_file_names_survive_quotepath() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path().join("répo 🦀");
+        std::fs::create_dir(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&root)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .stdout(Stdio::null())
+                .status()
+                .expect("run git");
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        // Escaping is what a plain `git` call would do with this setting
+        git(&["config", "core.quotepath", "true"]);
+        std::fs::create_dir(root.join("naïve")).unwrap();
+        for f in ["café.rs", "🦀.rs", "naïve/ünïcode.py"] {
+            std::fs::write(root.join(f), "fn a() {}").unwrap();
+        }
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "one"]);
+
+        assert_eq!(repo_root(root.join("naïve")), Some(root.clone()));
+        let listed = list_git_tracked_files(&root).unwrap();
+        assert_eq!(listed.len(), 3);
+        assert!(listed.iter().all(|f| f.is_file()), "{:?}", listed);
+        assert!(listed.contains(&root.join("naïve").join("ünïcode.py")));
+
+        let first = head_commit(&root).unwrap();
+        std::fs::write(root.join("🦀.rs"), "fn b() {}").unwrap();
+        git(&["commit", "-q", "-am", "two"]);
+        assert_eq!(
+            files_changed_since(&root, &first).unwrap(),
+            vec![root.join("🦀.rs")]
+        );
+    }
+
+    #[test]
+    fn git_dir_and_work_tree_overrides_decide_the_root() {
+        let tree = tempfile::tempdir().expect("tempdir");
+        let store = tempfile::tempdir().expect("tempdir");
+        let elsewhere = tempfile::tempdir().expect("tempdir");
+        let root = tree.path().canonicalize().unwrap();
+        let git_dir = store.path().join("repo.git");
+        let env = GitEnv::from_vars(elsewhere.path(), |name| match name {
+            "GIT_DIR" => Some(git_dir.clone().into()),
+            "GIT_WORK_TREE" => Some(root.clone().into()),
+            _ => None,
+        });
+        assert_eq!(env.git_dir.as_deref(), Some(git_dir.as_path()));
+
+        let status = Command::new("git")
+            .args(["init", "-q", "--separate-git-dir"])
+            .arg(&git_dir)
+            .arg(&root)
+            .status()
+            .expect("run git");
+        assert!(status.success());
+        // Leave no `.git` in the tree, as deployment layouts do
+        std::fs::remove_file(root.join(".git")).unwrap();
+        std::fs::write(root.join("a.py"), "").unwrap();
+        let status = env
+            .command(&root)
+            .args(["add", "a.py"])
+            .status()
+            .expect("run git");
+        assert!(status.success());
+
+        assert_eq!(find_git_root(elsewhere.path()), None);
+        assert_eq!(
+            resolve_repo_root(elsewhere.path(), &env),
+            Some(root.clone())
+        );
+        let mut command = env.command(&root);
+        command.args(["ls-files", "-z"]);
+        let listed: Vec<PathBuf> = TrackedFiles::spawn(&root, command)
+            .expect("spawn")
+            .collect::<Result<_, _>>()
+            .expect("listing");
+        assert_eq!(listed, vec![root.join("a.py")]);
+
+        // Relative values are taken from the directory they were set in
+        let relative = GitEnv::from_vars(Path::new("/work"), |name| {
+            (name == "GIT_DIR").then(|| "repo.git".into())
+        });
+        assert_eq!(relative.git_dir, Some(PathBuf::from("/work/repo.git")));
+        assert_eq!(relative.work_tree, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failure_after_partial_output_is_reported_last() {
+        let root = Path::new("/repo");
+        let mut command = Command::new("sh");
+        command.args(["-c", "printf 'a.py\\0b.py\\0'; exit 3"]);
+        let items: Vec<Result<PathBuf, String>> =
+            TrackedFiles::spawn(root, command).expect("spawn").collect();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0], Ok(root.join("a.py")));
+        assert_eq!(items[1], Ok(root.join("b.py")));
+        let err = items[2].as_ref().unwrap_err();
+        assert!(err.contains("failed with status"), "{}", err);
+    }
 }
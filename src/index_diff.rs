@@ -0,0 +1,223 @@
+use crate::db::StoredSymbol;
+use serde::Serialize;
+use std::collections::HashMap;
+// Index diff module compares the symbols of two index snapshots for `cearch diff-index`.
+
+/// A symbol as reported in a diff.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+    pub kind: String,
+    pub name: String,
+}
+
+impl From<&StoredSymbol> for Location {
+    fn from(s: &StoredSymbol) -> Self {
+        Self {
+            path: s.path.clone(),
+            line: s.line,
+            kind: s.kind.clone(),
+            name: s.name.clone(),
+        }
+    }
+}
+
+/// The same symbol in both snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Pair {
+    pub before: Location,
+    pub after: Location,
+}
+
+/// How the symbols of one index differ from another's. A symbol that moved and changed is
+/// listed under both `relocated` and `changed`.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct IndexDiff {
+    pub added: Vec<Location>,
+    pub removed: Vec<Location>,
+    /// Found at another path or line
+    pub relocated: Vec<Pair>,
+    /// Code differs
+    pub changed: Vec<Pair>,
+}
+
+/// Match the symbols of `after` to those of `before` and classify the differences.
+///
+/// Symbols are matched by path, kind and name, nearest line first. The rest are matched by
+/// identical code, which finds symbols moved to another file. Ids are not compared: the two
+/// indexes were built separately, so the same id can name unrelated symbols.
+pub fn diff(before: &[StoredSymbol], after: &[StoredSymbol]) -> IndexDiff {
+    let mut taken = vec![false; before.len()];
+    let mut matched: Vec<Option<usize>> = vec![None; after.len()];
+
+    let mut by_key: HashMap<(&str, &str, &str), Vec<usize>> = HashMap::new();
+    for (b, s) in before.iter().enumerate() {
+        by_key
+            .entry((&s.path, &s.kind, &s.name))
+            .or_default()
+            .push(b);
+    }
+    for (a, symbol) in after.iter().enumerate() {
+        let Some(candidates) = by_key.get_mut(&(&symbol.path, &symbol.kind, &symbol.name)) else {
+            continue;
+        };
+        if let Some((i, _)) = candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &b)| before[b].line.abs_diff(symbol.line))
+        {
+            let b = candidates.swap_remove(i);
+            taken[b] = true;
+            matched[a] = Some(b);
+        }
+    }
+
+    let mut by_code: HashMap<(&str, blake3::Hash), Vec<usize>> = HashMap::new();
+    for (b, s) in before.iter().enumerate().filter(|&(b, _)| !taken[b]) {
+        by_code
+            .entry((&s.kind, blake3::hash(s.code.as_bytes())))
+            .or_default()
+            .push(b);
+    }
+    for (a, symbol) in after.iter().enumerate() {
+        if matched[a].is_some() {
+            continue;
+        }
+        let key = (symbol.kind.as_str(), blake3::hash(symbol.code.as_bytes()));
+        if let Some(candidates) = by_code.get_mut(&key)
+            && !candidates.is_empty()
+        {
+            let b = candidates.remove(0);
+            taken[b] = true;
+            matched[a] = Some(b);
+        }
+    }
+
+    let mut out = IndexDiff::default();
+    for (symbol, matched) in after.iter().zip(matched) {
+        let Some(b) = matched else {
+            out.added.push(symbol.into());
+            continue;
+        };
+        let old = &before[b];
+        let pair = || Pair {
+            before: old.into(),
+            after: symbol.into(),
+        };
+        if old.path != symbol.path || old.line != symbol.line {
+            out.relocated.push(pair());
+        }
+        if blake3::hash(old.code.as_bytes()) != blake3::hash(symbol.code.as_bytes()) {
+            out.changed.push(pair());
+        }
+    }
+    out.removed = before
+        .iter()
+        .zip(taken)
+        .filter(|(_, taken)| !taken)
+        .map(|(s, _)| s.into())
+        .collect();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{DB, SymbolRecord, WriteOptions};
+
+    fn symbol(id: i64, path: &str, line: usize, name: &str, code: &str) -> StoredSymbol {
+        StoredSymbol {
+            id,
+            path: path.to_string(),
+            line,
+            kind: "fn".to_string(),
+            name: name.to_string(),
+            code: code.to_string(),
+            canonical_rowid: None,
+        }
+    }
+
+    fn names(locations: &[Location]) -> Vec<&str> {
+        locations.iter().map(|l| l.name.as_str()).collect()
+    }
+
+    #[test]
+    fn classifies_matches_by_location_then_code() {
+        let before = [
+            symbol(1, "a.rs", 1, "moved_file", "fn moved_file() {}"),
+            symbol(2, "a.rs", 5, "edited", "fn edited() {}"),
+            symbol(3, "a.rs", 9, "gone", "fn gone() {}"),
+            symbol(4, "b.rs", 1, "shifted", "fn shifted() {}"),
+            symbol(5, "b.rs", 8, "stable", "fn stable() {}"),
+            symbol(6, "d.rs", 1, "helper", "fn helper() { 1 }"),
+        ];
+        let after = [
+            // Ids belong to another index and mean nothing here
+            symbol(1, "c.rs", 3, "moved_file", "fn moved_file() {}"),
+            symbol(6, "a.rs", 5, "edited", "fn edited() { 1 }"),
+            symbol(5, "b.rs", 2, "fresh", "fn fresh() {}"),
+            symbol(9, "b.rs", 4, "shifted", "fn shifted() { 2 }"),
+            symbol(3, "b.rs", 8, "stable", "fn stable() {}"),
+            // Same name in another file with other code: a different symbol
+            symbol(2, "e.rs", 1, "helper", "fn helper() { 2 }"),
+        ];
+        let diff = diff(&before, &after);
+        assert_eq!(names(&diff.added), ["fresh", "helper"]);
+        assert_eq!(names(&diff.removed), ["gone", "helper"]);
+        let relocated: Vec<(&str, usize, &str, usize)> = diff
+            .relocated
+            .iter()
+            .map(|p| {
+                (
+                    p.before.path.as_str(),
+                    p.before.line,
+                    p.after.path.as_str(),
+                    p.after.line,
+                )
+            })
+            .collect();
+        assert_eq!(relocated, [("a.rs", 1, "c.rs", 3), ("b.rs", 1, "b.rs", 4)]);
+        let changed: Vec<&str> = diff.changed.iter().map(|p| p.after.name.as_str()).collect();
+        assert_eq!(changed, ["edited", "shifted"]);
+        assert_eq!(super::diff(&after, &after), IndexDiff::default());
+    }
+
+    #[test]
+    fn compares_index_files() {
+        let dirs = [
+            tempfile::tempdir().expect("tempdir"),
+            tempfile::tempdir().expect("tempdir"),
+        ];
+        let contents: [&[(&str, usize, &str, &str)]; 2] = [
+            &[
+                ("a.rs", 1, "keep", "fn keep() {}"),
+                ("a.rs", 4, "drop", "x"),
+            ],
+            &[("a.rs", 1, "keep", "fn keep() {}"), ("b.rs", 2, "new", "y")],
+        ];
+        for (dir, symbols) in dirs.iter().zip(contents) {
+            let db = DB::open_metadata_only(dir.path(), &WriteOptions::default()).expect("open");
+            let records: Vec<SymbolRecord> = symbols
+                .iter()
+                .map(|&(path, line, name, code)| SymbolRecord {
+                    code,
+                    ..SymbolRecord::fixture(path, line, name, &[])
+                })
+                .collect();
+            db.insert_symbols(&records).unwrap();
+        }
+        let [before, after] = dirs.each_ref().map(|dir| {
+            DB::open_snapshot(&DB::file_path(dir.path()))
+                .and_then(|db| db.stored_symbols())
+                .expect("snapshot")
+        });
+        let diff = diff(&before, &after);
+        assert_eq!(names(&diff.added), ["new"]);
+        assert_eq!(names(&diff.removed), ["drop"]);
+        assert!(diff.relocated.is_empty() && diff.changed.is_empty());
+
+        let missing = dirs[0].path().join("nothing.sqlite");
+        assert!(DB::open_snapshot(&missing).is_err());
+    }
+}
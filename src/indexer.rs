@@ -0,0 +1,1404 @@
+use crate::db::{self, DB};
+use crate::embed::{BatchEmbedder, Tokenizer};
+use crate::paths;
+use crate::plugin::{self, Plugin};
+use crate::progress::{Phase, Progress, RunStats, WarningKind};
+use crate::quarantine::Quarantine;
+use crate::symbols::{self, Symbol, SymbolKind, SymbolStream};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+// Indexer module runs one `cearch index` pass: parse, embed and insert each file, then record the run.
+
+/// Symbols embedded per model call; small enough to keep the per-file bar moving.
+const EMBED_BATCH_SIZE: usize = 64;
+
+/// Settings for an index run that affect what gets stored.
+pub struct Options {
+    /// Maximum number of symbols kept per file
+    pub symbol_limit: Option<usize>,
+    /// Embed identical code once and link copies to it
+    pub symbol_hash_dedup: bool,
+    /// Parse quarantined files instead of skipping them
+    pub retry_quarantined: bool,
+    /// Rebuild the FTS5 keyword index at the end of the run
+    pub build_fts: bool,
+    /// Recorded in the index metadata for `cearch stats`
+    pub sqlite_cache_size_mb: u32,
+    /// Leave out the end-of-run count of files without symbols
+    pub skip_empty_report: bool,
+    /// Embed code without its leading attribute/decorator lines
+    pub annotation_strip: bool,
+    /// Read every inserted embedding back and compare it bit for bit
+    pub verify_embeddings: bool,
+    /// List the file extensions that yielded no symbols at the end of the run
+    pub report_unsupported_extensions: bool,
+    /// Time each file's phases, write them to `.cearch/profile.json` and list the slowest
+    pub profile: bool,
+    /// Size budget of the database (`index.max_size_mb`)
+    pub max_size_bytes: Option<u64>,
+    /// Over the budget, delete the largest symbols instead of stopping
+    pub trim: bool,
+}
+
+/// Files listed as the slowest at the end of a `--profile` run.
+const PROFILE_TOP: usize = 10;
+
+/// Files and symbols seen for one file extension during a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExtensionCounts {
+    pub files: usize,
+    pub symbols: usize,
+}
+
+/// Lines listing the extensions without a single symbol among the files one run visited
+/// (unsupported files are not recorded in the index), most files first, each followed
+/// by a tip when a tree-sitter grammar exists for it. Empty when every extension had one.
+/// Extensions `plugins` handle count as supported.
+pub fn unsupported_extensions_report(
+    counts: &BTreeMap<String, ExtensionCounts>,
+    plugins: &[Plugin],
+) -> Vec<String> {
+    let mut empty: Vec<(&String, usize)> = counts
+        .iter()
+        .filter(|(_, c)| c.symbols == 0)
+        .map(|(ext, c)| (ext, c.files))
+        .collect();
+    if empty.is_empty() {
+        return Vec::new();
+    }
+    empty.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    let mut lines = vec!["file extensions with no symbols extracted in this run:".to_string()];
+    let mut tips = Vec::new();
+    for (ext, files) in empty {
+        let supported = plugin::language_name(plugins, Path::new(&format!("x.{}", ext))).is_some();
+        lines.push(format!(
+            "  .{:<8} {:>6} file{} ({})",
+            ext,
+            files,
+            if files == 1 { "" } else { "s" },
+            if supported {
+                "parsed, but empty"
+            } else {
+                "unsupported"
+            }
+        ));
+        if !supported && let Some((language, krate)) = symbols::grammar_suggestion(ext) {
+            tips.push(format!(
+                "tip: {} support for .{} files: add {} (see `cearch index --languages-file`)",
+                language, ext, krate
+            ));
+        }
+    }
+    lines.extend(tips);
+    lines
+}
+
+fn bitwise_equal(a: &[f32], b: &[f32]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.to_bits() == y.to_bits())
+}
+
+/// Offending paths listed by `strict_failure` before the rest are summarised.
+const STRICT_SHOWN_PATHS: usize = 5;
+
+/// Location of the warnings log written at the end of each run.
+pub fn log_path(repo_root: &Path) -> PathBuf {
+    repo_root.join(".cearch").join("index.log")
+}
+
+/// Per-file timings of the last `cearch index --profile` run.
+pub fn profile_path(repo_root: &Path) -> PathBuf {
+    repo_root.join(".cearch").join("profile.json")
+}
+
+/// Error message for `--strict` when any file failed to parse, embed or insert.
+pub fn strict_failure(stats: &RunStats, log: &Path) -> Option<String> {
+    let failed = stats.failed_paths();
+    if failed.is_empty() {
+        return None;
+    }
+    let shown: Vec<String> = failed
+        .iter()
+        .take(STRICT_SHOWN_PATHS)
+        .map(|p| p.display().to_string())
+        .collect();
+    let more = match failed.len().saturating_sub(STRICT_SHOWN_PATHS) {
+        0 => String::new(),
+        n => format!(" and {} more", n),
+    };
+    Some(format!(
+        "{} file{} failed: {}{} (see {})",
+        failed.len(),
+        if failed.len() == 1 { "" } else { "s" },
+        shown.join(", "),
+        more,
+        log.display()
+    ))
+}
+
+fn symbol_kind_str(kind: &SymbolKind) -> &'static str {
+    match kind {
+        SymbolKind::Function => "fn",
+        SymbolKind::Class => "class",
+        SymbolKind::Constant => "const",
+    }
+}
+
+/// State for a single index run over a repository.
+pub struct Indexer<'a> {
+    root: &'a Path,
+    db: &'a DB,
+    progress: &'a Progress,
+    opts: &'a Options,
+    pub stats: RunStats,
+    quarantine: Quarantine,
+    quarantined_skipped: usize,
+    truncated_files: usize,
+    /// Supported files that yielded no symbols this run
+    empty_files: usize,
+    /// Files and symbols per file extension, for `--report-unsupported-extensions`
+    extensions: BTreeMap<String, ExtensionCounts>,
+    /// Code hash -> rowid of its canonical symbol (None if that insert failed)
+    seen_code: HashMap<blake3::Hash, Option<i64>>,
+    /// Stores each embedded symbol's token ids when set (`--pre-tokenize`)
+    tokenizer: Option<Tokenizer>,
+    /// Bytes in use once the size budget was exceeded; the run should stop
+    over_budget: Option<u64>,
+    /// Directory whose files are stored under a label instead of relative to the repository
+    stored_under: Option<(PathBuf, String)>,
+    /// Code hashes of symbols stored already, in this index or another; matching symbols are
+    /// skipped (`index --history`)
+    known_code: Option<HashSet<[u8; 32]>>,
+    /// External extractors, consulted before the built-in parsers (`[plugins.<name>]`)
+    plugins: Vec<Plugin>,
+}
+
+impl<'a> Indexer<'a> {
+    pub fn new(
+        root: &'a Path,
+        db: &'a DB,
+        progress: &'a Progress,
+        opts: &'a Options,
+        files_total: Option<usize>,
+    ) -> Self {
+        let mut stats = RunStats::new(files_total);
+        if opts.profile {
+            stats.enable_profile();
+        }
+        // Files whose parse panicked on an earlier run are skipped unless retried
+        let quarantine = Quarantine::load(root).unwrap_or_else(|err| {
+            progress.warn(
+                &mut stats,
+                WarningKind::Other,
+                None,
+                &format!("{}; starting with an empty quarantine", err),
+            );
+            Quarantine::empty(root)
+        });
+        Self {
+            root,
+            db,
+            progress,
+            opts,
+            stats,
+            quarantine,
+            quarantined_skipped: 0,
+            truncated_files: 0,
+            empty_files: 0,
+            extensions: BTreeMap::new(),
+            seen_code: HashMap::new(),
+            tokenizer: None,
+            over_budget: None,
+            stored_under: None,
+            known_code: None,
+            plugins: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, kind: WarningKind, path: Option<&Path>, msg: &str) {
+        self.progress.warn(&mut self.stats, kind, path, msg);
+    }
+
+    /// Parse, embed and insert one file, recording any problems as warnings.
+    ///
+    /// `source` is the file's contents when they were read ahead (`--parallel-files`);
+    /// otherwise the file is read here. Without an embedder (`--metadata-only`) symbols are
+    /// inserted with empty vectors, which a metadata-only index ignores.
+    pub fn index_file(
+        &mut self,
+        f: &Path,
+        source: Option<std::io::Result<String>>,
+        embedder: Option<&mut BatchEmbedder>,
+    ) {
+        let started = Instant::now();
+        let stored_path = self.stored_path(f);
+        let _span = tracing::info_span!("index_file", path = %stored_path).entered();
+        let language = plugin::language_name(&self.plugins, f);
+        if let Some(language) = language {
+            self.stats.add_language_file(language);
+        }
+        let before = self.stats.symbols;
+        if let Some(symbols_in_file) = self.extract(f, &stored_path, source) {
+            self.embed_and_insert(f, &stored_path, symbols_in_file, embedder);
+            if let Some(language) = language {
+                self.stats
+                    .add_language_symbols(language, self.stats.symbols - before);
+            }
+        } else if let Err(err) = self.db.replace_file_symbols(&stored_path, &[]) {
+            // Nothing to index this time; what an earlier run stored for the file goes
+            self.warn(
+                WarningKind::Insert,
+                Some(f),
+                &format!("failed to remove old symbols of {}: {}", f.display(), err),
+            );
+        }
+        if let Some(ext) = f.extension().and_then(|e| e.to_str()) {
+            let counts = self.extensions.entry(ext.to_ascii_lowercase()).or_default();
+            counts.files += 1;
+            counts.symbols += self.stats.symbols - before;
+        }
+        // Same measure as the precount, so the byte totals line up
+        let bytes = std::fs::metadata(f).map(|m| m.len()).unwrap_or(0);
+        let symbols = self.stats.symbols - before;
+        tracing::debug!(symbols, bytes, "indexed {}", stored_path);
+        self.stats
+            .profile_file_done(&stored_path, bytes, symbols, started.elapsed());
+        self.stats.file_done(bytes, started.elapsed());
+        self.check_size_budget();
+        self.progress.report(&self.stats);
+    }
+
+    /// Bytes the database used when it went over its size budget, if it did.
+    pub fn over_budget(&self) -> Option<u64> {
+        self.over_budget
+    }
+
+    /// Hold the database to `max_size_bytes`: with `trim` the largest symbols go, otherwise
+    /// (or when trimming falls short) the run is marked as over budget.
+    fn check_size_budget(&mut self) {
+        let Some(max) = self.opts.max_size_bytes else {
+            return;
+        };
+        let mut used = match self.db.used_bytes() {
+            Ok(used) => used,
+            Err(err) => {
+                self.warn(
+                    WarningKind::Other,
+                    None,
+                    &format!("failed to measure the index size: {}", err),
+                );
+                return;
+            }
+        };
+        if used <= max {
+            return;
+        }
+        if self.opts.trim {
+            // Headroom, so the next few files do not trim again
+            match self
+                .db
+                .trim_to(max / 10 * 9)
+                .and_then(|removed| self.db.used_bytes().map(|used| (removed, used)))
+            {
+                Ok((removed, now)) => {
+                    used = now;
+                    self.warn(
+                        WarningKind::Skip,
+                        None,
+                        &format!(
+                            "trimmed the {} largest symbols to keep the index within {} MB",
+                            removed,
+                            max / (1024 * 1024)
+                        ),
+                    );
+                }
+                Err(err) => self.warn(
+                    WarningKind::Other,
+                    None,
+                    &format!("failed to trim the index: {}", err),
+                ),
+            }
+            if used <= max {
+                return;
+            }
+        }
+        self.over_budget = Some(used);
+    }
+
+    /// Record the number of files once a streamed listing has finished.
+    pub fn set_files_total(&mut self, files_total: usize) {
+        self.stats.files_total = Some(files_total);
+        self.progress.set_total(&self.stats);
+    }
+
+    /// Store files under `dir` as `<label>/<path within dir>` from now on, e.g. a dependency's
+    /// sources for `index --deps`.
+    pub fn store_paths_under(&mut self, dir: &Path, label: &str) {
+        self.stored_under = Some((dir.to_path_buf(), label.to_string()));
+    }
+
+    /// Skip symbols whose code hashes to one of `hashes` from now on, as well as repeats of
+    /// code this run stores.
+    pub fn skip_known_code(&mut self, hashes: HashSet<[u8; 32]>) {
+        self.known_code = Some(hashes);
+    }
+
+    fn stored_path(&self, f: &Path) -> String {
+        match &self.stored_under {
+            Some((dir, label)) if f.starts_with(dir) => {
+                format!("{}/{}", label, paths::to_stored(dir, f))
+            }
+            _ => paths::to_stored(self.root, f),
+        }
+    }
+
+    /// Extract files of the plugins' extensions with them from now on.
+    pub fn use_plugins(&mut self, plugins: Vec<Plugin>) {
+        self.plugins = plugins;
+    }
+
+    /// Store the token ids of every symbol embedded from now on (`--pre-tokenize`).
+    pub fn set_tokenizer(&mut self, tokenizer: Tokenizer) {
+        self.tokenizer = Some(tokenizer);
+    }
+
+    /// Symbols to index for `f`, or `None` if there is nothing to embed.
+    fn extract(
+        &mut self,
+        f: &Path,
+        stored_path: &str,
+        source: Option<std::io::Result<String>>,
+    ) -> Option<SymbolStream> {
+        if self.quarantine.contains(stored_path) && !self.opts.retry_quarantined {
+            self.quarantined_skipped += 1;
+            return None;
+        }
+        let parsing = self.stats.phase_start();
+        if let Some(plugin) = plugin::for_path(&self.plugins, f) {
+            let origin = plugin.origin();
+            let extracted = tracing::debug_span!("plugin", name = %plugin.name)
+                .in_scope(|| plugin.extract(self.root, f));
+            self.stats.phase_done(Phase::Parse, parsing);
+            return self.extracted_by_plugin(f, stored_path, &origin, extracted);
+        }
+        let extracted = tracing::debug_span!("parse").in_scope(|| match source {
+            Some(source) => symbols::stream_symbols_from_source(f, source),
+            None => symbols::stream_symbols_in_file(f),
+        });
+        self.stats.phase_done(Phase::Parse, parsing);
+        let symbols_in_file = match extracted {
+            Ok(v) => {
+                self.quarantine.remove(stored_path);
+                v
+            }
+            Err(symbols::ExtractError::Panicked(msg)) => {
+                self.warn(
+                    WarningKind::Parse,
+                    Some(f),
+                    &format!("parser panicked on {}: {}; quarantined", f.display(), msg),
+                );
+                self.quarantine.add(stored_path, &msg);
+                return None;
+            }
+            Err(err) => {
+                self.warn(
+                    WarningKind::Parse,
+                    Some(f),
+                    &format!("failed to parse {}: {}", f.display(), err),
+                );
+                return None;
+            }
+        };
+        // Only files in a supported language count as empty; the rest were never candidates
+        if plugin::language_name(&self.plugins, f).is_some() {
+            if symbols_in_file.is_empty() {
+                self.empty_files += 1;
+            }
+            if let Err(err) = self
+                .db
+                .set_file_empty(stored_path, symbols_in_file.is_empty())
+            {
+                self.warn(
+                    WarningKind::Insert,
+                    Some(f),
+                    &format!("failed to record {} as empty: {}", f.display(), err),
+                );
+            }
+        }
+        self.limit(f, symbols_in_file)
+    }
+
+    /// Symbols a plugin extracted from `f`; a plugin that failed is a parse warning, and
+    /// the file is skipped.
+    fn extracted_by_plugin(
+        &mut self,
+        f: &Path,
+        stored_path: &str,
+        origin: &str,
+        extracted: Result<Vec<Symbol>, String>,
+    ) -> Option<SymbolStream> {
+        let symbols_in_file = match extracted {
+            Ok(v) => v,
+            Err(err) => {
+                self.warn(
+                    WarningKind::Parse,
+                    Some(f),
+                    &format!("{} failed on {}: {}", origin, f.display(), err),
+                );
+                return None;
+            }
+        };
+        if let Err(err) = self.db.set_file_origin(stored_path, Some(origin)) {
+            self.warn(
+                WarningKind::Insert,
+                Some(f),
+                &format!("failed to record the origin of {}: {}", f.display(), err),
+            );
+        }
+        self.limit(f, SymbolStream::from_symbols(f, symbols_in_file))
+    }
+
+    /// `symbols_in_file` capped to `--symbol-limit`, or `None` if there are none.
+    fn limit(&mut self, f: &Path, mut symbols_in_file: SymbolStream) -> Option<SymbolStream> {
+        if symbols_in_file.is_empty() {
+            return None;
+        }
+
+        // Cap huge (often generated) files; symbols are already in file order
+        if let Some(limit) = self.opts.symbol_limit
+            && symbols_in_file.len() > limit
+        {
+            symbols_in_file.truncate(limit);
+            self.truncated_files += 1;
+            self.warn(
+                WarningKind::Skip,
+                Some(f),
+                &format!("truncated {} to {} symbols", f.display(), limit),
+            );
+        }
+        Some(symbols_in_file)
+    }
+
+    fn embed_and_insert(
+        &mut self,
+        f: &Path,
+        stored_path: &str,
+        mut symbols_in_file: SymbolStream,
+        mut embedder: Option<&mut BatchEmbedder>,
+    ) {
+        // Optional per-file bar
+        let file_name = f
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file_pb = self
+            .progress
+            .file_bar(symbols_in_file.len() as u64, &file_name);
+
+        let annotation_prefixes = symbols::annotation_prefixes(f);
+        // The first batch replaces whatever an earlier run stored for the file
+        let mut replaced = false;
+
+        // Embed in small batches to report progress without interfering with main bar; only
+        // one batch of code strings is cut from the source at a time
+        loop {
+            let batch: Vec<Symbol> = symbols_in_file.by_ref().take(EMBED_BATCH_SIZE).collect();
+            if batch.is_empty() {
+                break;
+            }
+            let mut chunk: Vec<&Symbol> = batch.iter().collect();
+            if let Some(known) = &mut self.known_code {
+                chunk.retain(|sym| known.insert(*blake3::hash(sym.code.as_bytes()).as_bytes()));
+            }
+
+            // Set aside exact copies of already-seen code; they reuse its vector
+            let mut duplicates: Vec<(&Symbol, blake3::Hash)> = Vec::new();
+            let mut fresh_hashes: Vec<blake3::Hash> = Vec::new();
+            if self.opts.symbol_hash_dedup {
+                chunk.retain(|sym| {
+                    let hash = blake3::hash(sym.code.as_bytes());
+                    match self.seen_code.entry(hash) {
+                        Entry::Occupied(_) => {
+                            duplicates.push((sym, hash));
+                            false
+                        }
+                        Entry::Vacant(slot) => {
+                            slot.insert(None);
+                            fresh_hashes.push(hash);
+                            true
+                        }
+                    }
+                });
+            }
+
+            let codes: Vec<&str> = chunk
+                .iter()
+                .map(|s| {
+                    if self.opts.annotation_strip {
+                        symbols::strip_annotations(&s.code, annotation_prefixes)
+                    } else {
+                        s.code.as_str()
+                    }
+                })
+                .collect();
+            // Keyed by position in `chunk`; symbols skipped for hanging the model are absent
+            let embedding = self.stats.phase_start();
+            let span = tracing::debug_span!("embed", symbols = codes.len());
+            let embedded = span.in_scope(|| match embedder.as_deref_mut() {
+                Some(embedder) => embedder.embed_with_ids(codes.iter().copied().enumerate()),
+                None => Ok((0..codes.len()).map(|i| (i, Vec::new())).collect()),
+            });
+            self.stats.phase_done(Phase::Embed, embedding);
+            let embeddings = match embedded {
+                Ok(v) => v,
+                Err(err) => {
+                    self.warn(
+                        WarningKind::Embed,
+                        Some(f),
+                        &format!("failed to embed symbols for {}: {}", f.display(), err),
+                    );
+                    break;
+                }
+            };
+
+            let mut records: Vec<db::SymbolRecord> = Vec::with_capacity(chunk.len());
+            let mut record_hashes: Vec<blake3::Hash> = Vec::new();
+            for (i, (sym, embedded)) in chunk.iter().zip(&codes).enumerate() {
+                let Some(emb) = embeddings.get(&i) else {
+                    self.warn(
+                        WarningKind::Embed,
+                        Some(f),
+                        &format!(
+                            "embedding timed out on {}:{} {}; skipped",
+                            sym.path.display(),
+                            sym.line,
+                            sym.name
+                        ),
+                    );
+                    continue;
+                };
+                if let Some(hash) = fresh_hashes.get(i) {
+                    record_hashes.push(*hash);
+                }
+                records.push(db::SymbolRecord {
+                    path: stored_path,
+                    line: sym.line,
+                    kind: symbol_kind_str(&sym.kind),
+                    name: &sym.name,
+                    code: &sym.code,
+                    clean_code: (embedded.len() != sym.code.len()).then_some(*embedded),
+                    embedding: emb,
+                    parents: Some(&sym.parents),
+                });
+            }
+            let inserting = self.stats.phase_start();
+            let insert_span = tracing::debug_span!("insert", symbols = records.len()).entered();
+            let inserted = if replaced {
+                self.db.insert_symbols(&records)
+            } else {
+                self.db.replace_file_symbols(stored_path, &records)
+            };
+            match inserted {
+                Ok(rowids) => {
+                    replaced = true;
+                    if self.opts.verify_embeddings {
+                        self.verify_embeddings(f, &records, &rowids);
+                    }
+                    if self.tokenizer.is_some() {
+                        self.store_tokens(f, &records, &rowids);
+                    }
+                    self.stats.symbols += rowids.len();
+                    for (hash, rowid) in record_hashes.iter().zip(rowids) {
+                        self.seen_code.insert(*hash, Some(rowid));
+                    }
+                }
+                Err(err) => self.warn(
+                    WarningKind::Insert,
+                    Some(f),
+                    &format!("failed to insert symbols for {}: {}", f.display(), err),
+                ),
+            }
+
+            let mut dup_records = Vec::with_capacity(duplicates.len());
+            for (sym, hash) in &duplicates {
+                match self.seen_code.get(hash).copied().flatten() {
+                    Some(canonical_rowid) => dup_records.push(db::DuplicateRecord {
+                        path: stored_path,
+                        line: sym.line,
+                        kind: symbol_kind_str(&sym.kind),
+                        name: &sym.name,
+                        code: &sym.code,
+                        canonical_rowid,
+                        parents: Some(&sym.parents),
+                    }),
+                    None => self.warn(
+                        WarningKind::Skip,
+                        Some(f),
+                        &format!(
+                            "skipped {}:{} {}: its identical original was not indexed",
+                            sym.path.display(),
+                            sym.line,
+                            sym.name
+                        ),
+                    ),
+                }
+            }
+            match self.db.insert_duplicates(&dup_records) {
+                Ok(()) => self.stats.symbols += dup_records.len(),
+                Err(err) => self.warn(
+                    WarningKind::Insert,
+                    Some(f),
+                    &format!(
+                        "failed to insert duplicate symbols for {}: {}",
+                        f.display(),
+                        err
+                    ),
+                ),
+            }
+            drop(insert_span);
+            self.stats.phase_done(Phase::Insert, inserting);
+
+            file_pb.inc(batch.len() as u64);
+        }
+
+        file_pb.finish_and_clear();
+    }
+
+    /// Compare the stored vectors of freshly inserted `records` with the computed ones.
+    fn verify_embeddings(&mut self, f: &Path, records: &[db::SymbolRecord], rowids: &[i64]) {
+        for (record, &rowid) in records.iter().zip(rowids) {
+            let problem = match self.db.get_embedding(rowid) {
+                Ok(Some(stored)) if bitwise_equal(&stored, record.embedding) => continue,
+                Ok(Some(stored)) => format!(
+                    "differs from the computed one ({} stored values, {} computed)",
+                    stored.len(),
+                    record.embedding.len()
+                ),
+                Ok(None) => "is missing".to_string(),
+                Err(err) => format!("could not be read back: {}", err),
+            };
+            self.warn(
+                WarningKind::Verify,
+                Some(f),
+                &format!(
+                    "stored embedding of {}:{} {} {}",
+                    record.path, record.line, record.name, problem
+                ),
+            );
+        }
+    }
+
+    /// Store the token ids of the text each record was embedded from.
+    fn store_tokens(&mut self, f: &Path, records: &[db::SymbolRecord], rowids: &[i64]) {
+        let Some(tokenizer) = &self.tokenizer else {
+            return;
+        };
+        let texts: Vec<&str> = records
+            .iter()
+            .map(|r| r.clean_code.unwrap_or(r.code))
+            .collect();
+        let stored = tokenizer.encode(&texts).and_then(|tokens| {
+            let tokens: Vec<(i64, Vec<u32>)> = rowids.iter().copied().zip(tokens).collect();
+            self.db.set_tokens(&tokens)
+        });
+        if let Err(err) = stored {
+            self.warn(
+                WarningKind::Insert,
+                Some(f),
+                &format!("failed to store tokens for {}: {}", f.display(), err),
+            );
+        }
+    }
+
+    /// Record run metadata, write the warnings log and print the summary and digest.
+    pub fn finish(mut self) -> RunStats {
+        let db = self.db;
+        // Record run settings so `cearch stats` can report them
+        let meta_result = match self.opts.symbol_limit {
+            Some(limit) => db.set_meta("symbol_limit", &limit.to_string()),
+            None => db.remove_meta("symbol_limit"),
+        }
+        .and_then(|_| db.set_meta("truncated_files", &self.truncated_files.to_string()))
+        .and_then(|_| match self.opts.annotation_strip {
+            true => db.set_meta("annotation_strip", "1"),
+            false => db.remove_meta("annotation_strip"),
+        })
+        .and_then(|_| db.page_size())
+        .and_then(|page_size| db.set_meta("sqlite_page_size", &page_size.to_string()))
+        .and_then(|_| {
+            db.set_meta(
+                "sqlite_cache_size_mb",
+                &self.opts.sqlite_cache_size_mb.to_string(),
+            )
+        });
+        if let Err(err) = meta_result {
+            self.warn(
+                WarningKind::Other,
+                None,
+                &format!("failed to record index metadata: {}", err),
+            );
+        }
+
+        // Without a rebuild the keyword index would miss this run's symbols
+        let fts_result = if self.opts.build_fts {
+            db.rebuild_fts().and_then(|_| db.set_meta("fts_built", "1"))
+        } else {
+            db.remove_meta("fts_built")
+        };
+        if let Err(err) = fts_result {
+            self.warn(
+                WarningKind::Other,
+                None,
+                &format!("failed to build keyword index: {}", err),
+            );
+        }
+
+        if let Err(err) = self.quarantine.save() {
+            self.warn(WarningKind::Other, None, &err);
+        }
+
+        self.progress.finish(&self.stats);
+        if self.quarantined_skipped > 0 {
+            eprintln!(
+                "{} quarantined files skipped — see {}, use --retry-quarantined to retry",
+                self.quarantined_skipped,
+                Quarantine::file_path(self.root).display()
+            );
+        }
+
+        let log = log_path(self.root);
+        if let Err(err) = self.stats.write_log(&log) {
+            tracing::warn!("failed to write {}: {}", log.display(), err);
+        }
+        if let Some(digest) = self.stats.digest(&log) {
+            eprintln!("{}", digest);
+        }
+        if self.empty_files > 0 && !self.opts.skip_empty_report {
+            eprintln!(
+                "{} file{} had no extractable symbols; list them with `cearch list --empty-files`",
+                self.empty_files,
+                if self.empty_files == 1 { "" } else { "s" }
+            );
+        }
+        if self.opts.report_unsupported_extensions {
+            for line in unsupported_extensions_report(&self.extensions, &self.plugins) {
+                eprintln!("{}", line);
+            }
+        }
+        if let Some(profile) = self.stats.profile() {
+            let path = profile_path(self.root);
+            match profile.write(&path) {
+                Ok(()) => eprintln!("profile written to {}", path.display()),
+                Err(err) => tracing::warn!("{}", err),
+            }
+            let slowest = profile.slowest(PROFILE_TOP);
+            if !slowest.is_empty() {
+                eprintln!("slowest files (dominant phase):");
+                for line in slowest {
+                    eprintln!("{}", line);
+                }
+            }
+        }
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::ProgressMode;
+
+    fn options() -> Options {
+        Options {
+            symbol_limit: Some(1),
+            symbol_hash_dedup: false,
+            retry_quarantined: false,
+            build_fts: false,
+            sqlite_cache_size_mb: 64,
+            skip_empty_report: false,
+            annotation_strip: false,
+            verify_embeddings: false,
+            report_unsupported_extensions: false,
+            profile: false,
+            max_size_bytes: None,
+            trim: false,
+        }
+    }
+
+    #[test]
+    fn parse_failures_and_truncation_are_collected() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_with_dim(root, 4, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = options();
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(2));
+
+        // Not valid UTF-8, so reading the source fails
+        let broken = root.join("broken.py");
+        std::fs::write(&broken, [0xff, 0xfe, 0x00]).unwrap();
+        assert!(indexer.extract(&broken, "broken.py", None).is_none());
+
+        let two = root.join("two.py");
+        std::fs::write(&two, "def a():\n    pass\n\ndef b():\n    pass\n").unwrap();
+        assert_eq!(indexer.extract(&two, "two.py", None).unwrap().len(), 1);
+
+        assert_eq!(indexer.stats.count(WarningKind::Parse), 1);
+        // Truncation is deliberate and does not fail a strict run
+        assert_eq!(indexer.stats.failed_paths(), vec![broken.as_path()]);
+        assert_eq!(indexer.stats.count(WarningKind::Skip), 1);
+        assert!(indexer.stats.warnings[0].message.contains("broken.py"));
+
+        let stats = indexer.finish();
+        let log = std::fs::read_to_string(log_path(root)).unwrap();
+        assert!(log.contains("[parse] failed to parse"), "{}", log);
+        assert_eq!(
+            stats.digest(&log_path(root)).unwrap(),
+            format!(
+                "1 file failed to parse, 1 skip (see {})",
+                log_path(root).display()
+            )
+        );
+    }
+
+    #[test]
+    fn strict_fails_only_when_a_file_broke() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_with_dim(root, 4, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = options();
+        let log = log_path(root);
+
+        // Unsupported and symbol-free files never reach the model
+        let notes = root.join("notes.txt");
+        std::fs::write(&notes, "hello").unwrap();
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(1));
+        assert!(indexer.extract(&notes, "notes.txt", None).is_none());
+        assert_eq!(strict_failure(&indexer.finish(), &log), None);
+
+        let unreadable = root.join("unreadable.py");
+        std::fs::write(&unreadable, [0xff, 0xfe]).unwrap();
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(2));
+        assert!(indexer.extract(&notes, "notes.txt", None).is_none());
+        assert!(
+            indexer
+                .extract(&unreadable, "unreadable.py", None)
+                .is_none()
+        );
+        let report = strict_failure(&indexer.finish(), &log).expect("strict failure");
+        assert!(
+            report.starts_with(&format!("1 file failed: {}", unreadable.display())),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn verification_reports_stored_vectors_that_differ() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_with_dim(root, 4, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = Options {
+            verify_embeddings: true,
+            ..options()
+        };
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(1));
+        let record = |name, embedding| db::SymbolRecord {
+            code: "def a(): pass",
+            ..db::SymbolRecord::fixture("a.py", 1, name, embedding)
+        };
+        let computed = [0.1f32, -0.0, 3.5, f32::MIN_POSITIVE];
+        let records = [record("a", &computed[..]), record("b", &computed[..])];
+        let rowids = db.insert_symbols(&records).unwrap();
+        indexer.verify_embeddings(Path::new("a.py"), &records, &rowids);
+        assert_eq!(indexer.stats.count(WarningKind::Verify), 0);
+
+        // What the store holds no longer matches what was computed, down to the sign of zero
+        let changed = [0.1f32, 0.0, 3.5, f32::MIN_POSITIVE];
+        let records = [record("a", &changed[..])];
+        indexer.verify_embeddings(Path::new("a.py"), &records, &rowids[..1]);
+        indexer.verify_embeddings(Path::new("a.py"), &records, &[9999]);
+        assert_eq!(indexer.stats.count(WarningKind::Verify), 2);
+        assert!(indexer.stats.warnings[1].message.ends_with("is missing"));
+        assert_eq!(indexer.stats.failed_paths(), vec![Path::new("a.py")]);
+    }
+
+    #[test]
+    fn extensions_without_symbols_are_reported_with_tips() {
+        let counts = |files, symbols| ExtensionCounts { files, symbols };
+        let seen: BTreeMap<String, ExtensionCounts> = [
+            ("rs".to_string(), counts(4, 30)),
+            ("py".to_string(), counts(1, 0)),
+            ("ex".to_string(), counts(12, 0)),
+            ("md".to_string(), counts(3, 0)),
+        ]
+        .into();
+        assert_eq!(
+            unsupported_extensions_report(&seen, &[]),
+            [
+                "file extensions with no symbols extracted in this run:",
+                "  .ex           12 files (unsupported)",
+                "  .md            3 files (unsupported)",
+                "  .py            1 file (parsed, but empty)",
+                "tip: Elixir support for .ex files: add tree-sitter-elixir (see `cearch index --languages-file`)",
+            ]
+        );
+        let covered: BTreeMap<String, ExtensionCounts> = [("rs".to_string(), counts(1, 1))].into();
+        assert!(unsupported_extensions_report(&covered, &[]).is_empty());
+
+        // A plugin's extensions are supported, if empty
+        let plugins = plugin::from_config(&BTreeMap::from([(
+            "docs".to_string(),
+            crate::config::PluginConfig {
+                extensions: vec!["md".to_string()],
+                command: vec!["extract".to_string()],
+                ..Default::default()
+            },
+        )]))
+        .unwrap();
+        assert!(
+            unsupported_extensions_report(&seen, &plugins)
+                .contains(&"  .md            3 files (parsed, but empty)".to_string())
+        );
+    }
+
+    #[test]
+    fn dependency_files_are_stored_under_their_label() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = options();
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, None);
+        let krate = root.join("registry").join("serde-1.0.200");
+        let own = root.join("src").join("a.rs");
+        assert_eq!(indexer.stored_path(&own), "src/a.rs");
+        indexer.store_paths_under(&krate, "serde-1.0.200");
+        assert_eq!(
+            indexer.stored_path(&krate.join("src").join("de.rs")),
+            "serde-1.0.200/src/de.rs"
+        );
+        assert_eq!(indexer.stored_path(&own), "src/a.rs");
+    }
+
+    #[test]
+    fn annotation_strip_embeds_code_without_attributes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let source = "#[derive(Debug)]\n#[inline]\nfn f() {}\n";
+        let embedded = |annotation_strip| {
+            let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+            let progress = Progress::new(ProgressMode::None);
+            let opts = Options {
+                annotation_strip,
+                ..options()
+            };
+            let mut indexer = Indexer::new(root, &db, &progress, &opts, None);
+            indexer.index_file(&root.join("a.rs"), Some(Ok(source.to_string())), None);
+            indexer.finish();
+            let id = db.stored_symbols().unwrap()[0].id;
+            db.embedded_text(id).unwrap().unwrap()
+        };
+        assert_eq!(embedded(false), source.trim_end());
+        assert_eq!(embedded(true), "fn f() {}");
+    }
+
+    #[test]
+    fn indexing_a_file_again_replaces_its_symbols() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = Options {
+            symbol_limit: None,
+            ..options()
+        };
+        let file = root.join("a.py");
+        let locations = |source: &str| {
+            let mut indexer = Indexer::new(root, &db, &progress, &opts, None);
+            indexer.index_file(&file, Some(Ok(source.to_string())), None);
+            indexer.finish();
+            let symbols = db.stored_symbols().unwrap();
+            symbols
+                .into_iter()
+                .map(|s| (s.name, s.line))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            locations("def alpha():\n    pass\n\ndef beta():\n    pass\n"),
+            [("alpha".to_string(), 1), ("beta".to_string(), 4)]
+        );
+        // alpha moved down two lines and beta is gone
+        assert_eq!(
+            locations("import os\n\ndef alpha():\n    pass\n"),
+            [("alpha".to_string(), 3)]
+        );
+        assert_eq!(locations(""), []);
+    }
+
+    #[test]
+    fn keyword_index_is_only_kept_by_runs_that_build_it() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let run = |build_fts: bool| {
+            let opts = Options {
+                build_fts,
+                ..options()
+            };
+            let mut indexer = Indexer::new(root, &db, &progress, &opts, None);
+            let source = "def open_db():\n    pass\n";
+            indexer.index_file(&root.join("a.py"), Some(Ok(source.to_string())), None);
+            indexer.finish();
+            db.has_keyword_index().unwrap()
+        };
+        assert!(!run(false));
+        assert!(run(true));
+        assert_eq!(db.keyword_search("open_db", 5).unwrap()[0].name, "open_db");
+        // A later run without --build-fts leaves it stale, so it no longer counts
+        assert!(!run(false));
+    }
+
+    #[test]
+    fn known_code_is_not_stored_again() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = Options {
+            symbol_limit: None,
+            ..options()
+        };
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, None);
+        let kept = "fn with_retry() {\n    attempt();\n}";
+        let current = "fn current() {}";
+        indexer.skip_known_code(HashSet::from(
+            [*blake3::hash(current.as_bytes()).as_bytes()],
+        ));
+        let commit = "3c31b8cf628988c07380c435c47b1fca594463aa";
+        indexer.store_paths_under(root, commit);
+        // Versions read from git need not exist on disk
+        let source = format!("{}\n\n{}\n\n{}\n", current, kept, kept);
+        indexer.index_file(&root.join("src").join("retry.rs"), Some(Ok(source)), None);
+        let stats = indexer.finish();
+        assert_eq!(stats.symbols, 1);
+        let stored = db.stored_symbols().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].path, format!("{}/src/retry.rs", commit));
+        assert_eq!(stored[0].code, kept);
+        assert_eq!(
+            db.all_code_hashes().unwrap(),
+            HashSet::from([*blake3::hash(kept.as_bytes()).as_bytes()])
+        );
+    }
+
+    #[test]
+    fn profile_times_every_file_within_the_run() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = Options {
+            symbol_limit: None,
+            profile: true,
+            ..options()
+        };
+        let files = ["a.py", "b.rs", "notes.txt"];
+        std::fs::write(
+            root.join("a.py"),
+            "def a():\n    pass\n\ndef b():\n    pass\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("b.rs"), "fn c() {}\n").unwrap();
+        std::fs::write(root.join("notes.txt"), "hello").unwrap();
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(files.len()));
+        for file in files {
+            indexer.index_file(&root.join(file), None, None);
+        }
+        indexer.finish();
+
+        let profile: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(profile_path(root)).unwrap()).unwrap();
+        assert_eq!(profile["schema_version"], 1);
+        let rows = profile["files"].as_array().unwrap();
+        let paths: Vec<&str> = rows.iter().map(|r| r["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, files);
+        assert_eq!(rows[0]["symbols"], 2);
+        assert_eq!(rows[0]["bytes"], 37);
+        assert_eq!(rows[1]["symbols"], 1);
+        assert_eq!(rows[2]["symbols"], 0);
+        let secs = |row: &serde_json::Value, key: &str| row[key].as_f64().unwrap();
+        let mut total = 0.0;
+        for row in rows {
+            let phases =
+                secs(row, "parse_secs") + secs(row, "embed_secs") + secs(row, "insert_secs");
+            assert!(phases <= secs(row, "total_secs") + 1e-6, "{}", row);
+            total += secs(row, "total_secs");
+        }
+        // Files are timed within the run, so together they cannot outlast it
+        let elapsed = secs(&profile, "elapsed_secs");
+        assert!(total <= elapsed + 1e-6, "{} of {}", total, elapsed);
+    }
+
+    #[test]
+    fn a_tiny_size_budget_stops_the_run_or_trims_it() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let files: Vec<PathBuf> = (0..20)
+            .map(|i| {
+                let file = root.join(format!("src/m{:02}.py", i));
+                std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+                let body = format!("    x = '{}'\n", "a".repeat(60)).repeat(120);
+                std::fs::write(&file, format!("def f{}():\n{}", i, body)).unwrap();
+                file
+            })
+            .collect();
+        let progress = Progress::new(ProgressMode::None);
+        let run = |trim: bool| {
+            let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+            // Each run starts from an empty, compacted index
+            for path in db.indexed_files().unwrap() {
+                db.delete_file(&path).unwrap();
+            }
+            db.vacuum().unwrap();
+            let budget = db.used_bytes().unwrap() + 32 * 1024;
+            let opts = Options {
+                symbol_limit: None,
+                max_size_bytes: Some(budget),
+                trim,
+                ..options()
+            };
+            let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(files.len()));
+            let mut indexed = 0;
+            for f in &files {
+                indexer.index_file(f, None, None);
+                indexed += 1;
+                if indexer.over_budget().is_some() {
+                    break;
+                }
+            }
+            let over = indexer.over_budget();
+            let trims = indexer.stats.count(WarningKind::Skip);
+            (
+                indexed,
+                over,
+                trims,
+                db.used_bytes().unwrap() <= budget,
+                db.count_symbols().unwrap(),
+            )
+        };
+
+        let (indexed, over, trims, within, _) = run(false);
+        assert!(indexed < files.len(), "stopped after {}", indexed);
+        assert!(over.is_some());
+        assert_eq!(trims, 0);
+        assert!(!within);
+
+        let (indexed, over, trims, within, symbols) = run(true);
+        assert_eq!(indexed, files.len());
+        assert_eq!(over, None);
+        assert!(trims > 0);
+        assert!(within);
+        assert!(symbols < files.len(), "{} symbols kept", symbols);
+
+        let db = DB::open_read(root).expect("open");
+        let breakdown = db.size_breakdown(10).unwrap();
+        assert_eq!(breakdown.directories[0].0, "src");
+        assert!(
+            breakdown
+                .tables
+                .iter()
+                .any(|(t, _)| t.starts_with("symbols"))
+        );
+        assert!(breakdown.report()[0].starts_with("index size: "));
+    }
+
+    /// Spans opened and events emitted, as `(level, name or message)`.
+    #[derive(Clone, Default)]
+    struct Recorded(std::sync::Arc<std::sync::Mutex<Vec<(tracing::Level, String)>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Recorded {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let meta = attrs.metadata();
+            let span = (*meta.level(), meta.name().to_string());
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Message(String);
+            impl tracing::field::Visit for Message {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    if field.name() == "message" {
+                        self.0 = format!("{:?}", value);
+                    }
+                }
+            }
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            let level = *event.metadata().level();
+            self.0.lock().unwrap().push((level, message.0));
+        }
+    }
+
+    #[test]
+    fn a_run_emits_spans_and_events_at_their_levels() {
+        use tracing::Level;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+        // Bars (hidden off a terminal) hand their warnings to tracing
+        let progress = Progress::new(ProgressMode::Bar);
+        let opts = Options {
+            symbol_limit: None,
+            ..options()
+        };
+        std::fs::write(
+            root.join("a.py"),
+            "def a():\n    pass\n\ndef b():\n    pass\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("notes.txt"), "hello").unwrap();
+        std::fs::write(root.join("broken.py"), [0xff, 0xfe]).unwrap();
+
+        let recorded = Recorded::default();
+        let subscriber = tracing_subscriber::registry().with(recorded.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(3));
+            progress.start(&indexer.stats, "Indexing repo");
+            for file in ["a.py", "notes.txt", "broken.py"] {
+                indexer.index_file(&root.join(file), None, None);
+            }
+            indexer.finish();
+        });
+
+        let seen = recorded.0.lock().unwrap();
+        let count = |level: Level, name: &str| {
+            seen.iter()
+                .filter(|(l, n)| *l == level && n == name)
+                .count()
+        };
+        assert_eq!(count(Level::INFO, "index_file"), 3);
+        assert_eq!(count(Level::DEBUG, "parse"), 3);
+        assert_eq!(count(Level::DEBUG, "embed"), 1);
+        assert_eq!(count(Level::DEBUG, "insert"), 1);
+        // Every file replaces what was stored for it, even when nothing is left
+        assert_eq!(count(Level::DEBUG, "replace_file_symbols"), 3);
+        assert_eq!(count(Level::DEBUG, "indexed a.py"), 1);
+        let warnings: Vec<&String> = seen
+            .iter()
+            .filter(|(l, _)| *l == Level::WARN)
+            .map(|(_, m)| m)
+            .collect();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(
+            warnings[0].starts_with("failed to parse"),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    #[test]
+    fn empty_files_are_recorded_until_they_gain_symbols() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let db = DB::open_with_dim(root, 4, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = options();
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(3));
+
+        let empty = root.join("empty.py");
+        std::fs::write(&empty, "").unwrap();
+        let notes = root.join("notes.txt");
+        std::fs::write(&notes, "hello").unwrap();
+        assert!(indexer.extract(&empty, "empty.py", None).is_none());
+        assert!(indexer.extract(&notes, "notes.txt", None).is_none());
+        assert_eq!(indexer.empty_files, 1);
+        assert_eq!(db.empty_files().unwrap(), vec!["empty.py".to_string()]);
+
+        std::fs::write(&empty, "def f():\n    pass\n").unwrap();
+        assert!(indexer.extract(&empty, "empty.py", None).is_some());
+        assert!(db.empty_files().unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plugin_symbols_are_indexed_like_parsed_ones() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        std::fs::create_dir_all(root.join(".cearch")).unwrap();
+        std::fs::create_dir_all(root.join("tools")).unwrap();
+        // One symbol per `rule <name>` line
+        std::fs::write(
+            root.join("tools").join("rules.sh"),
+            r#"awk '/^rule / { printf "{\"name\":\"%s\",\"line\":%d,\"end_line\":%d,\"kind\":\"function\",\"code\":\"%s\"}\n", $2, NR, NR, $0 }' "$1""#,
+        )
+        .unwrap();
+        std::fs::write(
+            crate::config::Config::file_path(root),
+            "[plugins.rules]\nextensions = [\"rules\"]\ncommand = [\"sh\", \"tools/rules.sh\"]\n\n\
+             [plugins.broken]\nextensions = [\"broken\"]\ncommand = [\"sh\", \"-c\", \"echo oops\"]\n",
+        )
+        .unwrap();
+        let config = crate::config::Config::load(root).unwrap();
+        let plugins = plugin::from_config(&config.plugins).unwrap();
+
+        let db = DB::open_metadata_only(root, &db::WriteOptions::default()).expect("open");
+        let progress = Progress::new(ProgressMode::None);
+        let opts = Options {
+            symbol_limit: None,
+            build_fts: true,
+            ..options()
+        };
+        let mut indexer = Indexer::new(root, &db, &progress, &opts, Some(2));
+        indexer.use_plugins(plugins);
+        let rules = root.join("billing.rules");
+        std::fs::write(&rules, "rule refund_window\n  days 30\nrule late_fee\n").unwrap();
+        indexer.index_file(&rules, None, None);
+        let broken = root.join("x.broken");
+        std::fs::write(&broken, "anything").unwrap();
+        indexer.index_file(&broken, None, None);
+        let stats = indexer.finish();
+
+        assert_eq!(stats.symbols, 2);
+        // Counted under the plugin's name, not as an unknown language
+        assert_eq!(stats.language_line(), "rules: 2");
+        // Malformed output skips the file with a warning
+        assert_eq!(stats.count(WarningKind::Parse), 1);
+        assert_eq!(stats.failed_paths(), vec![broken.as_path()]);
+        assert!(
+            stats.warnings[0]
+                .message
+                .contains("plugin:broken failed on"),
+            "{}",
+            stats.warnings[0].message
+        );
+        let found = db.search_by_name("late_fee", None).unwrap().unwrap();
+        assert_eq!((found.path.as_str(), found.line), ("billing.rules", 3));
+        let hits = db.keyword_search("refund_window", 5).unwrap();
+        assert_eq!(hits[0].name, "refund_window");
+        assert_eq!(
+            db.file_origin("billing.rules").unwrap().as_deref(),
+            Some("plugin:rules")
+        );
+        assert_eq!(db.file_origin("x.broken").unwrap(), None);
+    }
+}
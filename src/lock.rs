@@ -0,0 +1,143 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+// Lock module keeps two index runs from writing the same database at once.
+
+/// Held for the duration of an index run; removes `.cearch/index.lock` when dropped.
+///
+/// `std::process::exit` skips destructors, so drop the guard before exiting. A lock left
+/// behind by a crashed run is detected as stale and replaced.
+#[derive(Debug)]
+pub struct IndexLock {
+    path: PathBuf,
+}
+
+impl IndexLock {
+    pub fn path(repo_root: &Path) -> PathBuf {
+        repo_root.join(".cearch").join("index.lock")
+    }
+
+    /// Take the index lock for this process, replacing a lock whose owner has exited.
+    pub fn acquire(repo_root: &Path) -> Result<Self, String> {
+        let path = Self::path(repo_root);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        }
+        // A second attempt covers removing a stale lock; losing that race to another
+        // process means it now holds a live lock
+        for _ in 0..2 {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    writeln!(file, "{}", std::process::id())
+                        .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+                    return Ok(Self { path });
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let owner = std::fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|s| s.trim().parse::<u32>().ok());
+                    if let Some(pid) = owner
+                        && process_alive(pid)
+                    {
+                        return Err(format!(
+                            "another `cearch index` (pid {}) is running; remove {} if it is not",
+                            pid,
+                            path.display()
+                        ));
+                    }
+                    std::fs::remove_file(&path).map_err(|e| {
+                        format!("failed to remove stale lock {}: {}", path.display(), e)
+                    })?;
+                }
+                Err(err) => {
+                    return Err(format!("failed to create {}: {}", path.display(), err));
+                }
+            }
+        }
+        Err(format!(
+            "could not take {}; another index run started concurrently",
+            path.display()
+        ))
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Whether a process with `pid` exists; unknown platforms assume it does.
+#[cfg(not(windows))]
+fn process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else if cfg!(unix) {
+        std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(true)
+    } else {
+        true
+    }
+}
+
+/// Whether a process with `pid` is still running. One that exists but cannot be opened
+/// (another user's) counts as running.
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, ERROR_ACCESS_DENIED, GetLastError, STILL_ACTIVE,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    // SAFETY: the handle is checked before use and closed once the exit code is read
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return GetLastError() == ERROR_ACCESS_DENIED;
+        }
+        let mut code = 0u32;
+        let read = GetExitCodeProcess(handle, &mut code);
+        CloseHandle(handle);
+        // An exited process keeps its object while handles to it are open
+        read == 0 || code == STILL_ACTIVE as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_acquire_fails_until_released() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let lock = IndexLock::acquire(dir.path()).expect("first lock");
+        let err = IndexLock::acquire(dir.path()).unwrap_err();
+        assert!(err.contains(&std::process::id().to_string()), "{}", err);
+        drop(lock);
+        assert!(!IndexLock::path(dir.path()).exists());
+        IndexLock::acquire(dir.path()).expect("lock after release");
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn stale_lock_is_replaced() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = IndexLock::path(dir.path());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        // Above Linux's pid_max (and not a multiple of 4, as Windows pids are), so no such
+        // process can exist
+        std::fs::write(&path, "999999999\n").unwrap();
+        let _lock = IndexLock::acquire(dir.path()).expect("stale lock replaced");
+        let owner = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(owner.trim(), std::process::id().to_string());
+    }
+}
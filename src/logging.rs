@@ -0,0 +1,131 @@
+use indicatif::MultiProgress;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{Event, Level, Subscriber};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::{EnvFilter, Targets};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+// Logging module sets up `tracing` for `--log-level`/`CEARCH_LOG` and `--trace-out`, and
+// prints events as `warn: ...` lines that make way for any progress bars.
+
+/// Level or filter read when `--log-level` is not given, e.g. `debug` or `cearch::db=trace`.
+pub const ENV_VAR: &str = "CEARCH_LOG";
+/// cearch's own warnings and errors, as printed before logging was configurable.
+const DEFAULT_LEVEL: &str = "warn";
+
+/// Bars currently drawn by `Progress`; log lines are printed around them.
+static BARS: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// Print log lines through `bars` while they are drawn (`None` once they are finished).
+pub fn route_through(bars: Option<MultiProgress>) {
+    if let Ok(mut current) = BARS.lock() {
+        *current = bars;
+    }
+}
+
+/// Stderr, with any drawn bars hidden while a line is written so it does not tear them.
+struct StderrAroundBars;
+
+impl Write for StderrAroundBars {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let bars = BARS.lock().ok().and_then(|bars| bars.clone());
+        let written = match bars {
+            Some(bars) => bars.suspend(|| std::io::stderr().write_all(buf)),
+            None => std::io::stderr().write_all(buf),
+        };
+        written.map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// `warn: message key=value`, the format of cearch's warnings before they were events.
+struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let level = event.metadata().level().as_str().to_ascii_lowercase();
+        write!(writer, "{}: ", level)?;
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// The filter for printed events: `--log-level` wins over `CEARCH_LOG`. A bare level applies
+/// to cearch's own events only; anything else is a `tracing` filter directive, which can
+/// also let through events of libraries such as ort.
+pub fn filter(level: Option<&str>, env: Option<&str>) -> Result<EnvFilter, String> {
+    let spec = level
+        .or(env.filter(|s| !s.trim().is_empty()))
+        .unwrap_or(DEFAULT_LEVEL)
+        .trim();
+    let directives = if spec.parse::<Level>().is_ok() || spec.eq_ignore_ascii_case("off") {
+        format!("cearch={}", spec)
+    } else {
+        spec.to_string()
+    };
+    EnvFilter::try_new(&directives).map_err(|e| format!("invalid log level {:?}: {}", spec, e))
+}
+
+/// Install the global subscriber. With `trace_out`, every cearch span is also recorded in
+/// Chrome trace format (for Perfetto or `chrome://tracing`) until the guard is dropped.
+pub fn init(level: Option<&str>, trace_out: Option<&Path>) -> Result<Option<FlushGuard>, String> {
+    let filter = filter(level, std::env::var(ENV_VAR).ok().as_deref())?;
+    let (chrome, guard) = match trace_out {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+            let (layer, guard) = ChromeLayerBuilder::new()
+                .writer(std::io::BufWriter::new(file))
+                .include_args(true)
+                .build();
+            let layer = layer.with_filter(Targets::new().with_target("cearch", Level::TRACE));
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    let lines = tracing_subscriber::fmt::layer()
+        .event_format(Plain)
+        .with_ansi(false)
+        .with_writer(|| StderrAroundBars)
+        .with_filter(filter);
+    tracing_subscriber::registry()
+        .with(chrome)
+        .with(lines)
+        .try_init()
+        .map_err(|e| e.to_string())?;
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_default_to_cearch_warnings() {
+        let shown = |level, env| filter(level, env).unwrap().to_string();
+        assert_eq!(shown(None, None), "cearch=warn");
+        assert_eq!(shown(None, Some("debug")), "cearch=debug");
+        assert_eq!(shown(Some("error"), Some("debug")), "cearch=error");
+        assert_eq!(shown(None, Some(" ")), "cearch=warn");
+        assert_eq!(shown(Some("cearch::db=trace"), None), "cearch::db=trace");
+        assert!(filter(Some("cearch=loud"), None).is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use crate::db::{DB, SearchResult, SymbolLocation};
+use crate::paths;
+use crate::rpc::{self, INTERNAL_ERROR, INVALID_REQUEST, METHOD_NOT_FOUND, Request, RpcError};
+use crate::search::{self, Filters};
+use anyhow::{Result, bail};
+use serde_json::{Value, json};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+// Lsp module is a minimal Language Server Protocol server over stdio: workspace symbols from
+// the index and a custom `cearch/semanticSearch` request.
+
+/// LSP error for requests that arrive before `initialize`.
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+/// Most symbols returned by one `workspace/symbol` request.
+const WORKSPACE_SYMBOL_LIMIT: usize = 100;
+
+/// LSP `SymbolKind` of a stored symbol kind.
+fn symbol_kind(kind: &str) -> u32 {
+    match kind {
+        "fn" => 12,
+        "class" => 5,
+        "const" => 14,
+        _ => 13,
+    }
+}
+
+/// Read one `Content-Length`-framed message; `None` at end of input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let mut length = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            if length.is_some() {
+                bail!("input ended inside a message header");
+            }
+            return Ok(None);
+        }
+        let line = header.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    write!(output, "Content-Length: {}\r\n\r\n", body.len())?;
+    output.write_all(&body)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Where the server is in the LSP lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Uninitialized,
+    Running,
+    ShutDown,
+}
+
+/// Embedder and index kept open for the lifetime of `cearch lsp`.
+///
+/// `embed` turns a query into a vector; it is only called for `cearch/semanticSearch`.
+pub struct Server<E> {
+    root: PathBuf,
+    embed: E,
+    db: DB,
+    fingerprint: Vec<Option<SystemTime>>,
+    state: State,
+}
+
+impl<E: FnMut(&str) -> Result<Vec<f32>>> Server<E> {
+    pub fn open(repo_root: &Path, embed: E) -> Result<Self> {
+        // Taken before opening, so a run finishing in between still triggers a reload
+        let fingerprint = rpc::index_fingerprint(repo_root);
+        Ok(Server {
+            root: repo_root.to_path_buf(),
+            embed,
+            db: DB::open_read(repo_root)?,
+            fingerprint,
+            state: State::Uninitialized,
+        })
+    }
+
+    /// Answer messages from `input` until `exit` or the end of input.
+    ///
+    /// Returns whether the client shut the server down first, which LSP maps to exit code 0.
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> Result<bool> {
+        while let Some(body) = read_message(&mut input)? {
+            let message = match rpc::parse_message(&body) {
+                Ok(message) => message,
+                Err(reply) => {
+                    write_message(&mut output, &reply)?;
+                    continue;
+                }
+            };
+            // A response to a server-initiated request; this server sends none
+            if message.get("method").is_none()
+                && (message.get("result").is_some() || message.get("error").is_some())
+            {
+                continue;
+            }
+            let Request { id, method, params } = match Request::from_message(message) {
+                Ok(request) => request,
+                Err(reply) => {
+                    write_message(&mut output, &reply)?;
+                    continue;
+                }
+            };
+            if method == "exit" {
+                return Ok(self.state == State::ShutDown);
+            }
+            let outcome = self.handle(&method, &params);
+            // Notifications (no id) get no response
+            if let Some(id) = id {
+                write_message(&mut output, &rpc::response(id, outcome))?;
+            }
+        }
+        Ok(false)
+    }
+
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match (self.state, method) {
+            (State::Uninitialized, "initialize") => {
+                self.state = State::Running;
+                Ok(json!({
+                    "capabilities": {"workspaceSymbolProvider": true},
+                    "serverInfo": {"name": "cearch", "version": env!("CARGO_PKG_VERSION")},
+                }))
+            }
+            (State::Uninitialized, _) => Err(RpcError {
+                code: SERVER_NOT_INITIALIZED,
+                message: "server is not initialized".to_string(),
+            }),
+            (State::ShutDown, _) => Err(RpcError {
+                code: INVALID_REQUEST,
+                message: "server is shut down".to_string(),
+            }),
+            (State::Running, "initialize") => Err(RpcError {
+                code: INVALID_REQUEST,
+                message: "server is already initialized".to_string(),
+            }),
+            (State::Running, "shutdown") => {
+                self.state = State::ShutDown;
+                Ok(Value::Null)
+            }
+            (State::Running, "workspace/symbol") => self.workspace_symbol(params),
+            (State::Running, "cearch/semanticSearch") => self.semantic_search(params),
+            (State::Running, _) => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("unknown method {}", method),
+            }),
+        }
+    }
+
+    /// Reopen the index if it changed on disk since it was loaded.
+    fn refresh(&mut self) -> Result<(), RpcError> {
+        rpc::reopen_if_changed(&self.root, &mut self.db, &mut self.fingerprint)
+    }
+
+    /// A single-line LSP `Location`; the index does not record where symbols end.
+    fn location(&self, path: &str, line: usize) -> Value {
+        let line = line.saturating_sub(1);
+        json!({
+            "uri": paths::file_uri(&self.root, path),
+            "range": {
+                "start": {"line": line, "character": 0},
+                "end": {"line": line + 1, "character": 0},
+            },
+        })
+    }
+
+    /// `workspace/symbol {query}`: symbols whose names fuzzily match `query`.
+    fn workspace_symbol(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let query = rpc::str_param(params, "query")?;
+        self.refresh()?;
+        let symbols = self
+            .db
+            .fuzzy_symbols(query, WORKSPACE_SYMBOL_LIMIT)
+            .map_err(RpcError::internal)?;
+        let symbols: Vec<Value> = symbols
+            .into_iter()
+            .map(
+                |SymbolLocation {
+                     path,
+                     line,
+                     kind,
+                     name,
+                 }| {
+                    json!({
+                        "name": name,
+                        "kind": symbol_kind(&kind),
+                        "location": self.location(&path, line),
+                        "containerName": path,
+                    })
+                },
+            )
+            .collect();
+        Ok(Value::Array(symbols))
+    }
+
+    /// `cearch/semanticSearch {query, k, filters}`: locations of the symbols closest to the
+    /// embedded query, nearest first.
+    fn semantic_search(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let query = search::validate_query(rpc::str_param(params, "query")?)
+            .map_err(RpcError::invalid_params)?;
+        let k = rpc::k_param(params)?;
+        let filters: Filters = rpc::filters_param(params)?;
+        self.refresh()?;
+        if self.db.is_metadata_only().map_err(RpcError::internal)? {
+            return Err(RpcError {
+                code: INTERNAL_ERROR,
+                message: "index was built with --metadata-only and has no embeddings".to_string(),
+            });
+        }
+        let embedding = (self.embed)(query).map_err(RpcError::internal)?;
+        let fetch = search::expanded_k(k, search::K_EXPANSION, filters.is_active());
+        let mut results = self.db.knn(&embedding, fetch).map_err(RpcError::internal)?;
+        results.retain(|r| filters.keep(r));
+        results.truncate(k);
+        let locations: Vec<Value> = results
+            .iter()
+            .map(|SearchResult { path, line, .. }| self.location(path, *line))
+            .collect();
+        Ok(Value::Array(locations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SymbolRecord, WriteOptions};
+
+    fn build_index(root: &Path, symbols: &[(&str, usize, &str, &str, [f32; 2])]) {
+        let db = DB::open_with_dim(root, 2, &WriteOptions::default()).expect("open");
+        let records: Vec<SymbolRecord> = symbols
+            .iter()
+            .map(|(path, line, kind, name, emb)| SymbolRecord {
+                kind,
+                ..SymbolRecord::fixture(path, *line, name, emb)
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+    }
+
+    fn mock_embedder(query: &str) -> Result<Vec<f32>> {
+        Ok(match query {
+            "left" => vec![1.0, 0.0],
+            _ => vec![0.0, 1.0],
+        })
+    }
+
+    /// Frame each JSON message as an LSP client would.
+    fn framed(messages: &[Value]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for message in messages {
+            write_message(&mut out, message).unwrap();
+        }
+        out
+    }
+
+    fn replies(output: &[u8]) -> Vec<Value> {
+        let mut input = output;
+        let mut out = Vec::new();
+        while let Some(body) = read_message(&mut input).expect("framed reply") {
+            out.push(serde_json::from_slice(&body).expect("json reply"));
+        }
+        out
+    }
+
+    #[test]
+    fn canned_session_over_stdio() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        build_index(
+            dir.path(),
+            &[
+                ("src/a.rs", 3, "fn", "parse_config", [1.0, 0.0]),
+                ("src/b.rs", 1, "class", "ConfigParser", [0.0, 1.0]),
+                ("src/b.rs", 9, "const", "PC", [0.1, 0.9]),
+                ("src/c.rs", 2, "fn", "render", [0.5, 0.5]),
+            ],
+        );
+        let mut server = Server::open(dir.path(), mock_embedder).expect("open");
+        let request = |id: i64, method: &str, params: Value| json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let input = framed(&[
+            request(1, "workspace/symbol", json!({"query": "pc"})),
+            request(2, "initialize", json!({"capabilities": {}})),
+            json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+            request(3, "workspace/symbol", json!({"query": "pc"})),
+            request(4, "cearch/semanticSearch", json!({"query": "left", "k": 2})),
+            request(
+                5,
+                "cearch/semanticSearch",
+                json!({"query": "up", "filters": {"kind": "const"}}),
+            ),
+            request(6, "textDocument/hover", json!({})),
+            request(7, "workspace/symbol", json!({"query": "100%"})),
+            request(8, "shutdown", Value::Null),
+            request(9, "workspace/symbol", json!({"query": ""})),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+            request(10, "workspace/symbol", json!({"query": ""})),
+        ]);
+        let mut output = Vec::new();
+        assert!(server.run(&input[..], &mut output).expect("run"));
+        let replies = replies(&output);
+        let ids: Vec<i64> = replies.iter().map(|r| r["id"].as_i64().unwrap()).collect();
+        assert_eq!(ids, [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        assert_eq!(replies[0]["error"]["code"], SERVER_NOT_INITIALIZED);
+        assert_eq!(
+            replies[1]["result"]["capabilities"]["workspaceSymbolProvider"],
+            true
+        );
+        // Exact match first, then the other names holding p..c in order
+        let names: Vec<&str> = replies[2]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["PC", "parse_config"]);
+        let pc = &replies[2]["result"][0];
+        assert_eq!(pc["kind"], 14);
+        assert_eq!(pc["containerName"], "src/b.rs");
+        assert!(
+            pc["location"]["uri"]
+                .as_str()
+                .unwrap()
+                .ends_with("/src/b.rs")
+        );
+        assert!(
+            pc["location"]["uri"]
+                .as_str()
+                .unwrap()
+                .starts_with("file://")
+        );
+        assert_eq!(
+            pc["location"]["range"]["start"],
+            json!({"line": 8, "character": 0})
+        );
+        assert_eq!(
+            pc["location"]["range"]["end"],
+            json!({"line": 9, "character": 0})
+        );
+
+        let hits = replies[3]["result"].as_array().unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(hits[0]["uri"].as_str().unwrap().ends_with("/src/a.rs"));
+        assert_eq!(hits[0]["range"]["start"]["line"], 2);
+        let consts = replies[4]["result"].as_array().unwrap();
+        assert_eq!(consts.len(), 1);
+        assert_eq!(consts[0]["range"]["start"]["line"], 8);
+
+        assert_eq!(replies[5]["error"]["code"], METHOD_NOT_FOUND);
+        // LIKE wildcards in the query are matched literally
+        assert_eq!(replies[6]["result"], json!([]));
+        assert!(replies[7]["result"].is_null());
+        assert_eq!(replies[8]["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[test]
+    fn exit_without_shutdown_is_unclean_and_index_reloads() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        build_index(dir.path(), &[("a.rs", 1, "fn", "alpha", [1.0, 0.0])]);
+        let mut server = Server::open(dir.path(), mock_embedder).expect("open");
+        let init = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}});
+        let symbols = json!({"jsonrpc": "2.0", "id": 2, "method": "workspace/symbol", "params": {"query": "a"}});
+        let mut output = Vec::new();
+        server
+            .run(&framed(&[init, symbols.clone()])[..], &mut output)
+            .expect("run");
+        assert_eq!(replies(&output)[1]["result"].as_array().unwrap().len(), 1);
+
+        // As `cearch clean` followed by `cearch index` would
+        DB::remove_files(dir.path()).unwrap();
+        build_index(
+            dir.path(),
+            &[
+                ("a.rs", 1, "fn", "alpha", [1.0, 0.0]),
+                ("b.rs", 1, "fn", "beta", [0.0, 1.0]),
+            ],
+        );
+        let exit = json!({"jsonrpc": "2.0", "method": "exit"});
+        let mut output = Vec::new();
+        let clean = server
+            .run(&framed(&[symbols, exit])[..], &mut output)
+            .expect("run");
+        assert!(!clean);
+        assert_eq!(replies(&output)[0]["result"].as_array().unwrap().len(), 2);
+    }
+}
@@ -1,10 +1,41 @@
 use clap::{Parser, Subcommand};
+mod annotate;
+mod cache;
+mod config;
 mod db;
+mod deps;
+mod download;
 mod embed;
+mod embedding_store;
+mod encryption;
+mod forge;
+mod graph;
+mod history;
+mod import;
 mod index;
+mod index_diff;
+mod indexer;
+mod lock;
+mod logging;
+mod lsp;
+mod manifest;
+mod migrate;
+mod output;
+mod paths;
+mod plugin;
+mod progress;
+mod quarantine;
+mod query_cache;
+mod query_file;
+mod rpc;
+mod saved_queries;
+mod search;
+mod selftest;
+mod serve;
+mod snippet;
 mod symbols;
-
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+mod template;
+mod verify;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -16,6 +47,16 @@ use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Which log events to print: a level (error, warn, info, debug, trace, off) for cearch's
+    /// own, or a `tracing` filter such as `cearch::db=trace,ort=warn`; defaults to `$CEARCH_LOG`,
+    /// then warn
+    #[arg(long, global = true, value_name = "LEVEL")]
+    log_level: Option<String>,
+    /// Record spans (per-file parse, embed and insert, model calls, database writes) to this
+    /// file in Chrome trace format, for Perfetto or chrome://tracing; written when the command
+    /// finishes
+    #[arg(long, global = true, value_name = "PATH")]
+    trace_out: Option<std::path::PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -28,359 +69,4061 @@ enum Commands {
         /// Verbose output (show progress bars)
         #[arg(short = 'v', long)]
         verbose: bool,
+        /// Progress output; defaults to bars on a terminal and plain lines otherwise with -v
+        #[arg(long, value_enum)]
+        progress: Option<progress::ProgressMode>,
+        /// Maximum number of symbols to index per file (default unlimited)
+        #[arg(long)]
+        symbol_limit: Option<usize>,
+        /// SQLite page size in bytes (only applies when the index is first created)
+        #[arg(long, default_value_t = 4096)]
+        sqlite_page_size: u32,
+        /// SQLite page cache size in megabytes (default 64, or `cache_size_mb` under `[sqlite]`
+        /// in .cearch/config.toml)
+        #[arg(long)]
+        sqlite_cache_size: Option<u32>,
+        /// Use SQLite's rollback journal instead of write-ahead logging, so the index is a
+        /// single file with no `-wal`/`-shm` siblings; writes are slower and fully synced
+        #[arg(long)]
+        no_wal: bool,
+        /// Rebuild the vector index and vacuum the database at the end of a run once this many
+        /// symbols have been deleted since the last compaction (0 never compacts)
+        #[arg(long, default_value_t = 10_000)]
+        compact_after: u64,
+        /// Abandon an embedding batch after this many milliseconds and retry it in halves,
+        /// skipping the symbols that hang the model
+        #[arg(long)]
+        embed_batch_timeout: Option<u64>,
+        /// TOML file with additional `[[language]]` configs backed by compiled tree-sitter grammars
+        #[arg(long)]
+        languages_file: Option<std::path::PathBuf>,
+        /// Run the symbol extractors under `[plugins.<name>]` in .cearch/config.toml; they are
+        /// commands the repository supplies, so they are ignored without this flag
+        #[arg(long)]
+        allow_plugins: bool,
+        /// Embed identical symbols only once (first occurrence by file modification time);
+        /// copies are linked to it and reported in query results
+        #[arg(long)]
+        symbol_hash_dedup: bool,
+        /// Parse files quarantined by an earlier parser crash instead of skipping them
+        #[arg(long)]
+        retry_quarantined: bool,
+        /// Also build the FTS5 keyword index used by `cearch query --keyword`
+        #[arg(long)]
+        build_fts: bool,
+        /// Exit with status 3 if any file fails to parse, embed or insert (all files are still
+        /// processed); quarantined files are retried
+        #[arg(long)]
+        strict: bool,
+        /// Don't report how many files had no extractable symbols
+        #[arg(long)]
+        skip_empty_report: bool,
+        /// After indexing, list the file extensions that yielded no symbols (unsupported, or
+        /// parsed but empty) among the files this run visited, with the tree-sitter grammar
+        /// that would add support
+        #[arg(long)]
+        report_unsupported_extensions: bool,
+        /// Where to keep embeddings: in the SQLite index, or in a flat `.cearch/embeddings.bin`
+        /// that keeps the database small (switching requires `cearch clean`)
+        #[arg(long, value_enum, default_value_t = embedding_store::EmbeddingStore::Inline)]
+        embedding_store: embedding_store::EmbeddingStore,
+        /// Embed code without leading annotation lines (`#[...]` in Rust, `@...` in Python);
+        /// the original code is still stored and shown
+        #[arg(long)]
+        annotation_strip: bool,
+        /// Skip sizing files before the run: progress counts files instead of bytes and has
+        /// no ETA, but indexing starts while git is still listing files (the file total
+        /// appears once a separate counting pass finishes)
+        #[arg(long)]
+        no_precount: bool,
+        /// Only re-index files that `git diff --name-only` reports as changed between the
+        /// last indexed commit and HEAD; uncommitted changes are not picked up
+        #[arg(long)]
+        hash_changed_only: bool,
+        /// Continue an interrupted run: skip files the index already holds and redo the file
+        /// that was being indexed when it stopped
+        #[arg(long, conflicts_with = "hash_changed_only")]
+        resume: bool,
+        /// When the index outgrows `index.max_size_mb` (in .cearch/config.toml), delete the
+        /// largest symbols to stay within it instead of stopping
+        #[arg(long)]
+        trim: bool,
+        /// Remove the symbols of indexed files that git no longer tracks (--hash-changed-only
+        /// runs already drop deleted files)
+        #[arg(long, conflicts_with = "hash_changed_only")]
+        prune_deleted: bool,
+        /// Index the sources of the crates in Cargo.lock (from the cargo registry or vendor/)
+        /// into .cearch/deps.sqlite instead of the repository, for `query --include-deps`;
+        /// only crates whose version changed are re-indexed
+        #[arg(long, conflicts_with_all = [
+            "metadata_only", "resume", "hash_changed_only", "prune_deleted", "trim",
+        ])]
+        deps: bool,
+        /// Index the versions of files that the last N commits (or the commits since a date,
+        /// e.g. 2024-01-31) replaced or deleted into .cearch/history.sqlite, for
+        /// `query --include-history`; code still at HEAD is left out
+        #[arg(long, value_name = "N|DATE", conflicts_with_all = [
+            "metadata_only", "resume", "hash_changed_only", "prune_deleted", "trim", "deps",
+        ])]
+        history: Option<String>,
+        /// Record this commit as the indexed version instead of detecting `HEAD`
+        #[arg(long)]
+        commit_hash: Option<String>,
+        /// Append a Markdown table of the run to `$GITHUB_STEP_SUMMARY` when it is set
+        /// (GitHub Actions step summaries)
+        #[arg(long)]
+        github_summary: bool,
+        /// When the run ends, write per-language file, symbol and failure counts and the
+        /// elapsed time to this file: TOML if it ends in `.toml`, JSON otherwise
+        #[arg(long, value_name = "PATH")]
+        language_stats_file: Option<std::path::PathBuf>,
+        /// Time parsing, embedding and inserting each file, write the table to
+        /// `.cearch/profile.json` and list the 10 slowest files when the run ends
+        #[arg(long)]
+        profile: bool,
+        /// Read every embedding back after inserting it and exit with status 1 if any differs
+        /// from the computed vector (slow; for debugging storage problems)
+        #[arg(long)]
+        verify_embeddings: bool,
+        /// Only parse files and store their symbols, without loading the model or computing
+        /// embeddings; serves `cearch lookup` and `query --keyword`, but not semantic queries
+        #[arg(long, conflicts_with_all = ["verify_embeddings", "embedding_store", "embed_batch_timeout"])]
+        metadata_only: bool,
+        /// After indexing, print pairs of symbols in different files whose embeddings are
+        /// closer than this L2 distance, as `source,target,distance` CSV on stdout
+        #[arg(long, conflicts_with = "metadata_only")]
+        dedup_across_files: Option<f32>,
+        /// Also save the --dedup-across-files pairs to the index's `duplicate_pairs` table,
+        /// replacing those of the previous run
+        #[arg(long, requires = "dedup_across_files")]
+        store_duplicates: bool,
+        /// Read up to this many upcoming files at once while the current one is parsed and
+        /// embedded (helps on network filesystems); files are still indexed in order
+        #[arg(long)]
+        parallel_files: Option<usize>,
+        /// Also store the token ids each symbol was embedded from, so `cearch reembed
+        /// --from-tokens` can skip tokenizing
+        #[arg(long, conflicts_with = "metadata_only")]
+        pre_tokenize: bool,
+        /// Stop the run and exit with status 1 once more than this many embedding errors
+        /// (failed batches or symbols skipped for hanging the model) have occurred; 0 stops
+        /// at the first one
+        #[arg(long, conflicts_with = "metadata_only")]
+        max_embedding_failures: Option<usize>,
+        /// Advanced: keep the index database in this file instead of .cearch/index.sqlite
+        /// (e.g. on a RAM disk); pass it to every later command, or set CEARCH_DB_PATH
+        #[arg(long)]
+        db_path: Option<std::path::PathBuf>,
+        /// Build the index with this name, `.cearch/<name>.sqlite`, instead of the default
+        /// `index`; an `[indexes.<name>]` table in .cearch/config.toml picks its files
+        #[arg(long, conflicts_with = "db_path")]
+        index_name: Option<String>,
     },
     /// Initialize cearch in this repo (.cearch dir, .gitignore, and model cache)
     Init {},
     /// Query the index with a code snippet or description
-    Query {
+    Query(Box<QueryArgs>),
+    /// List or delete queries saved with `cearch query --save`
+    Saved {
+        #[command(subcommand)]
+        command: SavedCommand,
+    },
+    /// Print where symbols with a given name are defined (works without embeddings)
+    Lookup {
+        /// Symbol name to look up (ignoring ASCII case)
+        #[arg(long)]
+        name: String,
+        /// Only list symbols under this repo-relative path prefix (e.g. src/)
+        #[arg(long)]
+        path_prefix: Option<String>,
+        /// How to print result paths
+        #[arg(long, value_enum, default_value_t = paths::PathStyle::Cwd)]
+        path_style: paths::PathStyle,
+    },
+    /// Compare the symbols of two index databases, e.g. copies of .cearch/index.sqlite taken
+    /// before and after a refactor: added, removed, relocated and changed symbols
+    DiffIndex {
+        /// The older index database
+        before: std::path::PathBuf,
+        /// The newer index database
+        after: std::path::PathBuf,
+        /// List the symbols in each category after the counts
+        #[arg(short, long)]
+        verbose: bool,
+        /// Print the counts and the symbols in each category as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a stored symbol in full: metadata, provenance and its complete code
+    Show {
+        /// Symbol id, as printed after query results (`42` or `#42`), or `path:line`
+        target: String,
+        /// Color the code by syntax
+        #[arg(long)]
+        highlight: bool,
+        /// Open the symbol in $VISUAL or $EDITOR instead of printing it
+        #[arg(long)]
+        open: bool,
+        /// How to print the symbol's path
+        #[arg(long, value_enum, default_value_t = paths::PathStyle::Cwd)]
+        path_style: paths::PathStyle,
+        /// Print only the first N lines of the code, followed by `… (+M more lines)`
+        #[arg(long, value_name = "N")]
+        snippet_lines: Option<usize>,
+        /// Fit code lines, line numbers included, to W columns, cutting longer ones with `…`;
+        /// defaults to $COLUMNS on a terminal
+        #[arg(long, value_name = "W")]
+        snippet_width: Option<usize>,
+        /// Continue code lines wider than --snippet-width on the next line instead of cutting them
+        #[arg(long)]
+        snippet_wrap: bool,
+    },
+    /// Write the indexed symbols in another tool's format (works without embeddings)
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = output::ExportFormat::Ctags)]
+        format: output::ExportFormat,
+        /// File to write (e.g. tags)
+        #[arg(short, long)]
+        out: std::path::PathBuf,
+    },
+    /// Load embeddings computed elsewhere (e.g. by a bigger model) into the index
+    ImportEmbeddings {
+        /// JSON Lines file; each record has `path` and `line` (or `symbol_id`), optional
+        /// `name`, `kind` and `code`, the `model` name and the `vector`
+        file: std::path::PathBuf,
+        /// Insert symbols the index does not have (records need path, line, name and kind)
+        /// instead of skipping them
+        #[arg(long)]
+        create_missing: bool,
+        /// Replace the index's embeddings when they come from another model or dimension
+        #[arg(long)]
+        force: bool,
+    },
+    /// Recompute the index's embeddings with the current model
+    Reembed {
+        /// Run the model on the token ids stored by `index --pre-tokenize` instead of
+        /// tokenizing the code again (currently required)
+        #[arg(long)]
+        from_tokens: bool,
+        /// Re-embed even when the index's embeddings already come from the current model
+        #[arg(long)]
+        force: bool,
+    },
+    /// Re-embed the stored symbols with another model, without parsing any files; an
+    /// interrupted migration continues where it stopped when run again
+    MigrateModel {
+        /// Model to migrate to, e.g. Qdrant/all-MiniLM-L6-v2-onnx (see `cearch models`)
+        model: String,
+        /// Symbols embedded per model call
+        #[arg(long, default_value_t = 64)]
+        batch_size: usize,
+        /// Show a progress bar
+        #[arg(short = 'v', long)]
+        verbose: bool,
+    },
+    /// Embed a query once and write its vector for `query --query-embedding-file`
+    EmbedQuery {
         /// The query string
-        query: String,
-        /// Number of results to return
-        #[arg(short = 'n', long, default_value_t = 7)]
-        num_results: usize,
+        text: String,
+        /// File to write: the vector as little-endian f32 values, no header
+        #[arg(short, long)]
+        out: std::path::PathBuf,
+    },
+    /// Clean the index and embeddings for a repository, keeping the cached models (see
+    /// `cearch cache`)
+    Clean {
+        /// Advanced: the index database to delete, when kept outside the repository
+        #[arg(long)]
+        db_path: Option<std::path::PathBuf>,
+        /// Only delete the index built with `index --index-name <name>`
+        #[arg(long, conflicts_with = "db_path")]
+        index_name: Option<String>,
+    },
+    /// Show statistics about the index
+    Stats {
+        /// Advanced: read the index database from this file instead of .cearch/index.sqlite
+        #[arg(long)]
+        db_path: Option<std::path::PathBuf>,
+        /// Show the index built with `index --index-name <name>`
+        #[arg(long, conflicts_with = "db_path")]
+        index_name: Option<String>,
+        /// Show what the index's space is spent on, by part of the index and by directory
+        #[arg(long)]
+        size: bool,
+        /// Print the index's manifest (as in .cearch/manifest.json) read from the index
+        /// itself; differences from the manifest file are reported on stderr
+        #[arg(long, conflicts_with = "size")]
+        json: bool,
+    },
+    /// List indexed files
+    List {
+        /// List files in a supported language that had no extractable symbols instead
+        #[arg(long)]
+        empty_files: bool,
+    },
+    /// Check the index for inconsistencies between symbols and vectors
+    Check {},
+    /// Re-embed a sample of symbols with the current model and compare the vectors with the
+    /// stored ones; exits 1 when the index has drifted or has corrupt vectors
+    Verify {
+        /// Number of symbols to re-embed
+        #[arg(long, default_value_t = 100)]
+        sample: usize,
+        /// Seed for picking the sample; the same seed picks the same symbols
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Report drift when the mean cosine similarity falls below this
+        #[arg(long, default_value_t = 0.99)]
+        threshold: f32,
+    },
+    /// Index and query a throwaway repository with this installation, printing each stage's
+    /// result and timing plus environment details to paste into bug reports
+    Selftest {
+        /// Skip the model: build a keyword index and query it instead of embeddings
+        #[arg(long)]
+        no_model: bool,
+    },
+    /// Write a graph linking each symbol (or file) to its nearest neighbours, with cosine
+    /// similarity as edge weight, for Graphviz or Gephi
+    Graph {
+        /// Neighbours linked from each node
+        #[arg(long, default_value_t = 3)]
+        k: usize,
+        /// Leave out edges below this cosine similarity
+        #[arg(long, default_value_t = 0.7)]
+        threshold: f32,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = graph::GraphFormat::Dot)]
+        format: graph::GraphFormat,
+        /// File to write; standard output if omitted
+        #[arg(short, long)]
+        out: Option<std::path::PathBuf>,
+        /// One node per file, with the mean of its symbols' vectors
+        #[arg(long)]
+        per_file: bool,
+        /// Only link neighbours from this many randomly picked nodes (for large indexes)
+        #[arg(long)]
+        sample: Option<usize>,
+        /// Seed for --sample; the same seed picks the same nodes
+        #[arg(long, default_value_t = 0, requires = "sample")]
+        seed: u64,
+        /// Only include symbols under this repo-relative path prefix (e.g. src/)
+        #[arg(long)]
+        paths: Option<String>,
+    },
+    /// Flag each function or class of a working-tree file that closely resembles a symbol
+    /// elsewhere in the index, e.g. to spot copies during review
+    Annotate {
+        /// The file to annotate; it need not be indexed
+        file: std::path::PathBuf,
+        /// Only flag matches at least this cosine-similar
+        #[arg(long, default_value_t = 0.9)]
+        threshold: f32,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = annotate::AnnotateFormat::Text)]
+        format: annotate::AnnotateFormat,
+    },
+    /// Reclaim space left by re-indexed and deleted files
+    Gc {},
+    /// Encrypt the index with the key in CEARCH_INDEX_NEW_KEY, replacing the key in
+    /// CEARCH_INDEX_KEY (if any); needs a build with the `encryption` feature
+    Rekey {
+        /// Read the new key from this file instead of CEARCH_INDEX_NEW_KEY
+        #[arg(long)]
+        new_key_file: Option<std::path::PathBuf>,
+        /// Store the index unencrypted instead
+        #[arg(long, conflicts_with = "new_key_file")]
+        decrypt: bool,
+        /// Advanced: the index database to rekey, when kept outside the repository
+        #[arg(long)]
+        db_path: Option<std::path::PathBuf>,
     },
-    /// Clean the index and embeddings for a repository
-    Clean {},
+    /// Manage cached embedding models (e.g. for machines without internet access)
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+    /// Show, prune or delete the cached models and the dependency and history embeddings
+    /// (`index --deps`, `index --history`)
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Keep the model and index loaded and answer newline-delimited JSON-RPC 2.0 requests on
+    /// stdin (methods: search, similar, symbols, status, shutdown), e.g. for editor plugins
+    Rpc {},
+    /// Run a minimal Language Server Protocol server on stdio: `workspace/symbol` from the
+    /// index, plus a `cearch/semanticSearch` request taking `{query, k, filters}`
+    Lsp {},
+    /// Keep the model and index loaded and answer queries over HTTP
+    Serve {
+        /// Port to listen on (0 picks a free port)
+        #[arg(short = 'p', long, default_value_t = 7878)]
+        port: u16,
+    },
+}
+
+// Boxed in `Commands::Query`, the variant with the most options
+#[derive(clap::Args, Debug)]
+struct QueryArgs {
+    /// The query string
+    #[arg(required_unless_present_any = [
+        "near_symbol", "query_embedding_file", "saved", "file", "term",
+    ])]
+    query: Option<String>,
+    /// Search with the embedding of the first indexed symbol with this name (ignoring
+    /// case) instead of a query string
+    #[arg(long, conflicts_with_all = ["query", "server", "keyword"])]
+    near_symbol: Option<String>,
+    /// Only look up --near-symbol under this repo-relative path prefix (e.g. src/db/)
+    #[arg(long, requires = "near_symbol")]
+    near_symbol_path: Option<String>,
+    /// Search with a query vector written by `cearch embed-query` instead of loading the model
+    #[arg(long, conflicts_with_all = ["query", "near_symbol", "server", "keyword"])]
+    query_embedding_file: Option<std::path::PathBuf>,
+    /// Number of results to return
+    #[arg(short = 'n', long, default_value_t = 7)]
+    num_results: usize,
+    /// Query a running `cearch serve` instance (e.g. http://127.0.0.1:7878) instead of loading the model
+    #[arg(long)]
+    server: Option<String>,
+    /// How to print result paths
+    #[arg(long, value_enum, default_value_t = paths::PathStyle::Cwd)]
+    path_style: paths::PathStyle,
+    /// Fetch extra candidates and cut the results at the elbow of the distance curve
+    /// (still capped at --num-results)
+    #[arg(long)]
+    threshold_auto: bool,
+    /// Write results to stdout as CSV (path,line,kind,name,score,code,id)
+    #[arg(long)]
+    output_csv: bool,
+    /// Leave the code column out of --output-csv
+    #[arg(long, requires = "output_csv")]
+    no_code: bool,
+    /// Write results to stdout as a Markdown table (File, Line, Kind, Name, Score) with
+    /// GitHub-style `path#L42` links, e.g. for PR comments
+    #[arg(long, conflicts_with_all = [
+        "server", "output_csv", "output_delta_diff", "format_string", "print0",
+    ])]
+    output_markdown: bool,
+    /// With --output-markdown, follow the table with each result's code in a fenced block
+    #[arg(long, requires = "output_markdown")]
+    show_code: bool,
+    /// Print the first N lines of each result's code below it (with --show-code, cut the
+    /// fenced blocks to N lines), followed by `… (+M more lines)`
+    #[arg(long, value_name = "N", conflicts_with_all = [
+        "server", "output_csv", "format_string", "output_delta_diff", "print0",
+    ])]
+    snippet_lines: Option<usize>,
+    /// Fit code lines to W columns, cutting longer ones with `…`; defaults to $COLUMNS on
+    /// a terminal
+    #[arg(long, value_name = "W", conflicts_with = "server")]
+    snippet_width: Option<usize>,
+    /// Continue code lines wider than --snippet-width on the next line instead of cutting them
+    #[arg(long, conflicts_with = "server")]
+    snippet_wrap: bool,
+    /// Match words against the FTS5 keyword index (see `index --build-fts`), ranked by
+    /// BM25, instead of embedding the query; the model is not loaded
+    #[arg(long, visible_alias = "keyword-only", conflicts_with_all = ["server", "threshold_auto"])]
+    keyword: bool,
+    /// Rank by embedding similarity only, never consulting the keyword index (the default)
+    #[arg(long, conflicts_with = "keyword")]
+    semantic_only: bool,
+    /// Show how results changed since this query was last run: `+` new, `-` gone,
+    /// `~old → new` moved rank
+    #[arg(long, conflicts_with_all = ["server", "keyword", "output_csv"])]
+    output_delta_diff: bool,
+    /// Only return symbols of this kind
+    #[arg(long, value_parser = ["fn", "class", "const"], conflicts_with = "server")]
+    filter_kind: Option<String>,
+    /// Only return symbols under this repo-relative path prefix (e.g. src/)
+    #[arg(long, conflicts_with = "server")]
+    path_prefix: Option<String>,
+    /// Fetch this many times --num-results candidates so enough survive filtering and
+    /// the grouping of identical code
+    #[arg(long, default_value_t = search::K_EXPANSION)]
+    k_expansion: f32,
+    /// Return at most this many results from any one file, filling in with lower-ranked
+    /// hits from other files; a hit's grouped identical copies count as one
+    #[arg(long, conflicts_with = "server")]
+    per_file: Option<usize>,
+    /// List hits with identical code separately instead of grouping them under the
+    /// best-ranked copy ("also at N other locations")
+    #[arg(long, conflicts_with = "server")]
+    expand_duplicates: bool,
+    /// Print how the vectors were searched, how many candidates were fetched and how many
+    /// remained after filters
+    #[arg(short, long)]
+    verbose: bool,
+    /// Exit with status 1 unless the index was built from this commit (a prefix of its
+    /// id is enough)
+    #[arg(long, conflicts_with = "server")]
+    require_commit: Option<String>,
+    /// Print each result through a template, e.g. "{path}:{line}\t{name}\t{score:.2}";
+    /// placeholders: id, path (or path:repo, path:cwd, path:absolute), line, col, name,
+    /// qualified_name, kind, lang, score, distance, code (code:80 cuts it to 80 chars),
+    /// url (as with --url; "{url}" alone prints only links)
+    #[arg(long, conflicts_with_all = ["server", "output_csv", "output_delta_diff"])]
+    format_string: Option<String>,
+    /// Print a link to each result on the repository's forge, from the `origin` remote
+    /// (GitHub, GitLab and Bitbucket, or `[forge] url_template` in .cearch/config.toml),
+    /// pinned to the indexed commit
+    #[arg(long, conflicts_with_all = [
+        "server", "output_csv", "output_markdown", "output_delta_diff", "format_string",
+    ])]
+    url: bool,
+    /// How to print each result: `text`, or `url` for its forge link in place of its
+    /// local path
+    #[arg(long, value_enum, default_value_t = output::QueryFormat::Text, conflicts_with_all = [
+        "server", "output_csv", "output_markdown", "output_delta_diff", "format_string",
+    ])]
+    format: output::QueryFormat,
+    /// End each result with a NUL byte instead of a newline (e.g. for `xargs -0`)
+    #[arg(long, conflicts_with_all = ["server", "output_csv", "output_delta_diff"])]
+    print0: bool,
+    /// Advanced: read the index database from this file instead of .cearch/index.sqlite
+    #[arg(long, conflicts_with = "server")]
+    db_path: Option<std::path::PathBuf>,
+    /// Query the index built with `index --index-name <name>`
+    #[arg(long, conflicts_with_all = ["db_path", "server"])]
+    index_name: Option<String>,
+    /// Save the query string, --filter-kind, --path-prefix and --keyword under this name,
+    /// then run it
+    #[arg(long, requires = "query", conflicts_with = "server")]
+    save: Option<String>,
+    /// Find code similar to a file in the working tree (`path`), or to the symbol enclosing
+    /// a line of it (`path:line`); the file need not be indexed. Results from the file
+    /// itself are left out
+    #[arg(long, conflicts_with_all = [
+        "query", "near_symbol", "query_embedding_file", "keyword", "server", "save", "saved",
+    ])]
+    file: Option<String>,
+    /// A weighted term, `text:weight` (e.g. `caching:2` or `tests:-1`); each is embedded on
+    /// its own and the vectors summed by weight. Repeat for more terms; a query string
+    /// joins them with weight 1. `word^weight` in the query string does the same
+    #[arg(long, conflicts_with_all = [
+        "keyword", "server", "near_symbol", "query_embedding_file", "file", "saved",
+    ])]
+    term: Vec<String>,
+    /// Search the query string as written, without reading `word^weight` in it as weighted
+    /// terms (e.g. for code like `x^2`)
+    #[arg(long, requires = "query")]
+    no_weights: bool,
+    /// Keep results from the --file itself
+    #[arg(long, requires = "file")]
+    include_self: bool,
+    /// Also search the dependency sources indexed by `cearch index --deps`; their results
+    /// are labeled `[dep name-version]` and shown with absolute paths
+    #[arg(long, conflicts_with_all = [
+        "keyword", "within", "save_results", "output_delta_diff", "server",
+    ])]
+    include_deps: bool,
+    /// Also search the replaced and deleted code indexed by `cearch index --history`; its
+    /// results are shown as `path@commit:line`
+    #[arg(long, conflicts_with_all = ["keyword", "within", "output_delta_diff", "server"])]
+    include_history: bool,
+    /// Append who last changed each result's first line, with the date and commit, from
+    /// `git blame` (one run per file); --save-results gets a `blame` object
+    #[arg(long, conflicts_with_all = ["output_delta_diff", "server"])]
+    blame: bool,
+    /// Order to print the results in; ties are broken by path, then line. `recency`
+    /// looks up and shows blame as --blame does
+    #[arg(long, value_enum, default_value_t = search::SortOrder::Score,
+        conflicts_with_all = ["output_delta_diff", "server"])]
+    sort: search::SortOrder,
+    /// Only rank the symbols listed in this JSON file of earlier results (written by
+    /// --save-results, or a `cearch serve` response); scores come from this query alone
+    #[arg(long, conflicts_with_all = ["server", "keyword"])]
+    within: Option<std::path::PathBuf>,
+    /// Also write the results to this file as JSON, e.g. for a later --within
+    #[arg(long, conflicts_with = "server")]
+    save_results: Option<std::path::PathBuf>,
+    /// Run a query saved with --save, with the filters saved alongside it
+    #[arg(long, conflicts_with_all = [
+        "query", "near_symbol", "query_embedding_file", "save", "filter_kind", "path_prefix",
+        "keyword", "semantic_only", "server",
+    ])]
+    saved: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum SavedCommand {
+    /// List saved queries with their filters
+    List {
+        /// Print them as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete a saved query
+    Rm {
+        /// Name given to --save
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Print where the cached models and embeddings are and the space they take
+    Info {},
+    /// Delete the cached models not loaded within an age, e.g. `--older-than 90d`
+    Prune {
+        /// Age in hours, days or weeks: 12h, 90d, 2w
+        #[arg(long)]
+        older_than: String,
+    },
+    /// Delete the cached models and embeddings, or only one of them, after confirmation
+    Clear {
+        /// Only delete the cached models
+        #[arg(long, conflicts_with = "embeddings")]
+        models: bool,
+        /// Only delete the dependency and history embeddings
+        #[arg(long)]
+        embeddings: bool,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ModelsCommand {
+    /// Install a model from a directory or .tar/.tar.gz archive instead of downloading it
+    Install {
+        /// Model name, e.g. Qdrant/all-MiniLM-L6-v2-onnx
+        name: String,
+        /// Directory or archive holding model.onnx, tokenizer.json and the config files
+        #[arg(long)]
+        from: std::path::PathBuf,
+    },
+    /// Write a cached model to a .tar/.tar.gz archive for `models install`
+    Export {
+        /// Model name, e.g. Qdrant/all-MiniLM-L6-v2-onnx
+        name: String,
+        /// Archive to write
+        #[arg(long)]
+        to: std::path::PathBuf,
+    },
+}
+
+/// Ask `question` on the terminal; anything but `y` or `yes` is no. Without a terminal to
+/// ask on, exits unless `yes` was given.
+fn confirm(question: &str, yes: bool) -> bool {
+    use std::io::{BufRead, IsTerminal, Write};
+    if yes {
+        return true;
+    }
+    if !std::io::stdin().is_terminal() {
+        eprintln!("error: not asking for confirmation without a terminal; pass --yes");
+        std::process::exit(2);
+    }
+    eprint!("{} [y/N] ", question);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Directories listed in a size breakdown (`stats --size`, the index size budget).
+const SIZE_TOP_DIRECTORIES: usize = 10;
+
+/// Printed before each line of a query result's `--snippet-lines` code.
+const SNIPPET_INDENT: &str = "    ";
+
+/// Links to results on the forge of the repository's remote, pinned to the indexed commit
+/// (or `HEAD`), exiting when the remote is not on a forge cearch can link to.
+fn forge_links_or_exit(root: &std::path::Path, db: &db::DB) -> forge::Permalinks {
+    let config = match config::Config::load(root) {
+        Ok(config) => config.forge,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    if let Some(template) = &config.url_template
+        && let Err(err) = forge::validate_template(template)
+    {
+        eprintln!("error: [forge] in config: {}", err);
+        std::process::exit(2);
+    }
+    let remote_name = config.remote.as_deref().unwrap_or("origin");
+    let Some(remote_url) = index::remote_url(root, remote_name) else {
+        eprintln!("error: --url needs a remote named {}", remote_name);
+        std::process::exit(2);
+    };
+    let Some(remote) = forge::parse_remote(&remote_url) else {
+        eprintln!(
+            "error: remote {} ({}) is not on a web forge",
+            remote_name, remote_url
+        );
+        std::process::exit(2);
+    };
+    let Some(template) = forge::template_for(&remote, config.url_template.as_deref()) else {
+        eprintln!(
+            "error: no link layout known for {}; set url_template under [forge] in {}",
+            remote.host,
+            config::Config::file_path(root).display()
+        );
+        std::process::exit(2);
+    };
+    let template = template.to_string();
+    let commit = match db.git_commit() {
+        Ok(Some(commit)) => Ok(commit),
+        Ok(None) => index::head_commit(root),
+        Err(err) => Err(format!("failed to inspect sqlite index: {}", err)),
+    };
+    let commit = match commit {
+        Ok(commit) => commit,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    // A commit that is not in this clone leaves every file without a pinned link
+    let changed = index::paths_changed_since(root, &commit);
+    let at_commit = index::paths_at_commit(root, &commit);
+    let (changed, at_commit) = match (changed, at_commit) {
+        (Ok(changed), Ok(at_commit)) => (changed, at_commit),
+        (Err(err), _) | (_, Err(err)) => {
+            tracing::warn!("{}", err);
+            Default::default()
+        }
+    };
+    forge::Permalinks {
+        remote,
+        template,
+        commit,
+        branch: index::current_branch(root),
+        changed,
+        at_commit,
+    }
+}
+
+/// Resolve the git repository root from the current working directory, exiting on failure.
+fn repo_root_or_exit() -> std::path::PathBuf {
+    let cwd = match std::env::current_dir() {
+        Ok(dir) => dir,
+        Err(err) => {
+            eprintln!("error: failed to read current directory: {}", err);
+            std::process::exit(2);
+        }
+    };
+    match index::repo_root(&cwd) {
+        Some(dir) => dir,
+        None => {
+            eprintln!("error: not inside a git repository: {}", cwd.display());
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Model recorded in the repository's index, when there is an index that records one.
+fn stored_embedding_model(root: &std::path::Path) -> Option<String> {
+    if !db::DB::file_path(root).exists() {
+        return None;
+    }
+    db::DB::open_read(root).ok()?.embedding_model().ok()?
+}
+
+/// Embed the texts a query is made of with the index's model (`model`, its
+/// `embedding_model`), exiting on failure.
+fn embed_query_texts<T: AsRef<str>>(texts: &[T], model: Option<&str>) -> Vec<Vec<f32>> {
+    let mut embedder = match embed::Embedder::for_index(model) {
+        Ok(e) => e,
+        Err(err) => {
+            eprintln!("error: failed to init embedder: {}", err);
+            std::process::exit(2);
+        }
+    };
+    match embedder.embed(texts) {
+        Ok(vectors) if vectors.len() == texts.len() => vectors,
+        Ok(_) => {
+            eprintln!("error: empty embedding");
+            std::process::exit(2);
+        }
+        Err(err) => {
+            eprintln!("error: failed to embed query: {}", err);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Embed the query string, exiting on failure.
+fn embed_query(query: &str, model: Option<&str>) -> Vec<f32> {
+    embed_query_texts(&[query], model).remove(0)
+}
+
+/// Query vector for `query --file`: the mean of its pieces' embeddings.
+fn embed_file_query(texts: &[String], model: Option<&str>) -> Vec<f32> {
+    query_file::mean_pool(&embed_query_texts(texts, model)).unwrap_or_else(|| {
+        eprintln!("error: empty embedding");
+        std::process::exit(2);
+    })
+}
+
+/// Query vector for a weighted query: its terms' embeddings summed by weight.
+fn embed_weighted_query(terms: &[search::WeightedTerm], model: Option<&str>) -> Vec<f32> {
+    let texts: Vec<&str> = terms.iter().map(|t| t.text.as_str()).collect();
+    let vectors = embed_query_texts(&texts, model);
+    let weighted: Vec<(f32, Vec<f32>)> = terms.iter().map(|t| t.weight).zip(vectors).collect();
+    search::combine_weighted(&weighted).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
+    })
+}
+
+/// Stored embedding of the first symbol named `name`, exiting if there is none.
+fn near_symbol_embedding(db: &db::DB, name: &str, path_prefix: Option<&str>) -> Vec<f32> {
+    let symbol = match db.search_by_name(name, path_prefix) {
+        Ok(Some(symbol)) => symbol,
+        Ok(None) => {
+            match path_prefix {
+                Some(prefix) => {
+                    eprintln!("error: no indexed symbol named {} under {}", name, prefix)
+                }
+                None => eprintln!("error: no indexed symbol named {}", name),
+            }
+            std::process::exit(2);
+        }
+        Err(err) => {
+            eprintln!("error: symbol lookup failed: {}", err);
+            std::process::exit(2);
+        }
+    };
+    eprintln!("using symbol: {}:{}", symbol.path, symbol.line);
+    match db.get_embedding(symbol.vector_rowid) {
+        Ok(Some(embedding)) => embedding,
+        Ok(None) => {
+            eprintln!(
+                "error: {}:{} has no stored embedding; run `cearch check`",
+                symbol.path, symbol.line
+            );
+            std::process::exit(2);
+        }
+        Err(err) => {
+            eprintln!("error: failed to read embedding: {}", err);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Rowid of the symbol `target` names: an id (`42` or `#42`), or `path:line` with the path
+/// relative to `cwd` or to the repo root. Exits with status 1 if nothing matches.
+fn resolve_symbol_target(
+    db: &db::DB,
+    root: &std::path::Path,
+    cwd: &std::path::Path,
+    target: &str,
+) -> i64 {
+    if let Ok(id) = target.trim_start_matches('#').parse::<i64>() {
+        return id;
+    }
+    let Some((path, line)) = target
+        .rsplit_once(':')
+        .and_then(|(path, line)| Some((path, line.parse::<usize>().ok()?)))
+    else {
+        eprintln!("error: expected a symbol id or path:line, not {}", target);
+        std::process::exit(2);
+    };
+    let from_cwd = cwd.join(path);
+    let from_cwd = from_cwd.canonicalize().unwrap_or(from_cwd);
+    let candidates = [
+        paths::to_stored(root, &from_cwd),
+        path.trim_start_matches("./").to_string(),
+    ];
+    for stored in &candidates {
+        match db.symbols_at_line(stored, line, None, None) {
+            Ok(ids) if ids.len() == 1 => return ids[0],
+            Ok(ids) if ids.len() > 1 => {
+                eprintln!(
+                    "error: several symbols start at {}:{}; use the id from `cearch query`",
+                    stored, line
+                );
+                std::process::exit(2);
+            }
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("error: symbol lookup failed: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+    eprintln!("no symbol starts at {}", target);
+    std::process::exit(1);
+}
+
+/// A side index (`index --deps`, `--history`): a database of its own next to the
+/// repository's index, filled by the same indexer with the same options.
+struct SideIndex {
+    db: db::DB,
+    embedder: embed::BatchEmbedder,
+    opts: indexer::Options,
+}
+
+impl SideIndex {
+    /// Load the model and open the side database with `open` (`DB::open_deps` or
+    /// `DB::open_history`), exiting on failure; `what` names the database in errors.
+    fn open(
+        root: &std::path::Path,
+        open: fn(&std::path::Path, usize, &db::WriteOptions) -> anyhow::Result<db::DB>,
+        what: &str,
+        symbol_limit: Option<usize>,
+        embed_batch_timeout: Option<u64>,
+    ) -> Self {
+        // Queries against the side index are embedded with the main index's model
+        let embedder = match embed::Embedder::for_index(stored_embedding_model(root).as_deref()) {
+            Ok(e) => e,
+            Err(err) => {
+                eprintln!("error: failed to init embedder: {}", err);
+                std::process::exit(2);
+            }
+        };
+        let model = embedder.model_repo();
+        let dim = embedder.dimension().unwrap_or(384);
+        let embedder = embed::BatchEmbedder::new(
+            embedder,
+            embed_batch_timeout.map(std::time::Duration::from_millis),
+        );
+        let config = match config::Config::load(root) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            }
+        };
+        let write_opts = db::WriteOptions::default().with_config(&config.sqlite, &config.vector);
+        let db = match open(root, dim, &write_opts) {
+            Ok(db) => db,
+            Err(err) => {
+                eprintln!("error: failed to open the {} index: {}", what, err);
+                std::process::exit(2);
+            }
+        };
+        if let Err(err) = db.set_meta("embedding_model", model) {
+            tracing::warn!("failed to update index metadata: {}", err);
+        }
+        let opts = indexer::Options {
+            symbol_limit,
+            symbol_hash_dedup: false,
+            retry_quarantined: false,
+            build_fts: false,
+            sqlite_cache_size_mb: write_opts.cache_size_mb,
+            skip_empty_report: true,
+            annotation_strip: false,
+            verify_embeddings: false,
+            report_unsupported_extensions: false,
+            profile: false,
+            max_size_bytes: None,
+            trim: false,
+        };
+        Self { db, embedder, opts }
+    }
+
+    /// Index the `total` files that `files` hands to the indexer, under the progress message
+    /// `title`, leaving out symbols whose code hash is `known`.
+    fn run(
+        &mut self,
+        root: &std::path::Path,
+        progress: &progress::Progress,
+        title: &str,
+        total: usize,
+        known: Option<std::collections::HashSet<[u8; 32]>>,
+        files: impl FnOnce(&db::DB, &mut indexer::Indexer, &mut embed::BatchEmbedder),
+    ) -> progress::RunStats {
+        let mut run = indexer::Indexer::new(root, &self.db, progress, &self.opts, Some(total));
+        if let Some(known) = known {
+            run.skip_known_code(known);
+        }
+        progress.start(&run.stats, title);
+        files(&self.db, &mut run, &mut self.embedder);
+        run.finish()
+    }
+}
+
+/// `cearch index --deps`: index the sources of the crates in Cargo.lock into
+/// `.cearch/deps.sqlite`. Crates already indexed at their locked version are kept; those
+/// no longer locked (e.g. after a version bump) are removed.
+fn index_deps(
+    root: &std::path::Path,
+    progress: progress::Progress,
+    symbol_limit: Option<usize>,
+    embed_batch_timeout: Option<u64>,
+) {
+    let _index_lock = match lock::IndexLock::acquire(root) {
+        Ok(lock) => lock,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let located = match deps::locate(root, deps::cargo_home().as_deref()) {
+        Ok(located) => located,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    // Crates for other platforms are often never fetched, so they are only summarised
+    if !located.missing.is_empty() {
+        let shown = located.missing.iter().take(5).cloned().collect::<Vec<_>>();
+        tracing::warn!(
+            "no sources found for {} crate{} ({}{}); run `cargo fetch` (git dependencies are not supported)",
+            located.missing.len(),
+            if located.missing.len() == 1 { "" } else { "s" },
+            shown.join(", "),
+            if located.missing.len() > shown.len() {
+                ", ..."
+            } else {
+                ""
+            }
+        );
+    }
+    let mut side = SideIndex::open(
+        root,
+        db::DB::open_deps,
+        "dependency",
+        symbol_limit,
+        embed_batch_timeout,
+    );
+    let indexed = match side.db.dep_crates() {
+        Ok(indexed) => indexed,
+        Err(err) => {
+            eprintln!("error: failed to read the dependency index: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let locked: std::collections::HashSet<String> =
+        located.found.iter().map(deps::DepCrate::label).collect();
+    let mut removed = 0;
+    for label in indexed.keys().filter(|label| !locked.contains(*label)) {
+        match side.db.remove_dep_crate(label) {
+            Ok(_) => removed += 1,
+            Err(err) => {
+                eprintln!("error: failed to remove {}: {}", label, err);
+                std::process::exit(2);
+            }
+        }
+    }
+    let pending: Vec<(&deps::DepCrate, Vec<std::path::PathBuf>)> = located
+        .found
+        .iter()
+        .filter(|krate| !indexed.contains_key(&krate.label()))
+        .map(|krate| (krate, deps::source_files(&krate.dir)))
+        .collect();
+    let files_total = pending.iter().map(|(_, files)| files.len()).sum();
+    side.run(
+        root,
+        &progress,
+        "Indexing dependencies",
+        files_total,
+        None,
+        |db, run, embedder| {
+            for (krate, files) in &pending {
+                let label = krate.label();
+                // Whatever an interrupted run left of the crate is indexed again
+                if let Err(err) = db.remove_dep_crate(&label) {
+                    eprintln!("error: failed to remove {}: {}", label, err);
+                    std::process::exit(2);
+                }
+                run.store_paths_under(&krate.dir, &label);
+                for f in files {
+                    run.index_file(f, None, Some(&mut *embedder));
+                }
+                if let Err(err) = db.record_dep_crate(&label, &krate.dir) {
+                    eprintln!("error: failed to record {}: {}", label, err);
+                    std::process::exit(2);
+                }
+            }
+        },
+    );
+    eprintln!(
+        "dependencies: {} crates indexed, {} unchanged, {} removed, {} without sources",
+        pending.len(),
+        located.found.len() - pending.len(),
+        removed,
+        located.missing.len()
+    );
+}
+
+/// `cearch index --history`: index the file versions in `range` that `HEAD` no longer has
+/// into `.cearch/history.sqlite`. Versions indexed by an earlier run are skipped, as are
+/// symbols whose code the repository's index (or an older version) already holds.
+fn index_history(
+    root: &std::path::Path,
+    range: &history::Range,
+    progress: progress::Progress,
+    symbol_limit: Option<usize>,
+    embed_batch_timeout: Option<u64>,
+) {
+    let _index_lock = match lock::IndexLock::acquire(root) {
+        Ok(lock) => lock,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let blobs = match index::historical_blobs(root, &range.git_args()) {
+        Ok(blobs) => blobs,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let current = if db::DB::file_path(root).exists() {
+        db::DB::open_read(root).and_then(|db| db.all_code_hashes())
+    } else {
+        Ok(std::collections::HashSet::new())
+    };
+    let mut known = match current {
+        Ok(known) => known,
+        Err(err) => {
+            eprintln!("error: failed to read the index: {}", err);
+            std::process::exit(2);
+        }
+    };
+    if known.is_empty() {
+        tracing::warn!(
+            "the repository is not indexed yet; run `cearch index` first so code still at HEAD is left out"
+        );
+    }
+    let mut side = SideIndex::open(
+        root,
+        db::DB::open_history,
+        "history",
+        symbol_limit,
+        embed_batch_timeout,
+    );
+    let indexed = side.db.history_blobs().and_then(|indexed| {
+        known.extend(side.db.all_code_hashes()?);
+        Ok(indexed)
+    });
+    let indexed = match indexed {
+        Ok(indexed) => indexed,
+        Err(err) => {
+            eprintln!("error: failed to read the history index: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let supported: Vec<&index::HistoricalBlob> = blobs
+        .iter()
+        .filter(|b| symbols::language_name(&b.path).is_some())
+        .collect();
+    let pending: Vec<&index::HistoricalBlob> = supported
+        .iter()
+        .copied()
+        .filter(|b| !indexed.contains(&b.blob))
+        .collect();
+    let mut unreadable = 0;
+    let stats = side.run(
+        root,
+        &progress,
+        "Indexing history",
+        pending.len(),
+        Some(known),
+        |db, run, embedder| {
+            for blob in &pending {
+                let source = match index::read_blob(root, &blob.blob) {
+                    Ok(source) => source,
+                    Err(err) => {
+                        tracing::debug!(
+                            "skipped {} at {}: {}",
+                            blob.path.display(),
+                            blob.commit,
+                            err
+                        );
+                        unreadable += 1;
+                        continue;
+                    }
+                };
+                run.store_paths_under(root, &blob.commit);
+                run.index_file(&blob.path, Some(Ok(source)), Some(&mut *embedder));
+                if let Err(err) = db.record_history_blob(&blob.blob) {
+                    eprintln!("error: failed to record {}: {}", blob.blob, err);
+                    std::process::exit(2);
+                }
+            }
+        },
+    );
+    eprintln!(
+        "history: {} file versions indexed ({} new symbols), {} indexed before, {} not UTF-8",
+        pending.len() - unreadable,
+        stats.symbols,
+        supported.len() - pending.len(),
+        unreadable
+    );
+}
+
+/// Open `path` at `line` in `$VISUAL` or `$EDITOR` (falling back to `vi`) and wait for it.
+///
+/// VS Code-style editors get `--goto path:line`; everything else gets `+line path`, which
+/// vi, Vim, Emacs, nano and most others understand.
+fn open_in_editor(path: &std::path::Path, line: usize) -> Result<(), String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let mut command = std::process::Command::new(program);
+    command.args(words);
+    let name = std::path::Path::new(program)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+    if matches!(name, "code" | "code-insiders" | "codium" | "cursor") {
+        command
+            .arg("--goto")
+            .arg(format!("{}:{}", path.display(), line));
+    } else {
+        command.arg(format!("+{}", line)).arg(path);
+    }
+    match command.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} exited with {}", program, status)),
+        Err(err) => Err(format!("failed to run {}: {}", program, err)),
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
+    // Flushed on drop; exiting early with `process::exit` leaves the trace incomplete
+    let _trace = match logging::init(cli.log_level.as_deref(), cli.trace_out.as_deref()) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+
+    // A database kept outside the repository; CEARCH_DB_PATH applies to every command
+    let db_path = match &cli.command {
+        Commands::Index { db_path, .. }
+        | Commands::Clean { db_path, .. }
+        | Commands::Rekey { db_path, .. }
+        | Commands::Stats { db_path, .. } => db_path.clone(),
+        Commands::Query(args) => args.db_path.clone(),
+        _ => None,
+    }
+    .or_else(|| {
+        std::env::var_os("CEARCH_DB_PATH")
+            .filter(|p| !p.is_empty())
+            .map(Into::into)
+    });
+    // One of several indexes kept side by side in .cearch
+    let index_name = match &cli.command {
+        Commands::Index { index_name, .. }
+        | Commands::Clean { index_name, .. }
+        | Commands::Stats { index_name, .. } => index_name.clone(),
+        Commands::Query(args) => args.index_name.clone(),
+        _ => None,
+    };
+    if let Some(name) = &index_name {
+        if db_path.is_some() {
+            eprintln!("error: --index-name cannot be combined with CEARCH_DB_PATH");
+            std::process::exit(2);
+        }
+        if let Err(err) = db::set_index_name(name) {
+            eprintln!("error: {:#}", err);
+            std::process::exit(2);
+        }
+    }
+    if let Some(path) = db_path
+        && let Err(err) = db::set_path_override(&path)
+    {
+        eprintln!("error: {:#}", err);
+        std::process::exit(2);
+    }
+    if let Err(err) = encryption::load_key() {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
+    }
 
     match cli.command {
-        Commands::Index { force: _, verbose } => {
-            let cwd = match std::env::current_dir() {
-                Ok(dir) => dir,
+        Commands::Index {
+            force: _,
+            verbose,
+            progress,
+            symbol_limit,
+            sqlite_page_size,
+            sqlite_cache_size,
+            no_wal,
+            compact_after,
+            embed_batch_timeout,
+            languages_file,
+            allow_plugins,
+            symbol_hash_dedup,
+            retry_quarantined,
+            build_fts,
+            strict,
+            skip_empty_report,
+            report_unsupported_extensions,
+            embedding_store,
+            annotation_strip,
+            no_precount,
+            hash_changed_only,
+            resume,
+            prune_deleted,
+            deps,
+            history,
+            trim,
+            commit_hash,
+            github_summary,
+            language_stats_file,
+            profile,
+            verify_embeddings,
+            metadata_only,
+            dedup_across_files,
+            store_duplicates,
+            parallel_files,
+            pre_tokenize,
+            max_embedding_failures,
+            db_path: _,
+            index_name: _,
+        } => {
+            let root = repo_root_or_exit();
+            if deps {
+                let progress =
+                    progress::Progress::new(progress::ProgressMode::resolve(progress, verbose));
+                index_deps(&root, progress, symbol_limit, embed_batch_timeout);
+                return;
+            }
+            if let Some(spec) = history {
+                let range = match history::Range::parse(&spec) {
+                    Ok(range) => range,
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
+                };
+                let progress =
+                    progress::Progress::new(progress::ProgressMode::resolve(progress, verbose));
+                index_history(&root, &range, progress, symbol_limit, embed_batch_timeout);
+                return;
+            }
+            if let Some(threshold) = dedup_across_files
+                && !(threshold.is_finite() && threshold > 0.0)
+            {
+                eprintln!("error: --dedup-across-files must be a positive distance");
+                std::process::exit(2);
+            }
+            if parallel_files == Some(0) {
+                eprintln!("error: --parallel-files must be at least 1");
+                std::process::exit(2);
+            }
+            if let Some(languages_file) = languages_file {
+                let registered = symbols::load_languages_file(&languages_file)
+                    .and_then(symbols::register_dynamic_languages);
+                if let Err(err) = registered {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            }
+            // Held until the end of the run; a lock left by an early exit is detected as stale
+            let index_lock = match lock::IndexLock::acquire(&root) {
+                Ok(lock) => lock,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            if let Some(sha) = &commit_hash
+                && (sha.is_empty() || !sha.chars().all(|c| c.is_ascii_hexdigit()))
+            {
+                eprintln!(
+                    "error: --commit-hash must be a hexadecimal commit id, not {}",
+                    sha
+                );
+                std::process::exit(2);
+            }
+            // Recorded after the run, so a commit made while indexing is picked up next time
+            let head = commit_hash.or_else(|| index::head_commit(&root).ok());
+            let changed: Option<std::collections::HashSet<std::path::PathBuf>> =
+                if hash_changed_only {
+                    let last = db::DB::open_read(&root)
+                        .ok()
+                        .and_then(|db| db.git_commit().ok().flatten());
+                    match (last, &head) {
+                        (Some(last), Some(_)) => match index::files_changed_since(&root, &last) {
+                            Ok(files) => {
+                                if verbose {
+                                    eprintln!("{} files changed since {}", files.len(), last);
+                                }
+                                Some(files.into_iter().collect())
+                            }
+                            Err(err) => {
+                                eprintln!(
+                                    "error: {}; run `cearch clean` and re-index without --hash-changed-only",
+                                    err
+                                );
+                                std::process::exit(2);
+                            }
+                        },
+                        _ => {
+                            tracing::warn!("no indexed commit recorded; indexing all files");
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+            // Files fully indexed by the interrupted run; the last one inserted may be partial
+            let mut unfinished = None;
+            let done: std::collections::HashSet<String> = if resume {
+                let db = db::DB::open_read(&root).ok();
+                let previous = db.as_ref().map(|db| {
+                    unfinished = db.last_inserted_path().ok().flatten();
+                    db.indexed_files()
+                        .and_then(|files| Ok((files, db.empty_files()?)))
+                });
+                match previous {
+                    Some(Ok((indexed, empty))) => indexed
+                        .into_iter()
+                        .chain(empty)
+                        .filter(|path| Some(path) != unfinished.as_ref())
+                        .collect(),
+                    Some(Err(err)) => {
+                        eprintln!("error: failed to read the interrupted run: {}", err);
+                        std::process::exit(2);
+                    }
+                    None => Default::default(),
+                }
+            } else {
+                Default::default()
+            };
+            if resume && verbose {
+                eprintln!("resuming: {} files already indexed", done.len());
+            }
+            // What a named index holds, from its `[indexes.<name>]` table, and the plugins
+            // extracting languages cearch cannot parse
+            let (index_profile, plugins) = match config::Config::load(&root) {
+                Ok(mut config) => (
+                    config.indexes.remove(db::index_name()).unwrap_or_default(),
+                    plugin::from_config(&config.plugins),
+                ),
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let plugins = match plugins {
+                Ok(plugins) if !allow_plugins && !plugins.is_empty() => {
+                    let names: Vec<&str> = plugins.iter().map(|p| p.name).collect();
+                    tracing::warn!(
+                        "not running plugins configured in {} ({}); pass --allow-plugins to run them",
+                        config::Config::file_path(&root).display(),
+                        names.join(", ")
+                    );
+                    Vec::new()
+                }
+                Ok(plugins) => plugins,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            if let Some(model) = &index_profile.model
+                && let Err(err) = embed::resolve_model_name(model)
+            {
+                eprintln!("error: [indexes.{}] in config: {}", db::index_name(), err);
+                std::process::exit(2);
+            }
+            // Languages can be left out at build time, or added with --languages-file
+            let supported = symbols::supported_languages();
+            if let Some(unknown) = index_profile
+                .languages
+                .iter()
+                .find(|l| !supported.iter().any(|s| s.eq_ignore_ascii_case(l)))
+            {
+                eprintln!(
+                    "error: [indexes.{}] in config: unsupported language {:?} (supported: {})",
+                    db::index_name(),
+                    unknown,
+                    supported.join(", ")
+                );
+                std::process::exit(2);
+            }
+            let manifest_profile = index_profile.clone();
+            let wanted = {
+                let (changed, root) = (changed.clone(), root.clone());
+                move |f: &std::path::PathBuf| {
+                    let stored = paths::to_stored(&root, f);
+                    changed.as_ref().is_none_or(|changed| changed.contains(f))
+                        && !done.contains(&stored)
+                        && index_profile.keeps(&stored)
+                }
+            };
+            // By default the whole listing is sized up front so progress and ETA follow bytes.
+            // With --no-precount files are indexed as git lists them, so a huge repo starts
+            // without a pause. Dedup keeps the oldest copy and needs the whole list to visit
+            // files oldest-first
+            type Listing = Box<dyn Iterator<Item = Result<std::path::PathBuf, String>>>;
+            type Counted = Option<std::sync::mpsc::Receiver<usize>>;
+            let listing: Result<(Listing, Option<usize>, Option<u64>, Counted), String> =
+                if symbol_hash_dedup || !no_precount {
+                    index::list_git_tracked_files(&root).map(|mut files| {
+                        files.retain(&wanted);
+                        if symbol_hash_dedup {
+                            index::sort_by_modification_time(&mut files);
+                        }
+                        let bytes = (!no_precount).then(|| progress::precount(&files));
+                        let total = files.len();
+                        (
+                            Box::new(files.into_iter().map(Ok)) as Listing,
+                            Some(total),
+                            bytes,
+                            None,
+                        )
+                    })
+                } else {
+                    index::stream_git_tracked_files(&root).map(|files| {
+                        // A second listing only counts the files, so the bar gets its total
+                        // without the list being held in memory or indexing waiting for it
+                        let wanted = std::sync::Arc::new(wanted);
+                        let (counted, count) = std::sync::mpsc::channel();
+                        let (counter_wanted, counter_root) =
+                            (std::sync::Arc::clone(&wanted), root.clone());
+                        std::thread::spawn(move || {
+                            let total = index::stream_git_tracked_files(&counter_root).and_then(
+                                |mut files| {
+                                    files.try_fold(0, |n, f| {
+                                        f.map(|f| n + usize::from(counter_wanted(&f)))
+                                    })
+                                },
+                            );
+                            if let Ok(total) = total {
+                                let _ = counted.send(total);
+                            }
+                        });
+                        let files = files.filter(move |f| f.as_ref().map_or(true, |f| wanted(f)));
+                        (Box::new(files) as Listing, None, None, Some(count))
+                    })
+                };
+            match listing {
+                Ok((files, files_total, bytes_total, count)) => {
+                    // Initialize embedder up-front (may download/cold-start); avoid drawing bars during this
+                    let mut tokenizer = None;
+                    // An index migrated to another model goes on being embedded with it
+                    let (mut model, mut dim) = (embed::DEFAULT_MODEL_REPO, 384);
+                    let mut embedder = if metadata_only {
+                        None
+                    } else {
+                        match embed::Embedder::for_index(stored_embedding_model(&root).as_deref()) {
+                            Ok(e) => {
+                                model = e.model_repo();
+                                dim = e.dimension().unwrap_or(dim);
+                                if pre_tokenize {
+                                    tokenizer = Some(e.tokenizer());
+                                }
+                                Some(embed::BatchEmbedder::new(
+                                    e,
+                                    embed_batch_timeout.map(std::time::Duration::from_millis),
+                                ))
+                            }
+                            Err(err) => {
+                                eprintln!("error: failed to init embedder: {}", err);
+                                std::process::exit(2);
+                            }
+                        }
+                    };
+
+                    // Open DB with model dimension
+                    let config = match config::Config::load(&root) {
+                        Ok(config) => config,
+                        Err(err) => {
+                            eprintln!("error: {}", err);
+                            std::process::exit(2);
+                        }
+                    };
+                    let mut write_opts = db::WriteOptions {
+                        page_size: sqlite_page_size,
+                        ..db::WriteOptions::default()
+                    }
+                    .with_config(&config.sqlite, &config.vector);
+                    if let Some(mb) = sqlite_cache_size {
+                        write_opts.cache_size_mb = mb;
+                    }
+                    write_opts.journal_mode = Some(if no_wal {
+                        db::JournalMode::Delete
+                    } else {
+                        db::JournalMode::Wal
+                    });
+                    let opened = match embedding_store {
+                        _ if metadata_only => db::DB::open_metadata_only(&root, &write_opts),
+                        embedding_store::EmbeddingStore::Inline => {
+                            db::DB::open_with_dim(&root, dim, &write_opts)
+                        }
+                        embedding_store::EmbeddingStore::Separate => {
+                            db::DB::open_split(&root, dim, &write_opts)
+                        }
+                    };
+                    let db = match opened {
+                        Ok(db) => db,
+                        Err(err) => {
+                            eprintln!("error: failed to open sqlite index: {}", err);
+                            std::process::exit(2);
+                        }
+                    };
+                    if !metadata_only {
+                        match db.embedding_model() {
+                            Ok(Some(stored)) if !embed::is_loadable(&stored) => {
+                                eprintln!(
+                                    "error: index holds embeddings imported from {}; run `cearch clean` before indexing with {}",
+                                    stored, model
+                                );
+                                std::process::exit(2);
+                            }
+                            Ok(_) => {}
+                            Err(err) => {
+                                eprintln!("error: failed to read index metadata: {}", err);
+                                std::process::exit(2);
+                            }
+                        }
+                        if let Err(err) = db.set_meta("embedding_model", model) {
+                            tracing::warn!("failed to update index metadata: {}", err);
+                        }
+                    }
+                    // Tokens are only valid for the tokenizer that produced them
+                    if pre_tokenize && let Err(err) = db.set_meta("tokens_model", model) {
+                        eprintln!("error: failed to update index metadata: {}", err);
+                        std::process::exit(2);
+                    }
+                    match db.get_meta("indexed_at") {
+                        Ok(None) if !resume && matches!(db.count_symbols(), Ok(n) if n > 0) => {
+                            tracing::warn!(
+                                "the last index run did not finish; pass --resume to continue it, or run `cearch clean` to start over"
+                            );
+                        }
+                        _ => {}
+                    }
+                    // Cleared until this run completes, so an interrupted run can be detected
+                    if let Err(err) = db.remove_meta("indexed_at") {
+                        tracing::warn!("failed to update index metadata: {}", err);
+                    }
+                    // Changed files are indexed afresh and deleted ones just disappear; so is the
+                    // file an interrupted run stopped in
+                    let redo = changed
+                        .iter()
+                        .flatten()
+                        .map(|f| paths::to_stored(&root, f))
+                        .chain(unfinished);
+                    for path in redo {
+                        if let Err(err) = db.delete_file(&path) {
+                            eprintln!("error: failed to remove old symbols of {}: {}", path, err);
+                            std::process::exit(2);
+                        }
+                    }
+                    // A full run never revisits files deleted from git; drop them when asked
+                    if prune_deleted {
+                        let pruned = index::list_git_tracked_files(&root)
+                            .map_err(anyhow::Error::msg)
+                            .and_then(|files| {
+                                let tracked: std::collections::HashSet<String> =
+                                    files.iter().map(|f| paths::to_stored(&root, f)).collect();
+                                db.prune_deleted(&tracked)
+                            });
+                        match pruned {
+                            Ok((_, 0)) => {}
+                            Ok((symbols, files)) => eprintln!(
+                                "pruned {} symbol{} from {} deleted file{}",
+                                symbols,
+                                if symbols == 1 { "" } else { "s" },
+                                files,
+                                if files == 1 { "" } else { "s" }
+                            ),
+                            Err(err) => {
+                                eprintln!("error: failed to prune deleted files: {}", err);
+                                std::process::exit(2);
+                            }
+                        }
+                    }
+                    match db.page_size() {
+                        Ok(actual) if actual != sqlite_page_size => tracing::warn!(
+                            "existing index uses page size {} (requested {}); run `cearch clean` and re-index to change it",
+                            actual,
+                            sqlite_page_size
+                        ),
+                        Ok(_) => {}
+                        Err(err) => tracing::warn!("failed to read page size: {}", err),
+                    }
+
+                    let progress =
+                        progress::Progress::new(progress::ProgressMode::resolve(progress, verbose));
+                    let opts = indexer::Options {
+                        symbol_limit,
+                        symbol_hash_dedup,
+                        // A strict run must not pass by skipping files that broke before
+                        retry_quarantined: retry_quarantined || strict,
+                        build_fts,
+                        sqlite_cache_size_mb: write_opts.cache_size_mb,
+                        skip_empty_report,
+                        annotation_strip,
+                        verify_embeddings,
+                        report_unsupported_extensions,
+                        profile,
+                        max_size_bytes: config.index.max_size_mb.map(|mb| mb * 1024 * 1024),
+                        trim,
+                    };
+                    let mut run = indexer::Indexer::new(&root, &db, &progress, &opts, files_total);
+                    run.use_plugins(plugins);
+                    run.stats.bytes_total = bytes_total;
+                    if let Some(tokenizer) = tokenizer {
+                        run.set_tokenizer(tokenizer);
+                    }
+                    progress.start(&run.stats, "Indexing repo");
+                    type Read =
+                        Result<(std::path::PathBuf, Option<std::io::Result<String>>), String>;
+                    let files: Box<dyn Iterator<Item = Read>> = match parallel_files {
+                        Some(n) => Box::new(
+                            index::ReadAhead::new(files, n)
+                                .map(|f| f.map(|(f, source)| (f, Some(source)))),
+                        ),
+                        None => Box::new(files.map(|f| f.map(|f| (f, None)))),
+                    };
+                    let mut listed = 0;
+                    let mut listing_error = None;
+                    let mut too_many_failures = false;
+                    for f in files {
+                        match f {
+                            Ok((f, source)) => {
+                                listed += 1;
+                                if let Some(total) = count.as_ref().and_then(|c| c.try_recv().ok())
+                                {
+                                    run.set_files_total(total);
+                                }
+                                run.index_file(&f, source, embedder.as_mut());
+                                if let Some(max) = max_embedding_failures
+                                    && run.stats.count(progress::WarningKind::Embed) > max
+                                {
+                                    too_many_failures = true;
+                                    break;
+                                }
+                                if run.over_budget().is_some() {
+                                    break;
+                                }
+                            }
+                            Err(err) => {
+                                listing_error = Some(err);
+                                break;
+                            }
+                        }
+                    }
+                    if files_total.is_none() {
+                        run.set_files_total(listed);
+                    }
+                    let over_budget = run.over_budget();
+                    let stats = run.finish();
+                    if github_summary && let Err(err) = stats.write_github_summary() {
+                        tracing::warn!("failed to write GitHub step summary: {}", err);
+                    }
+                    if let Some(path) = &language_stats_file
+                        && let Err(err) = stats.write_language_stats(path)
+                    {
+                        tracing::warn!("{}", err);
+                    }
+                    // Like `cearch gc`, but only once enough space is waiting to be reclaimed
+                    if listing_error.is_none() && !too_many_failures && over_budget.is_none() {
+                        match db.compact_if_due(compact_after) {
+                            Ok(Some(pending)) => eprintln!(
+                                "compacted the index after {} deleted symbols (--compact-after {})",
+                                pending, compact_after
+                            ),
+                            Ok(None) => {}
+                            Err(err) => tracing::warn!("failed to compact the index: {}", err),
+                        }
+                    }
+                    drop(index_lock);
+                    // What was indexed is kept, but files after the failure were never seen
+                    if let Some(err) = listing_error {
+                        eprintln!(
+                            "error: {}; only the {} files listed before it were indexed",
+                            err, listed
+                        );
+                        std::process::exit(2);
+                    }
+                    // Left unfinished, so the next run warns and can --resume
+                    if too_many_failures {
+                        eprintln!(
+                            "error: {} embedding failures exceed --max-embedding-failures {}; stopped after {} files (see {})",
+                            stats.count(progress::WarningKind::Embed),
+                            max_embedding_failures.unwrap_or_default(),
+                            listed,
+                            indexer::log_path(&root).display()
+                        );
+                        std::process::exit(1);
+                    }
+                    if let Some(used) = over_budget {
+                        eprintln!(
+                            "error: the index uses {:.1} MB, over its {} MB budget (index.max_size_mb in {}); stopped after {} files. Raise the budget, or re-run with --trim to drop the largest symbols",
+                            used as f64 / (1024.0 * 1024.0),
+                            config.index.max_size_mb.unwrap_or_default(),
+                            config::Config::file_path(&root).display(),
+                            listed
+                        );
+                        match db.size_breakdown(SIZE_TOP_DIRECTORIES) {
+                            Ok(breakdown) => {
+                                for line in breakdown.report() {
+                                    eprintln!("{}", line);
+                                }
+                            }
+                            Err(err) => tracing::warn!("failed to measure the index: {}", err),
+                        }
+                        std::process::exit(2);
+                    }
+                    if let Some(head) = &head
+                        && let Err(err) = db.set_meta("git_commit", head)
+                    {
+                        tracing::warn!("failed to record indexed commit: {}", err);
+                    }
+                    let finished_at = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    if let Err(err) = db.set_meta("indexed_at", &finished_at.to_string()) {
+                        tracing::warn!("failed to record index completion: {}", err);
+                    }
+                    match index::worktree_dirty(&root) {
+                        Ok(dirty) => {
+                            if let Err(err) =
+                                db.set_meta("git_dirty", if dirty { "1" } else { "0" })
+                            {
+                                tracing::warn!("failed to record worktree state: {}", err);
+                            }
+                        }
+                        Err(err) => tracing::warn!("failed to check worktree state: {}", err),
+                    }
+                    let manifest_path = manifest::file_path(&root);
+                    match manifest::Manifest::from_db(&db, &manifest_profile) {
+                        Ok(mut written) => {
+                            written.timings = Some(manifest::Timings {
+                                elapsed_secs: stats.language_stats().elapsed_secs,
+                            });
+                            if let Err(err) = written.write(&manifest_path) {
+                                tracing::warn!("failed to write manifest: {:#}", err);
+                            }
+                        }
+                        Err(err) => tracing::warn!("failed to describe the index: {}", err),
+                    }
+                    if let Some(threshold) = dedup_across_files {
+                        let pairs = match db.near_duplicates(threshold) {
+                            Ok(pairs) => pairs,
+                            Err(err) => {
+                                eprintln!("error: near-duplicate scan failed: {}", err);
+                                std::process::exit(2);
+                            }
+                        };
+                        if let Err(err) =
+                            output::write_near_duplicates_csv(std::io::stdout().lock(), &pairs)
+                        {
+                            eprintln!("error: failed to write near-duplicates: {}", err);
+                            std::process::exit(2);
+                        }
+                        if store_duplicates && let Err(err) = db.store_duplicate_pairs(&pairs) {
+                            eprintln!("error: failed to store near-duplicates: {}", err);
+                            std::process::exit(2);
+                        }
+                    }
+                    let corrupt = stats.count(progress::WarningKind::Verify);
+                    if corrupt > 0 {
+                        eprintln!(
+                            "error: {} stored embedding{} did not match the computed vector (see {})",
+                            corrupt,
+                            if corrupt == 1 { "" } else { "s" },
+                            indexer::log_path(&root).display()
+                        );
+                        std::process::exit(1);
+                    }
+                    if strict
+                        && let Some(report) =
+                            indexer::strict_failure(&stats, &indexer::log_path(&root))
+                    {
+                        eprintln!("error: strict: {}", report);
+                        // Distinct from 2, which means the run itself could not proceed
+                        std::process::exit(3);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Commands::Init {} => {
+            let root = repo_root_or_exit();
+            let cearch_dir = root.join(".cearch");
+            if let Err(err) = std::fs::create_dir_all(&cearch_dir) {
+                eprintln!("error: creating {}: {}", cearch_dir.display(), err);
+                std::process::exit(2);
+            }
+            // Update .gitignore
+            let gi = root.join(".gitignore");
+            let entry = ".cearch/\n";
+            let needs_append = match std::fs::read_to_string(&gi) {
+                Ok(s) => !s.lines().any(|l| {
+                    let t = l.trim();
+                    t == ".cearch/" || t == ".cearch"
+                }),
+                Err(_) => true,
+            };
+            if needs_append
+                && let Err(err) = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&gi)
+                    .and_then(|mut f| std::io::Write::write_all(&mut f, entry.as_bytes()))
+            {
+                tracing::warn!("failed to update {}: {}", gi.display(), err);
+            }
+            // Pre-download default model into cache (Embedder uses .cearch)
+            match embed::Embedder::new_default() {
+                Ok(_) => println!("initialized: {}", cearch_dir.display()),
+                Err(err) => {
+                    eprintln!("error: failed to initialize model cache: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Commands::Query(args) => {
+            let QueryArgs {
+                query,
+                num_results,
+                server,
+                path_style,
+                threshold_auto,
+                output_csv,
+                no_code,
+                output_markdown,
+                show_code,
+                snippet_lines,
+                snippet_width,
+                snippet_wrap,
+                keyword,
+                semantic_only: _,
+                output_delta_diff,
+                filter_kind,
+                path_prefix,
+                k_expansion,
+                per_file,
+                expand_duplicates,
+                verbose,
+                near_symbol,
+                near_symbol_path,
+                query_embedding_file,
+                require_commit,
+                format_string,
+                url,
+                format,
+                print0,
+                db_path: _,
+                index_name: _,
+                save,
+                saved,
+                within,
+                save_results,
+                file,
+                include_self,
+                include_deps,
+                include_history,
+                blame,
+                sort,
+                term,
+                no_weights,
+            } = *args;
+            let within = within.map(|file| match search::load_result_ids(&file) {
+                Ok(ids) => ids,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            });
+            if let Some(name) = &save
+                && let Err(err) = saved_queries::validate_name(name)
+            {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            }
+            // A saved query brings its own text and filters
+            let saved = saved.map(|name| {
+                let store = saved_queries::SavedQueries::load(&repo_root_or_exit());
+                match store.map(|store| store.get(&name).cloned()) {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => {
+                        eprintln!(
+                            "error: no saved query named {}; see `cearch saved list`",
+                            name
+                        );
+                        std::process::exit(2);
+                    }
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+            });
+            let (query, filter_kind, path_prefix, keyword) = match &saved {
+                Some(entry) => (
+                    Some(entry.query.clone()),
+                    entry.filter_kind.clone(),
+                    entry.path_prefix.clone(),
+                    entry.keyword,
+                ),
+                None => (query, filter_kind, path_prefix, keyword),
+            };
+            let template = match format_string.as_deref().map(template::Template::parse) {
+                Some(Ok(template)) => Some(template),
+                Some(Err(err)) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+                None => None,
+            };
+            if !(k_expansion.is_finite() && k_expansion >= 1.0) {
+                eprintln!("error: --k-expansion must be at least 1.0");
+                std::process::exit(2);
+            }
+            if per_file == Some(0) {
+                eprintln!("error: --per-file must be at least 1");
+                std::process::exit(2);
+            }
+            // Only zero can fail here; the index size is checked once it is open
+            if let Err(err) = search::clamp_num_results(num_results, usize::MAX) {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            }
+            let filters = search::Filters {
+                kind: filter_kind,
+                path_prefix,
+            };
+            // Reject blank queries before a server round trip or model load
+            let query = query.map(|q| match search::validate_query(&q) {
+                Ok(trimmed) => {
+                    if !keyword && search::is_short_query(trimmed) {
+                        eprintln!(
+                            "hint: semantic search works best with a phrase, e.g. \"open the database\""
+                        );
+                    }
+                    trimmed.to_string()
+                }
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            });
+            let has_query_text = query.is_some();
+            // Clap requires one of these; the others are remembered under their own key
+            let query = query.unwrap_or_else(|| {
+                match (&near_symbol, &near_symbol_path, &query_embedding_file) {
+                    (Some(name), Some(prefix), _) => {
+                        format!("--near-symbol {} --near-symbol-path {}", name, prefix)
+                    }
+                    (Some(name), None, _) => format!("--near-symbol {}", name),
+                    (None, _, Some(file)) => {
+                        format!("--query-embedding-file {}", file.display())
+                    }
+                    (None, _, None) => match &file {
+                        Some(file) => format!("--file {}", file),
+                        None => format!("--term {}", term.join(" --term ")),
+                    },
+                }
+            });
+            // Weighted terms are embedded one by one and combined into the query vector
+            let weighted = (!keyword).then(|| {
+                let mut terms: Vec<search::WeightedTerm> = term
+                    .iter()
+                    .map(|flag| {
+                        search::parse_term_flag(flag).unwrap_or_else(|err| {
+                            eprintln!("error: {}", err);
+                            std::process::exit(2);
+                        })
+                    })
+                    .collect();
+                if has_query_text && !no_weights {
+                    match search::parse_weighted_query(&query) {
+                        Some(parsed) => terms.extend(parsed),
+                        None if !terms.is_empty() => terms.push(search::WeightedTerm {
+                            text: query.clone(),
+                            weight: 1.0,
+                        }),
+                        None => {}
+                    }
+                }
+                terms
+            });
+            let weighted = weighted.filter(|terms| !terms.is_empty());
+            let file = file.map(|target| {
+                let (path, line) = query_file::parse_target(&target);
+                match query_file::query_texts(&path, line) {
+                    Ok(query) => (path, query),
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+            });
+            let fetched;
+            // A running server already has the model warm; prefer it when available, unless
+            // it may be serving another database (`--db-path` or CEARCH_DB_PATH)
+            let server = server.or_else(|| {
+                if db::path_overridden() {
+                    return None;
+                }
+                std::env::current_dir()
+                    .ok()
+                    .and_then(|cwd| index::repo_root(&cwd))
+                    .and_then(|root| serve::detect_local_server(&root))
+            });
+            if let Some(server) = server.filter(|_| {
+                !keyword
+                    && !output_delta_diff
+                    && require_commit.is_none()
+                    && save.is_none()
+                    && saved.is_none()
+                    && within.is_none()
+                    && save_results.is_none()
+                    && file.is_none()
+                    && weighted.is_none()
+                    && near_symbol.is_none()
+                    && query_embedding_file.is_none()
+                    && !include_deps
+                    && !include_history
+                    && !blame
+                    && sort == search::SortOrder::Score
+                    && per_file.is_none()
+                    && !url
+                    && format == output::QueryFormat::Text
+            }) {
+                match serve::query_remote(&server, &query, num_results) {
+                    Ok(body) => println!("{}", body),
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+                return;
+            }
+
+            let root = repo_root_or_exit();
+
+            // Open DB first so an empty index is reported before paying for model load
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+
+            if let Some(required) = &require_commit {
+                match db.git_commit() {
+                    Ok(Some(commit))
+                        if !required.is_empty()
+                            && commit
+                                .to_ascii_lowercase()
+                                .starts_with(&required.to_ascii_lowercase()) => {}
+                    Ok(Some(commit)) => {
+                        eprintln!(
+                            "error: index was built from commit {}, not {}; re-run `cearch index`",
+                            commit, required
+                        );
+                        std::process::exit(1);
+                    }
+                    Ok(None) => {
+                        eprintln!(
+                            "error: index records no commit; re-run `cearch index` in a git checkout or with --commit-hash"
+                        );
+                        std::process::exit(1);
+                    }
+                    Err(err) => {
+                        eprintln!("error: failed to inspect sqlite index: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+            }
+            if let Some(name) = save {
+                let entry = saved_queries::SavedQuery {
+                    name,
+                    query: query.clone(),
+                    filter_kind: filters.kind.clone(),
+                    path_prefix: filters.path_prefix.clone(),
+                    keyword,
+                    model: db.embedding_model().ok().flatten(),
+                    saved_at: std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_or(0, |d| d.as_secs()),
+                };
+                let name = entry.name.clone();
+                let saved = saved_queries::SavedQueries::load(&root).and_then(|mut store| {
+                    store.insert(entry);
+                    store.save()
+                });
+                if let Err(err) = saved {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+                eprintln!("saved query {}", name);
+            }
+            // Keyword results do not depend on the model
+            if let Some(entry) = &saved
+                && !keyword
+                && let Some(then) = &entry.model
+                && let Ok(Some(now)) = db.embedding_model()
+                && now != *then
+            {
+                eprintln!(
+                    "note: saved query {} was saved when the index used {}; it now uses {}",
+                    entry.name, then, now
+                );
+            }
+            if !keyword && matches!(db.is_metadata_only(), Ok(true)) {
+                eprintln!(
+                    "error: index was built with --metadata-only and has no embeddings; use `cearch lookup` or `query --keyword`, or re-index without it"
+                );
+                std::process::exit(2);
+            }
+            match db.health() {
+                Ok(db::IndexHealth::Empty) => {
+                    eprintln!("index is empty — run `cearch index`");
+                    std::process::exit(1);
+                }
+                // Keyword search does not read vectors
+                Ok(_) if keyword => {}
+                Ok(db::IndexHealth::Ready) => {}
+                Ok(db::IndexHealth::MissingVectors { symbols, vectors }) => {
+                    if vectors == 0 {
+                        eprintln!(
+                            "error: index is inconsistent: {} symbols but no vectors; run `cearch check`",
+                            symbols
+                        );
+                        std::process::exit(2);
+                    }
+                    tracing::warn!(
+                        "index is inconsistent: {} symbols but only {} vectors; run `cearch check`",
+                        symbols,
+                        vectors
+                    );
+                }
+                Err(err) => {
+                    eprintln!("error: failed to inspect sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            }
+            // Keyword search also matches linked duplicates, which have no vector of their own
+            let available = match if keyword {
+                db.count_symbols()
+            } else {
+                db.count_canonical_symbols()
+            } {
+                Ok(available) => available,
+                Err(err) => {
+                    eprintln!("error: failed to inspect sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let requested = num_results;
+            // Zero was rejected above, so this only clamps
+            let num_results = search::clamp_num_results(requested, available).unwrap_or(requested);
+            if num_results < requested {
+                eprintln!("index contains only {} symbols", available);
+            }
+            let fetch_k = search::expanded_k(
+                num_results,
+                k_expansion,
+                filters.is_active() || !expand_duplicates,
+            );
+            let fetch_k = search::per_file_k(fetch_k, per_file);
+            // Applied after the filters, so a filtered search still leads with its own copy
+            let collapse = |results: Vec<db::SearchResult>| {
+                if expand_duplicates {
+                    return results;
+                }
+                let ids: Vec<i64> = results.iter().map(|r| r.id).collect();
+                match db.code_hashes(&ids) {
+                    Ok(hashes) => search::collapse_duplicates(results, &hashes),
+                    Err(err) => {
+                        tracing::warn!("failed to group identical results: {}", err);
+                        results
+                    }
+                }
+            };
+
+            // Per result, the index it came from when dependencies or history are searched too
+            let mut extras = search::ResultExtras::default();
+            let mut results = if keyword {
+                if !matches!(db.has_keyword_index(), Ok(true)) {
+                    eprintln!("error: keyword index not built — run `cearch index --build-fts`");
+                    std::process::exit(2);
+                }
+                match db.keyword_search(&query, fetch_k) {
+                    Ok(mut results) => {
+                        fetched = results.len();
+                        results.retain(|r| filters.keep(r));
+                        let mut results = search::cap_per_file(collapse(results), per_file);
+                        results.truncate(num_results);
+                        results
+                    }
+                    Err(err) => {
+                        eprintln!("error: keyword search failed: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+            } else {
+                let embedding = match (&near_symbol, &query_embedding_file) {
+                    (Some(name), _) => {
+                        near_symbol_embedding(&db, name, near_symbol_path.as_deref())
+                    }
+                    (None, Some(file)) => match embed::read_query_embedding(file) {
+                        Ok(embedding) => embedding,
+                        Err(err) => {
+                            eprintln!("error: {:#}", err);
+                            std::process::exit(2);
+                        }
+                    },
+                    (None, None) => {
+                        let model = db.embedding_model().ok().flatten();
+                        if let Some(model) = &model
+                            && !embed::is_loadable(model)
+                        {
+                            tracing::warn!(
+                                "index holds embeddings from {} but the query is embedded with {}; use --near-symbol or --query-embedding-file",
+                                model,
+                                embed::DEFAULT_MODEL_REPO
+                            );
+                        }
+                        let model = model.as_deref();
+                        match (&weighted, &file) {
+                            (Some(terms), _) => embed_weighted_query(terms, model),
+                            (None, Some((_, query))) => {
+                                if verbose {
+                                    eprintln!("using {}", query.source);
+                                }
+                                embed_file_query(&query.texts, model)
+                            }
+                            (None, None) => embed_query(&query, model),
+                        }
+                    }
+                };
+
+                let k = if threshold_auto {
+                    search::AUTO_THRESHOLD_K.max(fetch_k)
+                } else {
+                    fetch_k
+                };
+                // The file's own symbols are the nearest; fetch past them
+                let own_path = file
+                    .as_ref()
+                    .filter(|_| !include_self)
+                    .map(|(path, query)| {
+                        let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+                        (paths::to_stored(&root, &path), query.symbols)
+                    });
+                let k = k + own_path.as_ref().map_or(0, |(_, n)| *n);
+                if verbose && let Ok(strategy) = db.knn_strategy() {
+                    eprintln!("search: {}", strategy.as_str());
+                }
+                let found = match &within {
+                    Some(ids) => db.knn_within(&embedding, k, ids),
+                    None => db.knn(&embedding, k),
+                };
+                let results = match found {
+                    Ok(mut results) => {
+                        fetched = results.len();
+                        if let Some((own, _)) = &own_path {
+                            results.retain(|r| &r.path != own);
+                        }
+                        results.retain(|r| filters.keep(r));
+                        let mut results = search::cap_per_file(collapse(results), per_file);
+                        if threshold_auto {
+                            let distances: Vec<f32> = results.iter().map(|r| r.distance).collect();
+                            results.truncate(search::elbow_cutoff(&distances));
+                        }
+                        results.truncate(num_results);
+                        results
+                    }
+                    Err(err) => {
+                        eprintln!("error: knn failed: {}", err);
+                        std::process::exit(2);
+                    }
+                };
+                if include_deps || include_history {
+                    let mut hits: Vec<(db::SearchResult, search::Origin)> = results
+                        .into_iter()
+                        .map(|hit| (hit, search::Origin::Repo))
+                        .collect();
+                    if include_deps {
+                        if !db::DB::deps_path(&root).exists() {
+                            eprintln!("error: no dependency index — run `cearch index --deps`");
+                            std::process::exit(2);
+                        }
+                        let found = db::DB::open_deps_read(&root)
+                            .and_then(|deps| Ok((deps.knn(&embedding, k)?, deps.dep_crates()?)));
+                        match found {
+                            Ok((mut found, crates)) => {
+                                found.retain(|r| filters.keep(r));
+                                hits.extend(deps::locate_hits(found, &crates));
+                            }
+                            Err(err) => {
+                                eprintln!("error: dependency search failed: {}", err);
+                                std::process::exit(2);
+                            }
+                        }
+                    }
+                    if include_history {
+                        if !db::DB::history_path(&root).exists() {
+                            eprintln!(
+                                "error: no history index — run `cearch index --history <N|DATE>`"
+                            );
+                            std::process::exit(2);
+                        }
+                        match db::DB::open_history_read(&root).and_then(|h| h.knn(&embedding, k)) {
+                            Ok(found) => hits.extend(
+                                history::locate_hits(found)
+                                    .into_iter()
+                                    .filter(|(hit, _)| filters.keep(hit)),
+                            ),
+                            Err(err) => {
+                                eprintln!("error: history search failed: {}", err);
+                                std::process::exit(2);
+                            }
+                        }
+                    }
+                    let (merged, merged_origins) =
+                        search::merge_ranked(hits, num_results, per_file)
+                            .into_iter()
+                            .unzip();
+                    extras.origins = merged_origins;
+                    merged
+                } else {
+                    results
+                }
+            };
+
+            // Only the repository's own files are in its history
+            let blame = blame || sort == search::SortOrder::Recency;
+            if blame {
+                let wanted: Vec<(&str, usize)> = results
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| extras.is_own(*i))
+                    .map(|(_, hit)| (hit.path.as_str(), hit.line))
+                    .collect();
+                let mut cache = index::BlameCache::new(&root);
+                cache.fill(&wanted);
+                extras.blames = results
+                    .iter()
+                    .enumerate()
+                    .map(|(i, hit)| {
+                        extras
+                            .is_own(i)
+                            .then(|| cache.get(&hit.path, hit.line).cloned())
+                            .flatten()
+                    })
+                    .collect();
+            }
+            extras.parents = search::result_parents(&db, &root, &results, |i| extras.is_own(i));
+            // Ranks are compared with the last run's, so reordered results are not remembered
+            let ranked = sort == search::SortOrder::Score;
+            search::sort_results(&mut results, &mut extras, sort);
+
+            if verbose {
+                eprintln!(
+                    "fetched {}, returned {} after filters",
+                    fetched,
+                    results.len()
+                );
+            }
+            if let Some(file) = &save_results
+                && let Err(err) = search::save_results(file, &results, &extras, sort)
+            {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            }
+
+            // Canonical to match the (canonical) repo root
+            let cwd = std::env::current_dir()
+                .and_then(|d| d.canonicalize())
+                .unwrap_or_else(|_| root.clone());
+
+            // Remember semantic results so a later run can be compared with --output-delta-diff
+            if !keyword && !include_deps && !include_history && ranked {
+                let mut cache = query_cache::QueryCache::load(&root);
+                let previous = cache.get(&query).map(|hits| hits.to_vec());
+                let current: Vec<query_cache::CachedHit> =
+                    results.iter().map(query_cache::CachedHit::from).collect();
+                cache.record(&query, current.clone());
+                if let Err(err) = cache.save() {
+                    tracing::warn!("{}", err);
+                }
+                if output_delta_diff {
+                    let previous = previous.unwrap_or_else(|| {
+                        eprintln!("no earlier results for this query; all results are new");
+                        Vec::new()
+                    });
+                    for delta in query_cache::diff(&previous, &current) {
+                        let (marker, hit) = match delta {
+                            query_cache::Delta::Added(hit) => ("+".to_string(), hit),
+                            query_cache::Delta::Removed(hit) => ("-".to_string(), hit),
+                            query_cache::Delta::Moved { hit, from, to } => {
+                                (format!("~{} → {}", from, to), hit)
+                            }
+                            query_cache::Delta::Unchanged(hit) => (" ".to_string(), hit),
+                        };
+                        println!(
+                            "{} {}:{} {} {}",
+                            marker,
+                            paths::display(&root, &cwd, &hit.path, path_style),
+                            hit.line,
+                            hit.name,
+                            output::format_score(hit.distance)
+                        );
+                    }
+                    return;
+                }
+            }
+
+            // Links are made from stored paths, before they are rewritten for display
+            let links = (url
+                || format == output::QueryFormat::Url
+                || template.as_ref().is_some_and(|t| t.has_url()))
+            .then(|| forge_links_or_exit(&root, &db));
+            let urls: Vec<Option<String>> = match &links {
+                Some(links) => {
+                    let mut warned = std::collections::HashSet::new();
+                    results
+                        .iter()
+                        .enumerate()
+                        .map(|(i, hit)| {
+                            if !extras.is_own(i) {
+                                return None;
+                            }
+                            let link = links.url(&hit.path, hit.line);
+                            if let Some(fallback) = &link.fallback
+                                && warned.insert(hit.path.clone())
+                            {
+                                let why = match fallback {
+                                    forge::Fallback::Changed => "differs from",
+                                    forge::Fallback::Missing => "is not in",
+                                };
+                                let target = match &links.branch {
+                                    Some(branch) => format!("linking to branch {}", branch),
+                                    None => "and no branch is checked out".to_string(),
+                                };
+                                tracing::warn!(
+                                    "{} {} commit {}; {}",
+                                    hit.path,
+                                    why,
+                                    history::short_hash(&links.commit),
+                                    target
+                                );
+                            }
+                            Some(link.url)
+                        })
+                        .collect()
+                }
+                None => Vec::new(),
+            };
+
+            let separator = if print0 { '\0' } else { '\n' };
+            if let Some(template) = &template {
+                use std::io::Write;
+                let mut out = std::io::stdout().lock();
+                for (i, hit) in results.iter().enumerate() {
+                    let ctx = template::PathContext {
+                        repo_root: &root,
+                        cwd: &cwd,
+                        style: path_style,
+                        url: urls.get(i).and_then(|url| url.as_deref()),
+                    };
+                    let rendered = match extras.origins.get(i) {
+                        Some(search::Origin::History(commit)) => {
+                            let mut hit = hit.clone();
+                            hit.path = format!("{}@{}", hit.path, history::short_hash(commit));
+                            template.render(&hit, &ctx)
+                        }
+                        _ => template.render(hit, &ctx),
+                    };
+                    let written = write!(out, "{}{}", rendered, separator);
+                    if let Err(err) = written {
+                        eprintln!("error: failed to write results: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+                return;
+            }
+            for (i, hit) in results.iter_mut().enumerate() {
+                hit.path = paths::display(&root, &cwd, &hit.path, path_style);
+                for other in &mut hit.duplicates {
+                    other.path = paths::display(&root, &cwd, &other.path, path_style);
+                }
+                match extras.origins.get(i) {
+                    // Dependency sources are outside the repository; say whose they are
+                    Some(search::Origin::Dependency(label)) => {
+                        hit.path = format!("[dep {}] {}", label, hit.path);
+                    }
+                    Some(search::Origin::History(commit)) => {
+                        hit.path = format!("{}@{}", hit.path, history::short_hash(commit));
+                    }
+                    Some(search::Origin::Repo) | None => {}
+                }
+            }
+            if output_csv {
+                if let Err(err) = output::write_csv(std::io::stdout().lock(), &results, !no_code) {
+                    eprintln!("error: failed to write csv: {}", err);
+                    std::process::exit(2);
+                }
+                return;
+            }
+            if output_markdown {
+                // Fenced blocks are not fitted to the terminal unless asked to be
+                let snippet = snippet::SnippetOptions {
+                    max_lines: snippet_lines,
+                    width: snippet_width,
+                    wrap: snippet_wrap,
+                };
+                let written =
+                    output::write_markdown(std::io::stdout().lock(), &results, show_code, &snippet);
+                if let Err(err) = written {
+                    eprintln!("error: failed to write markdown: {}", err);
+                    std::process::exit(2);
+                }
+                return;
+            }
+            let snippet = snippet_lines.map(|lines| {
+                snippet::SnippetOptions::from_flags(Some(lines), snippet_width, snippet_wrap)
+                    .indented(SNIPPET_INDENT.len())
+            });
+            let print_snippet = |code: &str| {
+                if let Some(opts) = &snippet {
+                    for line in snippet::format(code, opts).lines() {
+                        println!("{}{}", SNIPPET_INDENT, line);
+                    }
+                }
+            };
+            for (i, hit) in results.into_iter().enumerate() {
+                if format == output::QueryFormat::Url {
+                    match urls.get(i) {
+                        Some(Some(link)) => print!("{}{}", link, separator),
+                        _ => print!("{}:{}{}", hit.path, hit.line, separator),
+                    }
+                    print_snippet(&hit.code);
+                    continue;
+                }
+                // Ids of dependency and historical symbols belong to their own indexes, so
+                // `cearch show` cannot take them
+                if !extras.is_own(i) {
+                    print!(
+                        "{}:{} {} {}{}",
+                        hit.path,
+                        hit.line,
+                        hit.name,
+                        output::format_score(hit.distance),
+                        separator
+                    );
+                    print_snippet(&hit.code);
+                    continue;
+                }
+                let mut notes = String::new();
+                if hit.duplicate_files > 0 {
+                    notes.push_str(&format!(" (also in {} other files)", hit.duplicate_files));
+                }
+                match hit.duplicates.len() {
+                    0 => {}
+                    1 => notes.push_str(" (also at 1 other location)"),
+                    n => notes.push_str(&format!(" (also at {} other locations)", n)),
+                }
+                if let Some(Some(blame)) = extras.blames.get(i) {
+                    notes.push_str(&format!(
+                        " ({}, {}, {})",
+                        blame.author,
+                        output::format_unix_date(blame.time),
+                        history::short_hash(&blame.commit)
+                    ));
+                }
+                if let Some(Some(link)) = urls.get(i) {
+                    notes.push_str(&format!(" {}", link));
+                }
+                print!(
+                    "{}:{} {} {} #{}{}{}",
+                    hit.path,
+                    hit.line,
+                    extras.breadcrumb(i, &hit.name),
+                    output::format_score(hit.distance),
+                    hit.id,
+                    notes,
+                    separator
+                );
+                print_snippet(&hit.code);
+            }
+        }
+        Commands::Clean {
+            db_path: _,
+            index_name,
+        } => {
+            let root = repo_root_or_exit();
+            // A named index leaves the rest of .cearch, other indexes included, alone
+            if index_name.is_some() {
+                let db_path = db::DB::file_path(&root);
+                let split = embedding_store::SplitEmbeddingStore::file_path(&root);
+                let removed =
+                    db::DB::remove_files(&root).and_then(|_| match std::fs::remove_file(&split) {
+                        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+                        _ => Ok(()),
+                    });
+                if let Err(err) = removed {
+                    eprintln!(
+                        "error: failed to delete the {} index: {}",
+                        db::index_name(),
+                        err
+                    );
+                    std::process::exit(2);
+                }
+                println!("cleaned: {}", db_path.display());
+                return;
+            }
+            let cearch_dir = root.join(".cearch");
+            // A WAL left behind without its database would be replayed into the next index
+            if let Err(err) = db::DB::remove_files(&root) {
+                eprintln!("error: failed to delete the sqlite index: {}", err);
+                std::process::exit(2);
+            }
+            // Cached models are `cearch cache clear --models`' to delete, and saved queries
+            // `cearch saved rm`'s
+            let kept: Vec<std::path::PathBuf> = [
+                cache::models_dir(&cearch_dir),
+                saved_queries::SavedQueries::file_path(&root),
+            ]
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect();
+            if !kept.is_empty() {
+                let entries = std::fs::read_dir(&cearch_dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten();
+                for entry in entries.filter(|e| !kept.contains(&e.path())) {
+                    let path = entry.path();
+                    let removed = if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                        std::fs::remove_dir_all(&path)
+                    } else {
+                        std::fs::remove_file(&path)
+                    };
+                    if let Err(err) = removed {
+                        eprintln!("error: failed to delete {}: {}", path.display(), err);
+                        std::process::exit(2);
+                    }
+                }
+                let kept: Vec<String> = kept.iter().map(|p| p.display().to_string()).collect();
+                println!(
+                    "cleaned: {} (kept {})",
+                    cearch_dir.display(),
+                    kept.join(" and ")
+                );
+            } else if let Err(err) = std::fs::remove_dir_all(&cearch_dir) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    eprintln!("error: failed to delete .cearch directory: {}", err);
+                    std::process::exit(2);
+                }
+            } else {
+                // Remove .cearch entries from .gitignore if present
+                let gi = root.join(".gitignore");
+                if let Ok(contents) = std::fs::read_to_string(&gi) {
+                    let filtered = contents
+                        .lines()
+                        .filter(|l| {
+                            let t = l.trim();
+                            !(t == ".cearch/" || t == ".cearch")
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    if let Err(err) = std::fs::write(
+                        &gi,
+                        if filtered.is_empty() {
+                            String::new()
+                        } else {
+                            format!("{}\n", filtered)
+                        },
+                    ) {
+                        tracing::warn!("failed to update {}: {}", gi.display(), err);
+                    }
+                }
+                println!("cleaned: {}", cearch_dir.display());
+            }
+        }
+        Commands::Stats {
+            db_path: _,
+            index_name: _,
+            size,
+            json,
+        } => {
+            let root = repo_root_or_exit();
+            let names = db::DB::index_names(&root);
+            if !json && (names.len() > 1 || db::index_name() != db::DEFAULT_INDEX_NAME) {
+                println!(
+                    "index: {} (indexes: {})",
+                    db::index_name(),
+                    names.join(", ")
+                );
+            }
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            if json {
+                let profile = match config::Config::load(&root) {
+                    Ok(mut config) => config.indexes.remove(db::index_name()).unwrap_or_default(),
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
+                };
+                let live = match manifest::Manifest::from_db(&db, &profile) {
+                    Ok(live) => live,
+                    Err(err) => {
+                        eprintln!("error: failed to read index stats: {}", err);
+                        std::process::exit(2);
+                    }
+                };
+                let manifest_path = manifest::file_path(&root);
+                match manifest::Manifest::read(&manifest_path) {
+                    Ok(Some(written)) => {
+                        for difference in written.differences(&live) {
+                            tracing::warn!(
+                                "{} differs from the index: {}",
+                                manifest_path.display(),
+                                difference
+                            );
+                        }
+                    }
+                    Ok(None) => tracing::warn!(
+                        "{} is missing; run `cearch index` to write it",
+                        manifest_path.display()
+                    ),
+                    Err(err) => tracing::warn!("{:#}", err),
+                }
+                match serde_json::to_string_pretty(&live) {
+                    Ok(out) => println!("{}", out),
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+                return;
+            }
+            let counts = db
+                .count_symbols()
+                .and_then(|symbols| db.count_files().map(|files| (symbols, files)));
+            let (symbol_count, file_count) = match counts {
+                Ok(v) => v,
+                Err(err) => {
+                    eprintln!("error: failed to read index stats: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            println!("symbols: {}", symbol_count);
+            println!("files: {}", file_count);
+            let symbol_limit = db.get_meta("symbol_limit").ok().flatten();
+            println!(
+                "symbol limit: {}",
+                symbol_limit.as_deref().unwrap_or("unlimited")
+            );
+            if let Ok(Some(truncated)) = db.get_meta("truncated_files") {
+                println!("truncated files: {}", truncated);
+            }
+            if matches!(db.is_metadata_only(), Ok(true)) {
+                println!("embeddings: none (metadata-only)");
+            }
+            if let Ok(Some(commit)) = db.git_commit() {
+                println!("git commit: {}", commit);
+            }
+            println!(
+                "encryption: {}",
+                if encryption::is_encrypted(&db::DB::file_path(&root)) {
+                    "SQLCipher"
+                } else {
+                    "none"
+                }
+            );
+            if size {
+                match db.size_breakdown(SIZE_TOP_DIRECTORIES) {
+                    Ok(breakdown) => {
+                        for line in breakdown.report() {
+                            println!("{}", line);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("error: failed to measure the index: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+            }
+        }
+        Commands::Saved { command } => {
+            let root = repo_root_or_exit();
+            let mut store = match saved_queries::SavedQueries::load(&root) {
+                Ok(store) => store,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            match command {
+                SavedCommand::List { json } => {
+                    if json {
+                        match serde_json::to_string(store.list()) {
+                            Ok(json) => println!("{}", json),
+                            Err(err) => {
+                                eprintln!("error: {}", err);
+                                std::process::exit(2);
+                            }
+                        }
+                        return;
+                    }
+                    for entry in store.list() {
+                        let mut line = format!("{}\t{:?}", entry.name, entry.query);
+                        if let Some(kind) = &entry.filter_kind {
+                            line.push_str(&format!(" --filter-kind {}", kind));
+                        }
+                        if let Some(prefix) = &entry.path_prefix {
+                            line.push_str(&format!(" --path-prefix {}", prefix));
+                        }
+                        if entry.keyword {
+                            line.push_str(" --keyword");
+                        }
+                        println!("{}", line);
+                    }
+                }
+                SavedCommand::Rm { name } => {
+                    if !store.remove(&name) {
+                        eprintln!("error: no saved query named {}", name);
+                        std::process::exit(2);
+                    }
+                    if let Err(err) = store.save() {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+            }
+        }
+        Commands::Lookup {
+            name,
+            path_prefix,
+            path_style,
+        } => {
+            let root = repo_root_or_exit();
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let found = match db.find_symbols(&name, path_prefix.as_deref()) {
+                Ok(found) => found,
+                Err(err) => {
+                    eprintln!("error: lookup failed: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            if found.is_empty() {
+                eprintln!("no symbol named {}", name);
+                std::process::exit(1);
+            }
+            // Canonical to match the (canonical) repo root
+            let cwd = std::env::current_dir()
+                .and_then(|d| d.canonicalize())
+                .unwrap_or_else(|_| root.clone());
+            for symbol in found {
+                println!(
+                    "{}:{} {} {}",
+                    paths::display(&root, &cwd, &symbol.path, path_style),
+                    symbol.line,
+                    symbol.kind,
+                    symbol.name
+                );
+            }
+        }
+        Commands::DiffIndex {
+            before,
+            after,
+            verbose,
+            json,
+        } => {
+            let [before_symbols, after_symbols] = [&before, &after].map(|path| {
+                match db::DB::open_snapshot(path).and_then(|db| db.stored_symbols()) {
+                    Ok(symbols) => symbols,
+                    Err(err) => {
+                        eprintln!("error: {:#}", err);
+                        std::process::exit(2);
+                    }
+                }
+            });
+            let diff = index_diff::diff(&before_symbols, &after_symbols);
+            if json {
+                let report = serde_json::json!({
+                    "counts": {
+                        "added": diff.added.len(),
+                        "removed": diff.removed.len(),
+                        "relocated": diff.relocated.len(),
+                        "changed": diff.changed.len(),
+                    },
+                    "added": diff.added,
+                    "removed": diff.removed,
+                    "relocated": diff.relocated,
+                    "changed": diff.changed,
+                });
+                println!("{}", report);
+                return;
+            }
+            println!(
+                "added {}, removed {}, relocated {}, changed {}",
+                diff.added.len(),
+                diff.removed.len(),
+                diff.relocated.len(),
+                diff.changed.len()
+            );
+            if verbose {
+                let at = |l: &index_diff::Location| format!("{}:{}", l.path, l.line);
+                for l in &diff.added {
+                    println!("+ {} {} {}", at(l), l.kind, l.name);
+                }
+                for l in &diff.removed {
+                    println!("- {} {} {}", at(l), l.kind, l.name);
+                }
+                for p in &diff.relocated {
+                    println!(
+                        "> {} → {} {} {}",
+                        at(&p.before),
+                        at(&p.after),
+                        p.after.kind,
+                        p.after.name
+                    );
+                }
+                for p in &diff.changed {
+                    println!("~ {} {} {}", at(&p.after), p.after.kind, p.after.name);
+                }
+            }
+        }
+        Commands::Show {
+            target,
+            highlight,
+            open,
+            path_style,
+            snippet_lines,
+            snippet_width,
+            snippet_wrap,
+        } => {
+            let root = repo_root_or_exit();
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            // Canonical to match the (canonical) repo root
+            let cwd = std::env::current_dir()
+                .and_then(|d| d.canonicalize())
+                .unwrap_or_else(|_| root.clone());
+            let id = resolve_symbol_target(&db, &root, &cwd, &target);
+            let symbol = match db.get_symbol(id) {
+                Ok(Some(symbol)) => symbol,
+                Ok(None) => {
+                    eprintln!(
+                        "no symbol with id {}; ids change when a file is re-indexed",
+                        id
+                    );
+                    std::process::exit(1);
+                }
+                Err(err) => {
+                    eprintln!("error: symbol lookup failed: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            if open {
+                if let Err(err) =
+                    open_in_editor(&paths::to_native(&root, &symbol.path), symbol.line)
+                {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+                return;
+            }
+            let provenance = output::Provenance {
+                indexed_at: db
+                    .get_meta("indexed_at")
+                    .ok()
+                    .flatten()
+                    .and_then(|t| t.parse().ok()),
+                git_commit: db.git_commit().ok().flatten(),
+                origin: db.file_origin(&symbol.path).ok().flatten(),
+            };
+            let shown_path = paths::display(&root, &cwd, &symbol.path, path_style);
+            let written = output::write_symbol(
+                std::io::stdout().lock(),
+                &symbol,
+                &shown_path,
+                &provenance,
+                highlight,
+                &snippet::SnippetOptions::from_flags(snippet_lines, snippet_width, snippet_wrap),
+            );
+            if let Err(err) = written {
+                eprintln!("error: failed to write symbol: {}", err);
+                std::process::exit(2);
+            }
+        }
+        Commands::ImportEmbeddings {
+            file,
+            create_missing,
+            force,
+        } => {
+            let root = repo_root_or_exit();
+            let embeddings = match std::fs::File::open(&file)
+                .map_err(anyhow::Error::from)
+                .and_then(|f| import::read_records(std::io::BufReader::new(f)))
+            {
+                Ok(embeddings) => embeddings,
+                Err(err) => {
+                    eprintln!("error: {}: {}", file.display(), err);
+                    std::process::exit(2);
+                }
+            };
+            let config = match config::Config::load(&root) {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let write_opts =
+                db::WriteOptions::default().with_config(&config.sqlite, &config.vector);
+            let index_lock = match lock::IndexLock::acquire(&root) {
+                Ok(lock) => lock,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let opts = import::ImportOptions {
+                create_missing,
+                force,
+            };
+            let report = import::import(&root, &embeddings, &opts, &write_opts);
+            drop(index_lock);
+            match report {
+                Ok(report) => println!(
+                    "imported {} ({} created), updated {}, skipped {} ({}, {} dimensions)",
+                    report.imported,
+                    report.created,
+                    report.updated,
+                    report.skipped,
+                    embeddings.model,
+                    embeddings.dim
+                ),
+                Err(err) => {
+                    eprintln!("error: import failed: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Commands::Reembed { from_tokens, force } => {
+            if !from_tokens {
+                eprintln!(
+                    "error: reembed needs --from-tokens; to embed from source, re-run `cearch index`"
+                );
+                std::process::exit(2);
+            }
+            let root = repo_root_or_exit();
+            let index_lock = match lock::IndexLock::acquire(&root) {
+                Ok(lock) => lock,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let db = match db::DB::open_maintenance(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let models = db
+                .get_meta("tokens_model")
+                .and_then(|tokens| Ok((tokens, db.embedding_model()?, db.embedding_dim()?)));
+            let (model, dim) = match models {
+                Ok((_, Some(model), _)) if !embed::is_loadable(&model) => {
+                    eprintln!(
+                        "error: index holds embeddings imported from {}; run `cearch clean` before re-embedding with {}",
+                        model,
+                        embed::DEFAULT_MODEL_REPO
+                    );
+                    std::process::exit(2);
+                }
+                // A migrated index is re-embedded with the model it was migrated to
+                Ok((Some(tokens), model, _))
+                    if tokens != model.as_deref().unwrap_or(embed::DEFAULT_MODEL_REPO) =>
+                {
+                    eprintln!(
+                        "error: stored tokens come from the {} tokenizer, not {}; re-run `cearch index --pre-tokenize`",
+                        tokens,
+                        model.as_deref().unwrap_or(embed::DEFAULT_MODEL_REPO)
+                    );
+                    std::process::exit(2);
+                }
+                Ok((None, _, _)) => {
+                    eprintln!(
+                        "error: index has no stored tokens; run `cearch index --pre-tokenize`"
+                    );
+                    std::process::exit(2);
+                }
+                Ok((_, Some(model), _)) if !force => {
+                    println!(
+                        "embeddings already come from {}; nothing to re-embed (pass --force to re-embed anyway)",
+                        model
+                    );
+                    return;
+                }
+                Ok((_, model, dim)) => (model, dim),
+                Err(err) => {
+                    eprintln!("error: failed to read index metadata: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let mut embedder = match embed::Embedder::for_index(model.as_deref()) {
+                Ok(e) => e,
+                Err(err) => {
+                    eprintln!("error: failed to init embedder: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let reembedded = db.tokenized_symbols().and_then(|rowids| {
+                for chunk in rowids.chunks(64) {
+                    let tokens = chunk
+                        .iter()
+                        .map(|&rowid| db.get_tokens(rowid))
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    let embeddings = embedder.embed_from_tokens(&tokens)?;
+                    if let Some(dim) = dim
+                        && let Some(e) = embeddings.iter().find(|e| e.len() != dim)
+                    {
+                        anyhow::bail!(
+                            "model produced {} dimensions but the index stores {}",
+                            e.len(),
+                            dim
+                        );
+                    }
+                    let pairs: Vec<(i64, &[f32])> = chunk
+                        .iter()
+                        .copied()
+                        .zip(embeddings.iter().map(Vec::as_slice))
+                        .collect();
+                    db.set_embeddings(&pairs)?;
+                }
+                Ok(rowids.len())
+            });
+            drop(index_lock);
+            match reembedded {
+                Ok(n) => println!("re-embedded {} symbols from stored tokens", n),
+                Err(err) => {
+                    eprintln!("error: reembed failed: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Commands::MigrateModel {
+            model,
+            batch_size,
+            verbose,
+        } => {
+            let root = repo_root_or_exit();
+            let repo = match embed::resolve_model_name(&model) {
+                Ok(repo) => repo,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let index_lock = match lock::IndexLock::acquire(&root) {
+                Ok(lock) => lock,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let db = match db::DB::open_maintenance(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let mut embedder = match embed::Embedder::new_default() {
+                Ok(e) => e,
+                Err(err) => {
+                    eprintln!("error: failed to init embedder: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            if let Err(err) = embedder.change_model(repo) {
+                eprintln!("error: failed to load {}: {:#}", repo, err);
+                std::process::exit(2);
+            }
+            let bar = match db.vector_rowids() {
+                Ok(rowids) if verbose => indicatif::ProgressBar::new(rowids.len() as u64),
+                _ => indicatif::ProgressBar::hidden(),
+            };
+            if let Ok(style) = progress::style(progress::MAIN_TEMPLATE) {
+                bar.set_style(style);
+            }
+            bar.set_message(format!("embedding with {}", repo));
+            let migrated = migrate::migrate(
+                &db,
+                repo,
+                batch_size,
+                |texts| embedder.embed(texts),
+                |n| bar.inc(n as u64),
+            );
+            bar.finish_and_clear();
+            drop(index_lock);
+            match migrated {
+                Ok(report) => println!(
+                    "migrated {} symbols to {} ({} dimensions; {} embedded by an earlier run)",
+                    report.vectors, repo, report.dim, report.resumed
+                ),
+                Err(err) => {
+                    eprintln!("error: migration failed: {:#}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Commands::Export { format, out } => {
+            let root = repo_root_or_exit();
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let symbols = match db.all_symbols() {
+                Ok(symbols) => symbols,
+                Err(err) => {
+                    eprintln!("error: failed to read symbols: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            if symbols.is_empty() {
+                eprintln!("index is empty — run `cearch index`");
+                std::process::exit(1);
+            }
+            let written = std::fs::File::create(&out).and_then(|file| match format {
+                output::ExportFormat::Ctags => {
+                    output::write_ctags(std::io::BufWriter::new(file), &symbols)
+                }
+            });
+            if let Err(err) = written {
+                eprintln!("error: failed to write {}: {}", out.display(), err);
+                std::process::exit(2);
+            }
+            eprintln!("wrote {} tags to {}", symbols.len(), out.display());
+        }
+        Commands::List { empty_files } => {
+            let root = repo_root_or_exit();
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let listed = if empty_files {
+                db.empty_files()
+            } else {
+                db.indexed_files()
+            };
+            let files = match listed {
+                Ok(files) => files,
                 Err(err) => {
-                    eprintln!("error: failed to read current directory: {}", err);
+                    eprintln!("error: failed to read index: {}", err);
                     std::process::exit(2);
                 }
             };
-
-            let root = match index::find_git_root(&cwd) {
-                Some(dir) => dir,
-                None => {
-                    eprintln!("error: not inside a git repository: {}", cwd.display());
+            let cwd = std::env::current_dir()
+                .and_then(|d| d.canonicalize())
+                .unwrap_or_else(|_| root.clone());
+            for f in files {
+                println!("{}", paths::display(&root, &cwd, &f, paths::PathStyle::Cwd));
+            }
+        }
+        Commands::Models { command } => {
+            let cache_root = repo_root_or_exit().join(".cearch");
+            let (ModelsCommand::Install { name, .. } | ModelsCommand::Export { name, .. }) =
+                &command;
+            let repo = match embed::resolve_model_name(name) {
+                Ok(repo) => repo,
+                Err(err) => {
+                    eprintln!("error: {}", err);
                     std::process::exit(2);
                 }
             };
-            match index::list_git_tracked_files(&root) {
-                Ok(files) => {
-                    // Initialize embedder up-front (may download/cold-start); avoid drawing bars during this
-                    let mut embedder = match embed::Embedder::new_default() {
-                        Ok(e) => e,
+            match command {
+                ModelsCommand::Install { from, .. } => {
+                    let installed =
+                        embed::install_model(&cache_root, repo, &from, embed::verify_model);
+                    let dir = match installed {
+                        Ok(dir) => dir,
                         Err(err) => {
-                            eprintln!("error: failed to init embedder: {}", err);
+                            eprintln!("error: failed to install {}: {:#}", repo, err);
                             std::process::exit(2);
                         }
                     };
-
-                    // Open DB with model dimension; AllMiniLML6V2 is 384 dims
-                    let db = match db::DB::open_with_dim(&root, 384) {
-                        Ok(db) => db,
+                    println!("installed {} in {}", repo, dir.display());
+                }
+                ModelsCommand::Export { to, .. } => {
+                    if let Err(err) = embed::export_model(&cache_root, repo, &to) {
+                        eprintln!("error: failed to export {}: {:#}", repo, err);
+                        std::process::exit(2);
+                    }
+                    println!("exported {} to {}", repo, to.display());
+                }
+            }
+        }
+        Commands::Cache { command } => {
+            let root = repo_root_or_exit();
+            let cache_root = root.join(".cearch");
+            let models = cache::models(&cache_root);
+            let embeddings = cache::embedding_caches(&root);
+            let describe_model = |model: &cache::CachedModel| {
+                let used = model
+                    .last_used
+                    .map_or("never".to_string(), output::format_unix_date);
+                format!(
+                    "{} ({}, last used {})",
+                    model.name,
+                    indicatif::HumanBytes(model.bytes),
+                    used
+                )
+            };
+            let describe_embeddings = |(name, path): &(&str, std::path::PathBuf)| {
+                format!(
+                    "{}: {} ({})",
+                    name,
+                    path.display(),
+                    indicatif::HumanBytes(cache::database_size(path))
+                )
+            };
+            match command {
+                CacheCommand::Info {} => {
+                    let models_dir = cache::models_dir(&cache_root);
+                    println!(
+                        "models: {} ({})",
+                        models_dir.display(),
+                        indicatif::HumanBytes(cache::size_of(&models_dir))
+                    );
+                    for model in &models {
+                        println!("  {}", describe_model(model));
+                    }
+                    if embeddings.is_empty() {
+                        println!("embeddings: none");
+                    } else {
+                        println!("embeddings:");
+                        for cached in &embeddings {
+                            println!("  {}", describe_embeddings(cached));
+                        }
+                    }
+                }
+                CacheCommand::Prune { older_than } => {
+                    let age = match cache::parse_age(&older_than) {
+                        Ok(age) => age,
                         Err(err) => {
-                            eprintln!("error: failed to open sqlite index: {}", err);
+                            eprintln!("error: {}", err);
                             std::process::exit(2);
                         }
                     };
-
-                    // Optional progress
-                    let mp = if verbose {
-                        Some(MultiProgress::new())
-                    } else {
+                    let stale = cache::unused_models(&cache_root, age, cache::now());
+                    if let Err(err) = cache::remove_models(&cache_root, &stale) {
+                        eprintln!("error: failed to delete cached models: {}", err);
+                        std::process::exit(2);
+                    }
+                    for model in &stale {
+                        println!("deleted {}", describe_model(model));
+                    }
+                    println!(
+                        "{} of {} cached models unused in {}",
+                        stale.len(),
+                        models.len(),
+                        older_than
+                    );
+                }
+                CacheCommand::Clear {
+                    models: only_models,
+                    embeddings: only_embeddings,
+                    yes,
+                } => {
+                    let models = if only_embeddings { Vec::new() } else { models };
+                    let embeddings = if only_models { Vec::new() } else { embeddings };
+                    if models.is_empty() && embeddings.is_empty() {
+                        println!("nothing to delete");
+                        return;
+                    }
+                    // `index --deps` and `--history` write these databases under the lock
+                    let _index_lock = if embeddings.is_empty() {
                         None
-                    };
-                    let main_pb = if let Some(ref mp) = mp {
-                        let pb = mp.add(ProgressBar::new(files.len() as u64));
-                        if let Ok(style) = ProgressStyle::with_template(
-                            "{spinner:.green} {pos}/{len} [{bar:40.white/black}] {per_sec} ETA {eta} {msg}",
-                        ) {
-                            pb.set_style(style.progress_chars("=> "));
-                        }
-                        pb.set_message(String::from("Indexing repo"));
-                        Some(pb)
                     } else {
-                        None
-                    };
-
-                    // Process each file: parse symbols, embed in chunks with a per-file bar, then insert
-                    for f in files {
-                        let symbols_in_file = match symbols::enumerate_symbols_in_file(&f) {
-                            Ok(v) => v,
+                        match lock::IndexLock::acquire(&root) {
+                            Ok(lock) => Some(lock),
                             Err(err) => {
-                                if let Some(ref mp) = mp {
-                                    let _ = mp.println(format!(
-                                        "warn: failed to parse {}: {}",
-                                        f.display(),
-                                        err
-                                    ));
-                                } else {
-                                    eprintln!("warn: failed to parse {}: {}", f.display(), err);
-                                }
-                                if let Some(ref main_pb) = main_pb {
-                                    main_pb.inc(1);
-                                }
-                                continue;
-                            }
-                        };
-
-                        if symbols_in_file.is_empty() {
-                            if let Some(ref main_pb) = main_pb {
-                                main_pb.inc(1);
-                            }
-                            continue;
-                        }
-
-                        // Optional per-file bar
-                        let file_pb = if let Some(ref mp) = mp {
-                            let pb = mp.add(ProgressBar::new(symbols_in_file.len() as u64));
-                            if let Ok(style) = ProgressStyle::with_template(
-                                "  ↳ {spinner:.green} {pos}/{len} [{bar.white/black}] {per_sec} {msg}",
-                            ) {
-                                pb.set_style(style.progress_chars("=> "));
-                            }
-                            if let Some(name) = f.file_name().and_then(|s| s.to_str()) {
-                                pb.set_message(name.to_string());
-                            }
-                            Some(pb)
-                        } else {
-                            None
-                        };
-
-                        // Embed in small batches to report progress without interfering with main bar
-                        let batch_size: usize = 64;
-                        let mut idx = 0usize;
-                        while idx < symbols_in_file.len() {
-                            let end = usize::min(idx + batch_size, symbols_in_file.len());
-                            let chunk = &symbols_in_file[idx..end];
-                            let codes = chunk.iter().map(|s| s.code.as_str());
-                            let embeddings_chunk = match embedder.embed(codes) {
-                                Ok(v) => v,
-                                Err(err) => {
-                                    if let Some(ref mp) = mp {
-                                        let _ = mp.println(format!(
-                                            "warn: failed to embed symbols for {}: {}",
-                                            f.display(),
-                                            err
-                                        ));
-                                    } else {
-                                        eprintln!(
-                                            "warn: failed to embed symbols for {}: {}",
-                                            f.display(),
-                                            err
-                                        );
-                                    }
-                                    break;
-                                }
-                            };
-
-                            for (sym, emb) in chunk.iter().zip(embeddings_chunk.into_iter()) {
-                                let kind = match sym.kind {
-                                    symbols::SymbolKind::Function => "fn",
-                                    symbols::SymbolKind::Class => "class",
-                                };
-                                if let Err(err) = db.insert_symbol(
-                                    &sym.path, sym.line, kind, &sym.name, &sym.code, &emb,
-                                ) {
-                                    if let Some(ref mp) = mp {
-                                        let _ = mp.println(format!(
-                                            "warn: failed to insert symbol {}:{}: {}",
-                                            sym.path.display(),
-                                            sym.line,
-                                            err
-                                        ));
-                                    } else {
-                                        eprintln!(
-                                            "warn: failed to insert symbol {}:{}: {}",
-                                            sym.path.display(),
-                                            sym.line,
-                                            err
-                                        );
-                                    }
-                                }
-                            }
-
-                            if let Some(ref file_pb) = file_pb {
-                                file_pb.inc((end - idx) as u64);
+                                eprintln!("error: {}", err);
+                                std::process::exit(2);
                             }
-                            idx = end;
-                        }
-
-                        if let Some(file_pb) = file_pb {
-                            file_pb.finish_and_clear();
-                        }
-                        if let Some(ref main_pb) = main_pb {
-                            main_pb.inc(1);
                         }
+                    };
+                    let listed: Vec<String> = models
+                        .iter()
+                        .map(describe_model)
+                        .chain(embeddings.iter().map(describe_embeddings))
+                        .collect();
+                    for line in &listed {
+                        eprintln!("  {}", line);
                     }
-
-                    if let Some(main_pb) = main_pb {
-                        main_pb.finish_with_message("indexing complete");
+                    if !confirm(&format!("delete these {}?", listed.len()), yes) {
+                        eprintln!("nothing deleted");
+                        std::process::exit(1);
+                    }
+                    if let Err(err) = cache::remove_models(&cache_root, &models) {
+                        eprintln!("error: failed to delete cached models: {}", err);
+                        std::process::exit(2);
+                    }
+                    for (_, path) in &embeddings {
+                        if let Err(err) = db::DB::remove_database(path) {
+                            eprintln!("error: failed to delete {}: {}", path.display(), err);
+                            std::process::exit(2);
+                        }
                     }
+                    println!("deleted {}", listed.len());
                 }
+            }
+        }
+        Commands::Check {} => {
+            let root = repo_root_or_exit();
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
                 Err(err) => {
-                    eprintln!("error: {}", err);
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let counts = db
+                .count_canonical_symbols()
+                .and_then(|symbols| db.count_vectors().map(|vectors| (symbols, vectors)));
+            let (symbol_count, vector_count) = match counts {
+                Ok(v) => v,
+                Err(err) => {
+                    eprintln!("error: failed to read index: {}", err);
                     std::process::exit(2);
                 }
+            };
+            println!("symbols: {}", symbol_count);
+            if matches!(db.is_metadata_only(), Ok(true)) {
+                println!("vectors: none (metadata-only index)");
+                println!("ok");
+                return;
             }
+            println!("vectors: {}", vector_count);
+            if symbol_count != vector_count {
+                eprintln!(
+                    "error: symbol and vector counts differ; re-run `cearch clean` and `cearch index`"
+                );
+                std::process::exit(1);
+            }
+            println!("ok");
         }
-        Commands::Init {} => {
-            // Resolve repo root
-            let cwd = match std::env::current_dir() {
-                Ok(dir) => dir,
+        Commands::Selftest { no_model } => {
+            if let Err(stage) = selftest::run(no_model) {
+                eprintln!("error: selftest failed at stage: {}", stage);
+                std::process::exit(1);
+            }
+            println!("selftest passed");
+        }
+        Commands::Graph {
+            k,
+            threshold,
+            format,
+            out,
+            per_file,
+            sample,
+            seed,
+            paths: path_prefix,
+        } => {
+            if k == 0 {
+                eprintln!("error: --k must be at least 1");
+                std::process::exit(2);
+            }
+            let root = repo_root_or_exit();
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
                 Err(err) => {
-                    eprintln!("error: failed to read current directory: {}", err);
+                    eprintln!("error: failed to open sqlite index: {}", err);
                     std::process::exit(2);
                 }
             };
-            let root = match index::find_git_root(&cwd) {
-                Some(dir) => dir,
-                None => {
-                    eprintln!("error: not inside a git repository: {}", cwd.display());
+            if matches!(db.is_metadata_only(), Ok(true)) {
+                eprintln!("error: index is metadata-only; there are no vectors to compare");
+                std::process::exit(2);
+            }
+            let opts = graph::GraphOptions {
+                k,
+                threshold,
+                per_file,
+                sample,
+                seed,
+                path_prefix: path_prefix.map(|p| {
+                    paths::normalize_separators(&p)
+                        .trim_start_matches("./")
+                        .to_string()
+                }),
+            };
+            let built = match graph::build(&db, &opts) {
+                Ok(built) => built,
+                Err(err) => {
+                    eprintln!("error: {:#}", err);
                     std::process::exit(2);
                 }
             };
-            let cearch_dir = root.join(".cearch");
-            if let Err(err) = std::fs::create_dir_all(&cearch_dir) {
-                eprintln!("error: creating {}: {}", cearch_dir.display(), err);
+            let written = match &out {
+                Some(path) => std::fs::File::create(path)
+                    .and_then(|file| graph::write(std::io::BufWriter::new(file), &built, format)),
+                None => graph::write(std::io::stdout().lock(), &built, format),
+            };
+            if let Err(err) = written {
+                eprintln!("error: failed to write graph: {}", err);
                 std::process::exit(2);
             }
-            // Update .gitignore
-            let gi = root.join(".gitignore");
-            let entry = ".cearch/\n";
-            let needs_append = match std::fs::read_to_string(&gi) {
-                Ok(s) => !s.lines().any(|l| {
-                    let t = l.trim();
-                    t == ".cearch/" || t == ".cearch"
-                }),
-                Err(_) => true,
+            eprintln!("{} nodes, {} edges", built.nodes.len(), built.edges.len());
+        }
+        Commands::Annotate {
+            file,
+            threshold,
+            format,
+        } => {
+            let root = repo_root_or_exit();
+            let symbols = match annotate::file_symbols(&file) {
+                Ok(symbols) => symbols,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
             };
-            if needs_append {
-                if let Err(err) = std::fs::OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&gi)
-                    .and_then(|mut f| std::io::Write::write_all(&mut f, entry.as_bytes()))
-                {
-                    eprintln!("warn: failed to update {}: {}", gi.display(), err);
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
+                    std::process::exit(2);
                 }
+            };
+            if matches!(db.is_metadata_only(), Ok(true)) {
+                eprintln!("error: index is metadata-only; there are no vectors to compare");
+                std::process::exit(2);
             }
-            // Pre-download default model into cache (Embedder uses .cearch)
-            match embed::Embedder::new_default() {
-                Ok(_) => println!("initialized: {}", cearch_dir.display()),
+            let model = db.embedding_model().ok().flatten();
+            if let Some(model) = &model
+                && !embed::is_loadable(model)
+            {
+                tracing::warn!(
+                    "index holds embeddings from {} but the file is embedded with {}",
+                    model,
+                    embed::DEFAULT_MODEL_REPO
+                );
+            }
+            let mut embedder = match embed::Embedder::for_index(model.as_deref()) {
+                Ok(e) => e,
                 Err(err) => {
-                    eprintln!("error: failed to initialize model cache: {}", err);
+                    eprintln!("error: failed to init embedder: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let strip = matches!(db.get_meta("annotation_strip"), Ok(Some(v)) if v == "1");
+            let texts = annotate::embedded_texts(&symbols, &file, strip);
+            let vectors = match embedder.embed(texts) {
+                Ok(vectors) => vectors,
+                Err(err) => {
+                    eprintln!("error: failed to embed {}: {}", file.display(), err);
+                    std::process::exit(2);
+                }
+            };
+            let file = file.canonicalize().unwrap_or(file);
+            let stored = paths::to_stored(&root, &file);
+            let annotations = match annotate::annotate(&db, &stored, &symbols, &vectors, threshold)
+            {
+                Ok(annotations) => annotations,
+                Err(err) => {
+                    eprintln!("error: knn failed: {:#}", err);
                     std::process::exit(2);
                 }
+            };
+            if let Err(err) =
+                annotate::write(std::io::stdout().lock(), &stored, &annotations, format)
+            {
+                eprintln!("error: failed to write annotations: {}", err);
+                std::process::exit(2);
             }
+            eprintln!(
+                "{} of {} symbols have a possible duplicate",
+                annotations.len(),
+                symbols.len()
+            );
         }
-        Commands::Query { query, num_results } => {
-            // Resolve repo root from current working directory
-            let cwd = match std::env::current_dir() {
-                Ok(dir) => dir,
+        Commands::Verify {
+            sample,
+            seed,
+            threshold,
+        } => {
+            let root = repo_root_or_exit();
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
                 Err(err) => {
-                    eprintln!("error: failed to read current directory: {}", err);
+                    eprintln!("error: failed to open sqlite index: {}", err);
                     std::process::exit(2);
                 }
             };
-            let root = match index::find_git_root(&cwd) {
-                Some(dir) => dir,
-                None => {
-                    eprintln!("error: not inside a git repository: {}", cwd.display());
+            if matches!(db.is_metadata_only(), Ok(true)) {
+                eprintln!("error: index is metadata-only; there are no vectors to verify");
+                std::process::exit(2);
+            }
+            let model = match db.embedding_model() {
+                Ok(Some(model)) if !embed::is_loadable(&model) => {
+                    eprintln!(
+                        "error: index holds embeddings imported from {}, which cearch cannot recompute",
+                        model
+                    );
+                    std::process::exit(2);
+                }
+                Ok(model) => model,
+                Err(err) => {
+                    eprintln!("error: failed to read index metadata: {}", err);
                     std::process::exit(2);
                 }
             };
-
-            // Embed the query string
-            let mut embedder = match embed::Embedder::new_default() {
+            let mut embedder = match embed::Embedder::for_index(model.as_deref()) {
                 Ok(e) => e,
                 Err(err) => {
                     eprintln!("error: failed to init embedder: {}", err);
                     std::process::exit(2);
                 }
             };
-            let embedding = match embedder.embed([query.as_str()]) {
-                Ok(mut v) => {
-                    if v.is_empty() {
-                        eprintln!("error: empty embedding");
-                        std::process::exit(2);
-                    }
-                    v.remove(0)
+            let report = match verify::verify(&db, sample, seed, |texts| embedder.embed(texts)) {
+                Ok(report) => report,
+                Err(err) => {
+                    eprintln!("error: {:#}", err);
+                    std::process::exit(2);
+                }
+            };
+            let describe = |rowid: i64| match db.get_symbol(rowid) {
+                Ok(Some(s)) => format!("#{} {}:{} {}", rowid, s.path, s.line, s.name),
+                _ => format!("#{}", rowid),
+            };
+            println!(
+                "verified {} of {} vectors (seed {})",
+                report.similarities.len(),
+                report.total,
+                seed
+            );
+            if let Some(mean) = report.mean() {
+                let q = |q| report.quantile(q).unwrap_or(mean);
+                println!(
+                    "cosine similarity: min {:.4}  p10 {:.4}  median {:.4}  max {:.4}  mean {:.4}",
+                    q(0.0),
+                    q(0.1),
+                    q(0.5),
+                    q(1.0),
+                    mean
+                );
+                let mut lowest = report.similarities.clone();
+                lowest.sort_by(|a, b| a.1.total_cmp(&b.1));
+                for &(rowid, similarity) in lowest.iter().take(5).filter(|s| s.1 < threshold) {
+                    println!("  {:.4} {}", similarity, describe(rowid));
+                }
+            }
+            for corrupt in &report.corrupt {
+                eprintln!("corrupt: {}: {}", describe(corrupt.rowid), corrupt.reason);
+            }
+            let drifted = report.drifted(threshold);
+            if drifted {
+                eprintln!(
+                    "error: mean similarity is below {}; the model has changed since indexing, run `cearch clean` and `cearch index` to re-embed every symbol",
+                    threshold
+                );
+            }
+            if drifted || !report.corrupt.is_empty() {
+                std::process::exit(1);
+            }
+            println!("ok");
+        }
+        Commands::EmbedQuery { text, out } => {
+            let text = match search::validate_query(&text) {
+                Ok(text) => text,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
                 }
+            };
+            let model = stored_embedding_model(&repo_root_or_exit());
+            let embedding = embed_query(text, model.as_deref());
+            if let Err(err) = embed::write_query_embedding(&out, &embedding) {
+                eprintln!("error: {:#}", err);
+                std::process::exit(2);
+            }
+        }
+        Commands::Gc {} => {
+            let root = repo_root_or_exit();
+            let db_path = db::DB::file_path(&root);
+            if !db_path.exists() {
+                eprintln!("index is empty — run `cearch index`");
+                std::process::exit(1);
+            }
+            let _index_lock = match lock::IndexLock::acquire(&root) {
+                Ok(lock) => lock,
                 Err(err) => {
-                    eprintln!("error: failed to embed query: {}", err);
+                    eprintln!("error: {}", err);
                     std::process::exit(2);
                 }
             };
-
-            // Open DB and perform KNN
-            let db = match db::DB::open_read(&root) {
+            let db = match db::DB::open_maintenance(&root) {
                 Ok(db) => db,
                 Err(err) => {
                     eprintln!("error: failed to open sqlite index: {}", err);
                     std::process::exit(2);
                 }
             };
-
-            match db.knn(&embedding, num_results) {
-                Ok(results) => {
-                    for (path, line, name, dist) in results {
-                        let rel = path.strip_prefix(&root).unwrap_or(&path);
-                        println!("{}:{} {} {:.3}", rel.display(), line, name, dist);
+            let file_size = || std::fs::metadata(&db_path).map(|m| m.len()).unwrap_or(0);
+            let file_before = file_size();
+            if !db.is_split() {
+                let compacted = db.vec_index_bytes().and_then(|before| {
+                    db.vacuum_vec_index()?;
+                    Ok((before, db.vec_index_bytes()?))
+                });
+                match compacted {
+                    Ok((before, after)) => println!(
+                        "vec_index: {} -> {}",
+                        indicatif::HumanBytes(before),
+                        indicatif::HumanBytes(after)
+                    ),
+                    Err(err) => {
+                        eprintln!("error: failed to compact vec_index: {}", err);
+                        std::process::exit(2);
+                    }
+                }
+            }
+            if let Err(err) = db.vacuum() {
+                eprintln!("error: failed to vacuum sqlite index: {}", err);
+                std::process::exit(2);
+            }
+            println!(
+                "index.sqlite: {} -> {}",
+                indicatif::HumanBytes(file_before),
+                indicatif::HumanBytes(file_size())
+            );
+        }
+        Commands::Rekey {
+            new_key_file,
+            decrypt,
+            db_path: _,
+        } => {
+            let root = repo_root_or_exit();
+            if !db::DB::file_path(&root).exists() {
+                eprintln!("index is empty — run `cearch index`");
+                std::process::exit(1);
+            }
+            let new_key = if decrypt {
+                None
+            } else {
+                match encryption::read_key(
+                    std::env::var(encryption::NEW_KEY_ENV).ok(),
+                    new_key_file,
+                ) {
+                    Ok(Some(key)) => Some(key),
+                    Ok(None) => {
+                        eprintln!(
+                            "error: set {} or pass --new-key-file (or --decrypt)",
+                            encryption::NEW_KEY_ENV
+                        );
+                        std::process::exit(2);
+                    }
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
                     }
                 }
+            };
+            let _index_lock = match lock::IndexLock::acquire(&root) {
+                Ok(lock) => lock,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            if let Err(err) = db::DB::rekey(&root, encryption::key(), new_key.as_deref()) {
+                eprintln!("error: failed to rekey the index: {:#}", err);
+                std::process::exit(2);
+            }
+            if decrypt {
+                println!(
+                    "index decrypted; unset {} and {}",
+                    encryption::KEY_ENV,
+                    encryption::KEY_FILE_ENV
+                );
+            } else {
+                println!(
+                    "index encrypted with the new key; set {} to it from now on",
+                    encryption::KEY_ENV
+                );
+            }
+        }
+        Commands::Rpc {} => {
+            let root = repo_root_or_exit();
+            let model = stored_embedding_model(&root);
+            let mut embedder = match embed::Embedder::for_index(model.as_deref()) {
+                Ok(e) => e,
+                Err(err) => {
+                    eprintln!("error: failed to init embedder: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            let embed = |query: &str| -> anyhow::Result<Vec<f32>> {
+                embedder
+                    .embed([query])?
+                    .pop()
+                    .ok_or_else(|| anyhow::anyhow!("empty embedding"))
+            };
+            let mut session = match rpc::Session::open(&root, embed) {
+                Ok(session) => session,
                 Err(err) => {
-                    eprintln!("error: knn failed: {}", err);
+                    eprintln!("error: failed to open sqlite index: {}", err);
                     std::process::exit(2);
                 }
+            };
+            if let Err(err) = session.run(std::io::stdin().lock(), std::io::stdout().lock()) {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
             }
         }
-        Commands::Clean {} => {
-            // Resolve repo root from current working directory
-            let cwd = match std::env::current_dir() {
-                Ok(dir) => dir,
+        Commands::Lsp {} => {
+            let root = repo_root_or_exit();
+            // Loaded on the first semantic search, so symbol lookups work without the model
+            let mut embedder = None;
+            let embed = |query: &str| -> anyhow::Result<Vec<f32>> {
+                if embedder.is_none() {
+                    let model = stored_embedding_model(&root);
+                    embedder = Some(embed::Embedder::for_index(model.as_deref())?);
+                }
+                embedder
+                    .as_mut()
+                    .expect("just initialized")
+                    .embed([query])?
+                    .pop()
+                    .ok_or_else(|| anyhow::anyhow!("empty embedding"))
+            };
+            let mut server = match lsp::Server::open(&root, embed) {
+                Ok(server) => server,
                 Err(err) => {
-                    eprintln!("error: failed to read current directory: {}", err);
+                    eprintln!("error: failed to open sqlite index: {}", err);
                     std::process::exit(2);
                 }
             };
-            let root = match index::find_git_root(&cwd) {
-                Some(dir) => dir,
-                None => {
-                    eprintln!("error: not inside a git repository: {}", cwd.display());
+            match server.run(std::io::stdin().lock(), std::io::stdout().lock()) {
+                Ok(true) => {}
+                // LSP: exiting without a shutdown request is an error
+                Ok(false) => std::process::exit(1),
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Commands::Serve { port } => {
+            let root = repo_root_or_exit();
+            let db = match db::DB::open_read(&root) {
+                Ok(db) => db,
+                Err(err) => {
+                    eprintln!("error: failed to open sqlite index: {}", err);
                     std::process::exit(2);
                 }
             };
-            let cearch_dir = root.join(".cearch");
-            if let Err(err) = std::fs::remove_dir_all(&cearch_dir) {
-                if err.kind() != std::io::ErrorKind::NotFound {
-                    eprintln!("error: failed to delete .cearch directory: {}", err);
+            let git_commit = db.git_commit().ok().flatten();
+            // Embed queries with the model the index was built with
+            let model = db.embedding_model().ok().flatten();
+            let mut embedder = match embed::Embedder::for_index(model.as_deref()) {
+                Ok(e) => e,
+                Err(err) => {
+                    eprintln!("error: failed to init embedder: {}", err);
                     std::process::exit(2);
                 }
-            } else {
-                // Remove .cearch entries from .gitignore if present
-                let gi = root.join(".gitignore");
-                if let Ok(contents) = std::fs::read_to_string(&gi) {
-                    let filtered = contents
-                        .lines()
-                        .filter(|l| {
-                            let t = l.trim();
-                            !(t == ".cearch/" || t == ".cearch")
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
-                    if let Err(err) = std::fs::write(
-                        &gi,
-                        if filtered.is_empty() {
-                            String::new()
-                        } else {
-                            format!("{}\n", filtered)
-                        },
-                    ) {
-                        eprintln!("warn: failed to update {}: {}", gi.display(), err);
+            };
+            let search = |query: &str, k: usize| -> anyhow::Result<Vec<serve::QueryHit>> {
+                // The index may be rebuilt with another model while the server runs
+                if let Some(model) = db.embedding_model()?
+                    && let Ok(repo) = embed::resolve_model_name(&model)
+                    && repo != embedder.model_repo()
+                {
+                    let previous = embedder.model_repo();
+                    embedder.change_model(repo)?;
+                    eprintln!("switched model from {} to {}", previous, repo);
+                    if embedder.dimension_changed_since_last_embed() {
+                        tracing::warn!(
+                            "{} and {} embed into different dimensions; vectors from one cannot be compared with the other's",
+                            previous,
+                            repo
+                        );
                     }
                 }
-                println!("cleaned: {}", cearch_dir.display());
+                let embedding = embedder
+                    .embed([query])?
+                    .pop()
+                    .ok_or_else(|| anyhow::anyhow!("empty embedding"))?;
+                let hits = db
+                    .knn(&embedding, k)?
+                    .into_iter()
+                    .map(|hit| serve::QueryHit {
+                        id: hit.id,
+                        path: paths::to_stored(&root, std::path::Path::new(&hit.path)),
+                        line: hit.line,
+                        name: hit.name,
+                        distance: hit.distance,
+                    })
+                    .collect();
+                Ok(hits)
+            };
+            if let Err(err) = serve::run(&root, port, git_commit.as_deref(), search) {
+                eprintln!("error: server failed: {}", err);
+                std::process::exit(2);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query_args(args: &[&str]) -> Result<QueryArgs, clap::Error> {
+        let cli = Cli::try_parse_from(["cearch", "query"].iter().chain(args))?;
+        match cli.command {
+            Commands::Query(args) => Ok(*args),
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn search_mode_flags_pick_one_mode() {
+        assert!(query_args(&["open_db", "--keyword-only"]).unwrap().keyword);
+        assert!(query_args(&["open_db", "--keyword"]).unwrap().keyword);
+        let semantic = query_args(&["open the database", "--semantic-only"]).unwrap();
+        assert!(semantic.semantic_only && !semantic.keyword);
+        let err = query_args(&["open_db", "--semantic-only", "--keyword-only"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+}
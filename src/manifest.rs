@@ -0,0 +1,317 @@
+use crate::config::IndexProfile;
+use crate::db::{self, DB};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+// Manifest module describes an index in `.cearch/manifest.json` after each index run, for
+// tools that should not need to open SQLite.
+
+/// Version of the manifest layout; bump it when a field changes meaning or goes away, not
+/// when one is added.
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+/// What an index holds and how it was built: the index's meta table and counts, the file
+/// selection in effect and the run's timings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub manifest_version: u32,
+    /// Version of the cearch that wrote the manifest
+    pub cearch_version: String,
+    /// `index`, or the name given to `--index-name`
+    pub index_name: String,
+    /// Repository of the embedding model; none for a metadata-only index
+    pub model: Option<String>,
+    pub dimension: Option<usize>,
+    /// `inline` or `separate` (`--embedding-store`)
+    pub embedding_store: String,
+    /// The database's schema version (`PRAGMA user_version`)
+    pub schema_version: u32,
+    /// Commit the index was built from
+    pub git_commit: Option<String>,
+    /// Tracked files had uncommitted changes when the index was built
+    pub dirty: Option<bool>,
+    /// Unix seconds the last index run finished
+    pub indexed_at: Option<u64>,
+    pub files: usize,
+    pub symbols: usize,
+    /// Files and symbols per language name, e.g. `Rust`
+    pub languages: BTreeMap<String, LanguageCounts>,
+    pub ignore: IgnoreSettings,
+    /// Only known right after an index run
+    pub timings: Option<Timings>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LanguageCounts {
+    pub files: usize,
+    pub symbols: usize,
+}
+
+/// Which files an index run considered.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IgnoreSettings {
+    /// Only files git tracks are indexed, so `.gitignore` always applies
+    pub git_tracked_only: bool,
+    /// Languages, path prefixes and excluded path prefixes of `[indexes.<name>]`
+    pub languages: Vec<String>,
+    pub paths: Vec<String>,
+    pub exclude: Vec<String>,
+    pub symbol_limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+    pub elapsed_secs: f64,
+}
+
+impl Manifest {
+    /// Describe the index `db` as it is now, selected by `profile`.
+    pub fn from_db(db: &DB, profile: &IndexProfile) -> Result<Self> {
+        let mut languages: BTreeMap<String, LanguageCounts> = BTreeMap::new();
+        let per_file = db.symbols_per_file()?;
+        for (path, symbols) in &per_file {
+            let language = crate::symbols::language_name(Path::new(path)).unwrap_or("other");
+            let counts = languages.entry(language.to_string()).or_default();
+            counts.files += 1;
+            counts.symbols += symbols;
+        }
+        let metadata_only = db.is_metadata_only()?;
+        Ok(Self {
+            manifest_version: MANIFEST_SCHEMA_VERSION,
+            cearch_version: env!("CARGO_PKG_VERSION").to_string(),
+            index_name: db::index_name().to_string(),
+            model: if metadata_only {
+                None
+            } else {
+                db.embedding_model()?
+            },
+            dimension: if metadata_only {
+                None
+            } else {
+                db.embedding_dim()?
+            },
+            embedding_store: if db.is_split() { "separate" } else { "inline" }.to_string(),
+            schema_version: db.schema_version()?,
+            git_commit: db.git_commit()?,
+            dirty: db.get_meta("git_dirty")?.map(|d| d == "1"),
+            indexed_at: db.get_meta("indexed_at")?.and_then(|t| t.parse().ok()),
+            files: per_file.len(),
+            symbols: per_file.iter().map(|(_, n)| n).sum(),
+            languages,
+            ignore: IgnoreSettings {
+                git_tracked_only: true,
+                languages: profile.languages.clone(),
+                paths: profile.paths.clone(),
+                exclude: profile.exclude.clone(),
+                symbol_limit: db.get_meta("symbol_limit")?.and_then(|n| n.parse().ok()),
+            },
+            timings: None,
+        })
+    }
+
+    /// Write the manifest to `path` through a temporary file renamed over it, so readers
+    /// never see half of one.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)? + "\n";
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        std::fs::write(&tmp, json).with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))
+    }
+
+    /// Read the manifest at `path`; a missing file is `None`.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(Some(
+                serde_json::from_str(&contents)
+                    .with_context(|| format!("invalid {}", path.display()))?,
+            )),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// Fields describing the index's contents that differ from `other`, e.g.
+    /// `symbols: 120 != 118`; the writer's version and timings are not compared.
+    pub fn differences(&self, other: &Manifest) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut compare = |field: &str, a: String, b: String| {
+            if a != b {
+                out.push(format!("{}: {} != {}", field, a, b));
+            }
+        };
+        compare(
+            "model",
+            format!("{:?}", self.model),
+            format!("{:?}", other.model),
+        );
+        compare(
+            "dimension",
+            format!("{:?}", self.dimension),
+            format!("{:?}", other.dimension),
+        );
+        compare(
+            "embedding_store",
+            self.embedding_store.clone(),
+            other.embedding_store.clone(),
+        );
+        compare(
+            "schema_version",
+            self.schema_version.to_string(),
+            other.schema_version.to_string(),
+        );
+        compare(
+            "git_commit",
+            format!("{:?}", self.git_commit),
+            format!("{:?}", other.git_commit),
+        );
+        compare(
+            "dirty",
+            format!("{:?}", self.dirty),
+            format!("{:?}", other.dirty),
+        );
+        compare(
+            "indexed_at",
+            format!("{:?}", self.indexed_at),
+            format!("{:?}", other.indexed_at),
+        );
+        compare("files", self.files.to_string(), other.files.to_string());
+        compare(
+            "symbols",
+            self.symbols.to_string(),
+            other.symbols.to_string(),
+        );
+        compare(
+            "languages",
+            format!("{:?}", self.languages),
+            format!("{:?}", other.languages),
+        );
+        out
+    }
+}
+
+/// Location of the manifest of the index in use: `.cearch/manifest.json` for the default
+/// index, `.cearch/<name>.manifest.json` for a named one.
+pub fn file_path(repo_root: &Path) -> PathBuf {
+    let cearch_dir = repo_root.join(".cearch");
+    match db::index_name() {
+        db::DEFAULT_INDEX_NAME => cearch_dir.join("manifest.json"),
+        name => cearch_dir.join(format!("{}.manifest.json", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SymbolRecord, WriteOptions};
+
+    fn indexed(root: &Path) -> DB {
+        let db = DB::open_metadata_only(root, &WriteOptions::default()).expect("open");
+        db.insert_symbols(&[
+            SymbolRecord::fixture("src/a.rs", 1, "a", &[]),
+            SymbolRecord::fixture("src/a.rs", 5, "b", &[]),
+            SymbolRecord::fixture("tools/c.py", 1, "c", &[]),
+        ])
+        .unwrap();
+        db.set_meta("git_commit", "abc123").unwrap();
+        db.set_meta("git_dirty", "1").unwrap();
+        db.set_meta("indexed_at", "1709296200").unwrap();
+        db
+    }
+
+    #[test]
+    fn manifest_schema() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = indexed(dir.path());
+        let profile = IndexProfile {
+            exclude: vec!["vendor/".to_string()],
+            ..Default::default()
+        };
+        let mut manifest = Manifest::from_db(&db, &profile).unwrap();
+        manifest.timings = Some(Timings { elapsed_secs: 1.5 });
+        let path = dir.path().join(".cearch").join("manifest.json");
+        manifest.write(&path).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "cearch_version",
+                "dimension",
+                "dirty",
+                "embedding_store",
+                "files",
+                "git_commit",
+                "ignore",
+                "index_name",
+                "indexed_at",
+                "languages",
+                "manifest_version",
+                "model",
+                "schema_version",
+                "symbols",
+                "timings",
+            ]
+        );
+        assert_eq!(json["manifest_version"], MANIFEST_SCHEMA_VERSION);
+        assert_eq!(json["cearch_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["index_name"], "index");
+        // Metadata-only: no model to describe
+        assert!(json["model"].is_null() && json["dimension"].is_null());
+        assert_eq!(json["embedding_store"], "inline");
+        assert!(json["schema_version"].as_u64().unwrap() > 0);
+        assert_eq!(json["git_commit"], "abc123");
+        assert_eq!(json["dirty"], true);
+        assert_eq!(json["indexed_at"], 1_709_296_200);
+        assert_eq!(json["files"], 2);
+        assert_eq!(json["symbols"], 3);
+        assert_eq!(json["languages"]["Rust"]["files"], 1);
+        assert_eq!(json["languages"]["Rust"]["symbols"], 2);
+        assert_eq!(json["languages"]["Python"]["symbols"], 1);
+        assert_eq!(json["ignore"]["git_tracked_only"], true);
+        assert_eq!(json["ignore"]["exclude"][0], "vendor/");
+        assert_eq!(json["timings"]["elapsed_secs"], 1.5);
+
+        // Written in one piece, and read back as it was
+        assert!(
+            !dir.path()
+                .join(".cearch")
+                .join("manifest.json.tmp")
+                .exists()
+        );
+        assert_eq!(Manifest::read(&path).unwrap(), Some(manifest.clone()));
+        assert_eq!(
+            Manifest::read(&dir.path().join("missing.json")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn differences_ignore_version_and_timings() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = indexed(dir.path());
+        let live = Manifest::from_db(&db, &IndexProfile::default()).unwrap();
+        let mut written = live.clone();
+        written.cearch_version = "0.0.1".to_string();
+        written.timings = Some(Timings { elapsed_secs: 3.0 });
+        assert!(live.differences(&written).is_empty());
+        written.symbols = 2;
+        written.git_commit = Some("def456".to_string());
+        assert_eq!(
+            written.differences(&live),
+            vec![
+                "git_commit: Some(\"def456\") != Some(\"abc123\")",
+                "symbols: 2 != 3",
+            ]
+        );
+    }
+}
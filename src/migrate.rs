@@ -0,0 +1,256 @@
+use crate::db::DB;
+use anyhow::{Result, bail};
+// Migrate module re-embeds the stored symbols with another model for `cearch migrate-model`,
+// without reading the working tree or asking git for anything.
+
+/// What a migration did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrateReport {
+    /// Symbols embedded by this run
+    pub embedded: usize,
+    /// Symbols already embedded by an interrupted run and not embedded again
+    pub resumed: usize,
+    /// Vectors the index holds afterwards
+    pub vectors: usize,
+    /// Dimensions of the new vectors
+    pub dim: usize,
+}
+
+/// Embed the code of every symbol with a vector using `embed`, `batch` symbols at a time,
+/// and swap the vectors in for the old ones once all are done.
+///
+/// New vectors are staged in the index as they come, so a run that is interrupted picks up
+/// where it stopped when run again for the same `model`. `on_batch` is told how many symbols
+/// each batch finished, for progress output. Symbols stored without code and indexes whose
+/// vectors live outside SQLite are refused before anything is embedded.
+pub fn migrate<E>(
+    db: &DB,
+    model: &str,
+    batch: usize,
+    mut embed: E,
+    mut on_batch: impl FnMut(usize),
+) -> Result<MigrateReport>
+where
+    E: FnMut(&[&str]) -> Result<Vec<Vec<f32>>>,
+{
+    if db.is_metadata_only()? {
+        bail!("index is metadata-only; run `cearch index` to embed it");
+    }
+    if db.is_split() {
+        bail!(
+            "index keeps its embeddings in embeddings.bin; run `cearch clean` and re-index with the new model"
+        );
+    }
+    let missing = db.count_vectors_without_code()?;
+    if missing > 0 {
+        bail!(
+            "{} symbols were stored without their code, so they cannot be re-embedded; run `cearch clean` and re-index with the new model",
+            missing
+        );
+    }
+    let rowids = db.vector_rowids()?;
+    if rowids.is_empty() {
+        bail!("index has no vectors to migrate");
+    }
+    let done = db.start_migration(model)?;
+    let pending: Vec<i64> = rowids
+        .iter()
+        .copied()
+        .filter(|rowid| !done.contains(rowid))
+        .collect();
+    let mut report = MigrateReport {
+        resumed: rowids.len() - pending.len(),
+        ..MigrateReport::default()
+    };
+    on_batch(report.resumed);
+    let mut dim: Option<usize> = None;
+    for chunk in pending.chunks(batch.max(1)) {
+        let texts = chunk
+            .iter()
+            .map(|&rowid| {
+                db.embedded_text(rowid)?
+                    .ok_or_else(|| anyhow::anyhow!("symbol {} disappeared during migration", rowid))
+            })
+            .collect::<Result<Vec<String>>>()?;
+        let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+        let embeddings = embed(&texts)?;
+        if embeddings.len() != chunk.len() {
+            bail!(
+                "model returned {} vectors for {} symbols",
+                embeddings.len(),
+                chunk.len()
+            );
+        }
+        for e in &embeddings {
+            match dim {
+                None => dim = Some(e.len()),
+                Some(dim) if dim != e.len() => {
+                    bail!(
+                        "model produced vectors of {} and {} dimensions",
+                        dim,
+                        e.len()
+                    )
+                }
+                Some(_) => {}
+            }
+        }
+        let pairs: Vec<(i64, &[f32])> = chunk
+            .iter()
+            .copied()
+            .zip(embeddings.iter().map(Vec::as_slice))
+            .collect();
+        db.stage_vectors(&pairs)?;
+        report.embedded += chunk.len();
+        on_batch(chunk.len());
+    }
+    let (vectors, dim) = db.finish_migration(model)?;
+    report.vectors = vectors;
+    report.dim = dim;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SymbolRecord, WriteOptions};
+    use crate::embed::Embedder;
+    use std::path::Path;
+
+    fn index_with(root: &Path, codes: &[&str]) -> Vec<i64> {
+        let db = DB::open_with_dim(root, 2, &WriteOptions::default()).expect("open");
+        let names: Vec<String> = (0..codes.len()).map(|i| format!("f{}", i)).collect();
+        let records: Vec<SymbolRecord> = codes
+            .iter()
+            .zip(&names)
+            .enumerate()
+            .map(|(i, (code, name))| SymbolRecord {
+                code,
+                ..SymbolRecord::fixture("a.rs", i + 1, name, &[1.0, 0.0])
+            })
+            .collect();
+        db.set_meta("embedding_model", "old").unwrap();
+        db.insert_symbols(&records).unwrap()
+    }
+
+    /// Three dimensions: the code's length, then a marker for the mock model.
+    fn mock(texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts
+            .iter()
+            .map(|t| vec![t.len() as f32, 0.5, 1.0])
+            .collect())
+    }
+
+    #[test]
+    fn migration_replaces_every_vector() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let ids = index_with(root, &["fn a() {}", "fn bb() {}", "fn ccc() {}"]);
+        let db = DB::open_maintenance(root).unwrap();
+        let mut progressed = 0;
+        let report = migrate(&db, "mock", 2, mock, |n| progressed += n).unwrap();
+        assert_eq!(
+            report,
+            MigrateReport {
+                embedded: 3,
+                resumed: 0,
+                vectors: 3,
+                dim: 3
+            }
+        );
+        assert_eq!(progressed, 3);
+        drop(db);
+
+        let db = DB::open_read(root).unwrap();
+        assert_eq!(db.count_vectors().unwrap(), db.count_symbols().unwrap());
+        assert_eq!(db.embedding_dim().unwrap(), Some(3));
+        assert_eq!(db.embedding_model().unwrap().as_deref(), Some("mock"));
+        assert_eq!(db.get_meta("migrate_model").unwrap(), None);
+        // No 2-dimension vector is left behind
+        for id in ids {
+            assert_eq!(db.get_embedding(id).unwrap().map(|e| e.len()), Some(3));
+        }
+        assert_eq!(db.knn(&[10.0, 0.5, 1.0], 1).unwrap()[0].name, "f1");
+    }
+
+    #[test]
+    fn interrupted_migration_resumes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        index_with(root, &["fn a() {}", "fn bb() {}", "fn ccc() {}"]);
+        let db = DB::open_maintenance(root).unwrap();
+        let mut calls = 0;
+        let failing = |texts: &[&str]| {
+            calls += 1;
+            if calls > 1 {
+                bail!("interrupted");
+            }
+            mock(texts)
+        };
+        assert!(migrate(&db, "mock", 2, failing, |_| {}).is_err());
+        // The old vectors still serve queries
+        assert_eq!(db.embedding_dim().unwrap(), Some(2));
+        assert_eq!(db.count_vectors().unwrap(), 3);
+
+        let mut embedded = Vec::new();
+        let report = migrate(
+            &db,
+            "mock",
+            2,
+            |texts| {
+                embedded.extend(texts.iter().map(|t| t.to_string()));
+                mock(texts)
+            },
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!((report.embedded, report.resumed), (1, 2));
+        assert_eq!(embedded, vec!["fn ccc() {}"]);
+        assert_eq!(report.vectors, 3);
+        assert_eq!(db.embedding_dim().unwrap(), Some(3));
+    }
+
+    #[test]
+    fn symbols_without_code_are_refused() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        index_with(root, &["fn a() {}", ""]);
+        let db = DB::open_maintenance(root).unwrap();
+        let err = migrate(&db, "mock", 2, mock, |_| {})
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("1 symbols were stored without their code"),
+            "{}",
+            err
+        );
+        assert_eq!(db.embedding_model().unwrap().as_deref(), Some("old"));
+        assert_eq!(db.embedding_dim().unwrap(), Some(2));
+    }
+
+    #[test]
+    fn migrated_index_is_queried_with_its_model() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        index_with(
+            root,
+            &[
+                "fn parse_config(path: &Path) {}",
+                "fn draw_circle(r: f32) {}",
+            ],
+        );
+        let db = DB::open_maintenance(root).unwrap();
+        let mut embedder = Embedder::new("all-MiniLM-L6-v2").expect("init model");
+        let repo = embedder.model_repo();
+        migrate(&db, repo, 8, |texts| embedder.embed(texts), |_| {}).unwrap();
+        drop(db);
+
+        // What `cearch query` does: load the model the index names and embed with it
+        let db = DB::open_read(root).unwrap();
+        let model = db.embedding_model().unwrap();
+        let mut embedder = Embedder::for_index(model.as_deref()).expect("load");
+        assert_eq!(embedder.model_repo(), repo);
+        let query = embedder.embed(["read the configuration file"]).unwrap();
+        assert_eq!(Some(query[0].len()), db.embedding_dim().unwrap());
+        assert_eq!(db.knn(&query[0], 1).unwrap()[0].name, "f0");
+    }
+}
@@ -0,0 +1,571 @@
+use crate::db::{NearDuplicate, SearchResult, StoredSymbol, SymbolLocation};
+use crate::snippet::{self, SnippetOptions};
+use std::io::Write;
+use std::path::Path;
+// Output module renders query results in machine-readable formats.
+
+/// File formats `cearch export` can write.
+/// How `cearch query` prints each result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum QueryFormat {
+    /// `path:line name score #id`, followed by the link with --url
+    Text,
+    /// The result's forge link alone (as with --url), or `path:line` where it has none
+    Url,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Universal Ctags `tags` file (extended format, sorted)
+    Ctags,
+}
+
+/// Write results as RFC 4180 CSV with a `path,line,kind,name,score,code,id` header.
+///
+/// `score` is the vector distance (lower is closer) and `id` the symbol's id for
+/// `cearch show`. Without `include_code` the code column is left out of both the header and
+/// the rows.
+pub fn write_csv<W: Write>(
+    out: W,
+    results: &[SearchResult],
+    include_code: bool,
+) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    let mut header = vec!["path", "line", "kind", "name", "score"];
+    if include_code {
+        header.push("code");
+    }
+    header.push("id");
+    writer.write_record(&header)?;
+    for hit in results {
+        let id = hit.id.to_string();
+        let line = hit.line.to_string();
+        let score = hit.distance.to_string();
+        let mut row = vec![
+            hit.path.as_str(),
+            line.as_str(),
+            hit.kind.as_str(),
+            hit.name.as_str(),
+            score.as_str(),
+        ];
+        if include_code {
+            row.push(hit.code.as_str());
+        }
+        row.push(id.as_str());
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write results as a Markdown table with `File | Line | Kind | Name | Score` columns, each
+/// file linked GitHub-style as `path#L42`. Columns are padded so the source lines up too.
+///
+/// Table cells cannot hold multi-line code, so with `show_code` each result's code follows
+/// the table in a fenced block under a link to it, cut to `snippet`.
+pub fn write_markdown<W: Write>(
+    mut out: W,
+    results: &[SearchResult],
+    show_code: bool,
+    snippet: &SnippetOptions,
+) -> std::io::Result<()> {
+    let header = ["File", "Line", "Kind", "Name", "Score"];
+    // Line and score are right-aligned
+    let right = [false, true, false, false, true];
+    let rows: Vec<[String; 5]> = results
+        .iter()
+        .map(|hit| {
+            [
+                markdown_link(&hit.path, &hit.path, hit.line),
+                hit.line.to_string(),
+                escape_cell(&hit.kind),
+                escape_cell(&hit.name),
+                format_score(hit.distance),
+            ]
+        })
+        .collect();
+    let mut widths = header.map(|h| h.len().max(3));
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: [&str; 5]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .zip(right)
+            .map(|((cell, width), right)| {
+                if right {
+                    format!("{:>w$}", cell, w = width)
+                } else {
+                    format!("{:<w$}", cell, w = width)
+                }
+            })
+            .collect();
+        format!("| {} |", padded.join(" | "))
+    };
+    writeln!(out, "{}", line(header))?;
+    let rules: Vec<String> = widths
+        .iter()
+        .zip(right)
+        .map(|(&width, right)| {
+            if right {
+                format!("{}:", "-".repeat(width - 1))
+            } else {
+                "-".repeat(width)
+            }
+        })
+        .collect();
+    writeln!(out, "| {} |", rules.join(" | "))?;
+    for row in &rows {
+        writeln!(out, "{}", line(row.each_ref().map(String::as_str)))?;
+    }
+    if show_code {
+        for hit in results {
+            let code = snippet::format(&hit.code, snippet)
+                .lines()
+                .collect::<Vec<_>>()
+                .join("\n");
+            let longest_run = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+            let fence = "`".repeat(longest_run.max(2) + 1);
+            let language = crate::symbols::language_name(Path::new(&hit.path))
+                .unwrap_or("")
+                .to_ascii_lowercase();
+            writeln!(
+                out,
+                "\n{} `{}`\n\n{}{}\n{}\n{}",
+                markdown_link(&format!("{}:{}", hit.path, hit.line), &hit.path, hit.line),
+                hit.name.replace('`', ""),
+                fence,
+                language,
+                code.trim_end(),
+                fence
+            )?;
+        }
+    }
+    out.flush()
+}
+
+fn markdown_link(text: &str, path: &str, line: usize) -> String {
+    let mut target = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' => target.push_str("%20"),
+            '(' => target.push_str("%28"),
+            ')' => target.push_str("%29"),
+            '|' => target.push_str("%7C"),
+            c => target.push(c),
+        }
+    }
+    let text = escape_cell(text).replace('[', "\\[").replace(']', "\\]");
+    format!("[{}]({}#L{})", text, target, line)
+}
+
+/// Escape the characters that would end a table cell or break its line.
+fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Write near-duplicate pairs as CSV with a `source,target,distance` header, locations
+/// given as `path:line`.
+pub fn write_near_duplicates_csv<W: Write>(out: W, pairs: &[NearDuplicate]) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(["source", "target", "distance"])?;
+    for pair in pairs {
+        writer.write_record([
+            format!("{}:{}", pair.source_path, pair.source_line),
+            format!("{}:{}", pair.target_path, pair.target_line),
+            pair.distance.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// When and from which commit the index was built, as shown by `cearch show`.
+pub struct Provenance {
+    /// Unix time the last index run finished
+    pub indexed_at: Option<u64>,
+    pub git_commit: Option<String>,
+    /// Extractor of the symbol's file when not a built-in parser, e.g. `plugin:dsl`
+    pub origin: Option<String>,
+}
+
+/// Write a stored symbol in full: its id, qualified name, kind, language, line range and the
+/// index's provenance, then its code with line numbers, colored by syntax with `highlight`
+/// and cut to `snippet` (the line numbers count towards its width).
+///
+/// `shown_path` is the symbol's path as it should be printed (see `paths::display`).
+pub fn write_symbol<W: Write>(
+    mut out: W,
+    symbol: &StoredSymbol,
+    shown_path: &str,
+    provenance: &Provenance,
+    highlight: bool,
+    snippet: &SnippetOptions,
+) -> std::io::Result<()> {
+    // Plugins name the languages they extract
+    let language = crate::symbols::language_name(Path::new(&symbol.path)).or_else(|| {
+        provenance
+            .origin
+            .as_deref()
+            .and_then(|o| o.strip_prefix("plugin:"))
+    });
+    writeln!(out, "#{} {}::{}", symbol.id, symbol.path, symbol.name)?;
+    writeln!(out, "kind:     {}", symbol.kind)?;
+    writeln!(out, "language: {}", language.unwrap_or("unknown"))?;
+    if let Some(origin) = &provenance.origin {
+        writeln!(out, "origin:   {}", origin)?;
+    }
+    writeln!(
+        out,
+        "lines:    {}:{}-{}",
+        shown_path,
+        symbol.line,
+        symbol.end_line()
+    )?;
+    let indexed = provenance
+        .indexed_at
+        .map_or_else(|| "unfinished run".to_string(), format_unix_time);
+    match &provenance.git_commit {
+        Some(commit) => writeln!(out, "indexed:  {} from commit {}", indexed, commit)?,
+        None => writeln!(out, "indexed:  {}", indexed)?,
+    }
+    if let Some(canonical) = symbol.canonical_rowid {
+        writeln!(out, "copy of:  #{}", canonical)?;
+    }
+    writeln!(out)?;
+    let colored = highlight
+        .then(|| crate::symbols::highlight(Path::new(&symbol.path), &symbol.code))
+        .flatten();
+    let code = colored.as_deref().unwrap_or(&symbol.code);
+    let width = symbol.end_line().to_string().len();
+    let snippet = snippet::format(code, &snippet.indented(width + 3));
+    for row in &snippet.rows {
+        match row.line {
+            Some(i) => writeln!(out, "{:>w$} | {}", symbol.line + i, row.text, w = width)?,
+            None => writeln!(out, "{:>w$} | {}", "", row.text, w = width)?,
+        }
+    }
+    if let Some(trailer) = snippet.trailer() {
+        writeln!(out, "{:>w$} | {}", "", trailer, w = width)?;
+    }
+    out.flush()
+}
+
+/// Format Unix time as a UTC date and time, e.g. `2024-03-01 12:30:00 UTC`.
+fn format_unix_time(secs: u64) -> String {
+    let time = secs % 86_400;
+    format!(
+        "{} {:02}:{:02}:{:02} UTC",
+        format_unix_date(secs),
+        time / 3_600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// A result score to three decimals. Scores that round to zero print as `0.000` whatever
+/// their sign, as FTS5 gives weak keyword matches tiny negative ranks.
+pub fn format_score(score: f32) -> String {
+    let formatted = format!("{:.3}", score);
+    match formatted.strip_prefix('-') {
+        Some(magnitude) if magnitude.chars().all(|c| c == '0' || c == '.') => magnitude.to_string(),
+        _ => formatted,
+    }
+}
+
+/// Format Unix time as a UTC date, e.g. `2024-03-01`.
+pub fn format_unix_date(secs: u64) -> String {
+    // Days to a proleptic Gregorian date, after Howard Hinnant's `civil_from_days`
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Single-letter ctags kind of a stored symbol kind.
+fn ctags_kind(kind: &str) -> &'static str {
+    match kind {
+        "fn" => "f",
+        "class" => "c",
+        "const" => "C",
+        _ => "s",
+    }
+}
+
+/// Escape a tag or file field as Universal Ctags does in `u-ctags` output mode.
+///
+/// Tabs and newlines would otherwise end the field or the line; a leading `!` would make
+/// the line a pseudo-tag.
+fn ctags_escape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for (i, c) in field.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '!' if i == 0 => out.push_str("\\!"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Write `symbols` as a sorted extended-format tags file.
+///
+/// Each tag is addressed by line number and carries `line:` and, where the file's language
+/// is known, `language:` fields. Lines are sorted bytewise by tag name, then file and line,
+/// so `readtags` and editors can binary-search the file.
+pub fn write_ctags<W: Write>(mut out: W, symbols: &[SymbolLocation]) -> std::io::Result<()> {
+    let mut lines: Vec<(String, String, usize, String)> = symbols
+        .iter()
+        .map(|s| {
+            let name = ctags_escape(&s.name);
+            let file = ctags_escape(&s.path);
+            let mut fields = format!("{}\tline:{}", ctags_kind(&s.kind), s.line);
+            if let Some(language) = crate::symbols::language_name(Path::new(&s.path)) {
+                fields.push_str("\tlanguage:");
+                fields.push_str(language);
+            }
+            (name, file, s.line, fields)
+        })
+        .collect();
+    lines.sort();
+    writeln!(
+        out,
+        "!_TAG_FILE_FORMAT\t2\t/extended format; --format=1 will not append ;\" to lines/"
+    )?;
+    writeln!(
+        out,
+        "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/"
+    )?;
+    writeln!(out, "!_TAG_OUTPUT_MODE\tu-ctags\t/u-ctags or e-ctags/")?;
+    writeln!(out, "!_TAG_PROGRAM_NAME\tcearch\t//")?;
+    writeln!(
+        out,
+        "!_TAG_PROGRAM_VERSION\t{}\t//",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    for (name, file, line, fields) in lines {
+        writeln!(out, "{}\t{}\t{};\"\t{}", name, file, line, fields)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(name: &str, code: &str) -> SearchResult {
+        SearchResult {
+            id: 12,
+            path: "src/a, b.py".to_string(),
+            line: 3,
+            kind: "fn".to_string(),
+            name: name.to_string(),
+            code: code.to_string(),
+            distance: 0.25,
+            duplicate_files: 0,
+            duplicates: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn scores_that_round_to_zero_have_no_sign() {
+        assert_eq!(format_score(0.25), "0.250");
+        assert_eq!(format_score(-1.5), "-1.500");
+        assert_eq!(format_score(-0.0), "0.000");
+        // The rank FTS5 gives a word found in half the symbols
+        assert_eq!(format_score(-1e-6), "0.000");
+        assert_eq!(format_score(-0.0004), "0.000");
+        assert_eq!(format_score(-0.0005), "-0.001");
+    }
+
+    #[test]
+    fn csv_escapes_commas_quotes_and_newlines() {
+        let mut buf = Vec::new();
+        let results = [hit("greet", "def greet():\n    print(\"hi, there\")")];
+        write_csv(&mut buf, &results, true).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "path,line,kind,name,score,code,id\n\
+             \"src/a, b.py\",3,fn,greet,0.25,\"def greet():\n    print(\"\"hi, there\"\")\",12\n"
+        );
+    }
+
+    #[test]
+    fn markdown_table_links_files_and_aligns_columns() {
+        let mut results = vec![
+            hit("greet", "def greet():\n    print(\"```\")\n"),
+            hit("a|b", ""),
+        ];
+        results[0].path = "src/greet.py".to_string();
+        results[1].line = 120;
+        let mut buf = Vec::new();
+        write_markdown(&mut buf, &results, false, &SnippetOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "| File                              | Line | Kind | Name  | Score |\n\
+             | --------------------------------- | ---: | ---- | ----- | ----: |\n\
+             | [src/greet.py](src/greet.py#L3)   |    3 | fn   | greet | 0.250 |\n\
+             | [src/a, b.py](src/a,%20b.py#L120) |  120 | fn   | a\\|b  | 0.250 |\n"
+        );
+
+        let mut buf = Vec::new();
+        write_markdown(&mut buf, &results[..1], true, &SnippetOptions::default()).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(
+            text.ends_with(
+                "\n[src/greet.py:3](src/greet.py#L3) `greet`\n\n\
+                 ````python\ndef greet():\n    print(\"```\")\n````\n"
+            ),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn csv_can_omit_code() {
+        let mut buf = Vec::new();
+        write_csv(&mut buf, &[hit("greet", "pass")], false).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "path,line,kind,name,score,id\n\"src/a, b.py\",3,fn,greet,0.25,12\n"
+        );
+    }
+
+    #[test]
+    fn symbol_record_lists_metadata_and_numbered_code() {
+        let symbol = StoredSymbol {
+            id: 42,
+            path: "src/db.rs".to_string(),
+            line: 9,
+            kind: "fn".to_string(),
+            name: "open".to_string(),
+            code: "fn open() {\n    connect()\n}".to_string(),
+            canonical_rowid: None,
+        };
+        let provenance = Provenance {
+            indexed_at: Some(1_709_296_200),
+            git_commit: Some("abc123".to_string()),
+            origin: None,
+        };
+        let mut buf = Vec::new();
+        let full = SnippetOptions::default();
+        write_symbol(&mut buf, &symbol, "db.rs", &provenance, false, &full).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "#42 src/db.rs::open\n\
+             kind:     fn\n\
+             language: Rust\n\
+             lines:    db.rs:9-11\n\
+             indexed:  2024-03-01 12:30:00 UTC from commit abc123\n\
+             \n\
+             \x209 | fn open() {\n\
+             10 |     connect()\n\
+             11 | }\n"
+        );
+        let mut buf = Vec::new();
+        let cut = SnippetOptions {
+            max_lines: Some(1),
+            width: Some(12),
+            wrap: false,
+        };
+        write_symbol(&mut buf, &symbol, "db.rs", &provenance, false, &cut).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(
+            text.ends_with("\x209 | fn ope…\n   | … (+2 more lines)\n"),
+            "{}",
+            text
+        );
+
+        let mut buf = Vec::new();
+        let extracted = StoredSymbol {
+            path: "billing.rules".to_string(),
+            ..symbol
+        };
+        let by_plugin = Provenance {
+            origin: Some("plugin:rules".to_string()),
+            ..provenance
+        };
+        write_symbol(
+            &mut buf,
+            &extracted,
+            "billing.rules",
+            &by_plugin,
+            false,
+            &full,
+        )
+        .unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(
+            text.contains("language: rules\norigin:   plugin:rules\n"),
+            "{}",
+            text
+        );
+        assert_eq!(format_unix_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_unix_time(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_unix_date(951_868_799), "2000-02-29");
+    }
+
+    #[test]
+    fn ctags_are_sorted_and_escaped() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = crate::db::DB::open_metadata_only(dir.path(), &Default::default()).expect("open");
+        let record = |path, line, kind, name| crate::db::SymbolRecord {
+            kind,
+            ..crate::db::SymbolRecord::fixture(path, line, name, &[])
+        };
+        db.insert_symbols(&[
+            record("src/b.rs", 9, "fn", "open"),
+            record("src/a.rs", 3, "const", "MAX_RETRIES"),
+            record("lib/a.py", 1, "class", "Open"),
+            record("src/a.rs", 1, "fn", "open"),
+            record("gen/x.txt", 4, "fn", "tab\tname\\x"),
+            record("gen/x.txt", 5, "fn", "!bang"),
+        ])
+        .unwrap();
+        let mut buf = Vec::new();
+        write_ctags(&mut buf, &db.all_symbols().unwrap()).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        let (header, tags): (Vec<&str>, Vec<&str>) =
+            text.lines().partition(|l| l.starts_with("!_"));
+        assert_eq!(header.len(), 5);
+        assert!(header[0].starts_with("!_TAG_FILE_FORMAT\t2\t"));
+        assert!(header[1].starts_with("!_TAG_FILE_SORTED\t1\t"));
+        assert_eq!(
+            tags,
+            [
+                "MAX_RETRIES\tsrc/a.rs\t3;\"\tC\tline:3\tlanguage:Rust",
+                "Open\tlib/a.py\t1;\"\tc\tline:1\tlanguage:Python",
+                "\\!bang\tgen/x.txt\t5;\"\tf\tline:5",
+                "open\tsrc/a.rs\t1;\"\tf\tline:1\tlanguage:Rust",
+                "open\tsrc/b.rs\t9;\"\tf\tline:9\tlanguage:Rust",
+                "tab\\tname\\\\x\tgen/x.txt\t4;\"\tf\tline:4",
+            ]
+        );
+        // As readtags reads a tag line: three tab-separated fields, then `;"` and extensions
+        for tag in &tags {
+            let fields: Vec<&str> = tag.split('\t').collect();
+            assert!(fields.len() >= 5, "{}", tag);
+            assert!(fields[2].ends_with(";\""), "{}", tag);
+            assert_eq!(
+                fields[4],
+                format!("line:{}", fields[2].trim_end_matches(";\""))
+            );
+        }
+        let mut sorted = tags.clone();
+        sorted.sort();
+        assert_eq!(tags, sorted);
+        assert_eq!(ctags_escape("a!"), "a!");
+    }
+}
@@ -0,0 +1,210 @@
+use std::path::{Component, Path, PathBuf};
+// Paths module converts between stored paths (repo-relative, `/`-separated) and native paths.
+
+/// Replace Windows `\` separators with `/`.
+pub fn normalize_separators(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// Convert a filesystem path into the form stored in the index.
+///
+/// Paths under `repo_root` become relative to it; all separators become `/` so an index
+/// is portable between platforms. Already-stored paths pass through unchanged.
+pub fn to_stored(repo_root: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(repo_root).unwrap_or(path);
+    let s = rel.to_string_lossy();
+    // On Unix `\` is a legal filename character, not a separator
+    if cfg!(windows) {
+        normalize_separators(&s)
+    } else {
+        s.into_owned()
+    }
+}
+
+/// Convert a stored (or git-reported) `/`-separated path into a native filesystem path.
+///
+/// Absolute paths, as written by older versions of cearch, are returned unchanged.
+pub fn to_native(repo_root: &Path, stored: &str) -> PathBuf {
+    if Path::new(stored).is_absolute() {
+        return PathBuf::from(stored);
+    }
+    let mut native = repo_root.to_path_buf();
+    native.extend(stored.split('/').filter(|seg| !seg.is_empty()));
+    native
+}
+
+/// `file://` URI of a stored path, as editors (LSP clients) address files.
+///
+/// Bytes other than unreserved characters and `/` are percent-encoded.
+pub fn file_uri(repo_root: &Path, stored: &str) -> String {
+    let native = to_native(repo_root, stored);
+    let native = native.to_string_lossy();
+    let path = if cfg!(windows) {
+        normalize_separators(&native)
+    } else {
+        native.into_owned()
+    };
+    let mut uri = String::from("file://");
+    // Windows drive paths (`C:/...`) need the extra slash of an empty authority
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(b as char)
+            }
+            // Kept so drive letters read `C:`
+            b':' => uri.push(':'),
+            b => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+/// How result paths are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PathStyle {
+    /// Relative to the repository root, `/`-separated (stable for scripts)
+    Repo,
+    /// Relative to the current directory, using `..` when needed
+    Cwd,
+    /// Absolute native path
+    Absolute,
+}
+
+/// Render a stored path for output in the given style.
+///
+/// `cwd` should be canonical (as the repo root is) so the two can be compared.
+pub fn display(repo_root: &Path, cwd: &Path, stored: &str, style: PathStyle) -> String {
+    match style {
+        PathStyle::Repo => to_stored(repo_root, Path::new(stored)),
+        PathStyle::Absolute => to_native(repo_root, stored).display().to_string(),
+        PathStyle::Cwd => {
+            let native = to_native(repo_root, stored);
+            relative_to(cwd, &native).display().to_string()
+        }
+    }
+}
+
+/// Express absolute `target` relative to absolute `base`, walking up with `..` as needed.
+///
+/// Falls back to `target` itself when the two share no root (e.g. different drives).
+pub fn relative_to(base: &Path, target: &Path) -> PathBuf {
+    let base: Vec<Component> = base.components().collect();
+    let target_parts: Vec<Component> = target.components().collect();
+    let common = base
+        .iter()
+        .zip(&target_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return target.to_path_buf();
+    }
+    let mut rel = PathBuf::new();
+    for _ in common..base.len() {
+        rel.push("..");
+    }
+    rel.extend(&target_parts[common..]);
+    if rel.as_os_str().is_empty() {
+        rel.push(".");
+    }
+    rel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_mixed_separators() {
+        assert_eq!(normalize_separators("src\\db/mod.rs"), "src/db/mod.rs");
+        assert_eq!(normalize_separators("src/db.rs"), "src/db.rs");
+    }
+
+    #[test]
+    fn file_uris_are_absolute_and_percent_encoded() {
+        let root = PathBuf::from("/work/my repo");
+        assert_eq!(
+            file_uri(&root, "src/a#b.rs"),
+            "file:///work/my%20repo/src/a%23b.rs"
+        );
+    }
+
+    #[test]
+    fn stored_paths_are_repo_relative() {
+        let root = PathBuf::from("/work/repo");
+        let file = root.join("src").join("db.rs");
+        assert_eq!(to_stored(&root, &file), "src/db.rs");
+        // Idempotent on already-stored paths
+        assert_eq!(to_stored(&root, Path::new("src/db.rs")), "src/db.rs");
+    }
+
+    #[test]
+    fn native_paths_rejoin_the_root() {
+        let root = PathBuf::from("/work/repo");
+        assert_eq!(
+            to_native(&root, "src/db/mod.rs"),
+            root.join("src").join("db").join("mod.rs")
+        );
+        assert_eq!(
+            to_native(&root, &to_stored(&root, &root.join("a.py"))),
+            root.join("a.py")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_paths_are_stored_with_forward_slashes() {
+        let root = PathBuf::from(r"C:\work\repo");
+        let file = PathBuf::from(r"C:\work\repo\src\db\mod.rs");
+        assert_eq!(to_stored(&root, &file), "src/db/mod.rs");
+        assert_eq!(to_native(&root, "src/db/mod.rs"), file);
+        // Mixed separators, as produced by joining git output onto a native root
+        let mixed = PathBuf::from(r"C:\work\repo\src/db/mod.rs");
+        assert_eq!(to_stored(&root, &mixed), "src/db/mod.rs");
+    }
+
+    #[test]
+    fn relative_to_walks_up_and_down() {
+        let root = PathBuf::from("/work/repo");
+        let file = root.join("src").join("db.rs");
+        // cwd above the result's directory
+        assert_eq!(relative_to(&root, &file), PathBuf::from("src/db.rs"));
+        // cwd is the result's directory
+        assert_eq!(
+            relative_to(&root.join("src"), &file),
+            PathBuf::from("db.rs")
+        );
+        // cwd is a sibling of the result's directory
+        assert_eq!(
+            relative_to(&root.join("tests").join("fixtures"), &file),
+            PathBuf::from("../../src/db.rs")
+        );
+    }
+
+    #[test]
+    fn display_styles() {
+        let root = PathBuf::from("/work/repo");
+        let cwd = root.join("services").join("api");
+        let stored = "services/api/src/handlers.rs";
+        assert_eq!(display(&root, &cwd, stored, PathStyle::Repo), stored);
+        assert_eq!(
+            display(&root, &cwd, stored, PathStyle::Cwd),
+            Path::new("src").join("handlers.rs").display().to_string()
+        );
+        assert_eq!(
+            display(&root, &root.join("web"), stored, PathStyle::Cwd),
+            Path::new("..")
+                .join("services/api/src/handlers.rs")
+                .display()
+                .to_string()
+        );
+        assert_eq!(
+            display(&root, &cwd, stored, PathStyle::Absolute),
+            root.join("services/api/src/handlers.rs")
+                .display()
+                .to_string()
+        );
+    }
+}
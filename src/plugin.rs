@@ -0,0 +1,343 @@
+use crate::config::PluginConfig;
+use crate::symbols::{Symbol, SymbolKind};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+// Plugin module runs external symbol extractors configured in `[plugins.<name>]`, for
+// languages no tree-sitter grammar covers.
+
+/// How long a plugin may take on one file unless `timeout_secs` says otherwise.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// How often a running plugin is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An extractor command and the file extensions it handles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugin {
+    /// Also the language name of the files it extracts, e.g. in index statistics
+    pub name: &'static str,
+    extensions: Vec<String>,
+    command: Vec<String>,
+    stdin: bool,
+    timeout: Duration,
+}
+
+/// One line of a plugin's output.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PluginSymbol {
+    name: String,
+    line: usize,
+    /// Accepted for extractors that print it; the last line follows from `code`
+    #[serde(default)]
+    #[allow(dead_code)]
+    end_line: Option<usize>,
+    kind: String,
+    code: String,
+}
+
+/// The plugins of a config's `[plugins.<name>]` tables, checked for a command and at least
+/// one extension.
+pub fn from_config(plugins: &BTreeMap<String, PluginConfig>) -> Result<Vec<Plugin>, String> {
+    plugins
+        .iter()
+        .map(|(name, cfg)| {
+            if cfg.command.is_empty() {
+                return Err(format!("[plugins.{}] in config: command is empty", name));
+            }
+            if cfg.extensions.is_empty() {
+                return Err(format!("[plugins.{}] in config: no extensions", name));
+            }
+            Ok(Plugin {
+                // Kept for the whole run, like the names of `--languages-file` languages
+                name: Box::leak(name.clone().into_boxed_str()),
+                extensions: cfg
+                    .extensions
+                    .iter()
+                    .map(|e| e.trim_start_matches('.').to_string())
+                    .collect(),
+                command: cfg.command.clone(),
+                stdin: cfg.stdin,
+                timeout: Duration::from_secs(cfg.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            })
+        })
+        .collect()
+}
+
+/// The plugin handling files like `path`, if any.
+pub fn for_path<'p>(plugins: &'p [Plugin], path: &Path) -> Option<&'p Plugin> {
+    let ext = path.extension().and_then(|e| e.to_str())?;
+    plugins
+        .iter()
+        .find(|p| p.extensions.iter().any(|e| e == ext))
+}
+
+/// Language name of `path`: the name of the plugin handling it, else that of the
+/// tree-sitter grammar it would be parsed with, as `symbols::language_name`.
+pub fn language_name(plugins: &[Plugin], path: &Path) -> Option<&'static str> {
+    for_path(plugins, path)
+        .map(|p| p.name)
+        .or_else(|| crate::symbols::language_name(path))
+}
+
+impl Plugin {
+    /// Recorded with every file the plugin extracted, e.g. `plugin:dsl`.
+    pub fn origin(&self) -> String {
+        format!("plugin:{}", self.name)
+    }
+
+    /// Run the plugin on `path` from `repo_root` and return the symbols it printed, in file
+    /// order. The path is the last argument, or with `stdin` the file's contents are written
+    /// to the plugin's standard input instead.
+    pub fn extract(&self, repo_root: &Path, path: &Path) -> Result<Vec<Symbol>, String> {
+        let input = if self.stdin {
+            Some(
+                std::fs::read(path)
+                    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?,
+            )
+        } else {
+            None
+        };
+        let mut command = Command::new(&self.command[0]);
+        command
+            .args(&self.command[1..])
+            .current_dir(repo_root)
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if input.is_none() {
+            command.arg(path);
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("failed to run {}: {}", self.command[0], e))?;
+        // Written and read on threads so a plugin filling a pipe cannot stall the other
+        if let (Some(input), Some(mut pipe)) = (input, child.stdin.take()) {
+            std::thread::spawn(move || pipe.write_all(&input));
+        }
+        let stdout = child.stdout.take().map(read_on_thread);
+        let stderr = child.stderr.take().map(read_on_thread);
+        let started = Instant::now();
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if started.elapsed() >= self.timeout => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    // The readers are left behind: a process the plugin started may still
+                    // hold the pipes open
+                    return Err(format!("timed out after {}s", self.timeout.as_secs()));
+                }
+                Ok(None) => std::thread::sleep(POLL_INTERVAL),
+                Err(e) => return Err(format!("failed to wait for {}: {}", self.command[0], e)),
+            }
+        };
+        let joined = |reader: Option<std::thread::JoinHandle<Vec<u8>>>| {
+            reader
+                .and_then(|r| r.join().ok())
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                .unwrap_or_default()
+        };
+        let (stdout, stderr) = (joined(stdout), joined(stderr));
+        if !status.success() {
+            let detail = stderr.lines().find(|l| !l.trim().is_empty());
+            return Err(match detail {
+                Some(detail) => format!("{}: {}", status, detail.trim()),
+                None => status.to_string(),
+            });
+        }
+        parse_output(path, &stdout)
+    }
+}
+
+fn read_on_thread(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        bytes
+    })
+}
+
+/// Symbols of `path` from a plugin's JSONL output, one `{name, line, kind, code}` object per
+/// line; blank lines are skipped. Any invalid line fails the whole file.
+pub fn parse_output(path: &Path, output: &str) -> Result<Vec<Symbol>, String> {
+    let mut symbols = Vec::new();
+    for (i, text) in output.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        let invalid = |msg: String| format!("output line {}: {}", i + 1, msg);
+        let parsed: PluginSymbol =
+            serde_json::from_str(text).map_err(|e| invalid(format!("invalid JSON: {}", e)))?;
+        if parsed.name.trim().is_empty() {
+            return Err(invalid("empty name".to_string()));
+        }
+        if parsed.line == 0 {
+            return Err(invalid("lines are numbered from 1".to_string()));
+        }
+        if parsed.code.is_empty() {
+            return Err(invalid(format!("no code for {}", parsed.name)));
+        }
+        let kind = match parsed.kind.as_str() {
+            "function" | "fn" => SymbolKind::Function,
+            "class" => SymbolKind::Class,
+            "constant" | "const" => SymbolKind::Constant,
+            other => {
+                return Err(invalid(format!(
+                    "unknown kind {:?} (expected function, class or constant)",
+                    other
+                )));
+            }
+        };
+        symbols.push(Symbol {
+            path: path.to_path_buf(),
+            line: parsed.line,
+            kind,
+            name: parsed.name,
+            code: parsed.code,
+            parents: Vec::new(),
+        });
+    }
+    symbols.sort_by_key(|s| s.line);
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin(command: &[&str], stdin: bool, timeout_secs: u64) -> Plugin {
+        Plugin {
+            name: "dsl",
+            extensions: vec!["dsl".to_string()],
+            command: command.iter().map(|s| s.to_string()).collect(),
+            stdin,
+            timeout: Duration::from_secs(timeout_secs),
+        }
+    }
+
+    #[test]
+    fn output_is_validated() {
+        let path = Path::new("rules.dsl");
+        let symbols = parse_output(
+            path,
+            "{\"name\":\"b\",\"line\":4,\"end_line\":5,\"kind\":\"class\",\"code\":\"rule b\\n  end\"}\n\n\
+             {\"name\":\"a\",\"line\":1,\"kind\":\"function\",\"code\":\"rule a\"}\n",
+        )
+        .unwrap();
+        let found: Vec<(&str, usize, &SymbolKind)> = symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.line, &s.kind))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("a", 1, &SymbolKind::Function),
+                ("b", 4, &SymbolKind::Class)
+            ]
+        );
+
+        for (output, expected) in [
+            ("not json", "output line 1: invalid JSON"),
+            (
+                "{\"name\":\"a\",\"line\":0,\"kind\":\"fn\",\"code\":\"x\"}",
+                "numbered from 1",
+            ),
+            (
+                "{\"name\":\"a\",\"line\":1,\"end_line\":1,\"kind\":\"macro\",\"code\":\"x\"}",
+                "unknown kind \"macro\"",
+            ),
+            (
+                "{\"name\":\" \",\"line\":1,\"end_line\":1,\"kind\":\"fn\",\"code\":\"x\"}",
+                "empty name",
+            ),
+            ("{\"name\":\"a\",\"line\":1}", "missing field"),
+        ] {
+            let err = parse_output(path, output).unwrap_err();
+            assert!(err.contains(expected), "{}: {}", output, err);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failures_are_reported() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        let file = root.join("rules.dsl");
+        std::fs::write(&file, "rule a\n").unwrap();
+
+        let ok =
+            "echo '{\"name\":\"a\",\"line\":1,\"end_line\":1,\"kind\":\"fn\",\"code\":\"rule a\"}'";
+        let symbols = plugin(&["sh", "-c", ok], false, 5)
+            .extract(root, &file)
+            .unwrap();
+        assert_eq!(symbols.len(), 1);
+
+        let failing = plugin(&["sh", "-c", "echo 'no grammar' >&2; exit 3"], false, 5);
+        let err = failing.extract(root, &file).unwrap_err();
+        assert!(err.contains("3") && err.ends_with("no grammar"), "{}", err);
+
+        let started = Instant::now();
+        let hanging = plugin(&["sh", "-c", "sleep 10"], false, 1);
+        assert_eq!(
+            hanging.extract(root, &file).unwrap_err(),
+            "timed out after 1s"
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let missing = plugin(&["/nonexistent/extractor"], false, 5);
+        assert!(
+            missing
+                .extract(root, &file)
+                .unwrap_err()
+                .starts_with("failed to run")
+        );
+
+        // The file's contents on stdin, no path argument
+        let echo = "read line; printf '{\"name\":\"%s\",\"line\":1,\"end_line\":1,\"kind\":\"fn\",\"code\":\"%s\"}\\n' \"$#\" \"$line\"";
+        let symbols = plugin(&["sh", "-c", echo], true, 5)
+            .extract(root, &file)
+            .unwrap();
+        assert_eq!(symbols[0].name, "0");
+        assert_eq!(symbols[0].code, "rule a");
+    }
+
+    #[test]
+    fn plugins_are_matched_by_extension() {
+        let mut configs = BTreeMap::new();
+        configs.insert(
+            "dsl".to_string(),
+            PluginConfig {
+                extensions: vec![".dsl".to_string(), "rules".to_string()],
+                command: vec!["extract".to_string()],
+                stdin: false,
+                timeout_secs: None,
+            },
+        );
+        let plugins = from_config(&configs).unwrap();
+        assert_eq!(
+            plugins[0].timeout,
+            Duration::from_secs(DEFAULT_TIMEOUT_SECS)
+        );
+        assert_eq!(
+            for_path(&plugins, Path::new("a/b.rules")).map(Plugin::origin),
+            Some("plugin:dsl".to_string())
+        );
+        assert!(for_path(&plugins, Path::new("a/b.py")).is_none());
+
+        configs.get_mut("dsl").unwrap().command.clear();
+        assert!(
+            from_config(&configs)
+                .unwrap_err()
+                .contains("command is empty")
+        );
+    }
+}
@@ -0,0 +1,1109 @@
+use crate::symbols;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+// Progress module owns bar construction and routes warnings around active bars.
+
+pub const MAIN_TEMPLATE: &str =
+    "{spinner:.green} {pos}/{len} [{bar:40.white/black}] {per_sec} ETA {eta} {msg}";
+pub const FILE_TEMPLATE: &str =
+    "  ↳ {spinner:.green} {pos}/{len} [{bar:40.white/black}] {per_sec} {msg}";
+pub const LANGUAGE_TEMPLATE: &str = "  {spinner:.green} {msg}";
+/// Main bar while the file listing is still streaming in and the total is unknown.
+pub const UNKNOWN_TOTAL_TEMPLATE: &str = "{spinner:.green} {pos}/? {per_sec} {msg}";
+/// Main bar driven by bytes when the sizes were counted up front; the prefix holds file
+/// counts and the smoothed ETA.
+pub const BYTES_TEMPLATE: &str =
+    "{spinner:.green} {prefix} [{bar:40.white/black}] {binary_bytes}/{binary_total_bytes} {msg}";
+
+/// Time for the weight of past throughput to halve in the ETA estimate.
+const THROUGHPUT_HALF_LIFE: Duration = Duration::from_secs(30);
+
+/// Version of the `--language-stats-file` layout; bump it when a field changes meaning or
+/// goes away, not when one is added.
+pub const LANGUAGE_STATS_SCHEMA_VERSION: u32 = 1;
+
+/// Version of the `.cearch/profile.json` layout written by `cearch index --profile`.
+pub const PROFILE_SCHEMA_VERSION: u32 = 1;
+
+/// Minimum time between periodic lines in the plain and json modes.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How progress is reported while indexing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressMode {
+    /// Interactive progress bars
+    Bar,
+    /// Periodic plain log lines, suitable for CI logs and redirected output
+    Plain,
+    /// No progress output (warnings are still printed)
+    None,
+    /// Periodic JSON lines on stderr
+    Json,
+}
+
+impl ProgressMode {
+    /// Pick a mode: an explicit choice wins, otherwise bars on a terminal and plain lines
+    /// elsewhere when verbose, and nothing when not.
+    pub fn resolve(explicit: Option<ProgressMode>, verbose: bool) -> ProgressMode {
+        match explicit {
+            Some(mode) => mode,
+            None if !verbose => ProgressMode::None,
+            None if std::io::stderr().is_terminal() => ProgressMode::Bar,
+            None => ProgressMode::Plain,
+        }
+    }
+}
+
+/// What went wrong for a recorded warning; used to group the end-of-run digest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A file could not be read or parsed
+    Parse,
+    /// Embedding failed or timed out
+    Embed,
+    /// Writing to the index failed
+    Insert,
+    /// A stored embedding read back differently from what was computed (`--verify-embeddings`)
+    Verify,
+    /// Symbols or files deliberately left out (truncation, quarantine)
+    Skip,
+    /// Anything else, e.g. failing to record run metadata
+    Other,
+}
+
+impl WarningKind {
+    fn label(self) -> &'static str {
+        match self {
+            WarningKind::Parse => "parse",
+            WarningKind::Embed => "embed",
+            WarningKind::Insert => "insert",
+            WarningKind::Verify => "verify",
+            WarningKind::Skip => "skip",
+            WarningKind::Other => "other",
+        }
+    }
+
+    /// Digest phrase for `n` warnings of this kind, e.g. `14 files failed to parse`.
+    fn describe(self, n: usize) -> String {
+        let plural = if n == 1 { "" } else { "s" };
+        match self {
+            WarningKind::Parse => format!("{} file{} failed to parse", n, plural),
+            WarningKind::Embed => format!("{} embedding error{}", n, plural),
+            WarningKind::Insert => format!("{} insert error{}", n, plural),
+            WarningKind::Verify => format!("{} corrupt stored embedding{}", n, plural),
+            WarningKind::Skip => format!("{} skip{}", n, plural),
+            WarningKind::Other => format!("{} other warning{}", n, plural),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// File the warning is about, if any
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+/// Counters for an index run, shared by periodic progress lines and the final summary.
+#[derive(Debug)]
+pub struct RunStats {
+    /// Unknown until the file listing has finished
+    pub files_total: Option<usize>,
+    pub files_done: usize,
+    pub symbols: usize,
+    pub started: Instant,
+    /// Every warning raised during the run, in order
+    pub warnings: Vec<Warning>,
+    /// Symbols indexed per language, in the order languages were first seen
+    pub language_symbols: Vec<(&'static str, usize)>,
+    /// Files seen per language, in the order languages were first seen
+    pub language_files: Vec<(&'static str, usize)>,
+    /// Total size of the files to index, when counted up front (see `precount`)
+    pub bytes_total: Option<u64>,
+    pub bytes_done: u64,
+    throughput: Throughput,
+    /// Per-file timings, collected only with `--profile`
+    profile: Option<Profiler>,
+}
+
+/// A stage of indexing one file, timed by `--profile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Parse,
+    Embed,
+    Insert,
+}
+
+impl Phase {
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Embed => "embed",
+            Phase::Insert => "insert",
+        }
+    }
+}
+
+/// Where one file's indexing time went.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FileProfile {
+    pub path: String,
+    pub bytes: u64,
+    pub symbols: usize,
+    pub parse_secs: f64,
+    pub embed_secs: f64,
+    pub insert_secs: f64,
+    /// Wall time of the whole file, including what no phase covers
+    pub total_secs: f64,
+}
+
+impl FileProfile {
+    /// The phase that took longest.
+    pub fn dominant_phase(&self) -> Phase {
+        [
+            (Phase::Parse, self.parse_secs),
+            (Phase::Embed, self.embed_secs),
+            (Phase::Insert, self.insert_secs),
+        ]
+        .into_iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(Phase::Parse, |(phase, _)| phase)
+    }
+}
+
+/// The table written to `.cearch/profile.json`, in indexing order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Profile {
+    pub schema_version: u32,
+    pub elapsed_secs: f64,
+    pub files: Vec<FileProfile>,
+}
+
+impl Profile {
+    /// The `n` slowest files, slowest first, with the phase that dominated each, e.g.
+    /// `   2.417s  src/big.rs (embed, 1830 symbols)`.
+    pub fn slowest(&self, n: usize) -> Vec<String> {
+        let mut files: Vec<&FileProfile> = self.files.iter().collect();
+        files.sort_by(|a, b| b.total_secs.total_cmp(&a.total_secs));
+        files
+            .into_iter()
+            .take(n)
+            .map(|f| {
+                format!(
+                    "  {:>8.3}s  {} ({}, {} symbol{})",
+                    f.total_secs,
+                    f.path,
+                    f.dominant_phase().label(),
+                    f.symbols,
+                    if f.symbols == 1 { "" } else { "s" }
+                )
+            })
+            .collect()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json + "\n")
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Profiler {
+    /// Phases timed so far for the file being indexed
+    current: FileProfile,
+    files: Vec<FileProfile>,
+}
+
+/// End-of-run totals written by `cearch index --language-stats-file`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LanguageStats {
+    pub schema_version: u32,
+    pub files: usize,
+    pub symbols: usize,
+    /// Distinct files with a parse, embed, insert or verify problem
+    pub failed_files: usize,
+    pub elapsed_secs: f64,
+    pub languages: BTreeMap<&'static str, LanguageCounts>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LanguageCounts {
+    pub files: usize,
+    pub symbols: usize,
+    pub failed_files: usize,
+}
+
+/// Bytes processed per second, smoothed so recent work counts most.
+///
+/// Each file contributes its size and the time it took, so the rate is weighted by work
+/// rather than by file: a run of tiny files barely moves it, while one huge file moves it
+/// in proportion to its size. Older samples decay with `THROUGHPUT_HALF_LIFE` of run time.
+#[derive(Debug, Default, Clone)]
+pub struct Throughput {
+    bytes: f64,
+    secs: f64,
+}
+
+impl Throughput {
+    pub fn observe(&mut self, bytes: u64, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let decay = 0.5f64.powf(secs / THROUGHPUT_HALF_LIFE.as_secs_f64());
+        self.bytes = self.bytes * decay + bytes as f64;
+        self.secs = self.secs * decay + secs;
+    }
+
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        (self.secs > 0.0 && self.bytes > 0.0).then(|| self.bytes / self.secs)
+    }
+
+    /// Time left for `remaining` bytes at the smoothed rate.
+    pub fn eta(&self, remaining: u64) -> Option<Duration> {
+        let rate = self.bytes_per_sec()?;
+        Some(Duration::from_secs_f64(remaining as f64 / rate))
+    }
+}
+
+/// Sum the sizes of `files` from metadata only; unreadable files count as empty.
+pub fn precount(files: &[PathBuf]) -> u64 {
+    files
+        .iter()
+        .filter_map(|f| std::fs::metadata(f).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+impl RunStats {
+    pub fn new(files_total: Option<usize>) -> Self {
+        Self {
+            files_total,
+            files_done: 0,
+            symbols: 0,
+            started: Instant::now(),
+            warnings: Vec::new(),
+            language_symbols: Vec::new(),
+            language_files: Vec::new(),
+            bytes_total: None,
+            bytes_done: 0,
+            throughput: Throughput::default(),
+            profile: None,
+        }
+    }
+
+    /// Collect per-file phase timings from now on (`--profile`).
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profiler::default());
+    }
+
+    /// Start timing a phase; `None`, and no clock read, when not profiling.
+    pub fn phase_start(&self) -> Option<Instant> {
+        self.profile.as_ref().map(|_| Instant::now())
+    }
+
+    /// Add the time since `started` (from `phase_start`) to the current file's `phase`.
+    pub fn phase_done(&mut self, phase: Phase, started: Option<Instant>) {
+        let (Some(profiler), Some(started)) = (&mut self.profile, started) else {
+            return;
+        };
+        let secs = started.elapsed().as_secs_f64();
+        let current = &mut profiler.current;
+        match phase {
+            Phase::Parse => current.parse_secs += secs,
+            Phase::Embed => current.embed_secs += secs,
+            Phase::Insert => current.insert_secs += secs,
+        }
+    }
+
+    /// Close the current file's profile with its totals.
+    pub fn profile_file_done(&mut self, path: &str, bytes: u64, symbols: usize, total: Duration) {
+        if let Some(profiler) = &mut self.profile {
+            let mut file = std::mem::take(&mut profiler.current);
+            file.path = path.to_string();
+            file.bytes = bytes;
+            file.symbols = symbols;
+            file.total_secs = total.as_secs_f64();
+            profiler.files.push(file);
+        }
+    }
+
+    /// The per-file table, when profiling.
+    pub fn profile(&self) -> Option<Profile> {
+        let profiler = self.profile.as_ref()?;
+        Some(Profile {
+            schema_version: PROFILE_SCHEMA_VERSION,
+            elapsed_secs: self.started.elapsed().as_secs_f64(),
+            files: profiler.files.clone(),
+        })
+    }
+
+    /// Count one finished file of `bytes` that took `elapsed` to index.
+    pub fn file_done(&mut self, bytes: u64, elapsed: Duration) {
+        self.files_done += 1;
+        self.bytes_done += bytes;
+        self.throughput.observe(bytes, elapsed);
+    }
+
+    /// Estimated time left; only known when sizes were counted up front.
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.bytes_total?.saturating_sub(self.bytes_done);
+        if remaining == 0 {
+            return None;
+        }
+        self.throughput.eta(remaining)
+    }
+
+    fn files_line(&self) -> String {
+        let total = match self.files_total {
+            Some(n) => n.to_string(),
+            None => "?".to_string(),
+        };
+        let eta = match self.eta() {
+            Some(eta) => format!(", ETA {}", format_duration(eta)),
+            None => String::new(),
+        };
+        format!("{}/{} files{}", self.files_done, total, eta)
+    }
+
+    pub fn add_language_symbols(&mut self, language: &'static str, n: usize) {
+        match self
+            .language_symbols
+            .iter_mut()
+            .find(|(l, _)| *l == language)
+        {
+            Some((_, count)) => *count += n,
+            None => self.language_symbols.push((language, n)),
+        }
+    }
+
+    pub fn add_language_file(&mut self, language: &'static str) {
+        match self.language_files.iter_mut().find(|(l, _)| *l == language) {
+            Some((_, count)) => *count += 1,
+            None => self.language_files.push((language, 1)),
+        }
+    }
+
+    /// Per-language counts as one line, e.g. `Python: 1234 | Rust: 456`.
+    pub fn language_line(&self) -> String {
+        self.language_symbols
+            .iter()
+            .map(|(language, n)| format!("{}: {}", language, n))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    pub fn count(&self, kind: WarningKind) -> usize {
+        self.warnings.iter().filter(|w| w.kind == kind).count()
+    }
+
+    /// Distinct files with a parse, embed or insert problem, in the order first seen.
+    pub fn failed_paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = Vec::new();
+        for w in &self.warnings {
+            let failure = matches!(
+                w.kind,
+                WarningKind::Parse | WarningKind::Embed | WarningKind::Insert | WarningKind::Verify
+            );
+            if let Some(path) = w.path.as_deref()
+                && failure
+                && !paths.contains(&path)
+            {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    /// End-of-run digest of the collected warnings, or `None` if there were none.
+    ///
+    /// e.g. `14 files failed to parse, 2 embedding errors (see .cearch/index.log)`
+    pub fn digest(&self, log_path: &Path) -> Option<String> {
+        let kinds = [
+            WarningKind::Parse,
+            WarningKind::Embed,
+            WarningKind::Insert,
+            WarningKind::Verify,
+            WarningKind::Skip,
+            WarningKind::Other,
+        ];
+        let parts: Vec<String> = kinds
+            .iter()
+            .map(|&kind| (kind, self.count(kind)))
+            .filter(|&(_, n)| n > 0)
+            .map(|(kind, n)| kind.describe(n))
+            .collect();
+        if parts.is_empty() {
+            return None;
+        }
+        Some(format!("{} (see {})", parts.join(", "), log_path.display()))
+    }
+
+    /// Write the warnings to `path`, replacing the log of any earlier run.
+    pub fn write_log(&self, path: &Path) -> std::io::Result<()> {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(out, "{}", self.summary())?;
+        for w in &self.warnings {
+            writeln!(out, "[{}] {}", w.kind.label(), w.message)?;
+        }
+        out.flush()
+    }
+
+    /// One-line human summary, e.g. `indexed 500/2300 files, 41k symbols, 3m12s elapsed`;
+    /// embedding failures so far are counted before the elapsed time when there are any.
+    pub fn summary(&self) -> String {
+        let failures = match self.count(WarningKind::Embed) {
+            0 => String::new(),
+            1 => "1 embedding failure, ".to_string(),
+            n => format!("{} embedding failures, ", n),
+        };
+        format!(
+            "indexed {}, {} symbols, {}{} elapsed",
+            self.files_line(),
+            format_count(self.symbols),
+            failures,
+            format_duration(self.started.elapsed())
+        )
+    }
+
+    /// Markdown table of the run for a GitHub Actions step summary.
+    pub fn github_summary(&self) -> String {
+        let mut rows = vec![
+            ("Files".to_string(), self.files_done.to_string()),
+            ("Symbols".to_string(), self.symbols.to_string()),
+        ];
+        for (language, n) in &self.language_symbols {
+            rows.push((format!("{} symbols", language), n.to_string()));
+        }
+        rows.push(("Warnings".to_string(), self.warnings.len().to_string()));
+        rows.push((
+            "Elapsed".to_string(),
+            format_duration(self.started.elapsed()),
+        ));
+        let mut out = String::from("### cearch index\n\n| Metric | Value |\n| --- | ---: |\n");
+        for (label, value) in rows {
+            out.push_str(&format!("| {} | {} |\n", label, value));
+        }
+        out
+    }
+
+    /// Append `github_summary` to the file named by `$GITHUB_STEP_SUMMARY`, if it is set.
+    pub fn write_github_summary(&self) -> std::io::Result<()> {
+        let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") else {
+            return Ok(());
+        };
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{}", self.github_summary())
+    }
+
+    /// Per-language totals of the run for `--language-stats-file`, keyed by language name.
+    pub fn language_stats(&self) -> LanguageStats {
+        let mut languages: BTreeMap<&'static str, LanguageCounts> = BTreeMap::new();
+        for &(language, files) in &self.language_files {
+            languages.entry(language).or_default().files += files;
+        }
+        for &(language, symbols) in &self.language_symbols {
+            languages.entry(language).or_default().symbols += symbols;
+        }
+        for path in self.failed_paths() {
+            if let Some(language) = symbols::language_name(path) {
+                languages.entry(language).or_default().failed_files += 1;
+            }
+        }
+        LanguageStats {
+            schema_version: LANGUAGE_STATS_SCHEMA_VERSION,
+            files: self.files_done,
+            symbols: self.symbols,
+            failed_files: self.failed_paths().len(),
+            elapsed_secs: self.started.elapsed().as_secs_f64(),
+            languages,
+        }
+    }
+
+    /// Write `language_stats` to `path`, as TOML when it ends in `.toml` and JSON otherwise.
+    pub fn write_language_stats(&self, path: &Path) -> Result<(), String> {
+        let stats = self.language_stats();
+        let contents = if path.extension().is_some_and(|e| e == "toml") {
+            toml::to_string(&stats).map_err(|e| e.to_string())?
+        } else {
+            serde_json::to_string_pretty(&stats).map_err(|e| e.to_string())? + "\n"
+        };
+        std::fs::write(path, contents)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    fn to_json(&self, event: &str) -> String {
+        serde_json::json!({
+            "event": event,
+            "files_done": self.files_done,
+            "files_total": self.files_total,
+            "symbols": self.symbols,
+            "bytes_done": self.bytes_done,
+            "bytes_total": self.bytes_total,
+            "eta_secs": self.eta().map(|d| d.as_secs_f64()),
+            "elapsed_secs": self.started.elapsed().as_secs_f64(),
+        })
+        .to_string()
+    }
+}
+
+/// Build a bar style from one of the templates above.
+pub fn style(template: &str) -> Result<ProgressStyle, indicatif::style::TemplateError> {
+    Ok(ProgressStyle::with_template(template)?.progress_chars("=> "))
+}
+
+enum Backend {
+    Bars {
+        mp: MultiProgress,
+        main: RefCell<Option<ProgressBar>>,
+        /// Status line with symbol counts per language
+        languages: RefCell<Option<ProgressBar>>,
+    },
+    /// Line-oriented output (plain or json) written to `out`
+    Lines {
+        json: bool,
+        out: RefCell<Box<dyn Write>>,
+        last_report: Cell<Option<Instant>>,
+        interval: Duration,
+    },
+    Off,
+}
+
+/// Progress output for a command in one of the `ProgressMode`s.
+pub struct Progress {
+    backend: Backend,
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode) -> Self {
+        Self::with_writer(mode, Box::new(std::io::stderr()), REPORT_INTERVAL)
+    }
+
+    /// Like `new`, but line modes write to `out` at most once per `interval`.
+    pub fn with_writer(mode: ProgressMode, out: Box<dyn Write>, interval: Duration) -> Self {
+        let backend = match mode {
+            ProgressMode::Bar => Backend::Bars {
+                mp: MultiProgress::new(),
+                main: RefCell::new(None),
+                languages: RefCell::new(None),
+            },
+            ProgressMode::Plain | ProgressMode::Json => Backend::Lines {
+                json: mode == ProgressMode::Json,
+                out: RefCell::new(out),
+                last_report: Cell::new(None),
+                interval,
+            },
+            ProgressMode::None => Backend::Off,
+        };
+        Self { backend }
+    }
+
+    /// Begin reporting a run; in bar mode this draws the top-level bar.
+    pub fn start(&self, stats: &RunStats, msg: &str) {
+        if let Backend::Bars {
+            mp,
+            main,
+            languages,
+        } = &self.backend
+        {
+            let (pb, template) = match (stats.bytes_total, stats.files_total) {
+                (Some(bytes), _) => (ProgressBar::new(bytes), BYTES_TEMPLATE),
+                (None, Some(n)) => (ProgressBar::new(n as u64), MAIN_TEMPLATE),
+                (None, None) => (ProgressBar::no_length(), UNKNOWN_TOTAL_TEMPLATE),
+            };
+            let pb = mp.add(pb);
+            crate::logging::route_through(Some(mp.clone()));
+            // Templates are constants covered by tests; keep the default style if one is ever broken
+            if let Ok(style) = style(template) {
+                pb.set_style(style);
+            }
+            pb.set_message(msg.to_string());
+            *main.borrow_mut() = Some(pb);
+
+            let spinner = mp.add(ProgressBar::new_spinner());
+            if let Ok(style) = ProgressStyle::with_template(LANGUAGE_TEMPLATE) {
+                spinner.set_style(style);
+            }
+            *languages.borrow_mut() = Some(spinner);
+        }
+    }
+
+    /// Switch the main bar to a known total once the file listing has finished.
+    pub fn set_total(&self, stats: &RunStats) {
+        if let (Backend::Bars { main, .. }, Some(total)) = (&self.backend, stats.files_total)
+            && let Some(pb) = main.borrow().as_ref()
+        {
+            pb.set_length(total as u64);
+            if let Ok(style) = style(MAIN_TEMPLATE) {
+                pb.set_style(style);
+            }
+        }
+    }
+
+    /// Report updated counters; line modes print at most once per interval.
+    pub fn report(&self, stats: &RunStats) {
+        match &self.backend {
+            Backend::Bars {
+                main, languages, ..
+            } => {
+                if let Some(pb) = main.borrow().as_ref() {
+                    set_main_position(pb, stats);
+                }
+                if let Some(spinner) = languages.borrow().as_ref() {
+                    spinner.set_message(stats.language_line());
+                    spinner.tick();
+                }
+            }
+            Backend::Lines {
+                json,
+                out,
+                last_report,
+                interval,
+            } => {
+                let now = Instant::now();
+                if last_report
+                    .get()
+                    .is_some_and(|last| now.duration_since(last) < *interval)
+                {
+                    return;
+                }
+                last_report.set(Some(now));
+                let line = if *json {
+                    stats.to_json("progress")
+                } else {
+                    stats.summary()
+                };
+                let _ = writeln!(out.borrow_mut(), "{}", line);
+            }
+            Backend::Off => {}
+        }
+    }
+
+    /// Finish the run, printing the final summary from the same counters.
+    pub fn finish(&self, stats: &RunStats) {
+        match &self.backend {
+            Backend::Bars {
+                main, languages, ..
+            } => {
+                if let Some(pb) = main.borrow_mut().take() {
+                    set_main_position(&pb, stats);
+                    pb.finish_with_message("indexing complete");
+                }
+                if let Some(spinner) = languages.borrow_mut().take() {
+                    spinner.finish_with_message(stats.language_line());
+                }
+                crate::logging::route_through(None);
+                eprintln!("{}", stats.summary());
+            }
+            Backend::Lines { json, out, .. } => {
+                let line = if *json {
+                    stats.to_json("done")
+                } else {
+                    stats.summary()
+                };
+                let _ = writeln!(out.borrow_mut(), "{}", line);
+            }
+            Backend::Off => {}
+        }
+    }
+
+    /// Nested bar for the work inside a single file (bar mode only).
+    pub fn file_bar(&self, len: u64, name: &str) -> Bar {
+        let Backend::Bars { mp, .. } = &self.backend else {
+            return Bar(None);
+        };
+        let pb = mp.add(ProgressBar::new(len));
+        if let Ok(style) = style(FILE_TEMPLATE) {
+            pb.set_style(style);
+        }
+        pb.set_message(name.to_string());
+        Bar(Some(pb))
+    }
+
+    /// Record a warning in `stats` and print it inline without tearing active bars.
+    ///
+    /// With progress disabled the warning is only recorded; it shows up in the digest.
+    pub fn warn(&self, stats: &mut RunStats, kind: WarningKind, path: Option<&Path>, msg: &str) {
+        stats.warnings.push(Warning {
+            kind,
+            path: path.map(Path::to_path_buf),
+            message: msg.to_string(),
+        });
+        match &self.backend {
+            // Printed around the bars by the logging setup, and subject to `--log-level`
+            Backend::Bars { .. } => tracing::warn!("{}", msg),
+            Backend::Lines {
+                json: true, out, ..
+            } => {
+                let line = serde_json::json!({ "event": "warning", "message": msg });
+                let _ = writeln!(out.borrow_mut(), "{}", line);
+            }
+            Backend::Lines { out, .. } => {
+                let _ = writeln!(out.borrow_mut(), "warn: {}", msg);
+            }
+            Backend::Off => {}
+        }
+    }
+}
+
+/// A progress bar that silently does nothing when progress output is disabled.
+pub struct Bar(Option<ProgressBar>);
+
+impl Bar {
+    pub fn inc(&self, delta: u64) {
+        if let Some(ref pb) = self.0 {
+            pb.inc(delta);
+        }
+    }
+
+    pub fn finish_and_clear(&self) {
+        if let Some(ref pb) = self.0 {
+            pb.finish_and_clear();
+        }
+    }
+}
+
+/// Abbreviate large counts: 950, 41k, 2.3M.
+fn format_count(n: usize) -> String {
+    match n {
+        0..1_000 => n.to_string(),
+        1_000..1_000_000 => format!("{}k", n / 1_000),
+        _ => format!("{:.1}M", n as f64 / 1_000_000.0),
+    }
+}
+
+fn set_main_position(pb: &ProgressBar, stats: &RunStats) {
+    if stats.bytes_total.is_some() {
+        pb.set_position(stats.bytes_done);
+        pb.set_prefix(stats.files_line());
+    } else {
+        pb.set_position(stats.files_done as u64);
+    }
+}
+
+/// Compact elapsed time: 42s, 3m12s, 1h05m.
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    /// Writer whose contents can be read back after `Progress` takes ownership of it.
+    #[derive(Clone, Default)]
+    struct Capture(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Capture {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn run(mode: ProgressMode, interval: Duration) -> String {
+        let capture = Capture::default();
+        let progress = Progress::with_writer(mode, Box::new(capture.clone()), interval);
+        let mut stats = RunStats::new(Some(3));
+        progress.start(&stats, "Indexing repo");
+        for _ in 0..3 {
+            stats.files_done += 1;
+            stats.symbols += 1500;
+            progress.report(&stats);
+        }
+        progress.warn(
+            &mut stats,
+            WarningKind::Parse,
+            Some(Path::new("broken.py")),
+            "failed to parse broken.py",
+        );
+        progress.finish(&stats);
+        capture.text()
+    }
+
+    #[test]
+    fn templates_are_valid() {
+        style(MAIN_TEMPLATE).expect("main template");
+        style(FILE_TEMPLATE).expect("file template");
+        style(UNKNOWN_TOTAL_TEMPLATE).expect("unknown total template");
+        style(BYTES_TEMPLATE).expect("bytes template");
+        ProgressStyle::with_template(LANGUAGE_TEMPLATE).expect("language template");
+    }
+
+    /// Index `sizes` at a steady `rate` bytes/sec plus a fixed per-file overhead.
+    fn simulate(sizes: &[u64], rate: f64, overhead: Duration) -> Throughput {
+        let mut t = Throughput::default();
+        for &size in sizes {
+            t.observe(size, Duration::from_secs_f64(size as f64 / rate) + overhead);
+        }
+        t
+    }
+
+    #[test]
+    fn throughput_is_weighted_by_size_not_file_count() {
+        let rate = 1_000_000.0;
+        // 1000 one-kilobyte files, then one 50 MB generated file, then more small ones
+        let mut sizes = vec![1_000u64; 1000];
+        sizes.push(50_000_000);
+        sizes.extend(vec![1_000u64; 1000]);
+        let t = simulate(&sizes, rate, Duration::ZERO);
+        let estimate = t.bytes_per_sec().unwrap();
+        assert!((estimate - rate).abs() / rate < 1e-6, "{}", estimate);
+        let eta = t.eta(10_000_000).unwrap().as_secs_f64();
+        assert!((eta - 10.0).abs() < 1e-3, "{}", eta);
+    }
+
+    #[test]
+    fn tiny_slow_files_barely_move_the_rate() {
+        let rate = 1_000_000.0;
+        let mut t = simulate(&[5_000_000; 10], rate, Duration::ZERO);
+        let before = t.bytes_per_sec().unwrap();
+        // A burst of empty-ish files that each cost a millisecond of overhead
+        for _ in 0..20 {
+            t.observe(10, Duration::from_millis(1));
+        }
+        let after = t.bytes_per_sec().unwrap();
+        assert!((before - after) / before < 0.01, "{} -> {}", before, after);
+    }
+
+    #[test]
+    fn recent_throughput_outweighs_old() {
+        let mut t = simulate(&[100_000_000], 1_000_000.0, Duration::ZERO);
+        // The machine slows to a tenth of the speed for several half-lives
+        for _ in 0..30 {
+            t.observe(1_000_000, Duration::from_secs(10));
+        }
+        let rate = t.bytes_per_sec().unwrap();
+        assert!(rate < 110_000.0, "{}", rate);
+    }
+
+    #[test]
+    fn eta_needs_a_precount() {
+        let mut stats = RunStats::new(Some(2));
+        stats.file_done(100, Duration::from_secs(1));
+        assert_eq!(stats.eta(), None);
+        stats.bytes_total = Some(300);
+        assert_eq!(stats.eta(), Some(Duration::from_secs(2)));
+        assert!(
+            stats.summary().starts_with("indexed 1/2 files, ETA 2s,"),
+            "{}",
+            stats.summary()
+        );
+        stats.file_done(200, Duration::from_secs(2));
+        assert_eq!(stats.eta(), None);
+    }
+
+    #[test]
+    fn unknown_total_is_shown_until_set() {
+        let mut stats = RunStats::new(None);
+        stats.files_done = 2;
+        assert!(
+            stats.summary().starts_with("indexed 2/? files"),
+            "{}",
+            stats.summary()
+        );
+        stats.files_total = Some(5);
+        assert!(
+            stats.summary().starts_with("indexed 2/5 files"),
+            "{}",
+            stats.summary()
+        );
+    }
+
+    #[test]
+    fn summary_counts_embedding_failures() {
+        let mut stats = RunStats::new(Some(1));
+        assert!(!stats.summary().contains("failure"), "{}", stats.summary());
+        for _ in 0..2 {
+            Progress::new(ProgressMode::None).warn(&mut stats, WarningKind::Embed, None, "x");
+        }
+        assert!(
+            stats
+                .summary()
+                .contains(", 0 symbols, 2 embedding failures, "),
+            "{}",
+            stats.summary()
+        );
+    }
+
+    #[test]
+    fn language_counts_accumulate_in_first_seen_order() {
+        let mut stats = RunStats::new(Some(3));
+        assert_eq!(stats.language_line(), "");
+        stats.add_language_symbols("Python", 1200);
+        stats.add_language_symbols("Rust", 456);
+        stats.add_language_symbols("Python", 34);
+        assert_eq!(stats.language_line(), "Python: 1234 | Rust: 456");
+    }
+
+    #[test]
+    fn language_stats_file_is_json_or_toml() {
+        let mut stats = RunStats::new(Some(3));
+        stats.files_done = 3;
+        stats.symbols = 12;
+        for language in ["Rust", "Python", "Rust"] {
+            stats.add_language_file(language);
+        }
+        stats.add_language_symbols("Rust", 12);
+        stats.warnings.push(Warning {
+            kind: WarningKind::Parse,
+            path: Some(PathBuf::from("src/broken.py")),
+            message: "failed to parse src/broken.py".into(),
+        });
+        let report = stats.language_stats();
+        assert_eq!(report.failed_files, 1);
+        let counts = |files, symbols, failed_files| LanguageCounts {
+            files,
+            symbols,
+            failed_files,
+        };
+        assert_eq!(report.languages["Python"], counts(1, 0, 1));
+        assert_eq!(report.languages["Rust"], counts(2, 12, 0));
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let json_path = dir.path().join("stats.json");
+        stats.write_language_stats(&json_path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap()).unwrap();
+        assert_eq!(json["schema_version"], LANGUAGE_STATS_SCHEMA_VERSION);
+        assert_eq!(json["languages"]["Rust"]["symbols"], 12);
+
+        let toml_path = dir.path().join("stats.toml");
+        stats.write_language_stats(&toml_path).unwrap();
+        let toml: toml::Table = std::fs::read_to_string(&toml_path)
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(toml["schema_version"].as_integer(), Some(1));
+        assert_eq!(
+            toml["languages"]["Python"]["failed_files"].as_integer(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn github_summary_is_a_markdown_table() {
+        let mut stats = RunStats::new(Some(3));
+        stats.files_done = 3;
+        stats.symbols = 1690;
+        stats.add_language_symbols("Python", 1234);
+        stats.add_language_symbols("Rust", 456);
+        stats.warnings.push(Warning {
+            kind: WarningKind::Parse,
+            path: None,
+            message: "failed to parse broken.py".into(),
+        });
+        assert_eq!(
+            stats.github_summary(),
+            "### cearch index\n\n| Metric | Value |\n| --- | ---: |\n| Files | 3 |\n| Symbols | 1690 |\n\
+             | Python symbols | 1234 |\n| Rust symbols | 456 |\n| Warnings | 1 |\n| Elapsed | 0s |\n"
+        );
+    }
+
+    #[test]
+    fn plain_mode_writes_lines_without_carriage_returns() {
+        let out = run(ProgressMode::Plain, Duration::ZERO);
+        assert!(!out.contains('\r'));
+        assert!(out.contains("indexed 1/3 files, 1k symbols"), "{}", out);
+        assert!(out.contains("warn: failed to parse broken.py"));
+        assert!(
+            out.ends_with("indexed 3/3 files, 4k symbols, 0s elapsed\n"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn plain_mode_rate_limits_periodic_lines() {
+        let out = run(ProgressMode::Plain, Duration::from_secs(3600));
+        // One periodic line, the warning, and the final summary
+        assert_eq!(out.lines().count(), 3, "{}", out);
+    }
+
+    #[test]
+    fn json_mode_emits_one_object_per_line() {
+        let out = run(ProgressMode::Json, Duration::ZERO);
+        assert!(!out.contains('\r'));
+        let events: Vec<serde_json::Value> = out
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("json line"))
+            .collect();
+        assert_eq!(events.last().unwrap()["event"], "done");
+        assert_eq!(events.last().unwrap()["symbols"], 4500);
+        assert!(events.iter().any(|e| e["event"] == "warning"));
+    }
+
+    #[test]
+    fn disabled_progress_is_a_no_op() {
+        let out = run(ProgressMode::None, Duration::ZERO);
+        assert!(out.is_empty());
+        assert!(
+            Progress::new(ProgressMode::None)
+                .file_bar(3, "a.rs")
+                .0
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn digest_groups_warnings_by_kind() {
+        let progress = Progress::new(ProgressMode::None);
+        let mut stats = RunStats::new(Some(3));
+        let log = Path::new(".cearch/index.log");
+        assert_eq!(stats.digest(log), None);
+        progress.warn(&mut stats, WarningKind::Embed, None, "embedding timed out");
+        progress.warn(&mut stats, WarningKind::Parse, None, "failed to parse a.py");
+        progress.warn(&mut stats, WarningKind::Parse, None, "failed to parse b.py");
+        assert_eq!(
+            stats.digest(log).unwrap(),
+            "2 files failed to parse, 1 embedding error (see .cearch/index.log)"
+        );
+    }
+
+    #[test]
+    fn log_lists_every_warning() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("index.log");
+        let mut stats = RunStats::new(Some(1));
+        Progress::new(ProgressMode::None).warn(
+            &mut stats,
+            WarningKind::Skip,
+            None,
+            "truncated a.py",
+        );
+        stats.write_log(&path).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.ends_with("[skip] truncated a.py\n"), "{}", log);
+    }
+
+    #[test]
+    fn formats_counts_and_durations() {
+        assert_eq!(format_count(950), "950");
+        assert_eq!(format_count(41_200), "41k");
+        assert_eq!(format_count(2_300_000), "2.3M");
+        assert_eq!(format_duration(Duration::from_secs(192)), "3m12s");
+        assert_eq!(format_duration(Duration::from_secs(3900)), "1h05m");
+    }
+}
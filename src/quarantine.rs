@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+// Quarantine module remembers files whose parsing panicked so later runs can skip them.
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedFile {
+    /// Stored (repo-relative) path
+    pub path: String,
+    /// Panic message from the failed parse
+    pub message: String,
+}
+
+/// The set of quarantined files, persisted as `.cearch/quarantine.json`.
+#[derive(Debug)]
+pub struct Quarantine {
+    file: PathBuf,
+    entries: Vec<QuarantinedFile>,
+    dirty: bool,
+}
+
+impl Quarantine {
+    /// Location of the quarantine list for a repository.
+    pub fn file_path(repo_root: &Path) -> PathBuf {
+        repo_root.join(".cearch").join("quarantine.json")
+    }
+
+    /// A quarantine with no entries, used when the saved list cannot be read.
+    pub fn empty(repo_root: &Path) -> Self {
+        Self {
+            file: Self::file_path(repo_root),
+            entries: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    /// Load the quarantine list; a missing file means nothing is quarantined.
+    pub fn load(repo_root: &Path) -> Result<Self, String> {
+        let file = Self::file_path(repo_root);
+        let entries = match std::fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("invalid {}: {}", file.display(), e))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(format!("failed to read {}: {}", file.display(), err)),
+        };
+        Ok(Self {
+            file,
+            entries,
+            dirty: false,
+        })
+    }
+
+    pub fn contains(&self, stored_path: &str) -> bool {
+        self.entries.iter().any(|e| e.path == stored_path)
+    }
+
+    /// Quarantine `stored_path`, replacing any earlier message for it.
+    pub fn add(&mut self, stored_path: &str, message: &str) {
+        self.remove(stored_path);
+        self.entries.push(QuarantinedFile {
+            path: stored_path.to_string(),
+            message: message.to_string(),
+        });
+        self.dirty = true;
+    }
+
+    /// Release `stored_path`, e.g. after a retry parsed it successfully.
+    pub fn remove(&mut self, stored_path: &str) {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.path != stored_path);
+        self.dirty |= self.entries.len() != before;
+    }
+
+    /// Write the list back if it changed; an empty list removes the file.
+    pub fn save(&self) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if self.entries.is_empty() {
+            return match std::fs::remove_file(&self.file) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    Err(format!("failed to remove {}: {}", self.file.display(), err))
+                }
+                _ => Ok(()),
+            };
+        }
+        let json = serde_json::to_string_pretty(&self.entries).map_err(|e| e.to_string())?;
+        std::fs::write(&self.file, json)
+            .map_err(|e| format!("failed to write {}: {}", self.file.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_releases_entries() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir_all(dir.path().join(".cearch")).unwrap();
+
+        let mut q = Quarantine::load(dir.path()).expect("load empty");
+        assert!(!q.contains("src/bad.py"));
+        q.add("src/bad.py", "index out of bounds");
+        q.save().unwrap();
+
+        let mut q = Quarantine::load(dir.path()).expect("reload");
+        assert!(q.contains("src/bad.py"));
+        q.remove("src/bad.py");
+        q.save().unwrap();
+        assert!(!Quarantine::file_path(dir.path()).exists());
+    }
+}
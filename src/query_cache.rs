@@ -0,0 +1,184 @@
+use crate::db::SearchResult;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+// Query cache module remembers the last results of recent queries so runs can be compared.
+
+/// Queries remembered; the least recently run ones are dropped first.
+const MAX_QUERIES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedHit {
+    /// Stored (repo-relative) path
+    pub path: String,
+    pub line: usize,
+    pub name: String,
+    pub distance: f32,
+}
+
+impl From<&SearchResult> for CachedHit {
+    fn from(r: &SearchResult) -> Self {
+        Self {
+            path: r.path.clone(),
+            line: r.line,
+            name: r.name.clone(),
+            distance: r.distance,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedQuery {
+    query: String,
+    hits: Vec<CachedHit>,
+}
+
+/// Last results per query string, persisted as `.cearch/query_cache.json`.
+#[derive(Debug)]
+pub struct QueryCache {
+    file: PathBuf,
+    /// Oldest first
+    queries: Vec<CachedQuery>,
+}
+
+impl QueryCache {
+    /// Load the cache; a missing or unreadable file starts an empty one.
+    pub fn load(repo_root: &Path) -> Self {
+        let file = repo_root.join(".cearch").join("query_cache.json");
+        let queries = std::fs::read_to_string(&file)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self { file, queries }
+    }
+
+    /// Results recorded the last time `query` was run.
+    pub fn get(&self, query: &str) -> Option<&[CachedHit]> {
+        self.queries
+            .iter()
+            .find(|q| q.query == query)
+            .map(|q| q.hits.as_slice())
+    }
+
+    /// Remember `hits` as the latest results for `query`.
+    pub fn record(&mut self, query: &str, hits: Vec<CachedHit>) {
+        self.queries.retain(|q| q.query != query);
+        self.queries.push(CachedQuery {
+            query: query.to_string(),
+            hits,
+        });
+        let excess = self.queries.len().saturating_sub(MAX_QUERIES);
+        self.queries.drain(..excess);
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string(&self.queries).map_err(|e| e.to_string())?;
+        std::fs::write(&self.file, json)
+            .map_err(|e| format!("failed to write {}: {}", self.file.display(), e))
+    }
+}
+
+/// How one result changed between two runs of a query. Ranks are 1-based.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delta<'a> {
+    Added(&'a CachedHit),
+    Removed(&'a CachedHit),
+    Moved {
+        hit: &'a CachedHit,
+        from: usize,
+        to: usize,
+    },
+    Unchanged(&'a CachedHit),
+}
+
+/// Compare two result lists; a result is the same symbol if its path and name match.
+///
+/// Current results come first in rank order, followed by the removed ones.
+pub fn diff<'a>(previous: &'a [CachedHit], current: &'a [CachedHit]) -> Vec<Delta<'a>> {
+    let mut matched = vec![false; previous.len()];
+    let mut out = Vec::with_capacity(current.len());
+    for (to, hit) in current.iter().enumerate() {
+        let found = previous
+            .iter()
+            .enumerate()
+            .position(|(i, p)| !matched[i] && p.path == hit.path && p.name == hit.name);
+        match found {
+            Some(from) => {
+                matched[from] = true;
+                if from == to {
+                    out.push(Delta::Unchanged(hit));
+                } else {
+                    out.push(Delta::Moved {
+                        hit,
+                        from: from + 1,
+                        to: to + 1,
+                    });
+                }
+            }
+            None => out.push(Delta::Added(hit)),
+        }
+    }
+    for (hit, _) in previous.iter().zip(&matched).filter(|(_, m)| !**m) {
+        out.push(Delta::Removed(hit));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(path: &str, name: &str) -> CachedHit {
+        CachedHit {
+            path: path.to_string(),
+            line: 1,
+            name: name.to_string(),
+            distance: 0.5,
+        }
+    }
+
+    #[test]
+    fn diff_marks_added_removed_and_moved() {
+        let previous = [hit("a.py", "x"), hit("b.py", "y"), hit("c.py", "z")];
+        let current = [
+            hit("b.py", "y"),
+            hit("a.py", "x"),
+            hit("c.py", "z"),
+            hit("d.py", "w"),
+        ];
+        let deltas = diff(&previous, &current);
+        assert_eq!(
+            deltas,
+            vec![
+                Delta::Moved {
+                    hit: &current[0],
+                    from: 2,
+                    to: 1
+                },
+                Delta::Moved {
+                    hit: &current[1],
+                    from: 1,
+                    to: 2
+                },
+                Delta::Unchanged(&current[2]),
+                Delta::Added(&current[3]),
+            ]
+        );
+        let deltas = diff(&current[..2], &previous);
+        assert_eq!(deltas.last(), Some(&Delta::Added(&previous[2])));
+        let deltas = diff(&previous, &current[..1]);
+        assert!(deltas.contains(&Delta::Removed(&previous[2])));
+    }
+
+    #[test]
+    fn cache_round_trips_and_keeps_latest_run() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir_all(dir.path().join(".cearch")).unwrap();
+        let mut cache = QueryCache::load(dir.path());
+        assert_eq!(cache.get("parse"), None);
+        cache.record("parse", vec![hit("a.py", "x")]);
+        cache.record("parse", vec![hit("b.py", "y")]);
+        cache.save().unwrap();
+        let cache = QueryCache::load(dir.path());
+        assert_eq!(cache.get("parse"), Some(&[hit("b.py", "y")][..]));
+    }
+}
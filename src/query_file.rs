@@ -0,0 +1,247 @@
+use crate::symbols;
+use std::path::{Path, PathBuf};
+// Query file module turns a file in the working tree into query text for `query --file`.
+
+/// Rough character budget of one embedded text; the model reads at most a few hundred
+/// tokens, so longer code is split and its vectors pooled.
+const CHUNK_CHARS: usize = 1500;
+
+/// What `query --file` embeds for a file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileQuery {
+    /// Texts whose embeddings are averaged into the query vector
+    pub texts: Vec<String>,
+    /// Symbols the file contributes to an index, so enough extra results can be fetched
+    /// when its own are left out
+    pub symbols: usize,
+    /// Where the query came from, e.g. the enclosing symbol's first line
+    pub source: String,
+}
+
+/// Split `<path>[:line]`; a path that exists as given keeps any colons it has.
+pub fn parse_target(target: &str) -> (PathBuf, Option<usize>) {
+    if !Path::new(target).exists()
+        && let Some((path, line)) = target.rsplit_once(':')
+        && let Ok(line) = line.parse::<usize>()
+    {
+        return (PathBuf::from(path), Some(line));
+    }
+    (PathBuf::from(target), None)
+}
+
+/// Texts to embed for `path`: the code of the symbol enclosing `line`, or of every symbol
+/// in the file. Files cearch cannot parse, or with no symbols, are embedded as raw text
+/// (with a line, just the chunk holding it). Long code is split into chunks.
+pub fn query_texts(path: &Path, line: Option<usize>) -> Result<FileQuery, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let found: Vec<symbols::Symbol> =
+        match symbols::stream_symbols_from_source(path, Ok(source.clone())) {
+            Ok(stream) => stream.collect(),
+            Err(err) => {
+                tracing::warn!("{}: {}; embedding it as plain text", path.display(), err);
+                Vec::new()
+            }
+        };
+    let count = found.len();
+    if let Some(line) = line {
+        // The innermost symbol spanning the line starts last
+        let enclosing = found
+            .iter()
+            .filter(|s| s.line <= line && line < s.line + s.code.lines().count().max(1))
+            .max_by_key(|s| s.line);
+        if let Some(symbol) = enclosing {
+            return Ok(FileQuery {
+                texts: chunks(&symbol.code),
+                symbols: count,
+                source: format!("{}:{} {}", path.display(), symbol.line, symbol.name),
+            });
+        }
+        if count > 0 {
+            return Err(format!(
+                "no symbol in {} spans line {}",
+                path.display(),
+                line
+            ));
+        }
+        let lines: Vec<&str> = source.lines().collect();
+        if line == 0 || line > lines.len() {
+            return Err(format!(
+                "{} has {} lines, not {}",
+                path.display(),
+                lines.len(),
+                line
+            ));
+        }
+        let (start, text) = chunk_around(&lines, line - 1);
+        return Ok(FileQuery {
+            texts: chunks(&text),
+            symbols: 0,
+            source: format!("{}:{} (plain text)", path.display(), start + 1),
+        });
+    }
+    if found.is_empty() {
+        if source.trim().is_empty() {
+            return Err(format!("{} is empty", path.display()));
+        }
+        return Ok(FileQuery {
+            texts: chunks(&source),
+            symbols: 0,
+            source: format!("{} (plain text)", path.display()),
+        });
+    }
+    Ok(FileQuery {
+        texts: found.iter().flat_map(|s| chunks(&s.code)).collect(),
+        symbols: count,
+        source: format!("{} ({} symbols)", path.display(), count),
+    })
+}
+
+/// Split `text` at line breaks into pieces of at most `CHUNK_CHARS` characters; a longer
+/// line is cut on its own. Blank pieces are dropped.
+fn chunks(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    for line in text.lines() {
+        if current.chars().count() + line.chars().count() + 1 > CHUNK_CHARS && !current.is_empty() {
+            out.push(std::mem::take(&mut current));
+        }
+        let mut chars = line.chars().peekable();
+        while chars.peek().is_some() {
+            let room = CHUNK_CHARS.saturating_sub(current.chars().count()).max(1);
+            current.extend(chars.by_ref().take(room));
+            if chars.peek().is_some() {
+                out.push(std::mem::take(&mut current));
+            }
+        }
+        current.push('\n');
+    }
+    out.push(current);
+    out.retain(|chunk| !chunk.trim().is_empty());
+    out
+}
+
+/// The lines around `index` that fit in one chunk, with the first line's index.
+fn chunk_around(lines: &[&str], index: usize) -> (usize, String) {
+    let (mut start, mut end) = (index, index + 1);
+    let mut size = lines[index].chars().count();
+    loop {
+        let mut grew = false;
+        for candidate in [start.checked_sub(1), (end < lines.len()).then_some(end)] {
+            let Some(i) = candidate else { continue };
+            let len = lines[i].chars().count() + 1;
+            if size + len > CHUNK_CHARS {
+                continue;
+            }
+            size += len;
+            grew = true;
+            if i < start {
+                start = i;
+            } else {
+                end = i + 1;
+            }
+        }
+        if !grew {
+            break;
+        }
+    }
+    (start, lines[start..end].join("\n"))
+}
+
+/// Average the embeddings of a file's pieces into one unit-length query vector.
+pub fn mean_pool(vectors: &[Vec<f32>]) -> Option<Vec<f32>> {
+    let first = vectors.first()?;
+    let mut sum = vec![0.0f32; first.len()];
+    for v in vectors {
+        for (s, x) in sum.iter_mut().zip(v) {
+            *s += x;
+        }
+    }
+    let norm = sum.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        sum.iter_mut().for_each(|x| *x /= norm);
+    }
+    Some(sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+fn outer() {
+    let x = 1;
+}
+
+fn retry(times: u32) {
+    for _ in 0..times {
+        attempt();
+    }
+}
+";
+
+    #[test]
+    fn picks_the_enclosing_symbol_or_every_symbol() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let file = dir.path().join("retry.rs");
+        std::fs::write(&file, SOURCE).unwrap();
+
+        let query = query_texts(&file, Some(7)).unwrap();
+        assert_eq!(query.texts.len(), 1);
+        assert!(query.texts[0].starts_with("fn retry"));
+        assert!(query.source.ends_with(":5 retry"), "{}", query.source);
+        assert_eq!(query.symbols, 2);
+
+        assert!(
+            query_texts(&file, Some(4))
+                .unwrap_err()
+                .contains("no symbol")
+        );
+
+        let whole = query_texts(&file, None).unwrap();
+        assert_eq!(whole.texts.len(), 2);
+        assert!(whole.texts[0].starts_with("fn outer"));
+    }
+
+    #[test]
+    fn unsupported_files_are_plain_text() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let file = dir.path().join("notes.txt");
+        let long: String = (0..400).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&file, &long).unwrap();
+
+        let whole = query_texts(&file, None).unwrap();
+        assert!(whole.texts.len() > 1);
+        assert!(whole.texts.iter().all(|t| t.chars().count() <= CHUNK_CHARS));
+        assert_eq!(whole.symbols, 0);
+
+        let one = query_texts(&file, Some(200)).unwrap();
+        assert_eq!(one.texts.len(), 1);
+        assert!(one.texts[0].contains("line 199\n"));
+        assert!(query_texts(&file, Some(401)).is_err());
+    }
+
+    #[test]
+    fn long_lines_are_cut_and_targets_parsed() {
+        let pieces = chunks(&"x".repeat(CHUNK_CHARS * 2 + 10));
+        let sizes: Vec<usize> = pieces.iter().map(|p| p.trim_end().len()).collect();
+        assert_eq!(sizes, [CHUNK_CHARS, CHUNK_CHARS, 10]);
+
+        assert_eq!(
+            parse_target("src/retry.rs:12"),
+            (PathBuf::from("src/retry.rs"), Some(12))
+        );
+        assert_eq!(
+            parse_target("src/retry.rs"),
+            (PathBuf::from("src/retry.rs"), None)
+        );
+    }
+
+    #[test]
+    fn pooled_vectors_have_unit_length() {
+        let pooled = mean_pool(&[vec![1.0, 0.0], vec![0.0, 1.0]]).unwrap();
+        assert!((pooled[0] - pooled[1]).abs() < 1e-6);
+        assert!((pooled.iter().map(|x| x * x).sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(mean_pool(&[]), None);
+    }
+}
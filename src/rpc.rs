@@ -0,0 +1,559 @@
+use crate::db::{DB, DuplicateHit, SearchResult};
+use crate::embedding_store::SplitEmbeddingStore;
+use crate::paths;
+use crate::search::{self, Filters};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+// Rpc module answers newline-delimited JSON-RPC 2.0 requests on stdio for editor plugins.
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Results returned when a request does not pass `k`.
+const DEFAULT_K: usize = 7;
+
+#[derive(Debug, Serialize)]
+struct Hit {
+    id: i64,
+    path: String,
+    line: usize,
+    kind: String,
+    name: String,
+    distance: f32,
+    /// Other locations of the same code, grouped under this hit
+    duplicates: Vec<DuplicateHit>,
+}
+
+impl From<SearchResult> for Hit {
+    fn from(result: SearchResult) -> Self {
+        Hit {
+            id: result.id,
+            path: result.path,
+            line: result.line,
+            kind: result.kind,
+            name: result.name,
+            distance: result.distance,
+            duplicates: result.duplicates,
+        }
+    }
+}
+
+/// A JSON-RPC error object.
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        RpcError {
+            code: INVALID_PARAMS,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(err: impl std::fmt::Display) -> Self {
+        RpcError {
+            code: INTERNAL_ERROR,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// A response object carrying `outcome` for the request with `id`.
+pub fn response(id: Value, outcome: Result<Value, RpcError>) -> Value {
+    match outcome {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": err.code, "message": err.message},
+        }),
+    }
+}
+
+/// Parse a message body as JSON; unparsable bodies yield the parse-error response to send.
+pub fn parse_message(body: &[u8]) -> Result<Value, Value> {
+    serde_json::from_slice(body).map_err(|err| {
+        let err = RpcError {
+            code: PARSE_ERROR,
+            message: format!("parse error: {}", err),
+        };
+        response(Value::Null, Err(err))
+    })
+}
+
+/// A request (or, without an id, a notification) taken from a JSON-RPC 2.0 message.
+pub struct Request {
+    pub id: Option<Value>,
+    pub method: String,
+    /// `{}` when the message has none
+    pub params: Value,
+}
+
+impl Request {
+    /// Parse one message as a request; an invalid one yields the error response to send.
+    pub fn parse(body: &[u8]) -> Result<Self, Value> {
+        parse_message(body).and_then(Self::from_message)
+    }
+
+    /// Check that `message` is a JSON-RPC 2.0 request, as `parse` does.
+    pub fn from_message(mut message: Value) -> Result<Self, Value> {
+        let invalid = |id: Value, message: &str| {
+            let err = RpcError {
+                code: INVALID_REQUEST,
+                message: message.to_string(),
+            };
+            response(id, Err(err))
+        };
+        if message.is_array() {
+            return Err(invalid(Value::Null, "batch requests are not supported"));
+        }
+        if message.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Err(invalid(Value::Null, "jsonrpc must be \"2.0\""));
+        }
+        let id = message.get("id").cloned();
+        if !matches!(
+            id,
+            None | Some(Value::Null | Value::Number(_) | Value::String(_))
+        ) {
+            return Err(invalid(Value::Null, "id must be a string, number or null"));
+        }
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            return Err(invalid(
+                id.unwrap_or(Value::Null),
+                "method must be a string",
+            ));
+        };
+        Ok(Request {
+            method: method.to_string(),
+            params: message
+                .get_mut("params")
+                .map(Value::take)
+                .unwrap_or(json!({})),
+            id,
+        })
+    }
+}
+
+/// Reopen `db` if the index changed on disk since `fingerprint` was taken.
+pub fn reopen_if_changed(
+    repo_root: &Path,
+    db: &mut DB,
+    fingerprint: &mut Vec<Option<SystemTime>>,
+) -> Result<(), RpcError> {
+    let current = index_fingerprint(repo_root);
+    if current != *fingerprint {
+        *db = DB::open_read(repo_root).map_err(RpcError::internal)?;
+        *fingerprint = current;
+    }
+    Ok(())
+}
+
+pub fn str_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::invalid_params(format!("{} must be a string", name)))
+}
+
+fn usize_param(params: &Value, name: &str) -> Result<Option<usize>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value.as_u64().map(|n| Some(n as usize)).ok_or_else(|| {
+            RpcError::invalid_params(format!("{} must be a non-negative integer", name))
+        }),
+    }
+}
+
+pub fn k_param(params: &Value) -> Result<usize, RpcError> {
+    match usize_param(params, "k")? {
+        Some(0) => Err(RpcError::invalid_params("k must be at least 1")),
+        k => Ok(k.unwrap_or(DEFAULT_K)),
+    }
+}
+
+/// `filters: {"kind": "fn", "path_prefix": "src/"}`, both optional.
+pub fn filters_param(params: &Value) -> Result<Filters, RpcError> {
+    let Some(filters) = params.get("filters").filter(|f| !f.is_null()) else {
+        return Ok(Filters::default());
+    };
+    let field = |name: &str| -> Result<Option<String>, RpcError> {
+        match filters.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(RpcError::invalid_params(format!(
+                "filters.{} must be a string",
+                name
+            ))),
+        }
+    };
+    if !filters.is_object() {
+        return Err(RpcError::invalid_params("filters must be an object"));
+    }
+    Ok(Filters {
+        kind: field("kind")?,
+        path_prefix: field("path_prefix")?,
+    })
+}
+
+/// Modification times of the files an open index reads from; a change means a run replaced
+/// or extended them and the index must be reopened.
+pub fn index_fingerprint(repo_root: &Path) -> Vec<Option<SystemTime>> {
+    [
+        DB::file_path(repo_root),
+        SplitEmbeddingStore::file_path(repo_root),
+    ]
+    .iter()
+    .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+    .collect()
+}
+
+/// Embedder and index kept open for the lifetime of `cearch rpc`.
+///
+/// `embed` turns a query into a vector; requests are answered one at a time, in order.
+pub struct Session<E> {
+    root: PathBuf,
+    embed: E,
+    db: DB,
+    fingerprint: Vec<Option<SystemTime>>,
+}
+
+impl<E: FnMut(&str) -> Result<Vec<f32>>> Session<E> {
+    pub fn open(repo_root: &Path, embed: E) -> Result<Self> {
+        // Taken before opening, so a run finishing in between still triggers a reload
+        let fingerprint = index_fingerprint(repo_root);
+        Ok(Session {
+            root: repo_root.to_path_buf(),
+            embed,
+            db: DB::open_read(repo_root)?,
+            fingerprint,
+        })
+    }
+
+    /// Answer requests from `input` until it ends or `shutdown` is called.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> Result<()> {
+        for line in input.split(b'\n') {
+            let line = line?;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let (reply, shutdown) = self.handle(&line);
+            if let Some(reply) = reply {
+                serde_json::to_writer(&mut output, &reply)?;
+                output.write_all(b"\n")?;
+                output.flush()?;
+            }
+            if shutdown {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The response to one request line (none for notifications), and whether to stop.
+    fn handle(&mut self, line: &[u8]) -> (Option<Value>, bool) {
+        let Request { id, method, params } = match Request::parse(line) {
+            Ok(request) => request,
+            Err(reply) => return (Some(reply), false),
+        };
+        let shutdown = method == "shutdown";
+        let outcome = if shutdown {
+            Ok(Value::Null)
+        } else if !params.is_object() {
+            Err(RpcError::invalid_params(
+                "params must be an object of named parameters",
+            ))
+        } else {
+            self.call(&method, &params)
+        };
+        // Requests without an id are notifications and get no response
+        (id.map(|id| response(id, outcome)), shutdown)
+    }
+
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "search" => self.search(params),
+            "similar" => self.similar(params),
+            "symbols" => self.symbols(params),
+            "status" => self.status(),
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("unknown method {}", method),
+            }),
+        }
+    }
+
+    /// Reopen the index if it changed on disk since it was loaded.
+    fn refresh(&mut self) -> Result<(), RpcError> {
+        reopen_if_changed(&self.root, &mut self.db, &mut self.fingerprint)
+    }
+
+    /// `search(query, k, filters)`: symbols closest to the embedded query.
+    fn search(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let query = search::validate_query(str_param(params, "query")?)
+            .map_err(RpcError::invalid_params)?;
+        let k = k_param(params)?;
+        let filters = filters_param(params)?;
+        self.refresh()?;
+        let embedding = (self.embed)(query).map_err(RpcError::internal)?;
+        self.nearest(&embedding, k, &filters, None)
+    }
+
+    /// `similar(path, line, k)`: symbols closest to the one defined at or above `line`.
+    fn similar(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let path = paths::to_stored(&self.root, Path::new(str_param(params, "path")?));
+        let line = usize_param(params, "line")?
+            .ok_or_else(|| RpcError::invalid_params("line must be a non-negative integer"))?;
+        let k = k_param(params)?;
+        self.refresh()?;
+        let symbol = self
+            .db
+            .symbol_at(&path, line)
+            .map_err(RpcError::internal)?
+            .ok_or_else(|| {
+                RpcError::invalid_params(format!("no indexed symbol at {}:{}", path, line))
+            })?;
+        let embedding = self
+            .db
+            .get_embedding(symbol.vector_rowid)
+            .map_err(RpcError::internal)?
+            .ok_or_else(|| {
+                RpcError::internal(format!(
+                    "{}:{} has no stored embedding",
+                    symbol.path, symbol.line
+                ))
+            })?;
+        self.nearest(
+            &embedding,
+            k,
+            &Filters::default(),
+            Some((&symbol.path, symbol.line)),
+        )
+    }
+
+    fn nearest(
+        &self,
+        embedding: &[f32],
+        k: usize,
+        filters: &Filters,
+        exclude: Option<(&str, usize)>,
+    ) -> Result<Value, RpcError> {
+        if self.db.is_metadata_only().map_err(RpcError::internal)? {
+            return Err(RpcError::internal(
+                "index was built with --metadata-only and has no embeddings",
+            ));
+        }
+        // One extra so leaving out the excluded symbol still leaves `k`
+        let fetch =
+            search::expanded_k(k, search::K_EXPANSION, true) + usize::from(exclude.is_some());
+        let mut results = self.db.knn(embedding, fetch).map_err(RpcError::internal)?;
+        results.retain(|r| filters.keep(r) && exclude != Some((r.path.as_str(), r.line)));
+        let ids: Vec<i64> = results.iter().map(|r| r.id).collect();
+        let hashes = self.db.code_hashes(&ids).map_err(RpcError::internal)?;
+        let mut results = search::collapse_duplicates(results, &hashes);
+        results.truncate(k);
+        let hits: Vec<Hit> = results.into_iter().map(Hit::from).collect();
+        serde_json::to_value(hits).map_err(RpcError::internal)
+    }
+
+    /// `symbols(ids)`: the stored symbols with these ids and their code, in the order given,
+    /// e.g. to re-rank `search` results; unknown ids are left out.
+    fn symbols(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let ids: Vec<i64> = params
+            .get("ids")
+            .and_then(Value::as_array)
+            .and_then(|ids| ids.iter().map(Value::as_i64).collect())
+            .ok_or_else(|| RpcError::invalid_params("ids must be an array of integers"))?;
+        self.refresh()?;
+        let symbols = self
+            .db
+            .get_symbols_by_rowids(&ids)
+            .map_err(RpcError::internal)?;
+        Ok(symbols
+            .into_iter()
+            .map(|s| {
+                json!({
+                    "id": s.id,
+                    "path": s.path,
+                    "line": s.line,
+                    "kind": s.kind,
+                    "name": s.name,
+                    "code": s.code,
+                })
+            })
+            .collect())
+    }
+
+    /// `status()`: what the loaded index holds.
+    fn status(&mut self) -> Result<Value, RpcError> {
+        self.refresh()?;
+        let db = &self.db;
+        let meta = |key| db.get_meta(key).map_err(RpcError::internal);
+        Ok(json!({
+            "root": self.root.display().to_string(),
+            "symbols": db.count_symbols().map_err(RpcError::internal)?,
+            "files": db.count_files().map_err(RpcError::internal)?,
+            "vectors": db.count_vectors().map_err(RpcError::internal)?,
+            "metadata_only": db.is_metadata_only().map_err(RpcError::internal)?,
+            "git_commit": db.git_commit().map_err(RpcError::internal)?,
+            "indexed_at": meta("indexed_at")?.and_then(|t| t.parse::<u64>().ok()),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SymbolRecord, WriteOptions};
+
+    fn build_index(root: &Path, symbols: &[(&str, usize, &str, &str, [f32; 2])]) {
+        let db = DB::open_with_dim(root, 2, &WriteOptions::default()).expect("open");
+        let records: Vec<SymbolRecord> = symbols
+            .iter()
+            .map(|(path, line, kind, name, emb)| SymbolRecord {
+                kind,
+                ..SymbolRecord::fixture(path, *line, name, emb)
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+    }
+
+    fn mock_embedder(query: &str) -> Result<Vec<f32>> {
+        Ok(match query {
+            "left" => vec![1.0, 0.0],
+            "up" => vec![0.0, 1.0],
+            _ => vec![0.7, 0.7],
+        })
+    }
+
+    fn session(
+        session: &mut Session<impl FnMut(&str) -> Result<Vec<f32>>>,
+        script: &str,
+    ) -> Vec<Value> {
+        let mut out = Vec::new();
+        session.run(script.as_bytes(), &mut out).expect("run");
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("json response"))
+            .collect()
+    }
+
+    #[test]
+    fn scripted_session_conforms_to_json_rpc() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        build_index(
+            dir.path(),
+            &[
+                ("a.rs", 1, "fn", "left_fn", [1.0, 0.0]),
+                ("b.rs", 1, "fn", "up_fn", [0.0, 1.0]),
+                ("b.rs", 5, "const", "UP_LIMIT", [0.1, 0.9]),
+            ],
+        );
+        let mut rpc = Session::open(dir.path(), mock_embedder).expect("open");
+        let script = r#"
+{"jsonrpc":"2.0","id":1,"method":"status"}
+{"jsonrpc":"2.0","id":2,"method":"search","params":{"query":" left ","k":1}}
+{"jsonrpc":"2.0","id":"three","method":"search","params":{"query":"up","filters":{"kind":"const"}}}
+{"jsonrpc":"2.0","id":4,"method":"similar","params":{"path":"b.rs","line":7,"k":1}}
+{"jsonrpc":"2.0","id":5,"method":"symbols","params":{"ids":[3,99,1]}}
+not json
+[{"jsonrpc":"2.0","id":5,"method":"status"}]
+{"jsonrpc":"2.0","id":6,"method":"nope"}
+{"jsonrpc":"2.0","id":7,"method":"search","params":{"query":"   "}}
+{"jsonrpc":"2.0","id":8,"method":"search","params":{"query":"up","k":0}}
+{"jsonrpc":"2.0","id":9,"method":"similar","params":{"path":"c.rs","line":1}}
+{"jsonrpc":"2.0","id":10,"method":"search","params":["up"]}
+{"jsonrpc":"1.0","id":11,"method":"status"}
+{"jsonrpc":"2.0","method":"status"}
+{"jsonrpc":"2.0","id":12,"method":"shutdown"}
+{"jsonrpc":"2.0","id":13,"method":"status"}
+"#;
+        let replies = session(&mut rpc, script);
+        let ids: Vec<&Value> = replies.iter().map(|r| &r["id"]).collect();
+        assert_eq!(
+            ids,
+            [
+                &json!(1),
+                &json!(2),
+                &json!("three"),
+                &json!(4),
+                &json!(5),
+                &Value::Null,
+                &Value::Null,
+                &json!(6),
+                &json!(7),
+                &json!(8),
+                &json!(9),
+                &json!(10),
+                &Value::Null,
+                &json!(12),
+            ]
+        );
+        assert!(replies.iter().all(|r| r["jsonrpc"] == "2.0"));
+        assert_eq!(replies[0]["result"]["symbols"], 3);
+        assert_eq!(replies[0]["result"]["files"], 2);
+        assert_eq!(replies[1]["result"][0]["name"], "left_fn");
+        assert_eq!(replies[1]["result"].as_array().unwrap().len(), 1);
+        let consts = replies[2]["result"].as_array().unwrap();
+        assert_eq!(consts.len(), 1);
+        assert_eq!(consts[0]["name"], "UP_LIMIT");
+        // The symbol at the cursor is UP_LIMIT, which is left out of its own neighbours
+        assert_eq!(replies[3]["result"][0]["name"], "up_fn");
+        let named: Vec<&Value> = replies[4]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| &s["name"])
+            .collect();
+        assert_eq!(named, [&json!("UP_LIMIT"), &json!("left_fn")]);
+        assert_eq!(replies[4]["result"][0]["code"], "UP_LIMIT");
+        let codes: Vec<&Value> = replies[5..13].iter().map(|r| &r["error"]["code"]).collect();
+        assert_eq!(
+            codes,
+            [
+                &json!(PARSE_ERROR),
+                &json!(INVALID_REQUEST),
+                &json!(METHOD_NOT_FOUND),
+                &json!(INVALID_PARAMS),
+                &json!(INVALID_PARAMS),
+                &json!(INVALID_PARAMS),
+                &json!(INVALID_PARAMS),
+                &json!(INVALID_REQUEST),
+            ]
+        );
+        assert!(replies[13]["result"].is_null());
+        assert!(replies[13].get("error").is_none());
+    }
+
+    #[test]
+    fn index_is_reloaded_when_replaced_on_disk() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        build_index(dir.path(), &[("a.rs", 1, "fn", "a", [1.0, 0.0])]);
+        let mut rpc = Session::open(dir.path(), mock_embedder).expect("open");
+        let status = r#"{"jsonrpc":"2.0","id":1,"method":"status"}"#;
+        assert_eq!(session(&mut rpc, status)[0]["result"]["symbols"], 1);
+
+        // As `cearch clean` followed by `cearch index` would
+        DB::remove_files(dir.path()).unwrap();
+        build_index(
+            dir.path(),
+            &[
+                ("a.rs", 1, "fn", "a", [1.0, 0.0]),
+                ("b.rs", 1, "fn", "b", [0.0, 1.0]),
+            ],
+        );
+        assert_eq!(session(&mut rpc, status)[0]["result"]["symbols"], 2);
+        let search = r#"{"jsonrpc":"2.0","id":2,"method":"search","params":{"query":"up","k":1}}"#;
+        assert_eq!(session(&mut rpc, search)[0]["result"][0]["name"], "b");
+    }
+}
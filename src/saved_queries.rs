@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+// Saved queries module keeps named searches for `cearch query --save`/`--saved`.
+
+/// A query with everything needed to run it again the same way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedQuery {
+    pub name: String,
+    pub query: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter_kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    /// Run against the keyword index instead of the embeddings
+    #[serde(default)]
+    pub keyword: bool,
+    /// The index's embedding model when the query was saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Unix seconds
+    pub saved_at: u64,
+}
+
+/// Saved queries in name order, persisted as `.cearch/saved_queries.json`.
+#[derive(Debug)]
+pub struct SavedQueries {
+    file: PathBuf,
+    queries: Vec<SavedQuery>,
+}
+
+impl SavedQueries {
+    pub fn file_path(repo_root: &Path) -> PathBuf {
+        repo_root.join(".cearch").join("saved_queries.json")
+    }
+
+    /// Load the saved queries; a missing file means none. Unlike the query cache, an
+    /// unreadable file is an error so saving cannot overwrite it.
+    pub fn load(repo_root: &Path) -> Result<Self, String> {
+        let file = Self::file_path(repo_root);
+        let queries = match std::fs::read_to_string(&file) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("invalid {}: {}", file.display(), e))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(format!("failed to read {}: {}", file.display(), err)),
+        };
+        Ok(Self { file, queries })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SavedQuery> {
+        self.queries.iter().find(|q| q.name == name)
+    }
+
+    pub fn list(&self) -> &[SavedQuery] {
+        &self.queries
+    }
+
+    /// Add `query`, replacing one saved under the same name.
+    pub fn insert(&mut self, query: SavedQuery) {
+        self.remove(&query.name);
+        let at = self.queries.partition_point(|q| q.name < query.name);
+        self.queries.insert(at, query);
+    }
+
+    /// Returns whether a query had that name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.queries.len();
+        self.queries.retain(|q| q.name != name);
+        self.queries.len() != before
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&self.queries).map_err(|e| e.to_string())?;
+        if let Some(parent) = self.file.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(&self.file, json + "\n")
+            .map_err(|e| format!("failed to write {}: {}", self.file.display(), e))
+    }
+}
+
+/// Names become CLI arguments and file keys, so keep them to a simple alphabet.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid saved query name {:?}: use letters, digits, '-', '_' and '.'",
+            name
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(name: &str, query: &str) -> SavedQuery {
+        SavedQuery {
+            name: name.to_string(),
+            query: query.to_string(),
+            filter_kind: Some("fn".to_string()),
+            path_prefix: None,
+            keyword: false,
+            model: Some("Qdrant/all-MiniLM-L6-v2-onnx".to_string()),
+            saved_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn saved_queries_round_trip_sorted_and_replace_by_name() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let mut store = SavedQueries::load(dir.path()).unwrap();
+        assert!(store.list().is_empty());
+        store.insert(saved("sqli", "sql built with format!"));
+        store.insert(saved("legacy", "legacy client calls"));
+        store.insert(saved("sqli", "string formatted sql"));
+        store.save().unwrap();
+
+        let mut store = SavedQueries::load(dir.path()).unwrap();
+        let names: Vec<&str> = store.list().iter().map(|q| q.name.as_str()).collect();
+        assert_eq!(names, ["legacy", "sqli"]);
+        assert_eq!(
+            store.get("sqli"),
+            Some(&saved("sqli", "string formatted sql"))
+        );
+        assert!(store.remove("legacy"));
+        assert!(!store.remove("legacy"));
+
+        std::fs::write(SavedQueries::file_path(dir.path()), "not json").unwrap();
+        assert!(
+            SavedQueries::load(dir.path())
+                .unwrap_err()
+                .contains("invalid")
+        );
+    }
+
+    #[test]
+    fn names_are_restricted() {
+        assert!(validate_name("sqli-v2.1_a").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("a b").is_err());
+        assert!(validate_name("../x").is_err());
+    }
+}
@@ -0,0 +1,969 @@
+// Search module holds post-processing applied to nearest-neighbour results.
+
+use crate::db::{DB, DuplicateHit, SearchResult};
+use crate::index::Blame;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Number of candidates fetched when `--threshold-auto` picks the cutoff.
+pub const AUTO_THRESHOLD_K: usize = 100;
+
+/// How many times more candidates `--per-file` fetches, to backfill the hits it drops.
+const PER_FILE_EXPANSION: usize = 4;
+/// Most candidates `--per-file` fetches.
+const PER_FILE_MAX_FETCH: usize = 1000;
+
+/// Filters applied to candidates after the nearest-neighbour search.
+#[derive(Debug, Default)]
+pub struct Filters {
+    /// Keep only symbols of this kind (`fn`, `class` or `const`)
+    pub kind: Option<String>,
+    /// Keep only symbols whose repo-relative path starts with this prefix
+    pub path_prefix: Option<String>,
+}
+
+impl Filters {
+    pub fn is_active(&self) -> bool {
+        self.kind.is_some() || self.path_prefix.is_some()
+    }
+
+    pub fn keep(&self, result: &SearchResult) -> bool {
+        let kind_ok = self.kind.as_ref().is_none_or(|k| &result.kind == k);
+        let path_ok = self.path_prefix.as_ref().is_none_or(|prefix| {
+            let prefix = crate::paths::normalize_separators(prefix);
+            result.path.starts_with(prefix.trim_start_matches("./"))
+        });
+        kind_ok && path_ok
+    }
+}
+
+/// The index a hit of a merged search came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// The repository's own index
+    Repo,
+    /// A dependency's sources (`index --deps`), by `name-version` label
+    Dependency(String),
+    /// A version no longer at `HEAD` (`index --history`), by the full hash of a commit
+    /// holding it
+    History(String),
+}
+
+/// Orders `query --sort` prints results in; ties fall back to path, then line, then id, so
+/// the output is the same from run to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Best match first
+    Score,
+    /// Grouped by file, top-down
+    Path,
+    /// By line number across files
+    Line,
+    /// Most recently changed first, by `git blame` of each result's first line; results
+    /// with no commit (uncommitted, or from dependencies) last
+    Recency,
+}
+
+/// What a query learned about its results beyond their rows, one entry per result; a list
+/// is empty when that was not looked up.
+#[derive(Debug, Default)]
+pub struct ResultExtras {
+    /// The index each result came from, when dependencies or history were searched too
+    pub origins: Vec<Origin>,
+    /// Who last changed each result's first line (`--blame`)
+    pub blames: Vec<Option<Blame>>,
+    /// Names of the symbols enclosing each result, outermost first
+    pub parents: Vec<Vec<String>>,
+}
+
+impl ResultExtras {
+    /// Whether result `i` is from the repository's own index.
+    pub fn is_own(&self, i: usize) -> bool {
+        matches!(self.origins.get(i), Some(Origin::Repo) | None)
+    }
+
+    /// `Outer > method > name` for result `i`, or just its name at the top level.
+    pub fn breadcrumb(&self, i: usize, name: &str) -> String {
+        match self.parents.get(i) {
+            Some(parents) if !parents.is_empty() => format!("{} > {}", parents.join(" > "), name),
+            _ => name.to_string(),
+        }
+    }
+}
+
+/// Put `results`, and what `extras` holds about them, in `order`. Recency is read from
+/// `extras.blames`.
+pub fn sort_results(results: &mut Vec<SearchResult>, extras: &mut ResultExtras, order: SortOrder) {
+    let time = |i: usize| {
+        extras
+            .blames
+            .get(i)
+            .and_then(Option::as_ref)
+            .map(|b| b.time)
+    };
+    let mut positions: Vec<usize> = (0..results.len()).collect();
+    positions.sort_by(|&a, &b| {
+        let (x, y) = (&results[a], &results[b]);
+        let primary = match order {
+            SortOrder::Score => x.distance.total_cmp(&y.distance),
+            SortOrder::Path => std::cmp::Ordering::Equal,
+            SortOrder::Line => x.line.cmp(&y.line),
+            // Reversed, so the newest come first and those without a time last
+            SortOrder::Recency => time(b).cmp(&time(a)),
+        };
+        primary
+            .then_with(|| x.path.cmp(&y.path))
+            .then(x.line.cmp(&y.line))
+            .then(x.id.cmp(&y.id))
+    });
+    permute(results, &positions);
+    permute(&mut extras.origins, &positions);
+    permute(&mut extras.blames, &positions);
+    permute(&mut extras.parents, &positions);
+}
+
+/// Reorder `items` so item `positions[i]` comes `i`th; lists not looked up (empty) stay so.
+fn permute<T>(items: &mut Vec<T>, positions: &[usize]) {
+    if items.len() != positions.len() {
+        return;
+    }
+    let mut taken: Vec<Option<T>> = std::mem::take(items).into_iter().map(Some).collect();
+    *items = positions.iter().filter_map(|&i| taken[i].take()).collect();
+}
+
+/// The enclosing symbols of the repository's own `results` (`own` tells them apart).
+///
+/// They are read from the index; for symbols indexed before they were recorded, the file is
+/// parsed as it is now instead, and a symbol it no longer holds at that line gets none.
+pub fn result_parents(
+    db: &DB,
+    repo_root: &Path,
+    results: &[SearchResult],
+    own: impl Fn(usize) -> bool,
+) -> Vec<Vec<String>> {
+    let ids: Vec<i64> = results
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| own(*i))
+        .map(|(_, r)| r.id)
+        .collect();
+    let mut stored = db.symbol_parents(&ids).unwrap_or_else(|err| {
+        tracing::warn!("failed to read enclosing symbols: {}", err);
+        HashMap::new()
+    });
+    let mut parsed: HashMap<&str, HashMap<(usize, String), Vec<String>>> = HashMap::new();
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            if !own(i) {
+                return Vec::new();
+            }
+            if let Some(parents) = stored.remove(&r.id) {
+                return parents;
+            }
+            parsed
+                .entry(r.path.as_str())
+                .or_insert_with(|| {
+                    crate::symbols::parents_in_file(&crate::paths::to_native(repo_root, &r.path))
+                })
+                .remove(&(r.line, r.name.clone()))
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// The `n` nearest of hits gathered from several indexes, at most `per_file` from any one
+/// path (`query --per-file`).
+pub fn merge_ranked(
+    mut hits: Vec<(SearchResult, Origin)>,
+    n: usize,
+    per_file: Option<usize>,
+) -> Vec<(SearchResult, Origin)> {
+    hits.sort_by(|a, b| a.0.distance.total_cmp(&b.0.distance));
+    let mut hits = cap_by_path(hits, per_file, |(hit, _)| &hit.path);
+    hits.truncate(n);
+    hits
+}
+
+/// Keep at most `per_file` results from any one path, in rank order, so lower-ranked hits
+/// from other files move up (`query --per-file`). Copies grouped under a hit by
+/// `collapse_duplicates` count once, for the hit's path.
+pub fn cap_per_file(results: Vec<SearchResult>, per_file: Option<usize>) -> Vec<SearchResult> {
+    cap_by_path(results, per_file, |hit| &hit.path)
+}
+
+fn cap_by_path<T>(items: Vec<T>, per_file: Option<usize>, path: impl Fn(&T) -> &str) -> Vec<T> {
+    let Some(cap) = per_file else {
+        return items;
+    };
+    let mut kept: HashMap<String, usize> = HashMap::new();
+    items
+        .into_iter()
+        .filter(|item| {
+            let count = kept.entry(path(item).to_string()).or_default();
+            *count += 1;
+            *count <= cap
+        })
+        .collect()
+}
+
+/// Candidates to fetch for `wanted` results when `--per-file` may drop some:
+/// `PER_FILE_EXPANSION` times as many, but never more than `PER_FILE_MAX_FETCH` (or
+/// `wanted`), so an index dominated by a few files is not scanned end to end.
+pub fn per_file_k(wanted: usize, per_file: Option<usize>) -> usize {
+    match per_file {
+        Some(_) => wanted
+            .saturating_mul(PER_FILE_EXPANSION)
+            .min(PER_FILE_MAX_FETCH)
+            .max(wanted),
+        None => wanted,
+    }
+}
+
+/// Queries with fewer characters than this still run, but with a hint to use a phrase.
+pub const SHORT_QUERY_CHARS: usize = 3;
+
+/// A query string ready to embed: trimmed, and rejected if nothing is left.
+pub fn validate_query(query: &str) -> Result<&str, String> {
+    let trimmed = query.trim();
+    if trimmed.is_empty() {
+        return Err("query is empty; describe the code you are looking for".to_string());
+    }
+    Ok(trimmed)
+}
+
+/// Whether a (trimmed) query is too short for semantic search to say much about.
+pub fn is_short_query(query: &str) -> bool {
+    query.chars().count() < SHORT_QUERY_CHARS
+}
+
+/// One part of a weighted query: its text is embedded on its own and scaled by `weight`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedTerm {
+    pub text: String,
+    pub weight: f32,
+}
+
+/// Split a query like `caching^2 invalidation^0.5` into weighted terms, or `None` for a
+/// plain query.
+///
+/// A word ending in `^<number>` closes a term that starts after the previous one, so
+/// `http client^2 tests^-1` weighs the phrase `http client` by 2. Words after the last
+/// weight form a term of weight 1. Only words with a letter before the `^` count, so code
+/// such as `a ^ b` or `2^10` stays a plain query.
+pub fn parse_weighted_query(query: &str) -> Option<Vec<WeightedTerm>> {
+    let mut terms = Vec::new();
+    let mut words: Vec<&str> = Vec::new();
+    for word in query.split_whitespace() {
+        let weighted = word.rsplit_once('^').and_then(|(text, weight)| {
+            let weight = weight.parse::<f32>().ok().filter(|w| w.is_finite())?;
+            text.chars()
+                .any(char::is_alphabetic)
+                .then_some((text, weight))
+        });
+        match weighted {
+            Some((text, weight)) => {
+                words.push(text);
+                terms.push(WeightedTerm {
+                    text: words.join(" "),
+                    weight,
+                });
+                words.clear();
+            }
+            None => words.push(word),
+        }
+    }
+    if terms.is_empty() {
+        return None;
+    }
+    if !words.is_empty() {
+        terms.push(WeightedTerm {
+            text: words.join(" "),
+            weight: 1.0,
+        });
+    }
+    Some(terms)
+}
+
+/// Parse a `--term text:weight` flag; without `:weight` the term weighs 1.
+pub fn parse_term_flag(flag: &str) -> Result<WeightedTerm, String> {
+    let (text, weight) = match flag.rsplit_once(':') {
+        Some((text, weight)) if weight.trim().parse::<f32>().is_ok() => {
+            (text, weight.trim().parse::<f32>().unwrap_or(1.0))
+        }
+        _ => (flag, 1.0),
+    };
+    let text = validate_query(text)?;
+    if !weight.is_finite() {
+        return Err(format!("--term {}: weight must be a finite number", flag));
+    }
+    Ok(WeightedTerm {
+        text: text.to_string(),
+        weight,
+    })
+}
+
+/// Combine term embeddings into one query vector: their weighted sum, scaled to unit length.
+///
+/// Negative weights pull the query away from a term. At least one weight must be positive,
+/// and terms that cancel out entirely leave nothing to search with.
+pub fn combine_weighted(terms: &[(f32, Vec<f32>)]) -> Result<Vec<f32>, String> {
+    if !terms.iter().any(|(weight, _)| *weight > 0.0) {
+        return Err("a weighted query needs at least one term with a positive weight".to_string());
+    }
+    let dim = terms.first().map_or(0, |(_, v)| v.len());
+    let mut sum = vec![0.0f32; dim];
+    for (weight, vector) in terms {
+        for (s, x) in sum.iter_mut().zip(vector) {
+            *s += weight * x;
+        }
+    }
+    let norm = sum.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm <= f32::EPSILON {
+        return Err("the weighted terms cancel out; adjust their weights".to_string());
+    }
+    Ok(sum.into_iter().map(|x| x / norm).collect())
+}
+
+/// Number of results to return for `-n requested` from an index of `available` symbols.
+///
+/// Zero is a usage error; asking for more than the index holds returns everything it has.
+pub fn clamp_num_results(requested: usize, available: usize) -> Result<usize, String> {
+    if requested == 0 {
+        return Err("-n must be at least 1".to_string());
+    }
+    Ok(requested.min(available))
+}
+
+/// Default headroom factor for `expanded_k`, as `query --k-expansion` and the long-running
+/// modes use it.
+pub const K_EXPANSION: f32 = 2.0;
+
+/// Candidates to fetch so that about `wanted` results survive the filters and duplicate
+/// grouping.
+///
+/// When neither is active nothing is discarded, so no headroom is needed.
+pub fn expanded_k(wanted: usize, factor: f32, discarding: bool) -> usize {
+    if !discarding {
+        return wanted;
+    }
+    (wanted as f32 * factor).ceil() as usize
+}
+
+/// Fold hits whose code is identical into the best-ranked one, listing the others under its
+/// `duplicates`. `hashes` maps rowids to their `code_hash`; hits without one are kept as is.
+pub fn collapse_duplicates(
+    results: Vec<SearchResult>,
+    hashes: &HashMap<i64, Vec<u8>>,
+) -> Vec<SearchResult> {
+    let mut out: Vec<SearchResult> = Vec::with_capacity(results.len());
+    let mut first: HashMap<&[u8], usize> = HashMap::new();
+    for hit in results {
+        let Some(hash) = hashes.get(&hit.id) else {
+            out.push(hit);
+            continue;
+        };
+        match first.get(hash.as_slice()) {
+            Some(&i) => out[i].duplicates.push(DuplicateHit {
+                id: hit.id,
+                path: hit.path,
+                line: hit.line,
+                distance: hit.distance,
+            }),
+            None => {
+                first.insert(hash, out.len());
+                out.push(hit);
+            }
+        }
+    }
+    out
+}
+
+/// Find the "elbow" of an ascending distance curve and return how many results precede it.
+///
+/// The elbow is the index `i` maximising `|d[i+1] - d[i]| - |d[i] - d[i-1]|`, i.e. where the
+/// gap to the next result grows the most compared to the previous gap. Results `0..=i` are
+/// kept. With fewer than three distances there is no curve to inspect and all are kept.
+///
+/// This is a heuristic: a flat curve (many equally relevant hits) or several similar jumps
+/// can place the elbow early, and a single outlier near the top dominates the choice.
+pub fn elbow_cutoff(distances: &[f32]) -> usize {
+    if distances.len() < 3 {
+        return distances.len();
+    }
+    let mut best = 1;
+    let mut best_score = f32::NEG_INFINITY;
+    for i in 1..distances.len() - 1 {
+        let next_gap = (distances[i + 1] - distances[i]).abs();
+        let prev_gap = (distances[i] - distances[i - 1]).abs();
+        let score = next_gap - prev_gap;
+        if score > best_score {
+            best = i;
+            best_score = score;
+        }
+    }
+    best + 1
+}
+
+/// A result as written by `query --save-results`, with its stored (repo-relative) path.
+#[derive(Debug, Serialize)]
+struct SavedResult<'a> {
+    id: i64,
+    path: &'a str,
+    line: usize,
+    kind: &'a str,
+    name: &'a str,
+    distance: f32,
+    /// Other locations of the same code
+    duplicates: &'a [DuplicateHit],
+    /// Set for a version no longer at `HEAD` (`query --include-history`); its id belongs to
+    /// the history index
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    historical: bool,
+    /// Commit holding a historical version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commit: Option<&'a str>,
+    /// Who last changed the symbol's first line (`query --blame`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blame: Option<&'a Blame>,
+    /// Names of the enclosing symbols, outermost first
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    parents: &'a [String],
+}
+
+/// `--within` only needs the ids, so `cearch serve` responses can be read back as well.
+#[derive(Debug, Deserialize)]
+struct ResultId {
+    id: i64,
+    #[serde(default)]
+    historical: bool,
+}
+
+/// What `query --save-results` writes: the results and the order they are in.
+#[derive(Debug, Serialize)]
+struct SavedResults<'a> {
+    sort: SortOrder,
+    results: Vec<SavedResult<'a>>,
+}
+
+/// Write results as JSON for a later `query --within`, with what else the query learned
+/// about them and the `sort` they are in.
+pub fn save_results(
+    file: &Path,
+    results: &[SearchResult],
+    extras: &ResultExtras,
+    sort: SortOrder,
+) -> Result<(), String> {
+    let saved: Vec<SavedResult> = results
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            let commit = match extras.origins.get(i) {
+                Some(Origin::History(commit)) => Some(commit.as_str()),
+                _ => None,
+            };
+            SavedResult {
+                id: r.id,
+                path: &r.path,
+                line: r.line,
+                kind: &r.kind,
+                name: &r.name,
+                distance: r.distance,
+                duplicates: &r.duplicates,
+                historical: commit.is_some(),
+                commit,
+                blame: extras.blames.get(i).and_then(Option::as_ref),
+                parents: extras.parents.get(i).map_or(&[], Vec::as_slice),
+            }
+        })
+        .collect();
+    let saved = SavedResults {
+        sort,
+        results: saved,
+    };
+    let json = serde_json::to_string_pretty(&saved).map_err(|e| e.to_string())?;
+    std::fs::write(file, json + "\n")
+        .map_err(|e| format!("failed to write {}: {}", file.display(), e))
+}
+
+/// The symbol ids of results written by `--save-results`, or of a JSON array of results;
+/// historical results are left out, since their ids are not the index's.
+pub fn load_result_ids(file: &Path) -> Result<Vec<i64>, String> {
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+    let invalid = |e: serde_json::Error| {
+        format!(
+            "{} is not a JSON array of results with an `id` field, nor `--save-results` output: {}",
+            file.display(),
+            e
+        )
+    };
+    let saved: serde_json::Value = serde_json::from_str(&contents).map_err(invalid)?;
+    // `--save-results` wraps the results with their sort; older output and `cearch serve`
+    // responses are bare arrays
+    let results = match saved {
+        serde_json::Value::Object(mut saved) if saved.contains_key("results") => {
+            saved.remove("results").unwrap_or_default()
+        }
+        other => other,
+    };
+    let ids: Vec<ResultId> = serde_json::from_value(results).map_err(invalid)?;
+    Ok(ids
+        .into_iter()
+        .filter(|r| !r.historical)
+        .map(|r| r.id)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_are_trimmed_and_empty_ones_rejected() {
+        assert_eq!(validate_query("  open db \n"), Ok("open db"));
+        assert!(validate_query("").is_err());
+        assert!(validate_query(" \t\n ").is_err());
+        assert!(is_short_query(validate_query(" db ").unwrap()));
+        assert!(is_short_query("é"));
+        assert!(!is_short_query("dbs"));
+    }
+
+    #[test]
+    fn cuts_before_the_sharpest_jump() {
+        let d = [0.10, 0.12, 0.13, 0.15, 0.60, 0.62, 0.65];
+        assert_eq!(elbow_cutoff(&d), 4);
+    }
+
+    fn result(path: &str, kind: &str) -> SearchResult {
+        SearchResult {
+            id: 1,
+            path: path.to_string(),
+            line: 1,
+            kind: kind.to_string(),
+            name: "x".to_string(),
+            code: String::new(),
+            distance: 0.5,
+            duplicate_files: 0,
+            duplicates: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn identical_code_collapses_into_the_best_hit() {
+        let hit = |id: i64, path: &str| SearchResult {
+            id,
+            path: path.to_string(),
+            ..result(path, "fn")
+        };
+        let hashes: HashMap<i64, Vec<u8>> =
+            [(1, vec![7]), (2, vec![9]), (3, vec![7]), (4, vec![7])].into();
+        let results = vec![
+            hit(1, "vendor/a/util.rs"),
+            hit(2, "src/other.rs"),
+            hit(3, "vendor/b/util.rs"),
+            hit(5, "src/unhashed.rs"),
+            hit(4, "src/util.rs"),
+        ];
+        let collapsed = collapse_duplicates(results, &hashes);
+        let ids: Vec<i64> = collapsed.iter().map(|r| r.id).collect();
+        assert_eq!(ids, [1, 2, 5]);
+        let others: Vec<&str> = collapsed[0]
+            .duplicates
+            .iter()
+            .map(|d| d.path.as_str())
+            .collect();
+        assert_eq!(others, ["vendor/b/util.rs", "src/util.rs"]);
+        assert!(collapsed[1].duplicates.is_empty());
+
+        // Filtered first, a path's own copy leads its group
+        let filters = Filters {
+            kind: None,
+            path_prefix: Some("src/".to_string()),
+        };
+        let results = vec![hit(1, "vendor/a/util.rs"), hit(4, "src/util.rs")];
+        let kept: Vec<SearchResult> = results.into_iter().filter(|r| filters.keep(r)).collect();
+        let collapsed = collapse_duplicates(kept, &hashes);
+        assert_eq!(collapsed.len(), 1);
+        assert_eq!(collapsed[0].path, "src/util.rs");
+    }
+
+    #[test]
+    fn sorts_break_ties_by_path_line_then_id() {
+        let hit = |id: i64, path: &str, line: usize, distance: f32| SearchResult {
+            id,
+            line,
+            distance,
+            ..result(path, "fn")
+        };
+        let blame = |time: u64| Blame {
+            author: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            commit: "9337d3dc98b4fdbd28d1115123c9e669bff0404a".to_string(),
+            time,
+        };
+        let results = vec![
+            hit(4, "src/b.rs", 30, 0.1),
+            hit(3, "src/a.rs", 30, 0.2),
+            hit(2, "src/a.rs", 5, 0.2),
+            hit(1, "src/a.rs", 5, 0.2),
+            hit(5, "src/b.rs", 5, 0.3),
+        ];
+        let sorted = |order: SortOrder| {
+            let mut results = results.clone();
+            let mut extras = ResultExtras {
+                origins: Vec::new(),
+                blames: vec![
+                    Some(blame(10)),
+                    None,
+                    Some(blame(10)),
+                    Some(blame(20)),
+                    None,
+                ],
+                parents: vec![vec!["B".to_string()], vec![], vec![], vec![], vec![]],
+            };
+            sort_results(&mut results, &mut extras, order);
+            let ids: Vec<i64> = results.iter().map(|r| r.id).collect();
+            (ids, extras)
+        };
+        // Equal scores keep a stable order: path, then line, then id
+        assert_eq!(sorted(SortOrder::Score).0, [4, 1, 2, 3, 5]);
+        assert_eq!(sorted(SortOrder::Path).0, [1, 2, 3, 5, 4]);
+        assert_eq!(sorted(SortOrder::Line).0, [1, 2, 5, 3, 4]);
+        let (ids, extras) = sorted(SortOrder::Recency);
+        assert_eq!(ids, [1, 2, 4, 3, 5]);
+        // What is known about each result moves with it
+        assert_eq!(extras.parents[2], ["B"]);
+        assert_eq!(extras.blames[0].as_ref().map(|b| b.time), Some(20));
+        assert!(extras.origins.is_empty());
+    }
+
+    #[test]
+    fn per_file_caps_backfill_from_other_files() {
+        let hit = |id: i64, path: &str| SearchResult {
+            id,
+            distance: id as f32 / 10.0,
+            ..result(path, "fn")
+        };
+        // 2 and 4 are copies of 1, from other files
+        let hashes: HashMap<i64, Vec<u8>> = [(1, vec![7]), (2, vec![7]), (4, vec![7])].into();
+        let results = vec![
+            hit(1, "src/big.rs"),
+            hit(2, "src/big.rs"),
+            hit(3, "src/big.rs"),
+            hit(4, "src/copy.rs"),
+            hit(5, "src/big.rs"),
+            hit(6, "src/small.rs"),
+            hit(7, "src/big.rs"),
+            hit(8, "src/other.rs"),
+        ];
+        let ids = |results: &[SearchResult]| results.iter().map(|r| r.id).collect::<Vec<_>>();
+        let capped = cap_per_file(collapse_duplicates(results.clone(), &hashes), Some(2));
+        // A group counts once, so 3 is big.rs's second hit
+        assert_eq!(ids(&capped), [1, 3, 6, 8]);
+        assert_eq!(capped[0].duplicates.len(), 2);
+        assert_eq!(ids(&cap_per_file(results.clone(), None)).len(), 8);
+
+        let hits = results
+            .into_iter()
+            .map(|hit| (hit, Origin::Repo))
+            .rev()
+            .collect();
+        let merged: Vec<i64> = merge_ranked(hits, 3, Some(1))
+            .iter()
+            .map(|(hit, _)| hit.id)
+            .collect();
+        assert_eq!(merged, [1, 4, 6]);
+
+        assert_eq!(per_file_k(10, None), 10);
+        assert_eq!(per_file_k(10, Some(1)), 40);
+        assert_eq!(per_file_k(500, Some(1)), PER_FILE_MAX_FETCH);
+        assert_eq!(per_file_k(5000, Some(1)), 5000);
+    }
+
+    #[test]
+    fn filters_match_kind_and_path_prefix() {
+        let none = Filters::default();
+        assert!(!none.is_active());
+        assert!(none.keep(&result("a.py", "fn")));
+
+        let filters = Filters {
+            kind: Some("class".to_string()),
+            path_prefix: Some("./src/".to_string()),
+        };
+        assert!(filters.keep(&result("src/model.py", "class")));
+        assert!(!filters.keep(&result("src/model.py", "fn")));
+        assert!(!filters.keep(&result("tests/src/model.py", "class")));
+    }
+
+    #[test]
+    fn result_counts_are_clamped_to_the_index() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = crate::db::DB::open_with_dim(dir.path(), 2, &Default::default()).expect("open");
+        let emb = [0.0f32, 1.0];
+        let records: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| crate::db::SymbolRecord {
+                code: "fn a() {}",
+                ..crate::db::SymbolRecord::fixture("a.rs", 1, name, &emb)
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+        let available = db.count_canonical_symbols().unwrap();
+
+        assert!(clamp_num_results(0, available).is_err());
+        assert_eq!(clamp_num_results(2, available), Ok(2));
+        assert_eq!(clamp_num_results(3, available), Ok(3));
+        assert_eq!(clamp_num_results(1_000_000, available), Ok(3));
+        assert_eq!(db.knn(&emb, 1_000_000).unwrap().len(), 3);
+    }
+
+    fn mock_embedder(query: &str) -> Vec<f32> {
+        match query {
+            "left" => vec![1.0, 0.0],
+            _ => vec![0.0, 1.0],
+        }
+    }
+
+    #[test]
+    fn second_query_only_searches_within_the_first_results() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = crate::db::DB::open_with_dim(dir.path(), 2, &Default::default()).expect("open");
+        let vectors: [(&str, [f32; 2]); 5] = [
+            ("far_left", [1.0, 0.0]),
+            ("left_up", [0.8, 0.6]),
+            ("mid", [0.6, 0.8]),
+            ("up", [0.0, 1.0]),
+            ("down", [0.0, -1.0]),
+        ];
+        let records: Vec<_> = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, (name, embedding))| {
+                crate::db::SymbolRecord::fixture("a.rs", i + 1, name, embedding)
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+
+        let broad = db.knn(&mock_embedder("left"), 3).unwrap();
+        let file = dir.path().join("results.json");
+        save_results(&file, &broad, &ResultExtras::default(), SortOrder::Score).unwrap();
+        let within = load_result_ids(&file).unwrap();
+        assert_eq!(within, broad.iter().map(|r| r.id).collect::<Vec<_>>());
+
+        // Historical results carry their commit, and are not the index's to search within
+        let origins = vec![
+            Origin::Repo,
+            Origin::History("3c31b8cf628988c07380c435c47b1fca594463aa".to_string()),
+        ];
+        let blame = Blame {
+            author: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            commit: "9337d3dc98b4fdbd28d1115123c9e669bff0404a".to_string(),
+            time: 951_782_400,
+        };
+        let extras = ResultExtras {
+            origins,
+            blames: vec![Some(blame)],
+            parents: vec![vec!["Outer".to_string()]],
+        };
+        save_results(&file, &broad, &extras, SortOrder::Path).unwrap();
+        let saved = std::fs::read_to_string(&file).unwrap();
+        assert!(saved.starts_with("{\n  \"sort\": \"path\",\n  \"results\": ["));
+        assert_eq!(saved.matches("\"parents\": [").count(), 1);
+        assert_eq!(saved.matches("\"historical\": true").count(), 1);
+        assert_eq!(saved.matches("\"author\": \"alice\"").count(), 1);
+        assert!(saved.contains("\"commit\": \"3c31b8cf628988c07380c435c47b1fca594463aa\""));
+        assert_eq!(load_result_ids(&file).unwrap(), [broad[0].id, broad[2].id]);
+
+        let narrowed = db.knn_within(&mock_embedder("up"), 10, &within).unwrap();
+        let names: Vec<&str> = narrowed.iter().map(|r| r.name.as_str()).collect();
+        // `up` itself is nearest overall but was not among the first results
+        assert_eq!(names, ["mid", "left_up", "far_left"]);
+        assert!(narrowed.iter().all(|r| within.contains(&r.id)));
+        assert!(narrowed[0].distance < narrowed[1].distance);
+        assert!(
+            db.knn_within(&mock_embedder("up"), 10, &[])
+                .unwrap()
+                .is_empty()
+        );
+
+        // Bare arrays, e.g. `cearch serve` responses, are read too
+        std::fs::write(&file, "[{\"id\": 4, \"path\": \"a.rs\"}]").unwrap();
+        assert_eq!(load_result_ids(&file).unwrap(), [4]);
+        std::fs::write(&file, "{}").unwrap();
+        let err = load_result_ids(&file).unwrap_err();
+        assert!(
+            err.contains("`id` field") && err.contains("expected a sequence"),
+            "{}",
+            err
+        );
+        std::fs::write(
+            &file,
+            "{\"sort\": \"score\", \"results\": [{\"path\": \"a.rs\"}]}",
+        )
+        .unwrap();
+        let err = load_result_ids(&file).unwrap_err();
+        assert!(err.contains("missing field `id`"), "{}", err);
+    }
+
+    #[test]
+    fn breadcrumbs_come_from_the_index_or_a_fresh_parse() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let root = dir.path();
+        std::fs::write(
+            root.join("nested.py"),
+            "class Outer:\n    def method(self):\n        def helper():\n            pass\n\
+             \n        return helper\n",
+        )
+        .unwrap();
+        let db = DB::open_with_dim(root, 2, &Default::default()).expect("open");
+        let stored = vec!["Stored".to_string()];
+        let record = |line, name, parents| crate::db::SymbolRecord {
+            parents,
+            ..crate::db::SymbolRecord::fixture("nested.py", line, name, &[1.0, 0.0])
+        };
+        // Only the first was indexed with its parents; the others predate them
+        let ids = db
+            .insert_symbols(&[
+                record(2, "method", Some(&stored)),
+                record(3, "helper", None),
+                record(9, "gone", None),
+            ])
+            .unwrap();
+        let hit = |id: i64, line: usize, name: &str| SearchResult {
+            id,
+            path: "nested.py".to_string(),
+            line,
+            kind: "fn".to_string(),
+            name: name.to_string(),
+            code: String::new(),
+            distance: 0.0,
+            duplicate_files: 0,
+            duplicates: Vec::new(),
+        };
+        let results = [
+            hit(ids[0], 2, "method"),
+            hit(ids[1], 3, "helper"),
+            hit(ids[2], 9, "gone"),
+            hit(ids[1], 3, "helper"),
+        ];
+        let extras = ResultExtras {
+            parents: result_parents(&db, root, &results, |i| i != 3),
+            ..Default::default()
+        };
+        assert_eq!(
+            extras.parents,
+            [vec!["Stored"], vec!["Outer", "method"], vec![], vec![]]
+        );
+        assert_eq!(extras.breadcrumb(1, "helper"), "Outer > method > helper");
+        assert_eq!(extras.breadcrumb(2, "gone"), "gone");
+    }
+
+    fn term(text: &str, weight: f32) -> WeightedTerm {
+        WeightedTerm {
+            text: text.to_string(),
+            weight,
+        }
+    }
+
+    #[test]
+    fn weighted_queries_are_parsed_and_plain_ones_left_alone() {
+        assert_eq!(
+            parse_weighted_query("caching^2 invalidation^0.5"),
+            Some(vec![term("caching", 2.0), term("invalidation", 0.5)])
+        );
+        assert_eq!(
+            parse_weighted_query("http client^2 tests^-1 retry loop"),
+            Some(vec![
+                term("http client", 2.0),
+                term("tests", -1.0),
+                term("retry loop", 1.0)
+            ])
+        );
+        assert_eq!(parse_weighted_query("open the database"), None);
+        assert_eq!(parse_weighted_query("let x = a ^ b; 2^10"), None);
+        assert_eq!(parse_weighted_query("x^y"), None);
+
+        assert_eq!(parse_term_flag("caching:2.0"), Ok(term("caching", 2.0)));
+        assert_eq!(parse_term_flag("tests: -1"), Ok(term("tests", -1.0)));
+        assert_eq!(parse_term_flag("std::fs"), Ok(term("std::fs", 1.0)));
+        assert!(parse_term_flag(" :2").is_err());
+        assert!(parse_term_flag("x:inf").is_err());
+    }
+
+    #[test]
+    fn weighted_terms_steer_the_search() {
+        let axis = |text: &str| match text {
+            "caching" => vec![1.0, 0.0, 0.0],
+            "invalidation" => vec![0.0, 1.0, 0.0],
+            _ => vec![0.0, 0.0, 1.0],
+        };
+        let embed = |terms: &[WeightedTerm]| -> Vec<(f32, Vec<f32>)> {
+            terms.iter().map(|t| (t.weight, axis(&t.text))).collect()
+        };
+        let query = combine_weighted(&embed(&[
+            term("caching", 2.0),
+            term("invalidation", 0.5),
+            term("tests", -1.0),
+        ]))
+        .unwrap();
+        let norm = (4.0f32 + 0.25 + 1.0).sqrt();
+        for (got, want) in query.iter().zip([2.0 / norm, 0.5 / norm, -1.0 / norm]) {
+            assert!((got - want).abs() < 1e-6, "{:?}", query);
+        }
+        assert!(combine_weighted(&embed(&[term("tests", -1.0)])).is_err());
+        assert!(
+            combine_weighted(&embed(&[term("caching", 1.0), term("caching", -1.0)]))
+                .unwrap_err()
+                .contains("cancel")
+        );
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = crate::db::DB::open_with_dim(dir.path(), 3, &Default::default()).expect("open");
+        let vectors: [(&str, [f32; 3]); 4] = [
+            ("cache_test", [0.7, 0.0, 0.7]),
+            ("invalidate", [0.0, 1.0, 0.0]),
+            ("lru_cache", [0.9, 0.4, 0.0]),
+            ("test_helpers", [0.0, 0.0, 1.0]),
+        ];
+        let records: Vec<_> = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, (name, embedding))| {
+                crate::db::SymbolRecord::fixture("a.rs", i + 1, name, embedding)
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+        let names = |query: &[f32]| -> Vec<String> {
+            db.knn(query, 4)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+        assert_eq!(
+            names(&query),
+            ["lru_cache", "cache_test", "invalidate", "test_helpers"]
+        );
+        // Without the negative term, the test that caches ranks first
+        let query = combine_weighted(&embed(&[term("caching", 2.0), term("tests", 2.0)])).unwrap();
+        assert_eq!(names(&query)[0], "cache_test");
+    }
+
+    #[test]
+    fn k_expands_only_for_active_filters() {
+        assert_eq!(expanded_k(7, 2.0, false), 7);
+        assert_eq!(expanded_k(7, 2.0, true), 14);
+        assert_eq!(expanded_k(5, 1.5, true), 8);
+    }
+
+    #[test]
+    fn short_curves_keep_everything() {
+        assert_eq!(elbow_cutoff(&[]), 0);
+        assert_eq!(elbow_cutoff(&[0.1, 0.9]), 2);
+    }
+}
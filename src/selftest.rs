@@ -0,0 +1,266 @@
+use crate::embed;
+use crate::index::{self, GitEnv};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+// Selftest module runs `cearch selftest`: init, index and query a throwaway repository with
+// the installed binary, to tell a broken installation from a problem with the user's repo.
+
+/// Files of the throwaway repository, as `(path, contents)`.
+const FILES: &[(&str, &str)] = &[
+    (
+        "app/config.py",
+        "def load_config(path):\n    with open(path) as f:\n        return json.load(f)\n\n\
+         class HttpClient:\n    def get(self, url):\n        return requests.get(url)\n",
+    ),
+    (
+        "src/math.rs",
+        "pub fn fibonacci(n: u64) -> u64 {\n    if n < 2 {\n        return n;\n    }\n    \
+         fibonacci(n - 1) + fibonacci(n - 2)\n}\n\n\
+         pub fn reverse_words(s: &str) -> String {\n    \
+         s.split_whitespace().rev().collect::<Vec<_>>().join(\" \")\n}\n",
+    ),
+];
+
+/// The canned query and the symbol expected as its top hit.
+const QUERY: &str = "fibonacci sequence";
+const EXPECTED: &str = "fibonacci";
+
+/// A directory removed when dropped, so a failing stage still cleans up.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> std::io::Result<Self> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let path =
+            std::env::temp_dir().join(format!("cearch-selftest-{}-{}", std::process::id(), nanos));
+        std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Lines describing the installation, for bug reports.
+pub fn environment(no_model: bool) -> Vec<String> {
+    let git = index::git_version().unwrap_or_else(|| "not found".to_string());
+    let exe = std::env::current_exe()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|e| format!("unknown ({})", e));
+    vec![
+        format!("cearch {}", env!("CARGO_PKG_VERSION")),
+        format!("os: {} {}", std::env::consts::OS, std::env::consts::ARCH),
+        format!("git: {}", git),
+        format!("executable: {}", exe),
+        format!(
+            "embedder: {}",
+            if no_model {
+                "none (keyword index)".to_string()
+            } else {
+                embed::DEFAULT_MODEL_REPO.to_string()
+            }
+        ),
+    ]
+}
+
+/// The symbol name of the first result line printed by `cearch query`
+/// (`path:line name score #id`).
+pub fn top_hit_name(stdout: &str) -> Option<&str> {
+    stdout.lines().next()?.split_whitespace().nth(1)
+}
+
+/// Run the stages, printing each one's outcome and time; returns the failing stage's name.
+///
+/// With `no_model` the model is never loaded: the index is built with `--metadata-only
+/// --build-fts` and queried with `--keyword`, and `init` (which fetches the model) is
+/// skipped. Otherwise a model already cached in the current repository is reused.
+pub fn run(no_model: bool) -> Result<(), &'static str> {
+    for line in environment(no_model) {
+        println!("  {}", line);
+    }
+    let started = Instant::now();
+    let dir = match TempDir::new() {
+        Ok(dir) => dir,
+        Err(err) => return stage("create repo", started, Err(err.to_string())),
+    };
+    let repo = dir.path();
+    stage("create repo", started, create_repo(repo, no_model))?;
+
+    if no_model {
+        println!("SKIP init (fetches the model)");
+    } else {
+        let started = Instant::now();
+        stage(
+            "init",
+            started,
+            cearch(repo, &["init"]).map(|_| String::new()),
+        )?;
+    }
+
+    let started = Instant::now();
+    let index: &[&str] = if no_model {
+        &["index", "--metadata-only", "--build-fts"]
+    } else {
+        &["index"]
+    };
+    stage("index", started, cearch(repo, index).map(|_| String::new()))?;
+
+    let started = Instant::now();
+    let query: &[&str] = if no_model {
+        &["query", QUERY, "-n", "3", "--keyword"]
+    } else {
+        &["query", QUERY, "-n", "3"]
+    };
+    let found = cearch(repo, query).and_then(|stdout| match top_hit_name(&stdout) {
+        Some(EXPECTED) => Ok(format!("top hit is {}", EXPECTED)),
+        Some(other) => Err(format!(
+            "expected {} as the top hit for {:?}, got {}",
+            EXPECTED, QUERY, other
+        )),
+        None => Err(format!("no results for {:?}", QUERY)),
+    });
+    stage("query", started, found)
+}
+
+/// Print a stage's outcome and time; a failure stops the run with the stage's name.
+fn stage(
+    name: &'static str,
+    started: Instant,
+    outcome: Result<String, String>,
+) -> Result<(), &'static str> {
+    let elapsed = started.elapsed();
+    match outcome {
+        Ok(detail) if detail.is_empty() => println!("PASS {} ({:.2?})", name, elapsed),
+        Ok(detail) => println!("PASS {} ({:.2?}): {}", name, elapsed, detail),
+        Err(err) => {
+            println!("FAIL {} ({:.2?}): {}", name, elapsed, err);
+            return Err(name);
+        }
+    }
+    Ok(())
+}
+
+/// A git repository holding `FILES`, with the current repository's cached model linked in.
+fn create_repo(repo: &Path, no_model: bool) -> Result<String, String> {
+    for (path, contents) in FILES {
+        let file = repo.join(path);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&file, contents).map_err(|e| e.to_string())?;
+    }
+    git(repo, &["init", "-q"])?;
+    git(repo, &["add", "."])?;
+    git(
+        repo,
+        &[
+            "-c",
+            "user.name=cearch selftest",
+            "-c",
+            "user.email=selftest@localhost",
+            "commit",
+            "-q",
+            "-m",
+            "selftest",
+        ],
+    )?;
+    if no_model {
+        return Ok(String::new());
+    }
+    let cached = std::env::current_dir()
+        .ok()
+        .and_then(crate::index::repo_root)
+        .map(|root| embed::model_dir(&root.join(".cearch"), embed::DEFAULT_MODEL_REPO))
+        .filter(|dir| dir.is_dir());
+    match cached {
+        Some(source) => {
+            let target = embed::model_dir(&repo.join(".cearch"), embed::DEFAULT_MODEL_REPO);
+            link_dir(&source, &target)
+                .map_err(|e| format!("failed to reuse the cached model: {}", e))?;
+            Ok(format!("reusing the model cached in {}", source.display()))
+        }
+        None => Ok("no cached model here; init will download it".to_string()),
+    }
+}
+
+/// Hard-link the files of `source` into `target`, copying where linking fails (e.g. across
+/// file systems).
+fn link_dir(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(target)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let to = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_dir(&entry.path(), &to)?;
+        } else if std::fs::hard_link(entry.path(), &to).is_err() {
+            std::fs::copy(entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run git in `repo`, ignoring any `GIT_DIR`/`GIT_WORK_TREE` of the calling shell.
+fn git(repo: &Path, args: &[&str]) -> Result<(), String> {
+    let out = GitEnv::default()
+        .command(repo)
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !out.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Run this cearch binary in `repo` and return its standard output.
+fn cearch(repo: &Path, args: &[&str]) -> Result<String, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("cannot find cearch: {}", e))?;
+    let out = std::process::Command::new(exe)
+        .args(args)
+        .current_dir(repo)
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_WORK_TREE")
+        .env_remove("CEARCH_DB_PATH")
+        .output()
+        .map_err(|e| format!("failed to run cearch: {}", e))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let last = stderr.lines().last().unwrap_or("").trim();
+        return Err(format!(
+            "cearch {} exited with {}: {}",
+            args[0], out.status, last
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_dir_is_removed_and_top_hit_parsed() {
+        let dir = TempDir::new().expect("temp dir");
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("a.txt"), "x").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+
+        let stdout = "src/math.rs:1 fibonacci 0.412 #3\napp/config.py:1 load_config 0.9 #1\n";
+        assert_eq!(top_hit_name(stdout), Some("fibonacci"));
+        assert_eq!(top_hit_name(""), None);
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+// Serve module keeps the model and index warm behind a tiny HTTP endpoint.
+
+#[derive(Debug, Serialize)]
+pub struct QueryHit {
+    pub id: i64,
+    pub path: String,
+    pub line: usize,
+    pub name: String,
+    pub distance: f32,
+}
+
+/// Location of the port file written by `cearch serve` so clients can auto-detect it.
+pub fn port_file() -> Option<PathBuf> {
+    std::env::home_dir().map(|home| home.join(".cache").join("cearch").join("server.port"))
+}
+
+/// Return the URL of a running local server for `repo_root`, if one advertised itself.
+///
+/// The port file records the repo the server was started in; servers for other repos
+/// are ignored, as are stale files whose port no longer accepts connections.
+pub fn detect_local_server(repo_root: &Path) -> Option<String> {
+    let contents = std::fs::read_to_string(port_file()?).ok()?;
+    let mut lines = contents.lines();
+    let port: u16 = lines.next()?.trim().parse().ok()?;
+    let served_root = lines.next()?;
+    if Path::new(served_root) != repo_root {
+        return None;
+    }
+    TcpStream::connect(("127.0.0.1", port)).ok()?;
+    Some(format!("http://127.0.0.1:{}", port))
+}
+
+/// Send a query to a running server and return the raw JSON response body.
+pub fn query_remote(server: &str, query: &str, k: usize) -> Result<String> {
+    let url = format!("{}/query", server.trim_end_matches('/'));
+    let mut response = ureq::get(&url)
+        .query("q", query)
+        .query("n", k.to_string())
+        .call()
+        .map_err(|e| anyhow!("request to {} failed: {}", url, e))?;
+    Ok(response.body_mut().read_to_string()?)
+}
+
+/// Serve `GET /query?q=...&n=...` requests until the process is killed.
+///
+/// `search` embeds the query and runs the nearest-neighbour lookup; it is called
+/// once per request, serially. `git_commit` is the commit the index was built from, sent
+/// as `X-Cearch-Git-Commit` so clients can tell when they are querying a stale index.
+pub fn run(
+    repo_root: &Path,
+    port: u16,
+    git_commit: Option<&str>,
+    mut search: impl FnMut(&str, usize) -> Result<Vec<QueryHit>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let bound = listener.local_addr()?.port();
+    if let Some(pf) = port_file() {
+        std::fs::create_dir_all(pf.parent().unwrap())?;
+        std::fs::write(&pf, format!("{}\n{}\n", bound, repo_root.display()))?;
+    }
+    println!("listening on http://127.0.0.1:{}", bound);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(s) => s,
+            Err(err) => {
+                tracing::warn!("failed to accept connection: {}", err);
+                continue;
+            }
+        };
+        let (status, body) = match read_request_target(&stream) {
+            Some(target) => handle(&target, &mut search),
+            None => (400, error_body("malformed request")),
+        };
+        let response = http_response(status, &body, git_commit);
+        if let Err(err) = stream.write_all(response.as_bytes()) {
+            tracing::warn!("failed to write response: {}", err);
+        }
+    }
+    Ok(())
+}
+
+fn http_response(status: u16, body: &str, git_commit: Option<&str>) -> String {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Internal Server Error",
+    };
+    let commit_header = git_commit
+        .map(|commit| format!("X-Cearch-Git-Commit: {}\r\n", commit))
+        .unwrap_or_default();
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        commit_header,
+        body
+    )
+}
+
+fn handle(
+    target: &str,
+    search: &mut impl FnMut(&str, usize) -> Result<Vec<QueryHit>>,
+) -> (u16, String) {
+    let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/query" {
+        return (404, error_body("not found"));
+    }
+    let mut q: Option<String> = None;
+    let mut n: usize = 7;
+    for pair in query_string.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match key {
+            "q" => q = Some(percent_decode(value)),
+            "n" => match value.parse() {
+                Ok(v) => n = v,
+                Err(_) => return (400, error_body("n must be a positive integer")),
+            },
+            _ => {}
+        }
+    }
+    let Some(q) = q else {
+        return (400, error_body("missing q parameter"));
+    };
+    let q = match crate::search::validate_query(&q) {
+        Ok(q) => q,
+        Err(err) => return (400, error_body(&err)),
+    };
+    match search(q, n) {
+        Ok(hits) => match serde_json::to_string(&hits) {
+            Ok(body) => (200, body),
+            Err(err) => (500, error_body(&err.to_string())),
+        },
+        Err(err) => (500, error_body(&err.to_string())),
+    }
+}
+
+/// Read the request line and drain headers, returning the request target of a GET.
+fn read_request_target(stream: &TcpStream) -> Option<String> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    parts.next().map(|t| t.to_string())
+}
+
+fn error_body(msg: &str) -> String {
+    serde_json::json!({ "error": msg }).to_string()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_percent_and_plus() {
+        assert_eq!(percent_decode("fn+parse%28x%29"), "fn parse(x)");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn responses_carry_the_indexed_commit() {
+        let response = http_response(200, "[]", Some("abc123"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\r\nX-Cearch-Git-Commit: abc123\r\n"));
+        assert!(response.ends_with("\r\n\r\n[]"));
+        assert!(!http_response(404, "{}", None).contains("X-Cearch"));
+    }
+
+    #[test]
+    fn routes_query_requests() {
+        let mut search = |q: &str, n: usize| {
+            Ok(vec![QueryHit {
+                id: 4,
+                path: "src/lib.rs".to_string(),
+                line: n,
+                name: q.to_string(),
+                distance: 0.5,
+            }])
+        };
+        let (status, body) = handle("/query?q=hello%20world&n=3", &mut search);
+        assert_eq!(status, 200);
+        assert!(body.contains("\"name\":\"hello world\""));
+        assert!(body.contains("\"line\":3"));
+        assert!(body.contains("\"id\":4"));
+        assert_eq!(handle("/query?n=3", &mut search).0, 400);
+        assert_eq!(handle("/query?q=%20%20&n=3", &mut search).0, 400);
+        assert_eq!(handle("/other", &mut search).0, 404);
+    }
+}
@@ -0,0 +1,338 @@
+use std::io::IsTerminal;
+// Snippet module cuts code for display at whole lines and fits long lines to a width, for
+// query results and `cearch show`.
+
+/// Shown in place of the part of a line that did not fit.
+const CUT_MARKER: char = '…';
+/// Starts each continuation of a wrapped line.
+const WRAP_MARKER: &str = "↪ ";
+/// Tabs are expanded so widths can be counted.
+const TAB: &str = "    ";
+
+/// How much of a symbol's code to show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnippetOptions {
+    /// Lines shown before the rest are summarized; all when unset
+    pub max_lines: Option<usize>,
+    /// Terminal columns a line may take; unlimited when unset
+    pub width: Option<usize>,
+    /// Continue long lines on the next row instead of cutting them
+    pub wrap: bool,
+}
+
+impl SnippetOptions {
+    /// Options from the `--snippet-*` flags; without `--snippet-width`, lines are fitted
+    /// to `$COLUMNS` when writing to a terminal.
+    pub fn from_flags(max_lines: Option<usize>, width: Option<usize>, wrap: bool) -> Self {
+        let width = width.or_else(|| {
+            std::io::stdout()
+                .is_terminal()
+                .then(|| std::env::var("COLUMNS").ok()?.trim().parse().ok())
+                .flatten()
+        });
+        Self {
+            max_lines,
+            width,
+            wrap,
+        }
+    }
+
+    /// The same options for text printed after a `columns`-wide prefix.
+    pub fn indented(self, columns: usize) -> Self {
+        Self {
+            width: self.width.map(|w| w.saturating_sub(columns)),
+            ..self
+        }
+    }
+}
+
+/// One row of a formatted snippet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnippetLine {
+    /// Which line of the code (from 0) the row shows; `None` for a wrapped continuation
+    pub line: Option<usize>,
+    pub text: String,
+}
+
+/// Code fitted for display by `format`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snippet {
+    pub rows: Vec<SnippetLine>,
+    /// Lines left out after `max_lines`
+    pub more_lines: usize,
+}
+
+impl Snippet {
+    /// The `… (+42 more lines)` row ending a cut snippet.
+    pub fn trailer(&self) -> Option<String> {
+        match self.more_lines {
+            0 => None,
+            1 => Some(format!("{} (+1 more line)", CUT_MARKER)),
+            n => Some(format!("{} (+{} more lines)", CUT_MARKER, n)),
+        }
+    }
+
+    /// The rows and trailer, one per line.
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        self.rows
+            .iter()
+            .map(|row| row.text.clone())
+            .chain(self.trailer())
+    }
+}
+
+/// Fit `code` to `opts`: keep the first `max_lines` lines whole and count the rest, then cut
+/// or wrap each line wider than `width` at a character boundary. Terminal color codes (from
+/// `symbols::highlight`) take no width and are never split.
+pub fn format(code: &str, opts: &SnippetOptions) -> Snippet {
+    let code = code.trim_end_matches(['\n', '\r']);
+    let total = code.lines().count();
+    let keep = opts.max_lines.map_or(total, |max| max.min(total));
+    let mut snippet = Snippet {
+        rows: Vec::with_capacity(keep),
+        more_lines: total - keep,
+    };
+    for (i, line) in code.lines().take(keep).enumerate() {
+        let line = line.replace('\t', TAB);
+        let Some(width) = opts.width.filter(|&w| display_width(&line) > w) else {
+            snippet.rows.push(SnippetLine {
+                line: Some(i),
+                text: line,
+            });
+            continue;
+        };
+        if !opts.wrap {
+            snippet.rows.push(SnippetLine {
+                line: Some(i),
+                text: cut(&line, width),
+            });
+            continue;
+        }
+        // Continuations give up room for their marker, but always make progress
+        let mut rest = line.as_str();
+        let mut first = true;
+        while !rest.is_empty() {
+            let room = if first {
+                width
+            } else {
+                width.saturating_sub(WRAP_MARKER.chars().count())
+            };
+            let (mut head, mut tail) = split_at_width(rest, room);
+            if head.is_empty() {
+                let first_char = rest.chars().next().map_or(0, char::len_utf8);
+                (head, tail) = rest.split_at(escape_end(rest, 0).max(first_char));
+            }
+            let mut text = if first {
+                String::new()
+            } else {
+                WRAP_MARKER.to_string()
+            };
+            text.push_str(head);
+            if has_escapes(head) {
+                text.push_str(RESET);
+            }
+            snippet.rows.push(SnippetLine {
+                line: first.then_some(i),
+                text,
+            });
+            rest = tail;
+            first = false;
+        }
+    }
+    snippet
+}
+
+/// Cut `text` to at most `width` columns, of which the last holds the cut marker.
+pub fn cut(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+    let (head, _) = split_at_width(text, width.saturating_sub(1));
+    let mut out = head.to_string();
+    if has_escapes(head) {
+        out.push_str(RESET);
+    }
+    if width > 0 {
+        out.push(CUT_MARKER);
+    }
+    out
+}
+
+/// Ends any color left open by a cut.
+const RESET: &str = "\x1b[0m";
+
+fn has_escapes(text: &str) -> bool {
+    text.contains('\x1b')
+}
+
+/// Split `text` after as many characters as fit in `width` columns; an escape sequence goes
+/// wherever the character after it goes.
+fn split_at_width(text: &str, width: usize) -> (&str, &str) {
+    let mut used = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        if c == '\x1b' {
+            let end = escape_end(text, at);
+            // An escape closing off the text so far stays with it
+            if used >= width && !text[at..end].ends_with("[0m") {
+                return text.split_at(at);
+            }
+            while chars.peek().is_some_and(|&(i, _)| i < end) {
+                chars.next();
+            }
+            continue;
+        }
+        let w = char_width(c);
+        if used + w > width {
+            return text.split_at(at);
+        }
+        used += w;
+        chars.next();
+    }
+    (text, "")
+}
+
+/// Byte offset just past the escape sequence starting at `at` (`ESC [ ... letter`).
+fn escape_end(text: &str, at: usize) -> usize {
+    let bytes = text.as_bytes();
+    let mut end = at + 1;
+    if bytes.get(end) == Some(&b'[') {
+        end += 1;
+        while end < bytes.len() && !bytes[end].is_ascii_alphabetic() {
+            end += 1;
+        }
+        end += 1;
+    }
+    end.min(bytes.len())
+}
+
+/// Columns `text` takes on a terminal, not counting escape sequences.
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut at = 0;
+    while let Some(c) = text[at..].chars().next() {
+        if c == '\x1b' {
+            at = escape_end(text, at);
+            continue;
+        }
+        width += char_width(c);
+        at += c.len_utf8();
+    }
+    width
+}
+
+/// Columns a character takes: two for East Asian wide characters and most emoji, none for
+/// combining marks and zero-width joiners, one otherwise.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F | 0x20D0..=0x20FF => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(snippet: &Snippet) -> Vec<String> {
+        snippet.lines().collect()
+    }
+
+    #[test]
+    fn cuts_at_whole_lines_with_a_trailer() {
+        let code = "fn a() {\n    one();\n    two();\n}\n";
+        let opts = SnippetOptions {
+            max_lines: Some(2),
+            ..Default::default()
+        };
+        let snippet = format(code, &opts);
+        assert_eq!(
+            texts(&snippet),
+            vec!["fn a() {", "    one();", "… (+2 more lines)"]
+        );
+        assert_eq!(snippet.rows[1].line, Some(1));
+        let one = SnippetOptions {
+            max_lines: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(format(code, &one).trailer().unwrap(), "… (+1 more line)");
+        assert_eq!(format(code, &SnippetOptions::default()).more_lines, 0);
+        assert_eq!(format("", &opts), Snippet::default());
+    }
+
+    #[test]
+    fn long_lines_are_cut_or_wrapped() {
+        let code = "let x = 1234567890;";
+        let cut = SnippetOptions {
+            width: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(texts(&format(code, &cut)), vec!["let x = 1…"]);
+        let wrap = SnippetOptions { wrap: true, ..cut };
+        let snippet = format(code, &wrap);
+        assert_eq!(texts(&snippet), vec!["let x = 12", "↪ 34567890", "↪ ;"]);
+        assert_eq!(
+            snippet.rows.iter().map(|r| r.line).collect::<Vec<_>>(),
+            vec![Some(0), None, None]
+        );
+        assert_eq!(texts(&format("\tx", &cut)), vec!["    x"]);
+    }
+
+    #[test]
+    fn emoji_and_cjk_are_cut_at_char_boundaries() {
+        // Each of these is two columns and several bytes
+        let emoji = "😀😃😄😁😆";
+        assert_eq!(display_width(emoji), 10);
+        assert_eq!(cut(emoji, 6), "😀😃…");
+        assert_eq!(cut(emoji, 5), "😀😃…");
+        let cjk = "let 名前 = \"東京都\";";
+        assert_eq!(display_width(cjk), 20);
+        assert_eq!(cut(cjk, 9), "let 名前…");
+        assert_eq!(cut(cjk, 8), "let 名…");
+        let wrap = SnippetOptions {
+            width: Some(7),
+            wrap: true,
+            ..Default::default()
+        };
+        for row in format(cjk, &wrap).rows {
+            assert!(display_width(&row.text) <= 7, "{}", row.text);
+        }
+        let joined: String = format(cjk, &wrap)
+            .rows
+            .iter()
+            .map(|r| r.text.trim_start_matches(WRAP_MARKER))
+            .collect();
+        assert_eq!(joined, cjk);
+        // Narrower than one wide character still makes progress
+        let tiny = SnippetOptions {
+            width: Some(1),
+            wrap: true,
+            ..Default::default()
+        };
+        assert_eq!(format("東京", &tiny).rows.len(), 2);
+        assert_eq!(cut("東京", 0), "");
+    }
+
+    #[test]
+    fn color_codes_take_no_width_and_are_closed() {
+        let colored = "\x1b[35mfn\x1b[0m main() {}";
+        assert_eq!(display_width(colored), 12);
+        assert_eq!(cut(colored, 12), colored);
+        assert_eq!(cut(colored, 2), "\x1b[35mf\x1b[0m…");
+        assert_eq!(cut(colored, 4), "\x1b[35mfn\x1b[0m \x1b[0m…");
+    }
+}
@@ -1,13 +1,15 @@
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use streaming_iterator::StreamingIterator;
 use tree_sitter::{Language, Parser, Query, QueryCursor};
-use tree_sitter_python as tspy;
-use tree_sitter_rust as tsrs;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SymbolKind {
     Function,
     Class,
+    Constant,
 }
 
 #[derive(Debug, Clone)]
@@ -17,59 +19,540 @@ pub struct Symbol {
     pub kind: SymbolKind,
     pub name: String,
     pub code: String,
+    /// Names of the symbols enclosing this one, outermost first
+    pub parents: Vec<String>,
 }
 
 struct LanguageConfig {
-    language: fn() -> Language,
+    /// Display name, e.g. for per-language progress counts
+    name: &'static str,
+    language: LanguageSource,
     extensions: &'static [&'static str],
     function_query: &'static str,
     class_query: Option<&'static str>,
+    constant_query: Option<&'static str>,
+    /// Line prefixes of annotations (attributes, decorators) removed by `--annotation-strip`
+    annotation_prefixes: &'static [&'static str],
 }
 
+enum LanguageSource {
+    /// Grammar compiled into cearch
+    #[cfg_attr(
+        not(any(feature = "lang-python", feature = "lang-rust")),
+        allow(dead_code)
+    )]
+    Builtin(fn() -> Language),
+    /// Grammar loaded from a shared library at runtime
+    Loaded(Language),
+}
+
+impl LanguageConfig {
+    fn language(&self) -> Language {
+        match &self.language {
+            LanguageSource::Builtin(f) => f(),
+            LanguageSource::Loaded(language) => language.clone(),
+        }
+    }
+}
+
+/// A language configuration supplied at runtime via `--languages-file`.
+///
+/// The grammar must be provided as a compiled tree-sitter shared library (e.g. built with
+/// `tree-sitter build`) exporting the usual `tree_sitter_<name>` constructor.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DynamicLanguageConfig {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub function_query: String,
+    pub class_query: Option<String>,
+    /// Query for module-level constants, e.g. JavaScript `const` declarations
+    pub constant_query: Option<String>,
+    /// Line prefixes of annotations stripped by `--annotation-strip`, e.g. `@`
+    #[serde(default)]
+    pub annotation_prefixes: Vec<String>,
+    /// Path to the compiled grammar, relative to the languages file
+    pub library: PathBuf,
+    /// Exported constructor; defaults to `tree_sitter_<name>`
+    pub symbol: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LanguagesFile {
+    #[serde(default)]
+    language: Vec<DynamicLanguageConfig>,
+}
+
+/// Languages registered at startup; consulted before the built-in registry.
+static DYNAMIC_LANGUAGES: OnceLock<Vec<LanguageConfig>> = OnceLock::new();
+
+/// Parse a TOML languages file (`[[language]]` tables).
+///
+/// Relative `library` paths are resolved against the file's directory.
+pub fn load_languages_file(path: &Path) -> Result<Vec<DynamicLanguageConfig>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let parsed: LanguagesFile = toml::from_str(&contents)
+        .map_err(|e| format!("invalid languages file {}: {}", path.display(), e))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    Ok(parsed
+        .language
+        .into_iter()
+        .map(|mut cfg| {
+            cfg.library = base.join(&cfg.library);
+            cfg
+        })
+        .collect())
+}
+
+/// Load the grammars for `configs` and add them to the registry for this process.
+///
+/// May only be called once; runtime languages take precedence over built-ins that
+/// claim the same extension.
+pub fn register_dynamic_languages(configs: Vec<DynamicLanguageConfig>) -> Result<(), String> {
+    let mut loaded = Vec::with_capacity(configs.len());
+    for cfg in configs {
+        let language = load_grammar(&cfg)?;
+        let queries = std::iter::once(&cfg.function_query)
+            .chain(cfg.class_query.as_ref())
+            .chain(cfg.constant_query.as_ref());
+        for query in queries {
+            Query::new(&language, query)
+                .map_err(|e| format!("invalid query for language {}: {:?}", cfg.name, e))?;
+        }
+        // Registered once per process and used until exit, so leaking to 'static is fine
+        let extensions: Vec<&'static str> = cfg
+            .extensions
+            .into_iter()
+            .map(|e| &*Box::leak(e.into_boxed_str()))
+            .collect();
+        let annotation_prefixes: Vec<&'static str> = cfg
+            .annotation_prefixes
+            .into_iter()
+            .map(|p| &*Box::leak(p.into_boxed_str()))
+            .collect();
+        loaded.push(LanguageConfig {
+            name: Box::leak(cfg.name.into_boxed_str()),
+            language: LanguageSource::Loaded(language),
+            extensions: Box::leak(extensions.into_boxed_slice()),
+            function_query: Box::leak(cfg.function_query.into_boxed_str()),
+            class_query: cfg.class_query.map(|q| &*Box::leak(q.into_boxed_str())),
+            constant_query: cfg.constant_query.map(|q| &*Box::leak(q.into_boxed_str())),
+            annotation_prefixes: Box::leak(annotation_prefixes.into_boxed_slice()),
+        });
+    }
+    DYNAMIC_LANGUAGES
+        .set(loaded)
+        .map_err(|_| "runtime languages were already registered".to_string())
+}
+
+fn load_grammar(cfg: &DynamicLanguageConfig) -> Result<Language, String> {
+    let symbol = cfg
+        .symbol
+        .clone()
+        .unwrap_or_else(|| format!("tree_sitter_{}", cfg.name));
+    // SAFETY: loading a grammar runs its initializers; users opt in by listing the library.
+    // The library is intentionally never unloaded because the Language points into it.
+    unsafe {
+        let library = libloading::Library::new(&cfg.library)
+            .map_err(|e| format!("failed to load grammar {}: {}", cfg.library.display(), e))?;
+        let constructor: libloading::Symbol<unsafe extern "C" fn() -> *const ()> =
+            library.get(symbol.as_bytes()).map_err(|e| {
+                format!(
+                    "grammar {} does not export {}: {}",
+                    cfg.library.display(),
+                    symbol,
+                    e
+                )
+            })?;
+        let language_fn = tree_sitter_language::LanguageFn::from_raw(*constructor);
+        std::mem::forget(library);
+        Ok(Language::new(language_fn))
+    }
+}
+
+#[cfg(feature = "lang-python")]
 fn lang_python() -> Language {
-    tspy::LANGUAGE.into()
+    tree_sitter_python::LANGUAGE.into()
 }
 
+#[cfg(feature = "lang-rust")]
 fn lang_rust() -> Language {
-    tsrs::LANGUAGE.into()
+    tree_sitter_rust::LANGUAGE.into()
 }
 
+/// Languages whose grammars are compiled in, one cargo feature each (`lang-python`,
+/// `lang-rust`); `lang-all` enables every one and is on by default.
 fn language_registry() -> &'static [LanguageConfig] {
     &[
+        #[cfg(feature = "lang-python")]
         LanguageConfig {
-            language: lang_python,
+            name: "Python",
+            language: LanguageSource::Builtin(lang_python),
             extensions: &["py"],
             function_query: r#"(function_definition name: (identifier) @name) @node"#,
             class_query: Some(r#"(class_definition name: (identifier) @name) @node"#),
+            // Python has no constants; by convention they are module-level ALL_CAPS names
+            constant_query: Some(
+                r#"(module (expression_statement
+                     (assignment left: (identifier) @name) @node)
+                   (#match? @name "^[A-Z][A-Z0-9_]*$"))"#,
+            ),
+            annotation_prefixes: &["@"],
         },
+        #[cfg(feature = "lang-rust")]
         LanguageConfig {
-            language: lang_rust,
+            name: "Rust",
+            language: LanguageSource::Builtin(lang_rust),
             extensions: &["rs"],
             function_query: r#"(function_item name: (identifier) @name) @node"#,
             class_query: None,
+            // Module-level consts only; associated consts in impl and trait blocks are not
+            constant_query: Some(
+                r#"[(source_file (const_item name: (identifier) @name) @node)
+                    (mod_item body: (declaration_list
+                      (const_item name: (identifier) @name) @node))]"#,
+            ),
+            annotation_prefixes: &["#[", "#!["],
         },
     ]
 }
 
+/// Names of the languages cearch can parse in this process: those registered with
+/// `--languages-file`, then the compiled-in ones.
+pub fn supported_languages() -> Vec<&'static str> {
+    let dynamic = DYNAMIC_LANGUAGES.get().map(|v| v.as_slice()).unwrap_or(&[]);
+    let mut names: Vec<&'static str> = Vec::new();
+    for cfg in dynamic.iter().chain(language_registry()) {
+        if !names.contains(&cfg.name) {
+            names.push(cfg.name);
+        }
+    }
+    names
+}
+
 fn language_config_for_path(path: &Path) -> Option<&'static LanguageConfig> {
     let ext = path.extension().and_then(|e| e.to_str())?;
-    language_registry()
+    let dynamic = DYNAMIC_LANGUAGES.get().map(|v| v.as_slice()).unwrap_or(&[]);
+    dynamic
         .iter()
-        .find(|&cfg| cfg.extensions.iter().any(|e| *e == ext))
+        .chain(language_registry())
+        .find(|&cfg| cfg.extensions.contains(&ext))
+}
+
+/// Published tree-sitter grammars for common extensions cearch does not parse, as
+/// `(extensions, language, crate)`; a grammar can be loaded with `index --languages-file`.
+const KNOWN_GRAMMARS: &[(&[&str], &str, &str)] = &[
+    (&["c", "h"], "C", "tree-sitter-c"),
+    (&["cc", "cpp", "cxx", "hh", "hpp"], "C++", "tree-sitter-cpp"),
+    (&["cs"], "C#", "tree-sitter-c-sharp"),
+    (&["ex", "exs"], "Elixir", "tree-sitter-elixir"),
+    (&["go"], "Go", "tree-sitter-go"),
+    (&["hs"], "Haskell", "tree-sitter-haskell"),
+    (&["java"], "Java", "tree-sitter-java"),
+    (
+        &["js", "jsx", "mjs", "cjs"],
+        "JavaScript",
+        "tree-sitter-javascript",
+    ),
+    (&["jl"], "Julia", "tree-sitter-julia"),
+    (&["lua"], "Lua", "tree-sitter-lua"),
+    (&["ml", "mli"], "OCaml", "tree-sitter-ocaml"),
+    (&["php"], "PHP", "tree-sitter-php"),
+    (&["rb"], "Ruby", "tree-sitter-ruby"),
+    (&["scala", "sc"], "Scala", "tree-sitter-scala"),
+    (&["sh", "bash"], "Bash", "tree-sitter-bash"),
+    (&["swift"], "Swift", "tree-sitter-swift"),
+    (&["ts", "tsx"], "TypeScript", "tree-sitter-typescript"),
+    (&["zig"], "Zig", "tree-sitter-zig"),
+];
+
+/// The language and tree-sitter crate that would add support for files ending in `.ext`.
+pub fn grammar_suggestion(ext: &str) -> Option<(&'static str, &'static str)> {
+    KNOWN_GRAMMARS
+        .iter()
+        .find(|(extensions, _, _)| extensions.contains(&ext))
+        .map(|&(_, language, krate)| (language, krate))
+}
+
+/// Name of the language `path` would be parsed as, if any.
+pub fn language_name(path: &Path) -> Option<&'static str> {
+    language_config_for_path(path).map(|cfg| cfg.name)
+}
+
+/// Annotation line prefixes for the language of `path`; empty if it has none.
+pub fn annotation_prefixes(path: &Path) -> &'static [&'static str] {
+    language_config_for_path(path).map_or(&[], |cfg| cfg.annotation_prefixes)
+}
+
+/// `code` with ANSI colors for comments, strings and keywords, found by parsing it with the
+/// grammar of `path`'s language; `None` when the language is not supported.
+pub fn highlight(path: &Path, code: &str) -> Option<String> {
+    fn visit(node: tree_sitter::Node, code: &str, out: &mut String, done: &mut usize) {
+        let kind = node.kind();
+        let color = if kind.contains("comment") {
+            Some("\x1b[90m")
+        } else if kind.contains("string") || kind.contains("char_literal") {
+            Some("\x1b[32m")
+        } else if !node.is_named() && kind.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            Some("\x1b[35m")
+        } else {
+            None
+        };
+        match color {
+            Some(color) if node.start_byte() < node.end_byte() => {
+                out.push_str(&code[*done..node.start_byte()]);
+                out.push_str(color);
+                out.push_str(&code[node.start_byte()..node.end_byte()]);
+                out.push_str("\x1b[0m");
+                *done = node.end_byte();
+            }
+            _ => {
+                for child in node.children(&mut node.walk()) {
+                    visit(child, code, out, done);
+                }
+            }
+        }
+    }
+
+    let cfg = language_config_for_path(path)?;
+    let mut parser = Parser::new();
+    parser.set_language(&cfg.language()).ok()?;
+    let tree = parser.parse(code, None)?;
+    let mut out = String::with_capacity(code.len());
+    let mut done = 0;
+    visit(tree.root_node(), code, &mut out, &mut done);
+    out.push_str(&code[done..]);
+    Some(out)
+}
+
+/// `code` without its leading annotation lines (and blank lines between them).
+///
+/// Code made up only of annotations is returned unchanged, so nothing embeds as empty.
+pub fn strip_annotations<'c>(code: &'c str, prefixes: &[&str]) -> &'c str {
+    let mut rest = code;
+    while let Some(line) = rest.lines().next() {
+        let trimmed = line.trim_start();
+        if !trimmed.is_empty() && !prefixes.iter().any(|p| trimmed.starts_with(p)) {
+            break;
+        }
+        rest = rest[line.len()..]
+            .strip_prefix('\n')
+            .unwrap_or(&rest[line.len()..]);
+    }
+    if rest.trim().is_empty() { code } else { rest }
+}
+
+/// Why symbols could not be extracted from a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractError {
+    /// Reading or parsing the file failed
+    Failed(String),
+    /// tree-sitter or a grammar panicked; holds the panic message
+    Panicked(String),
+}
+
+impl std::fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::Failed(msg) => f.write_str(msg),
+            ExtractError::Panicked(msg) => write!(f, "parser panicked: {}", msg),
+        }
+    }
 }
 
 /// Enumerate symbols (functions/classes) for a single source file.
-pub fn enumerate_symbols_in_file(path: &Path) -> Result<Vec<Symbol>, String> {
+///
+/// Symbols are returned in order of appearance in the file (by starting line). A panic
+/// while parsing is caught and reported as `ExtractError::Panicked`.
+#[cfg(test)]
+pub fn enumerate_symbols_in_file(path: &Path) -> Result<Vec<Symbol>, ExtractError> {
+    Ok(stream_symbols_in_file(path)?.collect())
+}
+
+/// Like `enumerate_symbols_in_file`, but each symbol's code is only copied out of the
+/// source when the symbol is yielded.
+///
+/// The tree is dropped after the queries run; the stream keeps just the source and the
+/// byte range of every symbol, so a consumer taking batches holds one batch of code strings
+/// at a time rather than the whole file's worth.
+pub fn stream_symbols_in_file(path: &Path) -> Result<SymbolStream, ExtractError> {
     let cfg = match language_config_for_path(path) {
         Some(v) => v,
-        None => return Ok(Vec::new()),
+        None => return Ok(SymbolStream::empty(path)),
     };
+    isolate(|| {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        extract_symbols(path, source, cfg)
+    })
+}
 
-    let source = std::fs::read_to_string(path)
-        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+/// Like `stream_symbols_in_file`, with the file's contents already read (see
+/// `index::ReadAhead`); a read error is reported as it would have been by reading here.
+pub fn stream_symbols_from_source(
+    path: &Path,
+    source: std::io::Result<String>,
+) -> Result<SymbolStream, ExtractError> {
+    let cfg = match language_config_for_path(path) {
+        Some(v) => v,
+        None => return Ok(SymbolStream::empty(path)),
+    };
+    let source = source
+        .map_err(|e| ExtractError::Failed(format!("failed to read {}: {}", path.display(), e)))?;
+    isolate(|| extract_symbols(path, source, cfg))
+}
+
+/// Where a symbol sits in its file's source, without a copy of its code.
+#[derive(Debug)]
+struct SymbolSpan {
+    line: usize,
+    kind: SymbolKind,
+    name: String,
+    byte_range: std::ops::Range<usize>,
+    parents: Vec<String>,
+}
+
+/// Symbols of one file in file order, cut from the source as they are yielded.
+#[derive(Debug)]
+pub struct SymbolStream {
+    path: PathBuf,
+    source: String,
+    spans: Vec<SymbolSpan>,
+    next: usize,
+}
+
+impl SymbolStream {
+    fn empty(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            source: String::new(),
+            spans: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// A stream of symbols extracted elsewhere, e.g. by a plugin; their code is kept in one
+    /// string like a parsed file's source.
+    pub fn from_symbols(path: &Path, symbols: Vec<Symbol>) -> Self {
+        let mut stream = Self::empty(path);
+        for symbol in symbols {
+            let start = stream.source.len();
+            stream.source.push_str(&symbol.code);
+            stream.spans.push(SymbolSpan {
+                line: symbol.line,
+                kind: symbol.kind,
+                name: symbol.name,
+                byte_range: start..stream.source.len(),
+                parents: symbol.parents,
+            });
+        }
+        stream
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next >= self.spans.len()
+    }
+
+    /// Yield at most `n` more symbols.
+    pub fn truncate(&mut self, n: usize) {
+        self.spans.truncate(self.next + n);
+    }
+}
+
+impl Iterator for SymbolStream {
+    type Item = Symbol;
+
+    fn next(&mut self) -> Option<Symbol> {
+        let span = self.spans.get_mut(self.next)?;
+        self.next += 1;
+        Some(Symbol {
+            path: self.path.clone(),
+            line: span.line,
+            kind: span.kind.clone(),
+            name: std::mem::take(&mut span.name),
+            code: self.source[span.byte_range.clone()].to_string(),
+            parents: std::mem::take(&mut span.parents),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.spans.len() - self.next;
+        (n, Some(n))
+    }
+}
+
+impl ExactSizeIterator for SymbolStream {}
+
+/// Run `f`, turning a panic into `ExtractError::Panicked`.
+///
+/// Parsers, trees and queries are created per file and never shared, so unwinding out of
+/// one leaves no state behind that later files could observe half-updated.
+fn isolate<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, ExtractError> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(result) => result.map_err(ExtractError::Failed),
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(ExtractError::Panicked(msg))
+        }
+    }
+}
 
+/// The first of the annotations (Rust attributes, Python decorators) right before `node`,
+/// or `node` itself; a symbol's code starts there so `--annotation-strip` has them to strip.
+///
+/// Inner attributes such as `#![allow(...)]` belong to the enclosing module and are left out.
+fn annotated_start<'t>(
+    node: tree_sitter::Node<'t>,
+    source: &str,
+    prefixes: &[&str],
+) -> tree_sitter::Node<'t> {
+    let mut start = node;
+    while let Some(prev) = start.prev_sibling() {
+        let text = &source[prev.byte_range()];
+        if prev.kind().starts_with("inner_") || !prefixes.iter().any(|p| text.starts_with(p)) {
+            break;
+        }
+        start = prev;
+    }
+    start
+}
+
+/// Fill in each span's enclosing spans, e.g. the class around a method.
+fn record_parents(spans: &mut [SymbolSpan]) {
+    // Outer spans first: by start, then the longest
+    spans.sort_by_key(|s| (s.byte_range.start, std::cmp::Reverse(s.byte_range.end)));
+    let mut open: Vec<(std::ops::Range<usize>, String)> = Vec::new();
+    for span in spans.iter_mut() {
+        let range = &span.byte_range;
+        while let Some((outer, _)) = open.last()
+            && !(outer.start <= range.start && range.end <= outer.end && outer != range)
+        {
+            open.pop();
+        }
+        span.parents = open.iter().map(|(_, name)| name.clone()).collect();
+        open.push((range.clone(), span.name.clone()));
+    }
+}
+
+/// The enclosing symbols of every symbol in the file as it is now, by line and name; empty
+/// when the file cannot be read or parsed.
+pub fn parents_in_file(path: &Path) -> HashMap<(usize, String), Vec<String>> {
+    stream_symbols_in_file(path)
+        .map(|stream| stream.map(|s| ((s.line, s.name), s.parents)).collect())
+        .unwrap_or_default()
+}
+
+fn extract_symbols(
+    path: &Path,
+    source: String,
+    cfg: &LanguageConfig,
+) -> Result<SymbolStream, String> {
     let mut parser = Parser::new();
-    let language = (cfg.language)();
+    let language = cfg.language();
     parser
         .set_language(&language)
         .map_err(|_| "failed to set language".to_string())?;
@@ -78,10 +561,10 @@ pub fn enumerate_symbols_in_file(path: &Path) -> Result<Vec<Symbol>, String> {
         .parse(&source, None)
         .ok_or_else(|| "failed to parse source".to_string())?;
 
-    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut spans: Vec<SymbolSpan> = Vec::new();
     let root = tree.root_node();
 
-    // Helper to run a query and push symbols
+    // Helper to run a query and record symbol spans
     let mut run_query = |query_src: &str, kind: SymbolKind| -> Result<(), String> {
         let query = Query::new(&language, query_src)
             .map_err(|e| format!("invalid query for {}: {:?}", path.display(), e))?;
@@ -105,14 +588,13 @@ pub fn enumerate_symbols_in_file(path: &Path) -> Result<Vec<Symbol>, String> {
             }
 
             if let (Some(name), Some(def_node)) = (name_text, def_node) {
-                let line = def_node.start_position().row + 1;
-                let code = source[def_node.byte_range()].to_string();
-                symbols.push(Symbol {
-                    path: path.to_path_buf(),
-                    line,
+                let start = annotated_start(def_node, &source, cfg.annotation_prefixes);
+                spans.push(SymbolSpan {
+                    line: start.start_position().row + 1,
                     kind: kind.clone(),
                     name,
-                    code,
+                    byte_range: start.start_byte()..def_node.end_byte(),
+                    parents: Vec::new(),
                 });
             }
         }
@@ -125,5 +607,328 @@ pub fn enumerate_symbols_in_file(path: &Path) -> Result<Vec<Symbol>, String> {
     if let Some(class_q) = cfg.class_query {
         run_query(class_q, SymbolKind::Class)?;
     }
-    Ok(symbols)
+    // Constants (if provided)
+    if let Some(constant_q) = cfg.constant_query {
+        run_query(constant_q, SymbolKind::Constant)?;
+    }
+    record_parents(&mut spans);
+    // Each query yields its own matches, so interleave them back into file order
+    spans.sort_by_key(|s| s.line);
+    drop(tree);
+    Ok(SymbolStream {
+        path: path.to_path_buf(),
+        source,
+        spans,
+        next: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "lang-python")]
+    fn symbols_are_ordered_by_line() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("mixed.py");
+        std::fs::write(
+            &path,
+            "def first():\n    pass\n\nclass Second:\n    def third(self):\n        pass\n",
+        )
+        .unwrap();
+        let symbols = enumerate_symbols_in_file(&path).expect("parse");
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["first", "Second", "third"]);
+    }
+
+    #[test]
+    #[cfg(feature = "lang-python")]
+    fn nested_definitions_know_their_parents() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("nested.py");
+        std::fs::write(
+            &path,
+            "class Outer:\n    def method(self):\n        def helper():\n            pass\n\
+             \n        return helper\n\n    def other(self):\n        pass\n\ndef top():\n    pass\n",
+        )
+        .unwrap();
+        let symbols = enumerate_symbols_in_file(&path).expect("parse");
+        let chains: Vec<(&str, Vec<&str>)> = symbols
+            .iter()
+            .map(|s| {
+                (
+                    s.name.as_str(),
+                    s.parents.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            chains,
+            [
+                ("Outer", vec![]),
+                ("method", vec!["Outer"]),
+                ("helper", vec!["Outer", "method"]),
+                ("other", vec!["Outer"]),
+                ("top", vec![]),
+            ]
+        );
+        let parents = parents_in_file(&path);
+        assert_eq!(parents[&(3, "helper".to_string())], ["Outer", "method"]);
+        assert_eq!(parents.len(), 5);
+        assert!(parents_in_file(&dir.path().join("gone.py")).is_empty());
+    }
+
+    #[test]
+    #[cfg(all(feature = "lang-python", feature = "lang-rust"))]
+    fn top_level_constants_are_extracted() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let rust = dir.path().join("retry.rs");
+        std::fs::write(
+            &rust,
+            "const MAX_RETRIES: u32 = 3;\n\nfn retry() {}\n\nimpl Client {\n    const TIMEOUT: u64 = 30;\n}\n\nmod limits {\n    const BURST: u32 = 5;\n}\n",
+        )
+        .unwrap();
+        let symbols = enumerate_symbols_in_file(&rust).expect("parse");
+        let found: Vec<(&str, &SymbolKind)> =
+            symbols.iter().map(|s| (s.name.as_str(), &s.kind)).collect();
+        assert_eq!(
+            found,
+            [
+                ("MAX_RETRIES", &SymbolKind::Constant),
+                ("retry", &SymbolKind::Function),
+                ("BURST", &SymbolKind::Constant),
+            ]
+        );
+        assert_eq!(symbols[0].code, "const MAX_RETRIES: u32 = 3;");
+
+        let python = dir.path().join("settings.py");
+        std::fs::write(
+            &python,
+            "DEFAULT_PORT = 8080\nlogger = get_logger()\n\ndef run():\n    LOCAL = 1\n",
+        )
+        .unwrap();
+        let symbols = enumerate_symbols_in_file(&python).expect("parse");
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["DEFAULT_PORT", "run"]);
+        assert_eq!(symbols[0].kind, SymbolKind::Constant);
+        assert_eq!(symbols[0].code, "DEFAULT_PORT = 8080");
+    }
+
+    #[test]
+    #[cfg(feature = "lang-python")]
+    fn large_file_streams_in_batches() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("generated.py");
+        let n = 20_000;
+        let source: String = (0..n)
+            .map(|i| format!("def f{}(x):\n    return x + {}\n\n", i, i))
+            .collect();
+        std::fs::write(&path, &source).unwrap();
+
+        let mut stream = stream_symbols_in_file(&path).expect("parse");
+        assert_eq!(stream.len(), n);
+        // Spans carry no code; only the batch taken here has been copied out of the source
+        let first: Vec<Symbol> = stream.by_ref().take(64).collect();
+        assert_eq!(first[0].name, "f0");
+        assert_eq!(first[63].code, "def f63(x):\n    return x + 63");
+        assert_eq!(stream.len(), n - 64);
+        assert!(stream.spans.iter().all(|s| s.byte_range.len() < 40));
+
+        stream.truncate(100);
+        let rest: Vec<Symbol> = stream.collect();
+        assert_eq!(rest.len(), 100);
+        assert_eq!(rest[99].name, "f163");
+        assert_eq!(rest[99].line, 164 * 3 - 2);
+    }
+
+    #[test]
+    #[cfg(all(feature = "lang-python", feature = "lang-rust"))]
+    fn leading_annotations_are_stripped() {
+        let rust = annotation_prefixes(Path::new("lib.rs"));
+        assert_eq!(
+            strip_annotations("#[inline]\n#[must_use]\npub fn f() {}", rust),
+            "pub fn f() {}"
+        );
+        // Only leading lines; attributes inside the body stay
+        assert_eq!(
+            strip_annotations("fn f() {\n    #[allow(unused)]\n    let x = 1;\n}", rust),
+            "fn f() {\n    #[allow(unused)]\n    let x = 1;\n}"
+        );
+        let python = annotation_prefixes(Path::new("m.py"));
+        assert_eq!(
+            strip_annotations("@staticmethod\n\n  @cache\ndef f():\n    pass", python),
+            "def f():\n    pass"
+        );
+        assert_eq!(strip_annotations("@decorator", python), "@decorator");
+        assert!(annotation_prefixes(Path::new("notes.txt")).is_empty());
+    }
+
+    #[test]
+    #[cfg(all(feature = "lang-python", feature = "lang-rust"))]
+    fn symbols_start_at_their_annotations() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let rust = dir.path().join("lib.rs");
+        std::fs::write(
+            &rust,
+            "#![allow(dead_code)]\n\n#[inline]\n#[must_use]\npub fn f() -> u8 {\n    1\n}\n",
+        )
+        .unwrap();
+        let found = enumerate_symbols_in_file(&rust).expect("parse");
+        assert_eq!(found[0].line, 3);
+        assert_eq!(
+            found[0].code,
+            "#[inline]\n#[must_use]\npub fn f() -> u8 {\n    1\n}"
+        );
+
+        let python = dir.path().join("m.py");
+        std::fs::write(
+            &python,
+            "class A:\n    @staticmethod\n    def f():\n        pass\n",
+        )
+        .unwrap();
+        let found = enumerate_symbols_in_file(&python).expect("parse");
+        assert_eq!(found[1].name, "f");
+        assert_eq!(found[1].line, 2);
+        assert_eq!(found[1].code, "@staticmethod\n    def f():\n        pass");
+    }
+
+    #[cfg(feature = "lang-python")]
+    fn exploding_language() -> Language {
+        panic!("grammar exploded")
+    }
+
+    #[test]
+    #[cfg(feature = "lang-python")]
+    fn panicking_grammar_is_reported_not_propagated() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("bad.boom");
+        std::fs::write(&path, "boom").unwrap();
+        let cfg = LanguageConfig {
+            name: "Boom",
+            language: LanguageSource::Builtin(exploding_language),
+            extensions: &["boom"],
+            function_query: "(identifier) @name @node",
+            class_query: None,
+            constant_query: None,
+            annotation_prefixes: &[],
+        };
+        let err = isolate(|| extract_symbols(&path, "boom".to_string(), &cfg)).unwrap_err();
+        assert_eq!(err, ExtractError::Panicked("grammar exploded".to_string()));
+
+        // Later files are unaffected
+        let ok = dir.path().join("ok.py");
+        std::fs::write(&ok, "def fine():\n    pass\n").unwrap();
+        assert_eq!(enumerate_symbols_in_file(&ok).unwrap().len(), 1);
+        // As read ahead by `--parallel-files`
+        let source = std::fs::read_to_string(&ok);
+        assert_eq!(stream_symbols_from_source(&ok, source).unwrap().len(), 1);
+        let missing = dir.path().join("missing.py");
+        let err = stream_symbols_from_source(&missing, std::fs::read_to_string(&missing));
+        assert!(matches!(err, Err(ExtractError::Failed(msg)) if msg.starts_with("failed to read")));
+    }
+
+    #[test]
+    fn registry_holds_the_compiled_in_languages() {
+        let mut expected = Vec::new();
+        if cfg!(feature = "lang-python") {
+            expected.push("Python");
+        }
+        if cfg!(feature = "lang-rust") {
+            expected.push("Rust");
+        }
+        assert_eq!(supported_languages(), expected);
+        assert_eq!(
+            language_name(Path::new("a.py")),
+            cfg!(feature = "lang-python").then_some("Python")
+        );
+        assert_eq!(
+            language_name(Path::new("a.rs")),
+            cfg!(feature = "lang-rust").then_some("Rust")
+        );
+        // A language left out is unsupported, not an error
+        let dir = tempfile::tempdir().expect("tempdir");
+        for (file, enabled) in [
+            ("a.py", cfg!(feature = "lang-python")),
+            ("a.rs", cfg!(feature = "lang-rust")),
+        ] {
+            let path = dir.path().join(file);
+            std::fs::write(&path, "").unwrap();
+            let symbols = enumerate_symbols_in_file(&path);
+            assert!(
+                symbols.is_ok_and(|s| s.is_empty()),
+                "{} ({})",
+                file,
+                enabled
+            );
+        }
+    }
+
+    #[test]
+    fn parses_languages_file() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("languages.toml");
+        std::fs::write(
+            &path,
+            r#"
+[[language]]
+name = "hcl"
+extensions = ["tf", "hcl"]
+function_query = "(block (identifier) @name) @node"
+library = "grammars/libtree-sitter-hcl.so"
+"#,
+        )
+        .unwrap();
+        let configs = load_languages_file(&path).expect("parse");
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].extensions, vec!["tf", "hcl"]);
+        assert_eq!(configs[0].class_query, None);
+        assert_eq!(
+            configs[0].library,
+            dir.path().join("grammars/libtree-sitter-hcl.so")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "lang-rust")]
+    fn highlight_colors_keywords_strings_and_comments() {
+        let code = "fn f() -> &'static str { \"s\" } // done";
+        let colored = highlight(Path::new("a.rs"), code).expect("rust is supported");
+        assert!(
+            colored.starts_with("\x1b[35mfn\x1b[0m f()"),
+            "{:?}",
+            colored
+        );
+        assert!(colored.contains("\x1b[32m\"s\"\x1b[0m"), "{:?}", colored);
+        assert!(colored.ends_with("\x1b[90m// done\x1b[0m"), "{:?}", colored);
+        let plain: String = colored
+            .split('\x1b')
+            .enumerate()
+            .map(|(i, part)| {
+                if i == 0 {
+                    part
+                } else {
+                    &part[part.find('m').unwrap() + 1..]
+                }
+            })
+            .collect();
+        assert_eq!(plain, code);
+        assert!(highlight(Path::new("a.txt"), code).is_none());
+    }
+
+    #[test]
+    fn missing_grammar_library_is_reported() {
+        let cfg = DynamicLanguageConfig {
+            name: "hcl".to_string(),
+            extensions: vec!["tf".to_string()],
+            function_query: "(block) @node".to_string(),
+            class_query: None,
+            constant_query: None,
+            annotation_prefixes: Vec::new(),
+            library: PathBuf::from("/nonexistent/libtree-sitter-hcl.so"),
+            symbol: None,
+        };
+        let err = load_grammar(&cfg).unwrap_err();
+        assert!(err.contains("failed to load grammar"), "{}", err);
+    }
 }
@@ -0,0 +1,377 @@
+use crate::db::SearchResult;
+use crate::paths::{self, PathStyle};
+use crate::symbols;
+use std::path::Path;
+// Template module renders query results through a user-supplied `--format-string`.
+
+/// Placeholders accepted by `Template::parse`, as listed in its errors.
+pub const PLACEHOLDERS: &[&str] = &[
+    "id",
+    "path",
+    "path:repo",
+    "path:cwd",
+    "path:absolute",
+    "line",
+    "col",
+    "name",
+    "qualified_name",
+    "kind",
+    "lang",
+    "score",
+    "distance",
+    "code",
+    "url",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    /// In the given style, or `--path-style` when `None`
+    Path(Option<PathStyle>),
+    Line,
+    Col,
+    Name,
+    QualifiedName,
+    Kind,
+    Lang,
+    Score,
+    Distance,
+    Code,
+    /// Link on the forge; needs `PathContext::urls`
+    Url,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field {
+        field: Field,
+        width: Option<usize>,
+        precision: Option<usize>,
+    },
+}
+
+/// Where rendered paths are relative to.
+pub struct PathContext<'a> {
+    pub repo_root: &'a Path,
+    /// Canonical current directory, as `paths::display` expects
+    pub cwd: &'a Path,
+    pub style: PathStyle,
+    /// The hit's forge link for `{url}`; none for hits outside the repository, e.g. from
+    /// dependencies, which `{url}` leaves empty
+    pub url: Option<&'a str>,
+}
+
+/// A parsed `--format-string`: literal text with `{placeholder}` fields.
+///
+/// `{field:N}` pads a field to at least `N` characters and `{field:.N}` cuts text to `N`
+/// characters or prints numbers with `N` decimals, as `format!` does. Code is printed on one
+/// line with runs of whitespace collapsed, and `{code:N}` cuts it to `N` columns, ending
+/// in `…`. `\t`, `\n` and `\\` are escapes, and `{{`/`}}` are literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => {
+                        return Err(format!(
+                            "unknown escape \\{} in format string (use \\t, \\n or \\\\)",
+                            other
+                        ));
+                    }
+                    None => return Err("format string ends with a lone \\".to_string()),
+                },
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err("unmatched } in format string (use }} for a brace)".to_string()),
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(format!(
+                                    "unclosed {{{} in format string (use {{{{ for a brace)",
+                                    placeholder
+                                ));
+                            }
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(&placeholder)?);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Whether the template prints forge links, which `PathContext::url` must then hold.
+    pub fn has_url(&self) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(
+                part,
+                Part::Field {
+                    field: Field::Url,
+                    ..
+                }
+            )
+        })
+    }
+
+    /// Render one result; `hit.path` must still be the stored (repo-relative) path.
+    pub fn render(&self, hit: &SearchResult, paths_in: &PathContext) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            let (field, width, precision) = match part {
+                Part::Literal(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+                Part::Field {
+                    field,
+                    width,
+                    precision,
+                } => (*field, *width, *precision),
+            };
+            let value = match field {
+                Field::Score | Field::Distance => match precision {
+                    Some(p) => format!("{:.*}", p, hit.distance),
+                    None => hit.distance.to_string(),
+                },
+                Field::Code => {
+                    let code = hit.code.split_whitespace().collect::<Vec<_>>().join(" ");
+                    match width {
+                        Some(max) => crate::snippet::cut(&code, max),
+                        None => code,
+                    }
+                }
+                text => {
+                    let text = match text {
+                        Field::Path(style) => paths::display(
+                            paths_in.repo_root,
+                            paths_in.cwd,
+                            &hit.path,
+                            style.unwrap_or(paths_in.style),
+                        ),
+                        Field::Id => hit.id.to_string(),
+                        Field::Line => hit.line.to_string(),
+                        // Symbols are recorded by line only; they start at its first column
+                        Field::Col => "1".to_string(),
+                        Field::Name => hit.name.clone(),
+                        Field::QualifiedName => format!("{}::{}", hit.path, hit.name),
+                        Field::Kind => hit.kind.clone(),
+                        Field::Lang => symbols::language_name(Path::new(&hit.path))
+                            .unwrap_or("")
+                            .to_string(),
+                        Field::Url => paths_in.url.unwrap_or_default().to_string(),
+                        _ => unreachable!("handled above"),
+                    };
+                    match precision {
+                        Some(p) => text.chars().take(p).collect(),
+                        None => text,
+                    }
+                }
+            };
+            match width {
+                Some(w) if field != Field::Code => out.push_str(&format!("{:<w$}", value, w = w)),
+                _ => out.push_str(&value),
+            }
+        }
+        out
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part, String> {
+    let unknown = || {
+        format!(
+            "unknown placeholder {{{}}} in format string; valid placeholders: {}",
+            placeholder,
+            PLACEHOLDERS.join(", ")
+        )
+    };
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (placeholder, None),
+    };
+    let style = match (name, spec) {
+        ("path", Some("repo")) => Some(PathStyle::Repo),
+        ("path", Some("cwd")) => Some(PathStyle::Cwd),
+        ("path", Some("absolute")) => Some(PathStyle::Absolute),
+        _ => None,
+    };
+    let field = match name {
+        "id" => Field::Id,
+        "path" => Field::Path(style),
+        "line" => Field::Line,
+        "col" => Field::Col,
+        "name" => Field::Name,
+        "qualified_name" => Field::QualifiedName,
+        "kind" => Field::Kind,
+        "lang" => Field::Lang,
+        "score" => Field::Score,
+        "distance" => Field::Distance,
+        "code" => Field::Code,
+        "url" => Field::Url,
+        _ => return Err(unknown()),
+    };
+    let (width, precision) = match spec {
+        _ if style.is_some() => (None, None),
+        None => (None, None),
+        Some(spec) => {
+            let (width, precision) = match spec.split_once('.') {
+                Some((width, precision)) => (width, Some(precision)),
+                None => (spec, None),
+            };
+            let number = |s: &str| {
+                s.parse::<usize>().map_err(|_| {
+                    format!(
+                        "invalid spec in {{{}}}: expected a width like :20 or a precision like :.2",
+                        placeholder
+                    )
+                })
+            };
+            (
+                (!width.is_empty()).then(|| number(width)).transpose()?,
+                precision.map(number).transpose()?,
+            )
+        }
+    };
+    if field == Field::Code && precision.is_some() {
+        return Err(format!(
+            "{{{}}}: code takes a maximum length like {{code:80}}, not a precision",
+            placeholder
+        ));
+    }
+    Ok(Part::Field {
+        field,
+        width,
+        precision,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn hit() -> SearchResult {
+        SearchResult {
+            id: 7,
+            path: "src/db.rs".to_string(),
+            line: 42,
+            kind: "fn".to_string(),
+            name: "open".to_string(),
+            code: "pub fn open(path: &Path) -> Result<Self> {\n    Self::connect(path)\n}"
+                .to_string(),
+            distance: 0.123456,
+            duplicate_files: 0,
+            duplicates: Vec::new(),
+        }
+    }
+
+    fn render_with_url(template: &str, url: Option<&str>) -> String {
+        let root = PathBuf::from("/work/repo");
+        let cwd = root.join("src");
+        let ctx = PathContext {
+            repo_root: &root,
+            cwd: &cwd,
+            style: PathStyle::Repo,
+            url,
+        };
+        Template::parse(template)
+            .expect("valid template")
+            .render(&hit(), &ctx)
+    }
+
+    fn render(template: &str) -> String {
+        render_with_url(
+            template,
+            Some("https://github.com/org/repo/blob/abc123/src/db.rs#L42"),
+        )
+    }
+
+    #[test]
+    fn renders_fields_and_escapes() {
+        assert_eq!(
+            render(r"{path}:{line}\t{name}\t{score:.2}"),
+            "src/db.rs:42\topen\t0.12"
+        );
+        assert_eq!(render("{path:cwd} {path:repo}"), "db.rs src/db.rs");
+        assert!(render("{path:absolute}").ends_with("repo/src/db.rs"));
+        assert_eq!(
+            render("{kind} {lang} {qualified_name}:{col}"),
+            "fn Rust src/db.rs::open:1"
+        );
+        assert_eq!(render("{distance}"), "0.123456");
+        assert_eq!(render("#{id}"), "#7");
+        assert_eq!(
+            render("{name} {url}"),
+            "open https://github.com/org/repo/blob/abc123/src/db.rs#L42"
+        );
+        // Hits without a link, e.g. from dependencies, print none
+        assert_eq!(render_with_url("{name} [{url}]", None), "open []");
+        assert!(Template::parse("{path} {url}").unwrap().has_url());
+        assert!(!Template::parse("{path}").unwrap().has_url());
+        assert_eq!(render(r"{{{name}}}\n\\"), "{open}\n\\");
+        assert_eq!(render("no fields"), "no fields");
+    }
+
+    #[test]
+    fn width_and_precision_specifiers() {
+        assert_eq!(render("[{name:6}]"), "[open  ]");
+        assert_eq!(render("[{name:2}]"), "[open]");
+        assert_eq!(render("[{name:.2}]"), "[op]");
+        assert_eq!(render("[{score:8.3}]"), "[0.123   ]");
+        assert_eq!(render("[{line:4}]"), "[42  ]");
+        // Code is flattened onto one line and cut with an ellipsis
+        assert_eq!(
+            render("{code}"),
+            "pub fn open(path: &Path) -> Result<Self> { Self::connect(path) }"
+        );
+        assert_eq!(render("{code:12}"), "pub fn open…");
+        assert_eq!(render("{code:500}").chars().count(), 64);
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected_up_front() {
+        let err = |t: &str| Template::parse(t).unwrap_err();
+        let unknown = err("{path}:{lnie}");
+        assert!(
+            unknown.starts_with("unknown placeholder {lnie}"),
+            "{}",
+            unknown
+        );
+        assert!(unknown.contains("line, col, name"), "{}", unknown);
+        assert!(err("{path:home}").contains("invalid spec"));
+        assert!(err("{name:x}").contains("invalid spec"));
+        assert!(err("{code:.5}").contains("maximum length"));
+        assert!(err("{name").starts_with("unclosed {name"));
+        assert!(err("name}").starts_with("unmatched }"));
+        assert!(err(r"\q").contains("unknown escape \\q"));
+        assert!(err("\\").contains("lone"));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::db::DB;
+use anyhow::Result;
+// Verify module re-embeds a sample of stored symbols for `cearch verify` and compares the
+// fresh vectors with the stored ones, to catch model drift and corrupted vectors.
+
+/// Symbols embedded per call to the embedder.
+const BATCH: usize = 32;
+
+/// A sampled vector that cannot be compared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Corrupt {
+    pub rowid: i64,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Symbols with a vector in the index
+    pub total: usize,
+    /// Cosine similarity of stored and fresh vectors, per sampled rowid, in rowid order
+    pub similarities: Vec<(i64, f32)>,
+    pub corrupt: Vec<Corrupt>,
+}
+
+impl Report {
+    pub fn mean(&self) -> Option<f32> {
+        if self.similarities.is_empty() {
+            return None;
+        }
+        let sum: f32 = self.similarities.iter().map(|&(_, s)| s).sum();
+        Some(sum / self.similarities.len() as f32)
+    }
+
+    /// The similarity below which a fraction `q` of the sample lies (nearest rank).
+    pub fn quantile(&self, q: f32) -> Option<f32> {
+        let mut sorted: Vec<f32> = self.similarities.iter().map(|&(_, s)| s).collect();
+        sorted.sort_by(f32::total_cmp);
+        let last = sorted.len().checked_sub(1)?;
+        Some(sorted[((q * last as f32).round() as usize).min(last)])
+    }
+
+    /// The index has drifted when the mean similarity is below `threshold`.
+    pub fn drifted(&self, threshold: f32) -> bool {
+        self.mean().is_some_and(|mean| mean < threshold)
+    }
+}
+
+/// Pick `n` of `rowids` with a generator seeded by `seed`, so the same seed picks the same
+/// symbols while the index is unchanged. Returned in rowid order.
+pub fn sample(rowids: &[i64], n: usize, seed: u64) -> Vec<i64> {
+    let mut pool = rowids.to_vec();
+    let n = n.min(pool.len());
+    let mut state = seed;
+    for i in 0..n {
+        let j = i + (splitmix64(&mut state) % (pool.len() - i) as u64) as usize;
+        pool.swap(i, j);
+    }
+    pool.truncate(n);
+    pool.sort_unstable();
+    pool
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Re-embed `n` sampled symbols with `embed` and compare them with their stored vectors.
+///
+/// A stored vector whose length is not the index's dimension, that has non-finite values or
+/// that cannot be read is reported as corrupt rather than compared.
+pub fn verify<E>(db: &DB, n: usize, seed: u64, mut embed: E) -> Result<Report>
+where
+    E: FnMut(&[&str]) -> Result<Vec<Vec<f32>>>,
+{
+    let rowids = db.vector_rowids()?;
+    let dim = db.embedding_dim()?;
+    let mut report = Report {
+        total: rowids.len(),
+        ..Report::default()
+    };
+    let mut pending: Vec<(i64, Vec<f32>, String)> = Vec::new();
+    for rowid in sample(&rowids, n, seed) {
+        let corrupt = |reason: String| Corrupt { rowid, reason };
+        let Some(stored) = db.get_embedding(rowid)? else {
+            report.corrupt.push(corrupt(
+                "stored vector is missing or unreadable".to_string(),
+            ));
+            continue;
+        };
+        if let Some(dim) = dim
+            && stored.len() != dim
+        {
+            report.corrupt.push(corrupt(format!(
+                "stored vector has {} dimensions, index records {}",
+                stored.len(),
+                dim
+            )));
+            continue;
+        }
+        if stored.iter().any(|v| !v.is_finite()) {
+            report
+                .corrupt
+                .push(corrupt("stored vector has non-finite values".to_string()));
+            continue;
+        }
+        let Some(text) = db.embedded_text(rowid)? else {
+            continue;
+        };
+        pending.push((rowid, stored, text));
+    }
+    for chunk in pending.chunks(BATCH) {
+        let texts: Vec<&str> = chunk.iter().map(|(_, _, text)| text.as_str()).collect();
+        let fresh = embed(&texts)?;
+        for ((rowid, stored, _), fresh) in chunk.iter().zip(fresh) {
+            if fresh.len() != stored.len() {
+                anyhow::bail!(
+                    "model produced {} dimensions but the index stores {}",
+                    fresh.len(),
+                    stored.len()
+                );
+            }
+            report.similarities.push((*rowid, cosine(stored, &fresh)));
+        }
+    }
+    Ok(report)
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SymbolRecord, WriteOptions};
+
+    fn mock_embedder(texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts
+            .iter()
+            .map(|text| vec![text.len() as f32, 1.0, 0.0])
+            .collect())
+    }
+
+    #[test]
+    fn sampling_is_deterministic_and_bounded() {
+        let rowids: Vec<i64> = (1..=50).collect();
+        let a = sample(&rowids, 10, 7);
+        assert_eq!(a.len(), 10);
+        assert!(a.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(a, sample(&rowids, 10, 7));
+        assert_ne!(a, sample(&rowids, 10, 8));
+        assert_eq!(sample(&rowids, 100, 7), rowids);
+    }
+
+    #[test]
+    fn detects_a_mutated_vector() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let db = DB::open_with_dim(dir.path(), 3, &WriteOptions::default()).expect("open");
+        let codes = ["fn a() {}", "fn bb() {}", "fn ccc() {}", "fn dddd() {}"];
+        let vectors = mock_embedder(&codes).unwrap();
+        let names = ["a", "bb", "ccc", "dddd"];
+        let records: Vec<SymbolRecord> = codes
+            .iter()
+            .zip(&names)
+            .zip(&vectors)
+            .enumerate()
+            .map(|(i, ((code, name), vector))| SymbolRecord {
+                code,
+                ..SymbolRecord::fixture("a.rs", i + 1, name, vector)
+            })
+            .collect();
+        db.insert_symbols(&records).unwrap();
+
+        let clean = verify(&db, 10, 0, mock_embedder).expect("verify");
+        assert_eq!(clean.total, 4);
+        assert_eq!(clean.similarities.len(), 4);
+        assert!(clean.corrupt.is_empty());
+        assert!(!clean.drifted(0.99));
+        assert!(clean.quantile(0.0).unwrap() > 0.999);
+
+        let id = clean.similarities[2].0;
+        db.set_embeddings(&[(id, &[0.0, -1.0, 1.0])]).unwrap();
+        let report = verify(&db, 10, 0, mock_embedder).expect("verify");
+        let (worst, similarity) = report
+            .similarities
+            .iter()
+            .copied()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        assert_eq!(worst, id);
+        assert!(similarity < 0.5, "{}", similarity);
+        assert!(report.drifted(0.99));
+        assert_eq!(report.quantile(0.0), Some(similarity));
+
+        let other = clean.similarities[0].0;
+        db.set_embeddings(&[(other, &[f32::NAN, 1.0, 0.0])])
+            .unwrap();
+        let report = verify(&db, 10, 0, mock_embedder).expect("verify");
+        assert_eq!(report.similarities.len(), 3);
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].rowid, other);
+        assert!(report.corrupt[0].reason.contains("non-finite"));
+    }
+}